@@ -0,0 +1,184 @@
+//! Evaluation of the constant expressions used by `:calc`, `:byte` and friends.
+//!
+//! Like Octo, there's no operator precedence: binary operators are evaluated
+//! right to left, so `2 * 3 + 1` is `8`. Use parentheses to be explicit. All
+//! arithmetic is done with floating point numbers.
+
+use crate::lexer::{parse_number, Token};
+
+/// The names that an expression can refer to.
+pub(crate) trait Scope {
+    /// The value of a constant or label, if it's defined.
+    fn value(&self, name: &str) -> Option<f64>;
+
+    /// The byte that's been assembled at some address so far, for the `@`
+    /// operator.
+    fn byte_at(&self, addr: usize) -> u8;
+}
+
+/// Evaluate an expression, which must use up all of `tokens`.
+pub(crate) fn evaluate(tokens: &[Token], scope: &impl Scope) -> Result<f64, String> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        scope,
+    };
+
+    let value = parser.expression()?;
+
+    match parser.tokens.get(parser.pos) {
+        Some(token) => Err(format!("Unexpected '{}' in expression", token.text)),
+        None => Ok(value),
+    }
+}
+
+struct Parser<'a, S> {
+    tokens: &'a [Token],
+    pos: usize,
+    scope: &'a S,
+}
+
+impl<S: Scope> Parser<'_, S> {
+    fn next(&mut self) -> Result<&str, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| "Unexpected end of expression".to_string())?;
+        self.pos += 1;
+        Ok(&token.text)
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let lhs = self.term()?;
+
+        let op = match self.tokens.get(self.pos) {
+            Some(token) if token.text != ")" => token.text.as_str(),
+            _ => return Ok(lhs),
+        };
+
+        self.pos += 1;
+        let rhs = self.expression()?;
+
+        let int = |value: f64| value as i64;
+        let bool = |value: bool| if value { 1.0 } else { 0.0 };
+
+        Ok(match op {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (int(lhs) & int(rhs)) as f64,
+            "|" => (int(lhs) | int(rhs)) as f64,
+            "^" => (int(lhs) ^ int(rhs)) as f64,
+            "<<" | ">>" => {
+                // Shifting by a negative amount, or by the width of the integer
+                // or more, would overflow.
+                let shifted = u32::try_from(int(rhs)).ok().and_then(|amount| {
+                    if op == "<<" {
+                        int(lhs).checked_shl(amount)
+                    } else {
+                        int(lhs).checked_shr(amount)
+                    }
+                });
+
+                match shifted {
+                    Some(value) => value as f64,
+                    None => return Err(format!("Can't shift by {rhs} in expression")),
+                }
+            }
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => bool(lhs < rhs),
+            "<=" => bool(lhs <= rhs),
+            "==" => bool(lhs == rhs),
+            "!=" => bool(lhs != rhs),
+            ">=" => bool(lhs >= rhs),
+            ">" => bool(lhs > rhs),
+            _ => return Err(format!("Unknown operator '{op}' in expression")),
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let token = self.next()?.to_string();
+
+        let unary = |f: fn(f64) -> f64, parser: &mut Self| Ok(f(parser.term()?));
+
+        match token.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                match self.next()? {
+                    ")" => Ok(value),
+                    other => Err(format!("Expected ')' but found '{other}'")),
+                }
+            }
+            "-" => unary(|x| -x, self),
+            "~" => unary(|x| !(x as i64) as f64, self),
+            "!" => unary(|x| if x == 0.0 { 1.0 } else { 0.0 }, self),
+            "sin" => unary(f64::sin, self),
+            "cos" => unary(f64::cos, self),
+            "tan" => unary(f64::tan, self),
+            "exp" => unary(f64::exp, self),
+            "log" => unary(f64::ln, self),
+            "abs" => unary(f64::abs, self),
+            "sqrt" => unary(f64::sqrt, self),
+            "sign" => unary(|x| if x == 0.0 { 0.0 } else { x.signum() }, self),
+            "ceil" => unary(f64::ceil, self),
+            "floor" => unary(f64::floor, self),
+            "@" => {
+                let addr = self.term()?;
+                Ok(self.scope.byte_at(addr as usize) as f64)
+            }
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            name => match parse_number(name) {
+                Some(value) => Ok(value as f64),
+                None => self
+                    .scope
+                    .value(name)
+                    .ok_or_else(|| format!("Undefined name '{name}' in expression")),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    struct Empty;
+
+    impl Scope for Empty {
+        fn value(&self, name: &str) -> Option<f64> {
+            (name == "HERE").then_some(0x200 as f64)
+        }
+
+        fn byte_at(&self, _addr: usize) -> u8 {
+            0
+        }
+    }
+
+    fn eval(source: &str) -> Result<f64, String> {
+        evaluate(&tokenize(source), &Empty)
+    }
+
+    #[test]
+    fn evaluates_right_to_left() {
+        assert_eq!(eval("2 * 3 + 1"), Ok(8.0));
+        assert_eq!(eval("( 2 * 3 ) + 1"), Ok(7.0));
+        assert_eq!(eval("HERE + 0x10"), Ok(0x210 as f64));
+        assert_eq!(eval("- 4 max -5"), Ok(-4.0));
+        assert!(eval("1 +").is_err());
+        assert!(eval("nope").is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_shifts() {
+        assert_eq!(eval("1 << 4"), Ok(16.0));
+        assert_eq!(eval("0x100 >> 63"), Ok(0.0));
+        assert!(eval("1 << 64").is_err());
+        assert!(eval("1 >> -1").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+//! Splitting Octo source into tokens.
+
+/// A single whitespace-separated token of Octo source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub text: String,
+    /// The line the token is on, counting from 1.
+    pub line: usize,
+}
+
+/// Split some source into tokens.
+///
+/// Octo's syntax is very simple: tokens are separated by whitespace, and a `#`
+/// starts a comment that runs to the end of the line.
+pub(crate) fn tokenize(source: &str) -> Vec<Token> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(index, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |text| Token {
+                text: text.to_string(),
+                line: index + 1,
+            })
+        })
+        .collect()
+}
+
+/// Parse a numeric literal: decimal, hex with a `0x` prefix, or binary with a
+/// `0b` prefix, all optionally negated with a leading `-`.
+pub(crate) fn parse_number(text: &str) -> Option<i64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
@@ -0,0 +1,1158 @@
+//! An assembler for Octo, the CHIP8, SUPER-CHIP and XO-CHIP assembly language.
+//!
+//! Octo is the de facto standard language for writing CHIP8 programs. See the
+//! [Octo manual](https://johnearnest.github.io/Octo/docs/Manual.html) for a
+//! description of the language. Everything is supported except for
+//! `:stringmode`, `:include`, and the debugger-only `:monitor` (which is parsed
+//! but ignored).
+//!
+//! ```
+//! let program = assembler::assemble(
+//!     ": main
+//!        v0 := 5
+//!        loop
+//!          v0 += -1
+//!          if v0 != 0 then
+//!        again",
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(program.rom, [0x60, 0x05, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02]);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+
+use calc::Scope;
+use lexer::{parse_number, tokenize, Token};
+use platform::Platform;
+use ram::PROGRAM_START_ADDRESS;
+use thiserror::Error;
+
+mod calc;
+mod lexer;
+mod symbols;
+
+pub use symbols::SymbolMap;
+
+/// The size of the address space that programs can be assembled into.
+const MEMORY_SIZE: usize = 0x10000;
+
+/// The most macro expansions allowed, to catch macros that call themselves
+/// forever.
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+/// Constants that are always defined, mapping keys on the keyboard to the
+/// keypad keys they're bound to.
+const OCTO_KEY_CONSTANTS: [(&str, u8); 16] = [
+    ("OCTO_KEY_1", 0x1),
+    ("OCTO_KEY_2", 0x2),
+    ("OCTO_KEY_3", 0x3),
+    ("OCTO_KEY_4", 0xC),
+    ("OCTO_KEY_Q", 0x4),
+    ("OCTO_KEY_W", 0x5),
+    ("OCTO_KEY_E", 0x6),
+    ("OCTO_KEY_R", 0xD),
+    ("OCTO_KEY_A", 0x7),
+    ("OCTO_KEY_S", 0x8),
+    ("OCTO_KEY_D", 0x9),
+    ("OCTO_KEY_F", 0xE),
+    ("OCTO_KEY_Z", 0xA),
+    ("OCTO_KEY_X", 0x0),
+    ("OCTO_KEY_C", 0xB),
+    ("OCTO_KEY_V", 0xF),
+];
+
+/// Words with a special meaning, which can't be used as names.
+const KEYWORDS: [&str; 43] = [
+    ":", ":=", "|=", "&=", "^=", "-=", "=-", "+=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=",
+    "key", "-key", "hex", "bighex", "random", "delay", "buzzer", "pitch", "long", "if", "then",
+    "begin", "else", "end", "loop", "while", "again", "jump", "jump0", "native", "sprite",
+    "return", "clear", "bcd", "save", "load", "i",
+];
+
+/// A successfully assembled program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The ROM, to be loaded at [`PROGRAM_START_ADDRESS`].
+    pub rom: Vec<u8>,
+    /// The names and source lines of everything in the ROM.
+    pub symbols: SymbolMap,
+    /// The simplest platform that can run the program, based on the
+    /// instructions it uses and its size.
+    pub platform: Platform,
+}
+
+/// An error in a program's source.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Line {line}: {message}")]
+pub struct AssembleError {
+    /// The line the error is on, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Assemble some Octo source into a ROM.
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    Assembler::new(source).run()
+}
+
+/// A user-defined macro.
+#[derive(Debug, Clone)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+/// A reference to a label that hadn't been defined yet, to be filled in once
+/// the whole program has been assembled.
+#[derive(Debug, Clone)]
+struct Fixup {
+    name: String,
+    line: usize,
+    /// The address of the instruction that needs fixing.
+    at: usize,
+    kind: FixupKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// The low 12 bits of an instruction like `1NNN`.
+    Addr12,
+    /// The 16-bit address after `F000`.
+    Addr16,
+    /// The two `vX := NN` instructions emitted by `:unpack N label`.
+    Unpack { nibble: u8 },
+    /// The two `vX := NN` instructions emitted by `:unpack long label`.
+    UnpackLong,
+}
+
+/// A structured control flow statement that hasn't been closed yet.
+#[derive(Debug, Clone)]
+enum Control {
+    /// An `if ... begin`, with the address of the jump over its body.
+    If { line: usize, jump: usize },
+    /// An `else`, with the address of the jump over its body.
+    Else { line: usize, jump: usize },
+    /// A `loop`, with the address it starts at and the addresses of the jumps
+    /// out of it for each `while`.
+    Loop {
+        line: usize,
+        start: usize,
+        whiles: Vec<usize>,
+    },
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    /// The line of the last token read, for error messages.
+    line: usize,
+
+    memory: Vec<Option<u8>>,
+    here: usize,
+    /// True while address `0x200` is reserved for a `jump main`.
+    has_main: bool,
+
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    macro_expansions: usize,
+
+    fixups: Vec<Fixup>,
+    control: Vec<Control>,
+
+    symbols: SymbolMap,
+    platform: Platform,
+}
+
+impl Scope for Assembler {
+    fn value(&self, name: &str) -> Option<f64> {
+        if name == "HERE" {
+            return Some(self.here as f64);
+        }
+
+        self.constants
+            .get(name)
+            .copied()
+            .or_else(|| self.labels.get(name).map(|&addr| addr as f64))
+    }
+
+    fn byte_at(&self, addr: usize) -> u8 {
+        self.memory.get(addr).copied().flatten().unwrap_or(0)
+    }
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        let start = PROGRAM_START_ADDRESS as usize;
+
+        let mut memory = vec![None; MEMORY_SIZE];
+        // Reserve space for a jump to `main`, in case it isn't the first thing
+        // in the program.
+        memory[start] = Some(0x10);
+        memory[start + 1] = Some(0x00);
+
+        Self {
+            tokens: tokenize(source).into(),
+            line: 1,
+            memory,
+            here: start + 2,
+            has_main: true,
+            labels: HashMap::new(),
+            constants: OCTO_KEY_CONSTANTS
+                .iter()
+                .map(|&(name, key)| (name.to_string(), key as f64))
+                .collect(),
+            aliases: HashMap::from([("compare-temp".to_string(), 0xF)]),
+            macros: HashMap::new(),
+            macro_expansions: 0,
+            fixups: Vec::new(),
+            control: Vec::new(),
+            symbols: SymbolMap::default(),
+            platform: Platform::Chip8,
+        }
+    }
+
+    fn run(mut self) -> Result<Program, AssembleError> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if let Some(control) = self.control.last() {
+            let (line, name) = match control {
+                Control::If { line, .. } => (*line, "if ... begin"),
+                Control::Else { line, .. } => (*line, "else"),
+                Control::Loop { line, .. } => (*line, "loop"),
+            };
+            return Err(AssembleError {
+                line,
+                message: format!("This {name} is never closed"),
+            });
+        }
+
+        let start = PROGRAM_START_ADDRESS as usize;
+
+        if self.has_main {
+            let main = *self.labels.get("main").ok_or(AssembleError {
+                line: self.line,
+                message: "This program doesn't define a main label".to_string(),
+            })?;
+            self.patch_jump(start, main as usize)?;
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            self.resolve(&fixup)?;
+        }
+
+        let end = self
+            .memory
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(start, |last| last + 1);
+
+        if end > 0x1000 {
+            self.require(Platform::XoChip);
+        }
+
+        let rom = self.memory[start..end]
+            .iter()
+            .map(|byte| byte.unwrap_or(0))
+            .collect();
+
+        let mut symbols = self.symbols;
+        symbols.labels = self.labels.into_iter().collect();
+        symbols.constants = self
+            .constants
+            .into_iter()
+            .filter(|(name, _)| !name.starts_with("OCTO_KEY_"))
+            .collect();
+
+        Ok(Program {
+            rom,
+            symbols,
+            platform: self.platform,
+        })
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AssembleError> {
+        Err(AssembleError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    /// Take the next token.
+    fn next(&mut self) -> Result<String, AssembleError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("Unexpected end of file"),
+        }
+    }
+
+    /// Look at a token coming up, without taking it.
+    fn peek(&self, offset: usize) -> Option<&str> {
+        self.tokens.get(offset).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("Expected '{expected}' but found '{token}'"));
+        }
+        Ok(())
+    }
+
+    /// Note that the program needs at least some platform to run.
+    fn require(&mut self, platform: Platform) {
+        let rank = |platform| match platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        };
+
+        if rank(platform) > rank(self.platform) {
+            self.platform = platform;
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                let start = PROGRAM_START_ADDRESS as usize;
+                if name == "main" && self.has_main && self.here == start + 2 {
+                    // `main` comes first, so there's no need to jump to it.
+                    self.has_main = false;
+                    self.here = start;
+                    self.memory[start] = None;
+                    self.memory[start + 1] = None;
+                }
+                self.define_label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.constant_value()?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":macro" => self.define_macro()?,
+            ":byte" => {
+                let value = match self.peek(0) {
+                    Some("{") => self.calc()?,
+                    _ => self.constant_value()?,
+                };
+                let byte = self.fit_byte(value as i64)?;
+                self.emit(byte)?;
+            }
+            ":org" => {
+                let addr = match self.peek(0) {
+                    Some("{") => self.calc()?,
+                    _ => self.constant_value()?,
+                } as i64;
+                if !(0..MEMORY_SIZE as i64).contains(&addr) {
+                    return self.error(format!("Address {addr:#X} is out of range for :org"));
+                }
+                self.here = addr as usize;
+            }
+            ":unpack" => self.unpack()?,
+            ":breakpoint" => {
+                let name = self.next()?;
+                self.symbols.breakpoints.insert(name, self.here as u16);
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ":assert" => {
+                if self.calc()? == 0.0 {
+                    return self.error("Assertion failed");
+                }
+            }
+            ":call" => {
+                let addr = self.address(FixupKind::Addr12)?;
+                self.instruction_nnn(0x2, addr)?;
+            }
+
+            ";" | "return" => self.instruction(0x00, 0xEE)?,
+            "clear" => self.instruction(0x00, 0xE0)?,
+            "exit" => {
+                self.require(Platform::SuperChip);
+                self.instruction(0x00, 0xFD)?;
+            }
+            "lores" => {
+                self.require(Platform::SuperChip);
+                self.instruction(0x00, 0xFE)?;
+            }
+            "hires" => {
+                self.require(Platform::SuperChip);
+                self.instruction(0x00, 0xFF)?;
+            }
+            "scroll-down" => {
+                self.require(Platform::SuperChip);
+                let n = self.nibble()?;
+                self.instruction(0x00, 0xC0 | n)?;
+            }
+            "scroll-up" => {
+                self.require(Platform::XoChip);
+                let n = self.nibble()?;
+                self.instruction(0x00, 0xD0 | n)?;
+            }
+            "scroll-right" => {
+                self.require(Platform::SuperChip);
+                self.instruction(0x00, 0xFB)?;
+            }
+            "scroll-left" => {
+                self.require(Platform::SuperChip);
+                self.instruction(0x00, 0xFC)?;
+            }
+            "plane" => {
+                self.require(Platform::XoChip);
+                let n = self.nibble()?;
+                if n > 3 {
+                    return self.error(format!("Plane mask {n} must be between 0 and 3"));
+                }
+                self.instruction(0xF0 | n, 0x01)?;
+            }
+            "audio" => {
+                self.require(Platform::XoChip);
+                self.instruction(0xF0, 0x02)?;
+            }
+            "jump" => {
+                let addr = self.address(FixupKind::Addr12)?;
+                self.instruction_nnn(0x1, addr)?;
+            }
+            "jump0" => {
+                let addr = self.address(FixupKind::Addr12)?;
+                self.instruction_nnn(0xB, addr)?;
+            }
+            "native" => {
+                let addr = self.address(FixupKind::Addr12)?;
+                self.instruction_nnn(0x0, addr)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                if n == 0 {
+                    self.require(Platform::SuperChip);
+                }
+                self.instruction(0xD0 | x, y << 4 | n)?;
+            }
+            "bcd" => self.fx_instruction(0x33)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek(0) == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.require(Platform::XoChip);
+                    let n = if token == "save" { 0x2 } else { 0x3 };
+                    self.instruction(0x50 | x, y << 4 | n)?;
+                } else {
+                    let nn = if token == "save" { 0x55 } else { 0x65 };
+                    self.instruction(0xF0 | x, nn)?;
+                }
+            }
+            "saveflags" | "loadflags" => {
+                let x = self.register()?;
+                self.require(if x < 8 {
+                    Platform::SuperChip
+                } else {
+                    Platform::XoChip
+                });
+                let nn = if token == "saveflags" { 0x75 } else { 0x85 };
+                self.instruction(0xF0 | x, nn)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let nn = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => {
+                        self.require(Platform::XoChip);
+                        0x3A
+                    }
+                };
+                self.fx_instruction(nn)?;
+            }
+            "i" => self.index_assignment()?,
+
+            "if" => self.if_statement()?,
+            "else" => {
+                let line = self.line;
+                let jump = match self.control.pop() {
+                    Some(Control::If { jump, .. }) => jump,
+                    _ => return self.error("This else doesn't belong to an if ... begin"),
+                };
+                let else_jump = self.here;
+                self.instruction(0x10, 0x00)?;
+                self.patch_jump(jump, self.here)?;
+                self.control.push(Control::Else {
+                    line,
+                    jump: else_jump,
+                });
+            }
+            "end" => {
+                let jump = match self.control.pop() {
+                    Some(Control::If { jump, .. } | Control::Else { jump, .. }) => jump,
+                    _ => return self.error("This end doesn't belong to an if ... begin"),
+                };
+                self.patch_jump(jump, self.here)?;
+            }
+            "loop" => self.control.push(Control::Loop {
+                line: self.line,
+                start: self.here,
+                whiles: Vec::new(),
+            }),
+            "while" => {
+                self.condition(true)?;
+                let jump = self.here;
+                self.instruction(0x10, 0x00)?;
+
+                match self
+                    .control
+                    .iter_mut()
+                    .rev()
+                    .find(|control| matches!(control, Control::Loop { .. }))
+                {
+                    Some(Control::Loop { whiles, .. }) => whiles.push(jump),
+                    _ => return self.error("This while isn't inside a loop"),
+                }
+            }
+            "again" => {
+                let (start, whiles) = match self.control.pop() {
+                    Some(Control::Loop { start, whiles, .. }) => (start, whiles),
+                    _ => return self.error("This again doesn't belong to a loop"),
+                };
+                let jump = self.here;
+                self.instruction(0x10, 0x00)?;
+                self.patch_jump(jump, start)?;
+                for jump in whiles {
+                    self.patch_jump(jump, self.here)?;
+                }
+            }
+
+            _ => {
+                if let Some(x) = self.register_index(&token) {
+                    self.register_assignment(x)?;
+                } else if self.macros.contains_key(&token) {
+                    self.expand_macro(&token)?;
+                } else if let Some(value) = parse_number(&token)
+                    .or_else(|| self.constants.get(&token).map(|&value| value as i64))
+                {
+                    let byte = self.fit_byte(value)?;
+                    self.emit(byte)?;
+                } else {
+                    // Anything else is a call to a subroutine, which may not
+                    // have been defined yet.
+                    self.tokens.push_front(Token {
+                        text: token,
+                        line: self.line,
+                    });
+                    let addr = self.address(FixupKind::Addr12)?;
+                    self.instruction_nnn(0x2, addr)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take a name that's about to be defined.
+    fn name(&mut self) -> Result<String, AssembleError> {
+        let name = self.next()?;
+
+        if self.register_index(&name).is_some()
+            || KEYWORDS.contains(&name.as_str())
+            || parse_number(&name).is_some()
+            || name.starts_with(':')
+            || name == "{"
+            || name == "}"
+        {
+            return self.error(format!("'{name}' can't be used as a name"));
+        }
+
+        Ok(name)
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), AssembleError> {
+        if self.labels.contains_key(&name) {
+            return self.error(format!("The label '{name}' is already defined"));
+        }
+        if addr >= MEMORY_SIZE {
+            return self.error(format!("The label '{name}' is past the end of memory"));
+        }
+
+        self.labels.insert(name, addr as u16);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.name()?;
+
+        let mut args = Vec::new();
+        loop {
+            let arg = self.next()?;
+            if arg == "{" {
+                break;
+            }
+            args.push(arg);
+        }
+
+        let body = self.braced_tokens()?;
+
+        self.macros.insert(
+            name,
+            Macro {
+                args,
+                body,
+                calls: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Take the tokens up to the `}` matching an already-taken `{`.
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, AssembleError> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+
+        loop {
+            let text = self.next()?;
+            match text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            tokens.push(Token {
+                text,
+                line: self.line,
+            });
+        }
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), AssembleError> {
+        self.macro_expansions += 1;
+        if self.macro_expansions > MAX_MACRO_EXPANSIONS {
+            return self.error(format!(
+                "Too many macro expansions. Does '{name}' call itself?"
+            ));
+        }
+
+        let line = self.line;
+        let mut values = HashMap::new();
+        for arg in self.macros[name].args.clone() {
+            values.insert(arg, self.next()?);
+        }
+
+        let m = self.macros.get_mut(name).unwrap();
+        values.insert("CALLS".to_string(), m.calls.to_string());
+        m.calls += 1;
+
+        // Expanded tokens are reported as being on the line the macro was used,
+        // so that errors and breakpoints point somewhere sensible.
+        for token in m.body.iter().rev() {
+            let text = values.get(&token.text).unwrap_or(&token.text);
+            self.tokens.push_front(Token {
+                text: text.clone(),
+                line,
+            });
+        }
+        Ok(())
+    }
+
+    /// Evaluate a braced `{ expression }`.
+    fn calc(&mut self) -> Result<f64, AssembleError> {
+        self.expect("{")?;
+        let tokens = self.braced_tokens()?;
+        calc::evaluate(&tokens, self).or_else(|message| self.error(message))
+    }
+
+    /// Take a number, constant, or already-defined label.
+    fn constant_value(&mut self) -> Result<f64, AssembleError> {
+        let token = self.next()?;
+
+        match parse_number(&token) {
+            Some(value) => Ok(value as f64),
+            None => match self.value(&token) {
+                Some(value) => Ok(value),
+                None => self.error(format!("Expected a number but found '{token}'")),
+            },
+        }
+    }
+
+    fn fit_byte(&self, value: i64) -> Result<u8, AssembleError> {
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("{value} doesn't fit in a byte"));
+        }
+        Ok(value as u8)
+    }
+
+    /// Take an 8-bit value.
+    fn byte(&mut self) -> Result<u8, AssembleError> {
+        let value = self.constant_value()? as i64;
+        self.fit_byte(value)
+    }
+
+    /// Take a 4-bit value.
+    fn nibble(&mut self) -> Result<u8, AssembleError> {
+        let value = self.constant_value()? as i64;
+        if !(0..=0xF).contains(&value) {
+            return self.error(format!("{value} doesn't fit in 4 bits"));
+        }
+        Ok(value as u8)
+    }
+
+    /// Take an address for an instruction about to be emitted at `here`. If
+    /// it's a label that hasn't been defined yet, a fixup is recorded and `0`
+    /// is returned.
+    fn address(&mut self, kind: FixupKind) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+
+        let value = parse_number(&token).or_else(|| self.value(&token).map(|value| value as i64));
+
+        match value {
+            Some(value) => {
+                let max = match kind {
+                    FixupKind::Addr12 => 0xFFF,
+                    _ => 0xFFFF,
+                };
+                if !(0..=max).contains(&value) {
+                    return self.error(format!("Address {value:#X} is out of range"));
+                }
+                Ok(value as u16)
+            }
+            None if self.register_index(&token).is_some() || KEYWORDS.contains(&token.as_str()) => {
+                self.error(format!("Expected an address but found '{token}'"))
+            }
+            None => {
+                self.fixups.push(Fixup {
+                    name: token,
+                    line: self.line,
+                    at: self.here,
+                    kind,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    fn resolve(&mut self, fixup: &Fixup) -> Result<(), AssembleError> {
+        let addr = match self.labels.get(&fixup.name) {
+            Some(&addr) => addr,
+            None => return self.error(format!("Undefined name '{}'", fixup.name)),
+        };
+
+        let at = fixup.at;
+        let [hi, lo] = addr.to_be_bytes();
+
+        match fixup.kind {
+            FixupKind::Addr12 => {
+                if addr > 0xFFF {
+                    return self.error(format!(
+                        "The label '{}' at {addr:#X} is out of range for this instruction",
+                        fixup.name
+                    ));
+                }
+                self.memory[at] = self.memory[at].map(|byte| byte | hi);
+                self.memory[at + 1] = Some(lo);
+            }
+            FixupKind::Addr16 => {
+                self.memory[at + 2] = Some(hi);
+                self.memory[at + 3] = Some(lo);
+            }
+            FixupKind::Unpack { nibble } => {
+                self.memory[at + 1] = Some(nibble << 4 | (hi & 0xF));
+                self.memory[at + 3] = Some(lo);
+            }
+            FixupKind::UnpackLong => {
+                self.memory[at + 1] = Some(hi);
+                self.memory[at + 3] = Some(lo);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Point a placeholder `1000` jump at some address.
+    fn patch_jump(&mut self, at: usize, target: usize) -> Result<(), AssembleError> {
+        if target > 0xFFF {
+            return self.error(format!("Can't jump to {target:#X}, which is out of range"));
+        }
+
+        let [hi, lo] = (target as u16).to_be_bytes();
+        self.memory[at] = Some(0x10 | hi);
+        self.memory[at + 1] = Some(lo);
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AssembleError> {
+        if self.here < PROGRAM_START_ADDRESS as usize {
+            return self.error(format!(
+                "Can't write to {:#X}, before the start of the program",
+                self.here
+            ));
+        }
+        if self.here >= MEMORY_SIZE {
+            return self.error("The program doesn't fit in memory");
+        }
+        if self.memory[self.here].is_some() {
+            return self.error(format!(
+                "Can't write to {:#X}, which has already been written to",
+                self.here
+            ));
+        }
+
+        self.memory[self.here] = Some(byte);
+        self.here += 1;
+        Ok(())
+    }
+
+    fn instruction(&mut self, hi: u8, lo: u8) -> Result<(), AssembleError> {
+        if self.here < MEMORY_SIZE {
+            self.symbols.lines.insert(self.here as u16, self.line);
+        }
+        self.emit(hi)?;
+        self.emit(lo)
+    }
+
+    /// Emit an instruction like `1NNN`, where `op` is the top nibble.
+    fn instruction_nnn(&mut self, op: u8, nnn: u16) -> Result<(), AssembleError> {
+        let [hi, lo] = nnn.to_be_bytes();
+        self.instruction(op << 4 | hi, lo)
+    }
+
+    /// Emit an `FXNN` instruction, taking `X` from the next token.
+    fn fx_instruction(&mut self, nn: u8) -> Result<(), AssembleError> {
+        let x = self.register()?;
+        self.instruction(0xF0 | x, nn)
+    }
+
+    fn register_index(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    /// Take a register.
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        match self.register_index(&token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("Expected a register but found '{token}'")),
+        }
+    }
+
+    fn is_register_next(&self) -> bool {
+        self.peek(0)
+            .is_some_and(|token| self.register_index(token).is_some())
+    }
+
+    fn register_assignment(&mut self, x: u8) -> Result<(), AssembleError> {
+        let op = self.next()?;
+
+        if op == ":=" {
+            match self.peek(0) {
+                Some("random") => {
+                    self.next()?;
+                    let nn = self.byte()?;
+                    return self.instruction(0xC0 | x, nn);
+                }
+                Some("key") => {
+                    self.next()?;
+                    return self.instruction(0xF0 | x, 0x0A);
+                }
+                Some("delay") => {
+                    self.next()?;
+                    return self.instruction(0xF0 | x, 0x07);
+                }
+                _ => {}
+            }
+        }
+
+        if self.is_register_next() {
+            let y = self.register()?;
+            let n = match op.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.error(format!("Unknown operator '{op}'")),
+            };
+            return self.instruction(0x80 | x, y << 4 | n);
+        }
+
+        let nn = self.byte()?;
+        match op.as_str() {
+            ":=" => self.instruction(0x60 | x, nn),
+            "+=" => self.instruction(0x70 | x, nn),
+            "-=" => self.instruction(0x70 | x, nn.wrapping_neg()),
+            _ => self.error(format!("The operator '{op}' needs a register on the right")),
+        }
+    }
+
+    fn index_assignment(&mut self) -> Result<(), AssembleError> {
+        let op = self.next()?;
+
+        if op == "+=" {
+            return self.fx_instruction(0x1E);
+        }
+        if op != ":=" {
+            return self.error(format!("Expected ':=' or '+=' but found '{op}'"));
+        }
+
+        match self.peek(0) {
+            Some("hex") => {
+                self.next()?;
+                self.fx_instruction(0x29)
+            }
+            Some("bighex") => {
+                self.next()?;
+                self.require(Platform::SuperChip);
+                self.fx_instruction(0x30)
+            }
+            Some("long") => {
+                self.next()?;
+                self.require(Platform::XoChip);
+                let addr = self.address(FixupKind::Addr16)?;
+                let [hi, lo] = addr.to_be_bytes();
+                self.instruction(0xF0, 0x00)?;
+                self.emit(hi)?;
+                self.emit(lo)
+            }
+            _ => {
+                let addr = self.address(FixupKind::Addr12)?;
+                self.instruction_nnn(0xA, addr)
+            }
+        }
+    }
+
+    fn unpack(&mut self) -> Result<(), AssembleError> {
+        let kind = if self.peek(0) == Some("long") {
+            self.next()?;
+            FixupKind::UnpackLong
+        } else {
+            FixupKind::Unpack {
+                nibble: self.nibble()?,
+            }
+        };
+
+        let addr = self.address(kind)?;
+        let [hi, lo] = addr.to_be_bytes();
+        let hi = match kind {
+            FixupKind::Unpack { nibble } => nibble << 4 | (hi & 0xF),
+            _ => hi,
+        };
+
+        let unpack_hi = self.aliases.get("unpack-hi").copied().unwrap_or(0x0);
+        let unpack_lo = self.aliases.get("unpack-lo").copied().unwrap_or(0x1);
+        self.instruction(0x60 | unpack_hi, hi)?;
+        self.instruction(0x60 | unpack_lo, lo)
+    }
+
+    fn if_statement(&mut self) -> Result<(), AssembleError> {
+        let line = self.line;
+        let condition_len = match self.peek(1) {
+            Some("key" | "-key") => 2,
+            _ => 3,
+        };
+
+        match self.peek(condition_len) {
+            Some("then") => {
+                self.condition(false)?;
+                self.expect("then")
+            }
+            Some("begin") => {
+                self.condition(true)?;
+                self.expect("begin")?;
+                let jump = self.here;
+                self.instruction(0x10, 0x00)?;
+                self.control.push(Control::If { line, jump });
+                Ok(())
+            }
+            _ => self.error("Expected 'then' or 'begin' after the condition"),
+        }
+    }
+
+    /// Emit instructions that skip the next instruction if a condition is false,
+    /// or if it's true when `negated` is set.
+    fn condition(&mut self, negated: bool) -> Result<(), AssembleError> {
+        let x = self.register()?;
+        let mut op = self.next()?;
+
+        if negated {
+            op = match op.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                ">=" => "<",
+                _ => return self.error(format!("Unknown comparison '{op}'")),
+            }
+            .to_string();
+        }
+
+        match op.as_str() {
+            "==" if self.is_register_next() => {
+                let y = self.register()?;
+                self.instruction(0x90 | x, y << 4)
+            }
+            "==" => {
+                let nn = self.byte()?;
+                self.instruction(0x40 | x, nn)
+            }
+            "!=" if self.is_register_next() => {
+                let y = self.register()?;
+                self.instruction(0x50 | x, y << 4)
+            }
+            "!=" => {
+                let nn = self.byte()?;
+                self.instruction(0x30 | x, nn)
+            }
+            "key" => self.instruction(0xE0 | x, 0xA1),
+            "-key" => self.instruction(0xE0 | x, 0x9E),
+            "<" | ">" | "<=" | ">=" => {
+                // There's no instruction for these, so subtract into a
+                // temporary register and check the borrow flag.
+                let temp = self.aliases["compare-temp"];
+                if self.is_register_next() {
+                    let y = self.register()?;
+                    self.instruction(0x80 | temp, y << 4)?;
+                } else {
+                    let nn = self.byte()?;
+                    self.instruction(0x60 | temp, nn)?;
+                }
+
+                let (subtract, skip) = match op.as_str() {
+                    ">" => (0x5, 0x30),
+                    "<" => (0x7, 0x30),
+                    ">=" => (0x7, 0x40),
+                    _ => (0x5, 0x40),
+                };
+                self.instruction(0x80 | temp, x << 4 | subtract)?;
+                self.instruction(skip | 0xF, 0x01)
+            }
+            _ => self.error(format!("Unknown comparison '{op}'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().rom
+    }
+
+    #[test]
+    fn jumps_to_main_if_it_is_not_first() {
+        assert_eq!(rom(": main clear"), [0x00, 0xE0]);
+        assert_eq!(
+            rom(": sub return : main sub"),
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+        assert!(assemble(": nope clear").is_err());
+    }
+
+    #[test]
+    fn resolves_forward_references() {
+        let program = assemble(
+            ": main
+               i := face
+               jump done
+             : face 0x3C 0x42
+             : done
+               i := long face",
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.rom,
+            [0xA2, 0x04, 0x12, 0x06, 0x3C, 0x42, 0xF0, 0x00, 0x02, 0x04]
+        );
+        assert_eq!(program.platform, Platform::XoChip);
+        assert_eq!(program.symbols.labels["face"], 0x204);
+        assert_eq!(program.symbols.line_of(0x206), Some(6));
+    }
+
+    #[test]
+    fn assembles_structured_control_flow() {
+        assert_eq!(
+            rom(": main
+                  if v0 == 1 begin v1 := 2 else v1 := 3 end
+                  loop while v2 key again"),
+            [
+                0x30, 0x01, // if v0 == 1 begin
+                0x12, 0x08, //
+                0x61, 0x02, //   v1 := 2
+                0x12, 0x0A, // else
+                0x61, 0x03, //   v1 := 3
+                0xE2, 0x9E, // loop while v2 key
+                0x12, 0x10, //
+                0x12, 0x0A, // again
+            ]
+        );
+        assert!(assemble(": main loop").is_err());
+        assert!(assemble(": main end").is_err());
+    }
+
+    #[test]
+    fn expands_macros_constants_and_aliases() {
+        assert_eq!(
+            rom(":const SPEED 3
+                 :alias x v4
+                 :macro move reg amount { reg += amount }
+                 :macro count { :byte { CALLS } }
+                 :calc DOUBLE { SPEED * 2 }
+                 : main
+                   move x SPEED
+                   move v5 DOUBLE
+                   count count"),
+            [0x74, 0x03, 0x75, 0x06, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let error = assemble(": main\n  v0 := 0x100").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(assemble(": main v0 := nope").is_err());
+        assert!(assemble(": main jump nowhere").is_err());
+        assert!(assemble(":calc x { 1 << 64 }").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+//! Command line interface to the assembler.
+
+use std::{fs, path::PathBuf};
+
+use clap::Parser;
+use color_eyre::eyre::Context;
+
+/// Assemble an Octo program into a CHIP8, SUPER-CHIP or XO-CHIP ROM.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// The Octo source file to assemble.
+    source: PathBuf,
+
+    /// Where to write the ROM. Defaults to the source file with its extension
+    /// replaced by the one for the platform the program needs, like `.ch8`.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Also write a symbol map, with the addresses of every label and the
+    /// source line of every instruction.
+    #[arg(short, long)]
+    symbols: Option<PathBuf>,
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let args = Args::parse();
+
+    let source = fs::read_to_string(&args.source)
+        .wrap_err_with(|| format!("Failed to read {:?}", args.source))?;
+
+    let program = assembler::assemble(&source)
+        .wrap_err_with(|| format!("Failed to assemble {:?}", args.source))?;
+
+    let output = args
+        .output
+        .unwrap_or_else(|| args.source.with_extension(program.platform.rom_extension()));
+
+    fs::write(&output, &program.rom).wrap_err_with(|| format!("Failed to write {output:?}"))?;
+
+    if let Some(symbols) = &args.symbols {
+        fs::write(symbols, program.symbols.to_string())
+            .wrap_err_with(|| format!("Failed to write {symbols:?}"))?;
+    }
+
+    println!(
+        "Assembled {} bytes for {} to {output:?}",
+        program.rom.len(),
+        program.platform
+    );
+
+    Ok(())
+}
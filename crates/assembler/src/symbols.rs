@@ -0,0 +1,85 @@
+//! The symbol map produced alongside an assembled ROM.
+
+use std::{collections::BTreeMap, fmt};
+
+/// Everything the assembler knows about the names and source lines of a
+/// program, for use by debuggers.
+///
+/// The [`fmt::Display`] implementation writes a simple text format, with one
+/// symbol per line:
+///
+/// ```text
+/// label main 0x202
+/// const speed 4
+/// breakpoint check-collision 0x21A
+/// line 0x202 12
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolMap {
+    /// Label names, and the addresses they mark.
+    pub labels: BTreeMap<String, u16>,
+    /// Constants defined with `:const` or `:calc`.
+    pub constants: BTreeMap<String, f64>,
+    /// Breakpoints defined with `:breakpoint`, and the addresses they're at.
+    pub breakpoints: BTreeMap<String, u16>,
+    /// The source line (counting from 1) that each instruction was assembled
+    /// from, by address. Data bytes aren't included.
+    pub lines: BTreeMap<u16, usize>,
+}
+
+impl SymbolMap {
+    /// The name of a label at some address, if there is one. If there are
+    /// several, the first in alphabetical order is returned.
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, &label_addr)| label_addr == addr)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The name of the nearest label at or before some address, and how far
+    /// past it the address is. Useful for describing addresses in the middle of
+    /// a subroutine, like `draw-player+6`.
+    pub fn nearest_label(&self, addr: u16) -> Option<(&str, u16)> {
+        self.labels
+            .iter()
+            .filter(|(_, &label_addr)| label_addr <= addr)
+            .max_by_key(|(_, &label_addr)| label_addr)
+            .map(|(name, &label_addr)| (name.as_str(), addr - label_addr))
+    }
+
+    /// The source line an instruction was assembled from.
+    pub fn line_of(&self, addr: u16) -> Option<usize> {
+        self.lines.get(&addr).copied()
+    }
+
+    /// The address of the first instruction on or after some source line, along
+    /// with the line it's actually on. This is where a breakpoint set on `line`
+    /// should go.
+    pub fn addr_for_line(&self, line: usize) -> Option<(usize, u16)> {
+        self.lines
+            .iter()
+            .filter(|(_, &addr_line)| addr_line >= line)
+            .min_by_key(|(&addr, &addr_line)| (addr_line, addr))
+            .map(|(&addr, &addr_line)| (addr_line, addr))
+    }
+}
+
+impl fmt::Display for SymbolMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, addr) in &self.labels {
+            writeln!(f, "label {name} {addr:#05X}")?;
+        }
+        for (name, value) in &self.constants {
+            writeln!(f, "const {name} {value}")?;
+        }
+        for (name, addr) in &self.breakpoints {
+            writeln!(f, "breakpoint {name} {addr:#05X}")?;
+        }
+        for (addr, line) in &self.lines {
+            writeln!(f, "line {addr:#05X} {line}")?;
+        }
+
+        Ok(())
+    }
+}
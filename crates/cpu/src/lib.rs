@@ -0,0 +1,711 @@
+//! The CHIP8 system's CPU: its registers, timers, and instruction execution.
+
+mod rng;
+
+use display::Display;
+use keypad::Keypad;
+use opcode::Instruction;
+use platform::{Platform, Quirks};
+use ram::{Ram, PROGRAM_START_ADDRESS};
+use sys_font::Font;
+use thiserror::Error;
+
+pub use rng::{ParseRngKindError, Rng, RngKind, RngOptions};
+
+/// The maximum depth of the call stack.
+pub const STACK_SIZE: usize = 16;
+
+/// The CHIP8's CPU.
+///
+/// This holds all of the state of a running CHIP8 program that isn't stored in
+/// [`Ram`] or on the [`Display`]. Instructions are executed one at a time by
+/// [`Cpu::step()`], and the timers count down whenever [`Cpu::tick_timers()`] is
+/// called, which should happen at 60 Hz.
+#[derive(Debug, Clone)]
+pub struct Cpu {
+    /// The general-purpose registers `V0` through `VF`. `VF` doubles as a flag
+    /// register for many instructions.
+    pub v: [u8; 16],
+    /// The index register, `I`, used for addressing memory.
+    pub i: u16,
+    /// The program counter, pointing at the next instruction to execute.
+    pub pc: u16,
+    /// The stack pointer, which is the number of return addresses on the stack.
+    pub sp: u8,
+    /// The call stack, holding return addresses.
+    pub stack: [u16; STACK_SIZE],
+    /// The delay timer, which counts down at 60 Hz.
+    pub delay_timer: u8,
+    /// The sound timer, which counts down at 60 Hz. A tone plays while it's
+    /// non-zero.
+    pub sound_timer: u8,
+    /// The SUPER-CHIP persistent flag registers, accessed by `FX75` and `FX85`.
+    pub flags: [u8; 16],
+    /// The XO-CHIP bitmask of display planes that drawing instructions affect.
+    pub planes: u8,
+    /// The XO-CHIP audio pattern buffer.
+    pub audio_pattern: [u8; 16],
+    /// The XO-CHIP audio pitch register.
+    pub pitch: u8,
+    /// The number of instructions executed since the last reset.
+    pub cycles: u64,
+
+    /// The key being waited on by `FX0A`, if any.
+    key_wait: Option<KeyWait>,
+
+    /// The random number generator used by `CXNN`.
+    rng: Rng,
+
+    platform: Platform,
+    quirks: Quirks,
+}
+
+/// The progress of an `FX0A` instruction. The COSMAC VIP waited for a key to be
+/// pressed *and then released* before continuing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyWait {
+    pressed: Option<u8>,
+}
+
+/// The result of successfully executing a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    /// The address the instruction was executed from.
+    pub addr: u16,
+    /// The instruction that was executed.
+    pub instruction: Instruction,
+    /// True if the instruction changed anything on the display.
+    pub display_changed: bool,
+    /// True if the instruction is waiting for a key press, and will be executed
+    /// again on the next step.
+    pub waiting_for_key: bool,
+}
+
+/// A way that an instruction can access memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+    /// The instruction itself being fetched.
+    Execute,
+}
+
+/// A range of memory that an instruction accesses. See [`Cpu::data_access()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryAccess {
+    pub access: Access,
+    /// The first address accessed.
+    pub addr: u16,
+    /// The number of bytes accessed. Addresses wrap around at the end of memory.
+    pub len: u16,
+}
+
+/// The reason that the CPU stopped executing instructions.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    #[error("The program exited")]
+    Exit,
+
+    #[error("Unknown instruction {opcode:#06X} at address {addr:#06X}")]
+    UnknownInstruction { addr: u16, opcode: u16 },
+
+    #[error("Unsupported machine language routine call to {nnn:#05X} at address {addr:#06X}")]
+    MachineCall { addr: u16, nnn: u16 },
+
+    #[error("Call stack overflow at address {addr:#06X}")]
+    StackOverflow { addr: u16 },
+
+    #[error("Return with an empty call stack at address {addr:#06X}")]
+    StackUnderflow { addr: u16 },
+}
+
+impl Cpu {
+    /// Create a new CPU for some platform, ready to start executing from
+    /// [`PROGRAM_START_ADDRESS`].
+    pub fn new(platform: Platform, quirks: Quirks) -> Self {
+        Self {
+            v: [0; 16],
+            i: 0,
+            pc: PROGRAM_START_ADDRESS,
+            sp: 0,
+            stack: [0; STACK_SIZE],
+            delay_timer: 0,
+            sound_timer: 0,
+            flags: [0; 16],
+            planes: 1,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            cycles: 0,
+            key_wait: None,
+            rng: Rng::from_time(RngKind::default()),
+            platform,
+            quirks,
+        }
+    }
+
+    /// Reset the CPU to the state it was in when it was created, except for the
+    /// SUPER-CHIP persistent flag registers, which survive resets.
+    ///
+    /// Registers, timers and the stack are cleared, and the program counter is
+    /// pointed back at [`PROGRAM_START_ADDRESS`].
+    pub fn reset(&mut self) {
+        *self = Self {
+            flags: self.flags,
+            rng: self.rng,
+            ..Self::new(self.platform, self.quirks)
+        };
+    }
+
+    /// The random number generator used by `CXNN`.
+    #[inline]
+    pub fn rng(&self) -> &Rng {
+        &self.rng
+    }
+
+    /// Replace the random number generator, to reseed it or switch algorithms.
+    #[inline]
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    /// The platform this CPU is emulating.
+    #[inline]
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// The quirks this CPU is currently emulating.
+    #[inline]
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Change the quirks this CPU emulates. This takes effect from the next
+    /// instruction onwards.
+    #[inline]
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// The return addresses currently on the call stack, from the outermost call
+    /// to the innermost.
+    #[inline]
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    /// Returns true if the CPU is blocked on an `FX0A` instruction.
+    #[inline]
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    /// Count the delay and sound timers down by one. Call this at 60 Hz.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    /// Fetch, decode and execute a single instruction.
+    ///
+    /// On success, returns a description of what was executed. If the program
+    /// can't continue, returns the reason why. In that case, the program counter
+    /// is left pointing at the offending instruction.
+    pub fn step(
+        &mut self,
+        ram: &mut Ram,
+        display: &mut dyn Display,
+        keypad: &Keypad,
+    ) -> Result<Step, HaltReason> {
+        use Instruction::*;
+
+        let addr = self.pc;
+        let instruction = self.fetch(ram);
+        let next_pc = addr.wrapping_add(instruction.len());
+
+        let mut step = Step {
+            addr,
+            instruction,
+            display_changed: false,
+            waiting_for_key: false,
+        };
+
+        self.pc = next_pc;
+
+        match instruction {
+            ScrollDown { n } => {
+                display.scroll_planes(self.planes, 0, n as i32);
+                step.display_changed = true;
+            }
+            ScrollUp { n } => {
+                display.scroll_planes(self.planes, 0, -(n as i32));
+                step.display_changed = true;
+            }
+            ClearScreen => {
+                display.clear_planes(self.planes);
+                step.display_changed = true;
+            }
+            Return => {
+                if self.sp == 0 {
+                    self.pc = addr;
+                    return Err(HaltReason::StackUnderflow { addr });
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
+            }
+            ScrollRight => {
+                display.scroll_planes(self.planes, 4, 0);
+                step.display_changed = true;
+            }
+            ScrollLeft => {
+                display.scroll_planes(self.planes, -4, 0);
+                step.display_changed = true;
+            }
+            Exit => {
+                self.pc = addr;
+                return Err(HaltReason::Exit);
+            }
+            LowRes => {
+                display.set_hires(false);
+                step.display_changed = true;
+            }
+            HighRes => {
+                display.set_hires(true);
+                step.display_changed = true;
+            }
+            MachineCall { nnn } => {
+                self.pc = addr;
+                return Err(HaltReason::MachineCall { addr, nnn });
+            }
+            Jump { nnn } => self.pc = nnn,
+            Call { nnn } => {
+                if self.sp as usize >= STACK_SIZE {
+                    self.pc = addr;
+                    return Err(HaltReason::StackOverflow { addr });
+                }
+                self.stack[self.sp as usize] = next_pc;
+                self.sp += 1;
+                self.pc = nnn;
+            }
+            SkipIfEqualImm { x, nn } => self.skip_if(ram, self.v[x as usize] == nn),
+            SkipIfNotEqualImm { x, nn } => self.skip_if(ram, self.v[x as usize] != nn),
+            SkipIfEqual { x, y } => self.skip_if(ram, self.v[x as usize] == self.v[y as usize]),
+            SaveRange { x, y } => {
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    ram.set(self.i.wrapping_add(offset as u16), self.v[reg]);
+                }
+            }
+            LoadRange { x, y } => {
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    self.v[reg] = ram[self.i.wrapping_add(offset as u16)];
+                }
+            }
+            LoadImm { x, nn } => self.v[x as usize] = nn,
+            AddImm { x, nn } => self.v[x as usize] = self.v[x as usize].wrapping_add(nn),
+            Move { x, y } => self.v[x as usize] = self.v[y as usize],
+            Or { x, y } => {
+                self.v[x as usize] |= self.v[y as usize];
+                self.reset_vf_if_quirky();
+            }
+            And { x, y } => {
+                self.v[x as usize] &= self.v[y as usize];
+                self.reset_vf_if_quirky();
+            }
+            Xor { x, y } => {
+                self.v[x as usize] ^= self.v[y as usize];
+                self.reset_vf_if_quirky();
+            }
+            Add { x, y } => {
+                let (result, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = carry as u8;
+            }
+            Sub { x, y } => {
+                let (result, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = !borrow as u8;
+            }
+            ShiftRight { x, y } => {
+                let src = self.v[if self.quirks.shifting { x } else { y } as usize];
+                self.v[x as usize] = src >> 1;
+                self.v[0xF] = src & 1;
+            }
+            SubReverse { x, y } => {
+                let (result, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.v[x as usize] = result;
+                self.v[0xF] = !borrow as u8;
+            }
+            ShiftLeft { x, y } => {
+                let src = self.v[if self.quirks.shifting { x } else { y } as usize];
+                self.v[x as usize] = src << 1;
+                self.v[0xF] = src >> 7;
+            }
+            SkipIfNotEqual { x, y } => self.skip_if(ram, self.v[x as usize] != self.v[y as usize]),
+            LoadIndex { nnn } => self.i = nnn,
+            JumpOffset { nnn } => {
+                let offset_reg = if self.quirks.jumping {
+                    (nnn >> 8) as usize
+                } else {
+                    0
+                };
+                self.pc = nnn.wrapping_add(self.v[offset_reg] as u16);
+            }
+            Random { x, nn } => self.v[x as usize] = self.rng.next_byte(ram) & nn,
+            Draw { x, y, n } => {
+                self.v[0xF] = self.draw(ram, display, x, y, n) as u8;
+                step.display_changed = true;
+            }
+            SkipIfKey { x } => self.skip_if(ram, keypad.is_pressed(self.v[x as usize])),
+            SkipIfNotKey { x } => self.skip_if(ram, !keypad.is_pressed(self.v[x as usize])),
+            LoadIndexLong { nnnn } => self.i = nnnn,
+            SelectPlanes { n } => self.planes = n,
+            LoadAudio => {
+                for (offset, byte) in self.audio_pattern.iter_mut().enumerate() {
+                    *byte = ram[self.i.wrapping_add(offset as u16)];
+                }
+            }
+            GetDelay { x } => self.v[x as usize] = self.delay_timer,
+            WaitKey { x } => {
+                let wait = self.key_wait.get_or_insert(KeyWait { pressed: None });
+
+                match wait.pressed {
+                    None => wait.pressed = keypad.pressed_keys().next(),
+                    Some(key) if !keypad.is_pressed(key) => {
+                        self.v[x as usize] = key;
+                        self.key_wait = None;
+                    }
+                    Some(_) => {}
+                }
+
+                if self.key_wait.is_some() {
+                    self.pc = addr;
+                    step.waiting_for_key = true;
+                }
+            }
+            SetDelay { x } => self.delay_timer = self.v[x as usize],
+            SetSound { x } => self.sound_timer = self.v[x as usize],
+            AddIndex { x } => self.i = self.i.wrapping_add(self.v[x as usize] as u16),
+            FontChar { x } => {
+                self.i = Font::PREFERRED_TABLE_STARTING_ADDRESS
+                    + Font::from_digit(self.v[x as usize]).table_offset() as u16;
+            }
+            BigFontChar { x } => {
+                self.i = Font::PREFERRED_BIG_TABLE_STARTING_ADDRESS
+                    + Font::from_digit(self.v[x as usize]).big_table_offset() as u16;
+            }
+            Bcd { x } => {
+                let vx = self.v[x as usize];
+                ram.set(self.i, vx / 100);
+                ram.set(self.i.wrapping_add(1), (vx / 10) % 10);
+                ram.set(self.i.wrapping_add(2), vx % 10);
+            }
+            SetPitch { x } => self.pitch = self.v[x as usize],
+            Store { x } => {
+                for reg in 0..=x as u16 {
+                    ram.set(self.i.wrapping_add(reg), self.v[reg as usize]);
+                }
+                if self.quirks.memory_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            }
+            Load { x } => {
+                for reg in 0..=x as u16 {
+                    self.v[reg as usize] = ram[self.i.wrapping_add(reg)];
+                }
+                if self.quirks.memory_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            }
+            SaveFlags { x } => self.flags[..=x as usize].copy_from_slice(&self.v[..=x as usize]),
+            LoadFlags { x } => self.v[..=x as usize].copy_from_slice(&self.flags[..=x as usize]),
+            Unknown { opcode } => {
+                self.pc = addr;
+                return Err(HaltReason::UnknownInstruction { addr, opcode });
+            }
+        }
+
+        self.cycles += 1;
+
+        Ok(step)
+    }
+
+    /// The memory that an instruction would read or write if it was executed
+    /// right now, other than the instruction itself. Only the selected planes
+    /// are counted for XO-CHIP sprites.
+    pub fn data_access(&self, instruction: &Instruction) -> Option<MemoryAccess> {
+        use Instruction::*;
+
+        let (access, len) = match *instruction {
+            SaveRange { x, y } => (Access::Write, x.abs_diff(y) as u16 + 1),
+            LoadRange { x, y } => (Access::Read, x.abs_diff(y) as u16 + 1),
+            Draw { n, .. } => {
+                let sprite_len = if n == 0 && self.platform.has_hires() {
+                    32
+                } else {
+                    n as u16
+                };
+                let plane_mask = if self.platform.has_xo_chip_extensions() {
+                    0b11
+                } else {
+                    0b01
+                };
+                let planes = (self.planes & plane_mask).count_ones() as u16;
+
+                (Access::Read, sprite_len * planes)
+            }
+            LoadAudio => (Access::Read, 16),
+            Bcd { .. } => (Access::Write, 3),
+            Store { x } => (Access::Write, x as u16 + 1),
+            Load { x } => (Access::Read, x as u16 + 1),
+            _ => return None,
+        };
+
+        (len > 0).then_some(MemoryAccess {
+            access,
+            addr: self.i,
+            len,
+        })
+    }
+
+    /// Fetch and decode the instruction at the program counter, without
+    /// executing it.
+    pub fn fetch(&self, ram: &Ram) -> Instruction {
+        let word = |addr: u16| u16::from_be_bytes([ram[addr], ram[addr.wrapping_add(1)]]);
+
+        Instruction::decode(word(self.pc), word(self.pc.wrapping_add(2)), self.platform)
+    }
+
+    /// Skip the next instruction if `condition` is true. This takes care of
+    /// skipping over the 4-byte XO-CHIP instruction `F000 NNNN`.
+    fn skip_if(&mut self, ram: &Ram, condition: bool) {
+        if condition {
+            self.pc = self.pc.wrapping_add(self.fetch(ram).len());
+        }
+    }
+
+    #[inline]
+    fn reset_vf_if_quirky(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+    }
+
+    /// Draw a sprite to every selected plane. Returns true if any pixel was
+    /// erased.
+    fn draw(&mut self, ram: &Ram, display: &mut dyn Display, x: u8, y: u8, n: u8) -> bool {
+        let (width, height) = display.resolution();
+
+        // SUPER-CHIP draws a 16x16 sprite when N is zero.
+        let (sprite_width, sprite_height) = if n == 0 && self.platform.has_hires() {
+            (16, 16)
+        } else {
+            (8, n as u32)
+        };
+        let bytes_per_row = sprite_width / 8;
+
+        let x0 = self.v[x as usize] as u32 % width;
+        let y0 = self.v[y as usize] as u32 % height;
+
+        let mut addr = self.i;
+        let mut collision = false;
+
+        for plane in 0..display.plane_count() {
+            if self.planes & (1 << plane) == 0 {
+                continue;
+            }
+
+            for row in 0..sprite_height {
+                let py = y0 + row;
+                if self.quirks.clipping && py >= height {
+                    break;
+                }
+
+                // Sprite rows are drawn in one go, with the leftmost pixel in
+                // the most significant bit.
+                let bits = (0..bytes_per_row).fold(0, |bits, byte| {
+                    let byte_addr = addr.wrapping_add((row * bytes_per_row + byte) as u16);
+                    bits | (ram[byte_addr] as u16) << (8 - byte * 8)
+                });
+
+                collision |= display.xor_row(
+                    plane,
+                    x0,
+                    py % height,
+                    bits,
+                    sprite_width,
+                    self.quirks.clipping,
+                );
+            }
+
+            addr = addr.wrapping_add((sprite_height * bytes_per_row) as u16);
+        }
+
+        collision
+    }
+}
+
+/// The registers affected by the XO-CHIP range instructions, which go backwards
+/// if `x > y`.
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x as usize, y as usize);
+
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use display_chip8::Chip8Display;
+
+    use super::*;
+
+    /// Run a program until the CPU halts or has run `max_steps` instructions.
+    fn run(program: &[u8], max_steps: usize) -> (Cpu, Ram, Chip8Display) {
+        let mut cpu = Cpu::new(Platform::Chip8, Quirks::CHIP8);
+        let mut ram = Ram::new();
+        let mut display = Chip8Display::new();
+        let keypad = Keypad::new();
+
+        ram.load_program(program).unwrap();
+
+        for _ in 0..max_steps {
+            if cpu.step(&mut ram, &mut display, &keypad).is_err() {
+                break;
+            }
+        }
+
+        (cpu, ram, display)
+    }
+
+    #[test]
+    fn arithmetic_sets_flags() {
+        #[rustfmt::skip]
+        let (cpu, _, _) = run(&[
+            0x60, 0xFF, // V0 = 0xFF
+            0x61, 0x02, // V1 = 0x02
+            0x80, 0x14, // V0 += V1
+            0x62, 0x01, // V2 = 0x01
+            0x82, 0x15, // V2 -= V1
+            0x83, 0x25, // V3 -= V2 (0 - 0xFF)
+        ], 6);
+
+        assert_eq!(cpu.v[0], 0x01);
+        assert_eq!(cpu.v[2], 0xFF);
+        assert_eq!(cpu.v[3], 0x01);
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        #[rustfmt::skip]
+        let (cpu, _, _) = run(&[
+            0x22, 0x06, // call 0x206
+            0x60, 0x42, // V0 = 0x42
+            0x12, 0x04, // jump 0x204 (loop forever)
+            0x61, 0x24, // V1 = 0x24
+            0x00, 0xEE, // return
+        ], 5);
+
+        assert_eq!(cpu.v[0], 0x42);
+        assert_eq!(cpu.v[1], 0x24);
+        assert_eq!(cpu.sp, 0);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn drawing_detects_collisions() {
+        #[rustfmt::skip]
+        let (cpu, _, mut display) = run(&[
+            0x00, 0xE0, // clear
+            0xA0, 0x50, // I = font character 0
+            0xD0, 0x05, // draw at (0, 0)
+            0x3F, 0x00, // skip if VF == 0
+            0x00, 0x00, // (halt)
+            0xD0, 0x05, // draw at (0, 0) again
+        ], 6);
+
+        assert_eq!(cpu.v[0xF], 1);
+        // The second draw erased the first.
+        assert!(!display.flip_pixel(0, 0));
+    }
+
+    #[test]
+    fn data_access_covers_selected_planes() {
+        let mut cpu = Cpu::new(Platform::XoChip, Quirks::XO_CHIP);
+        cpu.i = 0x300;
+
+        let draw = Instruction::Draw { x: 0, y: 0, n: 0 };
+        let access = |cpu: &Cpu, instruction| cpu.data_access(&instruction).unwrap();
+
+        assert_eq!(access(&cpu, draw).len, 32);
+        cpu.planes = 0b11;
+        assert_eq!(access(&cpu, draw).len, 64);
+
+        assert_eq!(
+            access(&cpu, Instruction::Store { x: 3 }),
+            MemoryAccess {
+                access: Access::Write,
+                addr: 0x300,
+                len: 4
+            }
+        );
+        assert_eq!(cpu.data_access(&Instruction::ClearScreen), None);
+    }
+
+    #[test]
+    fn reset_keeps_persistent_flags() {
+        let mut cpu = Cpu::new(Platform::SuperChip, Quirks::SUPER_CHIP);
+        cpu.v[3] = 0x12;
+        cpu.pc = 0x345;
+        cpu.delay_timer = 10;
+        cpu.flags[0] = 0x67;
+
+        cpu.reset();
+
+        assert_eq!(cpu.v[3], 0);
+        assert_eq!(cpu.pc, PROGRAM_START_ADDRESS);
+        assert_eq!(cpu.delay_timer, 0);
+        assert_eq!(cpu.flags[0], 0x67);
+    }
+
+    #[test]
+    fn unknown_instructions_halt() {
+        let mut cpu = Cpu::new(Platform::Chip8, Quirks::CHIP8);
+        let mut ram = Ram::new();
+        ram.load_program(&[0x50, 0x01]).unwrap();
+
+        assert_eq!(
+            cpu.step(&mut ram, &mut Chip8Display::new(), &Keypad::new()),
+            Err(HaltReason::UnknownInstruction {
+                addr: 0x200,
+                opcode: 0x5001
+            })
+        );
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn key_wait_needs_press_and_release() {
+        let mut cpu = Cpu::new(Platform::Chip8, Quirks::CHIP8);
+        let mut ram = Ram::new();
+        let mut display = Chip8Display::new();
+        let mut keypad = Keypad::new();
+        ram.load_program(&[0xF3, 0x0A]).unwrap();
+
+        let step = cpu.step(&mut ram, &mut display, &keypad).unwrap();
+        assert!(step.waiting_for_key);
+
+        keypad.press(0xB);
+        let step = cpu.step(&mut ram, &mut display, &keypad).unwrap();
+        assert!(step.waiting_for_key);
+
+        keypad.release(0xB);
+        let step = cpu.step(&mut ram, &mut display, &keypad).unwrap();
+        assert!(!step.waiting_for_key);
+        assert_eq!(cpu.v[3], 0xB);
+        assert_eq!(cpu.pc, 0x202);
+    }
+}
@@ -0,0 +1,313 @@
+//! A server for the Debug Adapter Protocol, so that CHIP8 programs can be
+//! debugged from VS Code and other editors.
+//!
+//! The server listens on localhost, and serves one client at a time. Clients
+//! connect to it directly (in VS Code, with `debugServer` in the launch
+//! configuration), and launch a ROM or an Octo source file:
+//!
+//! ```json
+//! { "request": "launch", "program": "game.8o", "stopOnEntry": true }
+//! ```
+//!
+//! Octo source is assembled on the fly, and its symbol map is used to set
+//! breakpoints and step by source line. ROMs without source can still be
+//! stepped an instruction at a time. Registers, the memory at `I` and the call
+//! stack are shown as variables, and the event breakpoints are offered as
+//! exception filters.
+
+mod protocol;
+mod session;
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use assembler::SymbolMap;
+use emulator::Emulator;
+
+/// How often the server checks for a new connection.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A program launched by a client.
+#[derive(Debug, Clone)]
+pub struct Launched {
+    pub path: PathBuf,
+    /// The symbols for the program, if it was assembled from Octo source.
+    pub symbols: Option<SymbolMap>,
+}
+
+/// A running DAP server. It's stopped when dropped.
+#[derive(Debug)]
+pub struct DapServer {
+    local_addr: SocketAddr,
+    launched: Arc<Mutex<Option<Launched>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DapServer {
+    /// Start listening for clients on a localhost port, in a background
+    /// thread. Port 0 picks any free port; see [`Self::local_addr()`].
+    pub fn start(emulator: &Emulator, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let launched = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let emulator = emulator.clone();
+            let launched = Arc::clone(&launched);
+            let stop = Arc::clone(&stop);
+
+            std::thread::Builder::new()
+                .name("dap-server".to_string())
+                .spawn(move || serve(listener, &emulator, &launched, &stop))?
+        };
+
+        tracing::info!(%local_addr, "DAP server listening");
+
+        Ok(Self {
+            local_addr,
+            launched,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The program most recently launched by a client, if there's been one
+    /// since the last call.
+    pub fn take_launched(&self) -> Option<Launched> {
+        self.launched.lock().unwrap().take()
+    }
+
+    /// Disconnect any client, and stop listening.
+    pub fn stop(self) {
+        // Dropping does the work.
+    }
+}
+
+impl Drop for DapServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("The DAP server thread panicked");
+            }
+        }
+
+        tracing::info!(local_addr = %self.local_addr, "DAP server stopped");
+    }
+}
+
+/// Accept clients one at a time, until `stop` is set.
+fn serve(
+    listener: TcpListener,
+    emulator: &Emulator,
+    launched: &Mutex<Option<Launched>>,
+    stop: &AtomicBool,
+) {
+    while !stop.load(Ordering::Acquire) {
+        let (stream, peer) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(error) => {
+                tracing::warn!("Failed to accept a DAP connection: {error}");
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        tracing::info!(%peer, "DAP client connected");
+
+        let result = stream
+            .set_nonblocking(false)
+            .and_then(|()| session::run(stream, emulator, launched, stop));
+
+        match result {
+            Ok(()) => tracing::info!(%peer, "DAP client disconnected"),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                tracing::info!(%peer, "DAP client disconnected");
+            }
+            Err(error) => tracing::warn!(%peer, "DAP connection failed: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+
+    use serde_json::{json, Value};
+    use ui_thread_waker::UiThreadWaker;
+
+    use super::*;
+
+    struct NoUiThread;
+
+    impl UiThreadWaker for NoUiThread {
+        fn wake_ui_thread(&self) {}
+    }
+
+    /// A minimal DAP client.
+    struct Client {
+        stream: BufReader<TcpStream>,
+        seq: i64,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let content = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            })
+            .to_string();
+            write!(
+                self.stream.get_mut(),
+                "Content-Length: {}\r\n\r\n{content}",
+                content.len()
+            )
+            .unwrap();
+
+            let response = self.receive(|message| message["type"] == "response");
+            assert_eq!(response["success"], true, "{response}");
+            response["body"].clone()
+        }
+
+        /// Wait for an event, skipping anything else.
+        fn event(&mut self, event: &str) -> Value {
+            self.receive(|message| message["event"] == event)["body"].clone()
+        }
+
+        fn receive(&mut self, wanted: impl Fn(&Value) -> bool) -> Value {
+            loop {
+                let mut len = None;
+                loop {
+                    let mut line = String::new();
+                    self.stream.read_line(&mut line).unwrap();
+                    match line.trim().strip_prefix("Content-Length:") {
+                        Some(value) => len = value.trim().parse().ok(),
+                        None if line.trim().is_empty() => break,
+                        None => {}
+                    }
+                }
+
+                let mut content = vec![0; len.unwrap()];
+                self.stream.read_exact(&mut content).unwrap();
+                let message: Value = serde_json::from_slice(&content).unwrap();
+                if wanted(&message) {
+                    return message;
+                }
+            }
+        }
+    }
+
+    const SOURCE: &str = "\
+: main
+  v0 := 5
+  increment
+  v1 := 7
+  loop again
+
+: increment
+  v0 += 1
+  return
+";
+
+    #[test]
+    fn steps_through_source_lines() {
+        let path = std::env::temp_dir().join(format!("dap-test-{}.8o", std::process::id()));
+        std::fs::write(&path, SOURCE).unwrap();
+
+        let mut emulator = Emulator::new();
+        emulator.start(NoUiThread).unwrap();
+        let server = DapServer::start(&emulator, 0).unwrap();
+        let mut client = Client {
+            stream: BufReader::new(TcpStream::connect(server.local_addr()).unwrap()),
+            seq: 0,
+        };
+
+        let capabilities = client.request("initialize", json!({ "adapterID": "chip8" }));
+        assert_eq!(capabilities["supportsConditionalBreakpoints"], true);
+
+        client.request("launch", json!({ "program": path }));
+        client.event("initialized");
+        let breakpoints = client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }, { "line": 6 }] }),
+        );
+        assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+        // The blank line moves to the next line with code.
+        assert_eq!(breakpoints["breakpoints"][1]["line"], 8);
+        client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }] }),
+        );
+        client.request("configurationDone", json!({}));
+
+        assert_eq!(client.event("stopped")["reason"], "breakpoint");
+        let frames = client.request("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(frames["stackFrames"][0]["line"], 3);
+
+        // Step into the subroutine, and back out again.
+        client.request("stepIn", json!({ "threadId": 1 }));
+        assert_eq!(client.event("stopped")["reason"], "step");
+        let frames = client.request("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(frames["stackFrames"][0]["line"], 8);
+        assert_eq!(frames["stackFrames"][0]["name"], "increment");
+        assert_eq!(frames["stackFrames"][1]["line"], 3);
+
+        client.request("stepOut", json!({ "threadId": 1 }));
+        client.event("stopped");
+        let frames = client.request("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(frames["stackFrames"][0]["line"], 4);
+
+        let registers = client.request("variables", json!({ "variablesReference": 1 }));
+        assert_eq!(registers["variables"][0]["value"], "0x06 (6)");
+
+        client.request(
+            "setVariable",
+            json!({ "variablesReference": 1, "name": "V1", "value": "0x2a" }),
+        );
+        assert_eq!(emulator.snapshot().cpu.v[1], 0x2A);
+
+        let memory = client.request(
+            "readMemory",
+            json!({ "memoryReference": "0x200", "count": 2 }),
+        );
+        assert_eq!(memory["data"], "YAU=");
+
+        // Reads past the end of memory are cut short, however long they are.
+        let memory = client.request(
+            "readMemory",
+            json!({ "memoryReference": "0xfff", "count": u64::MAX }),
+        );
+        assert_eq!(memory["unreadableBytes"], u64::MAX - 1);
+
+        assert!(server
+            .take_launched()
+            .is_some_and(|launched| launched.symbols.is_some()));
+
+        client.request("disconnect", json!({}));
+        drop(server);
+        emulator.stop();
+        std::fs::remove_file(path).unwrap();
+    }
+}
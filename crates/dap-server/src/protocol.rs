@@ -0,0 +1,172 @@
+//! Framing for the Debug Adapter Protocol: JSON messages, each after a
+//! `Content-Length` header.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// How long a read waits before giving up, so that the session can notice it's
+/// being stopped, or check on the emulator while it's running.
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// The end of a message's headers.
+const HEADER_END: &[u8] = b"\r\n\r\n";
+
+/// The longest message content accepted. Nothing a client sends comes close,
+/// so anything longer is a broken or hostile client.
+const MAX_CONTENT_LEN: usize = 1 << 20;
+
+/// A request from the client.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    pub seq: i64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// A connection to a client.
+pub struct Connection<'a> {
+    stream: TcpStream,
+    /// Bytes received that don't make up a whole message yet.
+    buffer: Vec<u8>,
+    stop: &'a AtomicBool,
+    /// The sequence number of the last message sent.
+    seq: i64,
+}
+
+impl<'a> Connection<'a> {
+    /// Wrap a connected socket. Reads give up when `stop` is set.
+    pub fn new(stream: TcpStream, stop: &'a AtomicBool) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        Ok(Self {
+            stream,
+            buffer: Vec::new(),
+            stop,
+            seq: 0,
+        })
+    }
+
+    /// Returns true once the server is being stopped.
+    pub fn is_stopping(&self) -> bool {
+        self.stop.load(Ordering::Acquire)
+    }
+
+    /// Wait a short time for a request. Returns `None` if a whole request
+    /// didn't arrive in time.
+    pub fn poll(&mut self) -> io::Result<Option<Request>> {
+        loop {
+            if let Some(message) = self.take_message()? {
+                // Responses to reverse requests, which we never send.
+                if message.kind != "request" {
+                    continue;
+                }
+                return Ok(Some(message));
+            }
+
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Send a successful response to a request.
+    pub fn respond(&mut self, request: &Request, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "command": request.command,
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    /// Send a response saying that a request failed.
+    pub fn respond_error(&mut self, request: &Request, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "command": request.command,
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    pub fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+
+        let content = message.to_string();
+        write!(
+            self.stream,
+            "Content-Length: {}\r\n\r\n{content}",
+            content.len()
+        )
+    }
+
+    /// Take the first whole message out of the buffer, if there is one.
+    fn take_message(&mut self) -> io::Result<Option<Request>> {
+        let header_len = match self
+            .buffer
+            .windows(HEADER_END.len())
+            .position(|window| window == HEADER_END)
+        {
+            Some(position) => position + HEADER_END.len(),
+            None => return Ok(None),
+        };
+
+        let headers = String::from_utf8_lossy(&self.buffer[..header_len]);
+        let content_len = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, len)| len.trim().parse::<usize>().ok())
+            .ok_or_else(|| invalid_data("Message without a Content-Length header"))?;
+        let message_len = header_len
+            .checked_add(content_len)
+            .filter(|_| content_len <= MAX_CONTENT_LEN)
+            .ok_or_else(|| invalid_data(&format!("Message too long: {content_len} bytes")))?;
+
+        if self.buffer.len() < message_len {
+            return Ok(None);
+        }
+
+        let message: Vec<u8> = self.buffer.drain(..message_len).collect();
+        let request = serde_json::from_slice(&message[header_len..])
+            .map_err(|error| invalid_data(&format!("Invalid message: {error}")))?;
+
+        Ok(Some(request))
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,777 @@
+//! A single client's debugging session, and the requests it sends.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Mutex;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::{json, Value};
+
+use assembler::SymbolMap;
+use emulator::{
+    BreakReason, Command, Condition, Emulator, EventBreakpoints, HaltReason, Register, StepTarget,
+};
+use platform::Platform;
+
+use crate::protocol::{Connection, Request};
+use crate::Launched;
+
+/// There's only ever one thread.
+const THREAD_ID: i64 = 1;
+
+/// The `variablesReference`s of each scope.
+const REGISTERS: i64 = 1;
+const MEMORY_AT_I: i64 = 2;
+const STACK: i64 = 3;
+
+/// The number of bytes shown in the "Memory at I" scope.
+const MEMORY_AT_I_LEN: u16 = 16;
+
+/// The event breakpoints offered as exception filters, by filter name.
+const EXCEPTION_FILTERS: [(&str, &str); 5] = [
+    ("collision", "Sprite collision"),
+    ("display_clear", "Display clear"),
+    ("key_wait", "Waiting for a key"),
+    ("sound_start", "Sound starting"),
+    ("unknown_opcode", "Unknown opcode"),
+];
+
+/// The program being debugged.
+struct Program {
+    path: PathBuf,
+    /// Only programs assembled from Octo source have symbols.
+    symbols: Option<SymbolMap>,
+}
+
+struct Session<'a> {
+    connection: Connection<'a>,
+    emulator: &'a Emulator,
+    launched: &'a Mutex<Option<Launched>>,
+
+    program: Option<Program>,
+    /// Breakpoints set by source line.
+    line_breakpoints: BTreeSet<u16>,
+    /// Breakpoints set with `:breakpoint` in the source.
+    named_breakpoints: BTreeSet<u16>,
+    lines_start_at_1: bool,
+    stop_on_entry: bool,
+    /// True while the emulator is running, and we're waiting to tell the
+    /// client when it stops.
+    running: bool,
+}
+
+/// Debug a single client's programs until it disconnects or `stop` is set.
+pub fn run(
+    stream: TcpStream,
+    emulator: &Emulator,
+    launched: &Mutex<Option<Launched>>,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut session = Session {
+        connection: Connection::new(stream, stop)?,
+        emulator,
+        launched,
+        program: None,
+        line_breakpoints: BTreeSet::new(),
+        named_breakpoints: BTreeSet::new(),
+        lines_start_at_1: true,
+        stop_on_entry: false,
+        running: false,
+    };
+
+    let result = session.serve();
+    session.clean_up();
+    result
+}
+
+impl Session<'_> {
+    fn serve(&mut self) -> io::Result<()> {
+        loop {
+            // Waiting for a request also paces the loop.
+            if let Some(request) = self.connection.poll()? {
+                tracing::trace!(?request, "Received DAP request");
+
+                if !self.handle(&request)? {
+                    return Ok(());
+                }
+            }
+
+            if self.connection.is_stopping() {
+                return Ok(());
+            }
+
+            if self.running {
+                self.check_stopped()?;
+            }
+        }
+    }
+
+    /// Handle a request. Returns false when the session is over.
+    fn handle(&mut self, request: &Request) -> io::Result<bool> {
+        let args = &request.arguments;
+
+        let result = match request.command.as_str() {
+            "initialize" => {
+                self.lines_start_at_1 = args["linesStartAt1"].as_bool().unwrap_or(true);
+                Ok(capabilities())
+            }
+            "launch" => {
+                let result = self.launch(args);
+                if result.is_ok() {
+                    self.connection.respond(request, json!({}))?;
+                    // Now that the program's loaded, we know where its source
+                    // lines are, and can take breakpoints.
+                    return self
+                        .connection
+                        .event("initialized", json!({}))
+                        .map(|()| true);
+                }
+                result
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(self.set_exception_breakpoints(args)),
+            "configurationDone" => {
+                self.connection.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None)?;
+                } else {
+                    self.resume();
+                }
+                return Ok(true);
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP8" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(scopes()),
+            "variables" => Ok(self.variables(args)),
+            "setVariable" => self.set_variable(args),
+            "readMemory" => self.read_memory(args),
+            "writeMemory" => self.write_memory(args),
+            "continue" => {
+                self.resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.connection.respond(request, json!({}))?;
+                return self.step(&request.command, args).map(|()| true);
+            }
+            "pause" => {
+                self.emulator.send_and_wait(Command::Pause);
+                Ok(json!({}))
+            }
+            "terminate" => {
+                self.connection.respond(request, json!({}))?;
+                return self
+                    .connection
+                    .event("terminated", json!({}))
+                    .map(|()| true);
+            }
+            "disconnect" => {
+                self.connection.respond(request, json!({}))?;
+                return Ok(false);
+            }
+            command => Err(format!("Unsupported request {command}")),
+        };
+
+        match result {
+            Ok(body) => self.connection.respond(request, body)?,
+            Err(message) => self.connection.respond_error(request, &message)?,
+        }
+
+        Ok(true)
+    }
+
+    /// Handle `launch`, which loads a ROM or assembles Octo source.
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["program"]
+            .as_str()
+            .map(PathBuf::from)
+            .ok_or("The launch configuration needs a \"program\" to run")?;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        self.emulator.send_and_wait(Command::Pause);
+        let symbols = load_program(self.emulator, &path)?;
+
+        // Breakpoints from a previous launch are in the wrong places now.
+        for addr in std::mem::take(&mut self.line_breakpoints)
+            .into_iter()
+            .chain(std::mem::take(&mut self.named_breakpoints))
+        {
+            self.emulator.send_and_wait(Command::RemoveBreakpoint(addr));
+        }
+
+        if let Some(symbols) = &symbols {
+            for &addr in symbols.breakpoints.values() {
+                self.emulator.send_and_wait(Command::AddBreakpoint(addr));
+                self.named_breakpoints.insert(addr);
+            }
+        }
+
+        *self.launched.lock().unwrap() = Some(Launched {
+            path: path.clone(),
+            symbols: symbols.clone(),
+        });
+        self.program = Some(Program { path, symbols });
+
+        Ok(json!({}))
+    }
+
+    /// Handle `setBreakpoints`, which replaces the breakpoints in a source
+    /// file.
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let path = args["source"]["path"].as_str().map(Path::new);
+
+        let symbols = match (&self.program, path) {
+            (Some(program), Some(path)) if same_file(&program.path, path) => {
+                program.symbols.as_ref()
+            }
+            _ => None,
+        };
+
+        let symbols = match symbols {
+            Some(symbols) => symbols,
+            None => {
+                let unverified = |_| json!({ "verified": false, "message": "This file isn't part of the program" });
+                let breakpoints: Vec<_> = requested.iter().map(unverified).collect();
+                return json!({ "breakpoints": breakpoints });
+            }
+        };
+
+        for addr in std::mem::take(&mut self.line_breakpoints) {
+            if !self.named_breakpoints.contains(&addr) {
+                self.emulator.send_and_wait(Command::RemoveBreakpoint(addr));
+            }
+        }
+
+        let mut breakpoints = Vec::with_capacity(requested.len());
+
+        for breakpoint in &requested {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let source_line = if self.lines_start_at_1 {
+                line
+            } else {
+                line + 1
+            };
+
+            let (source_line, addr) = match symbols.addr_for_line(source_line) {
+                Some(found) => found,
+                None => {
+                    breakpoints.push(json!({
+                        "verified": false,
+                        "line": line,
+                        "message": "There's no code on or after this line",
+                    }));
+                    continue;
+                }
+            };
+
+            let condition = match breakpoint["condition"]
+                .as_str()
+                .filter(|c| !c.trim().is_empty())
+            {
+                Some(condition) => match Condition::parse(condition) {
+                    Ok(condition) => Some(condition),
+                    Err(error) => {
+                        breakpoints.push(json!({
+                            "verified": false,
+                            "line": line,
+                            "message": format!("Invalid condition: {error}"),
+                        }));
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            self.emulator.send(Command::AddBreakpoint(addr));
+            self.emulator
+                .send_and_wait(Command::SetBreakpointCondition { addr, condition });
+            self.line_breakpoints.insert(addr);
+
+            breakpoints.push(json!({
+                "id": addr,
+                "verified": true,
+                "line": self.client_line(source_line),
+                "instructionReference": format_addr(addr),
+            }));
+        }
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    /// Handle `setExceptionBreakpoints`, which chooses the event breakpoints.
+    fn set_exception_breakpoints(&mut self, args: &Value) -> Value {
+        let filters: Vec<&str> = args["filters"]
+            .as_array()
+            .map(|filters| filters.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let enabled = |filter| filters.contains(&filter);
+
+        self.emulator
+            .send_and_wait(Command::SetEventBreakpoints(EventBreakpoints {
+                display_clear: enabled("display_clear"),
+                collision: enabled("collision"),
+                key_wait: enabled("key_wait"),
+                sound_start: enabled("sound_start"),
+                unknown_opcode: enabled("unknown_opcode"),
+            }));
+
+        json!({})
+    }
+
+    /// Handle `stackTrace`. The call stack's return addresses become frames,
+    /// at the calls that pushed them.
+    fn stack_trace(&self) -> Value {
+        let cpu = self.emulator.snapshot().cpu;
+
+        let calls = cpu.stack[..cpu.sp as usize]
+            .iter()
+            .rev()
+            .map(|&return_addr| return_addr.wrapping_sub(2));
+
+        let frames: Vec<_> = std::iter::once(cpu.pc)
+            .chain(calls)
+            .enumerate()
+            .map(|(id, addr)| self.frame(id, addr))
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    /// A stack frame at an address.
+    fn frame(&self, id: usize, addr: u16) -> Value {
+        let symbols = self.symbols();
+
+        let name = match symbols.and_then(|symbols| symbols.nearest_label(addr)) {
+            Some((label, 0)) => label.to_string(),
+            Some((label, offset)) => format!("{label}+{offset}"),
+            None => format_addr(addr),
+        };
+
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format_addr(addr),
+        });
+
+        let line = symbols.and_then(|symbols| symbols.line_of(addr));
+        if let (Some(program), Some(line)) = (&self.program, line) {
+            frame["source"] = json!({
+                "name": program.path.file_name().map(|name| name.to_string_lossy()),
+                "path": program.path,
+            });
+            frame["line"] = self.client_line(line).into();
+            frame["column"] = self.client_line(1).into();
+        }
+
+        frame
+    }
+
+    /// Handle `variables`, for one of the [`scopes()`].
+    fn variables(&self, args: &Value) -> Value {
+        let cpu = self.emulator.snapshot().cpu;
+
+        let variables: Vec<_> = match args["variablesReference"].as_i64() {
+            Some(REGISTERS) => REGISTER_NAMES
+                .iter()
+                .map(|&(name, register)| {
+                    let value = register.read(&cpu);
+                    let mut variable = json!({
+                        "name": name,
+                        "value": format_register(register, value),
+                        "type": if register.is_wide() { "u16" } else { "u8" },
+                        "variablesReference": 0,
+                    });
+                    if let Register::I | Register::Pc = register {
+                        variable["memoryReference"] = format_addr(value).into();
+                    }
+                    variable
+                })
+                .collect(),
+            Some(MEMORY_AT_I) => {
+                let ram = self.emulator.ram().lock().unwrap();
+                (0..MEMORY_AT_I_LEN)
+                    .map(|offset| {
+                        let addr = cpu.i.wrapping_add(offset);
+                        json!({
+                            "name": format!("[{}]", format_addr(addr)),
+                            "value": format!("{:#04x}", ram[addr]),
+                            "type": "u8",
+                            "variablesReference": 0,
+                            "memoryReference": format_addr(addr),
+                        })
+                    })
+                    .collect()
+            }
+            Some(STACK) => cpu.stack[..cpu.sp as usize]
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(depth, &addr)| {
+                    json!({
+                        "name": depth.to_string(),
+                        "value": format_addr(addr),
+                        "type": "u16",
+                        "variablesReference": 0,
+                        "memoryReference": format_addr(addr),
+                    })
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        json!({ "variables": variables })
+    }
+
+    /// Handle `setVariable`, for registers and memory.
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or_default();
+        let value = args["value"]
+            .as_str()
+            .and_then(parse_number)
+            .ok_or("Enter a number, like 42, 0x2A or 0b101010")?;
+
+        match args["variablesReference"].as_i64() {
+            Some(REGISTERS) => {
+                let register = REGISTER_NAMES
+                    .iter()
+                    .find(|(register_name, _)| register_name.eq_ignore_ascii_case(name))
+                    .map(|&(_, register)| register)
+                    .ok_or_else(|| format!("Unknown register {name}"))?;
+
+                let max = if register.is_wide() { 0xFFFF } else { 0xFF };
+                if value > max {
+                    return Err(format!("{name} only holds values up to {max:#x}"));
+                }
+
+                self.emulator
+                    .send_and_wait(Command::SetRegister(register, value as u16));
+                Ok(json!({ "value": format_register(register, value as u16) }))
+            }
+            Some(MEMORY_AT_I) => {
+                let addr = name
+                    .strip_prefix('[')
+                    .and_then(|name| name.strip_suffix(']'))
+                    .and_then(parse_number)
+                    .ok_or_else(|| format!("Unknown address {name}"))?;
+                let byte = u8::try_from(value).map_err(|_| "Bytes only hold values up to 0xff")?;
+
+                self.emulator.send_and_wait(Command::WriteMemory {
+                    addr: addr as u16,
+                    bytes: vec![byte],
+                });
+                Ok(json!({ "value": format!("{byte:#04x}") }))
+            }
+            _ => Err("These variables can't be changed".to_string()),
+        }
+    }
+
+    /// Handle `readMemory`. Reads that run off the end of memory are cut
+    /// short.
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let addr = memory_addr(args)?;
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+
+        let ram = self.emulator.ram().lock().unwrap();
+        let start = addr as usize;
+        let end = start.saturating_add(count).min(ram.len());
+        let data = if start < end {
+            ram.get_range(start as u16..=(end - 1) as u16)
+        } else {
+            &[]
+        };
+
+        Ok(json!({
+            "address": format_addr(addr),
+            "data": BASE64.encode(data),
+            "unreadableBytes": count - data.len(),
+        }))
+    }
+
+    /// Handle `writeMemory`.
+    fn write_memory(&self, args: &Value) -> Result<Value, String> {
+        let addr = memory_addr(args)?;
+        let bytes = BASE64
+            .decode(args["data"].as_str().unwrap_or_default())
+            .map_err(|error| format!("Invalid data: {error}"))?;
+        let len = bytes.len();
+
+        self.emulator
+            .send_and_wait(Command::WriteMemory { addr, bytes });
+
+        Ok(json!({ "bytesWritten": len }))
+    }
+
+    /// Handle `next`, `stepIn` and `stepOut`. These step by source line where
+    /// there are symbols for the code at the PC, and by instruction otherwise.
+    fn step(&mut self, command: &str, args: &Value) -> io::Result<()> {
+        let cpu = self.emulator.snapshot().cpu;
+        let by_instruction = args["granularity"].as_str() == Some("instruction");
+
+        let line = self.symbols().and_then(|symbols| symbols.line_of(cpu.pc));
+        let symbols = self.symbols().filter(|_| !by_instruction);
+
+        let target = match (symbols, line, command) {
+            // Out to the first line in the caller.
+            (Some(symbols), _, "stepOut") if cpu.sp > 0 => Some(StepTarget {
+                addrs: symbols.lines.keys().copied().collect(),
+                max_depth: Some(cpu.sp - 1),
+            }),
+            (None, _, "stepOut") if cpu.sp > 0 => Some(StepTarget {
+                addrs: [cpu.stack[cpu.sp as usize - 1]].into(),
+                max_depth: Some(cpu.sp - 1),
+            }),
+            // On to the next line, either skipping or following calls.
+            (Some(symbols), Some(line), _) => Some(StepTarget {
+                addrs: symbols
+                    .lines
+                    .iter()
+                    .filter(|(_, &addr_line)| addr_line != line)
+                    .map(|(&addr, _)| addr)
+                    .collect(),
+                max_depth: if command == "stepIn" {
+                    None
+                } else {
+                    Some(cpu.sp)
+                },
+            }),
+            _ => None,
+        };
+
+        match target {
+            Some(target) => {
+                self.emulator.send_and_wait(Command::RunTo(target));
+                self.running = true;
+                Ok(())
+            }
+            None => {
+                self.emulator.send_and_wait(Command::Step);
+                self.check_stopped()
+            }
+        }
+    }
+
+    fn resume(&mut self) {
+        self.emulator.send_and_wait(Command::Resume);
+        self.running = true;
+    }
+
+    /// Tell the client if the emulator has stopped.
+    fn check_stopped(&mut self) -> io::Result<()> {
+        let snapshot = self.emulator.snapshot();
+
+        if let Some(reason) = snapshot.halted {
+            self.running = false;
+
+            if reason == HaltReason::Cpu(cpu::HaltReason::Exit) {
+                self.connection.event("exited", json!({ "exitCode": 0 }))?;
+                return self.connection.event("terminated", json!({}));
+            }
+            return self.stopped("exception", Some(reason.to_string()));
+        }
+
+        if !snapshot.paused {
+            return Ok(());
+        }
+        self.running = false;
+
+        let (reason, description) = match snapshot.last_break.map(|hit| hit.reason) {
+            Some(BreakReason::Breakpoint) => ("breakpoint", None),
+            Some(BreakReason::Watchpoint { addr, .. }) => (
+                "data breakpoint",
+                Some(format!("Watchpoint at {}", format_addr(addr))),
+            ),
+            Some(BreakReason::Step) | None => ("step", None),
+            Some(reason) => ("exception", Some(describe_event(reason).to_string())),
+        };
+
+        self.stopped(reason, description)
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = description.clone().into();
+            body["text"] = description.into();
+        }
+
+        self.connection.event("stopped", body)
+    }
+
+    /// Remove our breakpoints, and let the program carry on without us.
+    fn clean_up(&mut self) {
+        for &addr in self.line_breakpoints.union(&self.named_breakpoints) {
+            self.emulator.send(Command::RemoveBreakpoint(addr));
+        }
+        self.emulator
+            .send(Command::SetEventBreakpoints(EventBreakpoints::default()));
+        self.emulator.send(Command::Resume);
+    }
+
+    fn symbols(&self) -> Option<&SymbolMap> {
+        self.program
+            .as_ref()
+            .and_then(|program| program.symbols.as_ref())
+    }
+
+    /// Convert a source line (counting from 1) to the client's numbering.
+    fn client_line(&self, line: usize) -> usize {
+        if self.lines_start_at_1 {
+            line
+        } else {
+            line - 1
+        }
+    }
+}
+
+/// What we support, sent in response to `initialize`.
+fn capabilities() -> Value {
+    let filters: Vec<_> = EXCEPTION_FILTERS
+        .iter()
+        .map(|(filter, label)| json!({ "filter": filter, "label": label, "default": false }))
+        .collect();
+
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsConditionalBreakpoints": true,
+        "supportsSetVariable": true,
+        "supportsReadMemoryRequest": true,
+        "supportsWriteMemoryRequest": true,
+        "supportsSteppingGranularity": true,
+        "supportsTerminateRequest": true,
+        "exceptionBreakpointFilters": filters,
+    })
+}
+
+/// The scopes in every frame. Registers are the same in all of them, since
+/// the CHIP8 doesn't save them on calls.
+fn scopes() -> Value {
+    json!({
+        "scopes": [
+            { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+            { "name": "Memory at I", "variablesReference": MEMORY_AT_I, "expensive": false },
+            { "name": "Stack", "variablesReference": STACK, "expensive": false },
+        ]
+    })
+}
+
+/// The registers, in the order they're shown.
+const REGISTER_NAMES: [(&str, Register); 21] = [
+    ("V0", Register::V(0x0)),
+    ("V1", Register::V(0x1)),
+    ("V2", Register::V(0x2)),
+    ("V3", Register::V(0x3)),
+    ("V4", Register::V(0x4)),
+    ("V5", Register::V(0x5)),
+    ("V6", Register::V(0x6)),
+    ("V7", Register::V(0x7)),
+    ("V8", Register::V(0x8)),
+    ("V9", Register::V(0x9)),
+    ("VA", Register::V(0xA)),
+    ("VB", Register::V(0xB)),
+    ("VC", Register::V(0xC)),
+    ("VD", Register::V(0xD)),
+    ("VE", Register::V(0xE)),
+    ("VF", Register::V(0xF)),
+    ("I", Register::I),
+    ("PC", Register::Pc),
+    ("SP", Register::Sp),
+    ("DT", Register::DelayTimer),
+    ("ST", Register::SoundTimer),
+];
+
+fn format_register(register: Register, value: u16) -> String {
+    if register.is_wide() {
+        format!("{value:#06x}")
+    } else {
+        format!("{value:#04x} ({value})")
+    }
+}
+
+/// Addresses are used as memory references, which are opaque strings to the
+/// client.
+fn format_addr(addr: u16) -> String {
+    format!("{addr:#06x}")
+}
+
+/// The address in a memory request, with its offset applied.
+fn memory_addr(args: &Value) -> Result<u16, String> {
+    let reference = args["memoryReference"].as_str().unwrap_or_default();
+    let offset = args["offset"].as_i64().unwrap_or(0);
+
+    parse_number(reference)
+        .and_then(|addr| (addr as i64).checked_add(offset))
+        .and_then(|addr| u16::try_from(addr).ok())
+        .ok_or_else(|| format!("Invalid memory reference {reference}"))
+}
+
+/// Parse a decimal, `0x` hex or `0b` binary number.
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn describe_event(reason: BreakReason) -> &'static str {
+    match reason {
+        BreakReason::DisplayClear => "Display clear",
+        BreakReason::Collision => "Sprite collision",
+        BreakReason::KeyWait => "Waiting for a key",
+        BreakReason::SoundStart => "Sound starting",
+        BreakReason::UnknownOpcode => "Unknown opcode",
+        BreakReason::Breakpoint | BreakReason::Watchpoint { .. } | BreakReason::Step => {
+            "Breakpoint"
+        }
+    }
+}
+
+/// Returns true if two paths are the same file.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Load a ROM, or assemble and load Octo source, switching platforms to suit.
+/// Returns the symbols for Octo source.
+fn load_program(emulator: &Emulator, path: &Path) -> Result<Option<SymbolMap>, String> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    if extension == Some("8o") {
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+        let program = assembler::assemble(&source)
+            .map_err(|error| format!("Failed to assemble {}: {error}", path.display()))?;
+
+        emulator.send(Command::SetPlatform(program.platform));
+        emulator.send_and_wait(Command::LoadRom(program.rom));
+
+        return Ok(Some(program.symbols));
+    }
+
+    let rom = std::fs::read(path)
+        .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+
+    if let Some(platform) = extension.and_then(Platform::from_rom_extension) {
+        emulator.send(Command::SetPlatform(platform));
+    }
+    emulator.send_and_wait(Command::LoadRom(rom));
+
+    Ok(None)
+}
@@ -0,0 +1,541 @@
+//! A disassembler for CHIP8, SUPER-CHIP and XO-CHIP programs.
+//!
+//! CHIP8 programs freely mix code and data, so rather than decoding every pair
+//! of bytes as an instruction, the disassembler follows the flow of the program
+//! from its entry point. Anything that's never reached is shown as raw data.
+//! Jump and call targets get labels, as do addresses loaded into `I`, which are
+//! almost always sprites or other data.
+
+use std::{collections::BTreeMap, fmt, ops::RangeInclusive};
+
+use opcode::Instruction;
+use platform::Platform;
+use ram::{Ram, PROGRAM_START_ADDRESS};
+
+mod syntax;
+
+pub use syntax::Syntax;
+
+/// The most bytes shown on a single line of data.
+pub const DATA_BYTES_PER_LINE: usize = 4;
+
+/// Disassembles CHIP8 programs, either from a ROM or from memory.
+#[derive(Debug, Clone)]
+pub struct Disassembler {
+    platform: Platform,
+    syntax: Syntax,
+    entry_points: Vec<u16>,
+}
+
+/// What a [`Label`] marks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LabelKind {
+    /// The target of a `2NNN` call.
+    Subroutine,
+    /// The target of a jump.
+    Branch,
+    /// An address loaded into `I`.
+    Data,
+}
+
+/// A name for an address referred to by the program.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Label {
+    pub name: String,
+    pub kind: LabelKind,
+}
+
+/// What's on a single [`Line`] of a [`Listing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineContents {
+    /// An instruction that's reachable from an entry point.
+    Instruction(Instruction),
+    /// Bytes that aren't reachable as code.
+    Data,
+}
+
+/// A single line of a [`Listing`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Line {
+    /// The address of the first byte on this line.
+    pub addr: u16,
+    /// The raw bytes on this line.
+    pub bytes: Vec<u8>,
+    pub contents: LineContents,
+}
+
+/// The instructions reachable from a [`Disassembler`]'s entry points. See
+/// [`Disassembler::flow()`].
+///
+/// Flows from different entry points can be listed together with
+/// [`Disassembler::list()`], so that when memory changes, only the flows that
+/// reached the changed bytes need following again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Flow {
+    /// Every instruction reached, by address, with the bytes it was decoded
+    /// from.
+    pub instructions: BTreeMap<u16, Vec<u8>>,
+    /// The addresses that the instructions jump to, call, or load into `I`.
+    pub labels: BTreeMap<u16, LabelKind>,
+}
+
+/// The result of disassembling a program.
+///
+/// Use the [`fmt::Display`] implementation to get a text listing, with one line
+/// per instruction showing its address, raw bytes, and mnemonic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub syntax: Syntax,
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<u16, Label>,
+}
+
+impl Disassembler {
+    /// Create a disassembler for programs written for some platform, using
+    /// Octo syntax.
+    pub fn new(platform: Platform) -> Self {
+        Self {
+            platform,
+            syntax: Syntax::default(),
+            entry_points: Vec::new(),
+        }
+    }
+
+    /// Use a different syntax for mnemonics.
+    pub fn with_syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Also follow the program's flow from some address.
+    ///
+    /// If no entry points are given, disassembly starts from
+    /// [`PROGRAM_START_ADDRESS`] if it's in range, or the start of the range if
+    /// not.
+    pub fn with_entry_point(mut self, addr: u16) -> Self {
+        self.entry_points.push(addr);
+        self
+    }
+
+    /// Disassemble a ROM, which will be loaded at [`PROGRAM_START_ADDRESS`].
+    pub fn disassemble_rom(&self, rom: &[u8]) -> Listing {
+        self.disassemble(rom, PROGRAM_START_ADDRESS)
+    }
+
+    /// Disassemble a range of memory.
+    ///
+    /// The range is inclusive, so that the very end of memory can be included.
+    pub fn disassemble_ram(&self, ram: &Ram, addr_range: RangeInclusive<u16>) -> Listing {
+        let origin = *addr_range.start();
+        self.disassemble(ram.get_range(addr_range), origin)
+    }
+
+    /// Disassemble some bytes, where the first byte is at address `origin`.
+    ///
+    /// Anything past the end of the address space is ignored.
+    pub fn disassemble(&self, bytes: &[u8], origin: u16) -> Listing {
+        self.list(bytes, origin, [&self.flow(bytes, origin)])
+    }
+
+    /// Follow the program's flow through some bytes, where the first byte is at
+    /// address `origin`, to find the instructions reachable from the entry
+    /// points.
+    ///
+    /// Anything past the end of the address space is ignored.
+    pub fn flow(&self, bytes: &[u8], origin: u16) -> Flow {
+        let bytes = clamp_to_address_space(bytes, origin);
+        let end = origin as usize + bytes.len();
+        let in_range = |addr: u16| (origin as usize..end).contains(&(addr as usize));
+
+        let mut flow = Flow::default();
+        let add_label = |flow: &mut Flow, addr: u16, kind: LabelKind| {
+            if !in_range(addr) {
+                return;
+            }
+
+            // Code labels take priority over data labels, since jumping
+            // somewhere is a much stronger hint than pointing `I` at it.
+            let label = flow.labels.entry(addr).or_insert(kind);
+            *label = (*label).min(kind);
+        };
+
+        let mut pending = if self.entry_points.is_empty() {
+            if in_range(PROGRAM_START_ADDRESS) {
+                vec![PROGRAM_START_ADDRESS]
+            } else {
+                vec![origin]
+            }
+        } else {
+            self.entry_points.clone()
+        };
+
+        while let Some(addr) = pending.pop() {
+            if !in_range(addr) || flow.instructions.contains_key(&addr) {
+                continue;
+            }
+
+            let offset = (addr - origin) as usize;
+            let instruction = Instruction::decode_bytes(&bytes[offset..], self.platform);
+            let len = instruction.len() as usize;
+
+            // Anything that doesn't decode, or that's cut off at the end of the
+            // program, is almost certainly data that we've wandered into.
+            if matches!(
+                instruction,
+                Instruction::Unknown { .. } | Instruction::MachineCall { .. }
+            ) || offset + len > bytes.len()
+            {
+                continue;
+            }
+
+            flow.instructions
+                .insert(addr, bytes[offset..offset + len].to_vec());
+
+            let next = addr.wrapping_add(len as u16);
+
+            match instruction {
+                Instruction::Return | Instruction::Exit => {}
+                Instruction::Jump { nnn } => {
+                    add_label(&mut flow, nnn, LabelKind::Branch);
+                    pending.push(nnn);
+                }
+                Instruction::JumpOffset { nnn } => {
+                    // The offset isn't known, but jump tables usually start with
+                    // an entry for `V0 == 0`.
+                    add_label(&mut flow, nnn, LabelKind::Branch);
+                    pending.push(nnn);
+                }
+                Instruction::Call { nnn } => {
+                    add_label(&mut flow, nnn, LabelKind::Subroutine);
+                    pending.push(nnn);
+                    pending.push(next);
+                }
+                Instruction::LoadIndex { nnn } => {
+                    add_label(&mut flow, nnn, LabelKind::Data);
+                    pending.push(next);
+                }
+                Instruction::LoadIndexLong { nnnn } => {
+                    add_label(&mut flow, nnnn, LabelKind::Data);
+                    pending.push(next);
+                }
+                Instruction::SkipIfEqualImm { .. }
+                | Instruction::SkipIfNotEqualImm { .. }
+                | Instruction::SkipIfEqual { .. }
+                | Instruction::SkipIfNotEqual { .. }
+                | Instruction::SkipIfKey { .. }
+                | Instruction::SkipIfNotKey { .. } => {
+                    pending.push(next);
+
+                    if in_range(next) {
+                        let skipped = Instruction::decode_bytes(
+                            &bytes[(next - origin) as usize..],
+                            self.platform,
+                        );
+                        pending.push(next.wrapping_add(skipped.len()));
+                    }
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        flow
+    }
+
+    /// List some bytes, where the first byte is at address `origin`, showing
+    /// the instructions reached by any of `flows` as code and everything else
+    /// as data.
+    ///
+    /// The flows should have been followed through the same bytes. See
+    /// [`Disassembler::flow()`].
+    pub fn list<'a>(
+        &self,
+        bytes: &[u8],
+        origin: u16,
+        flows: impl IntoIterator<Item = &'a Flow>,
+    ) -> Listing {
+        let bytes = clamp_to_address_space(bytes, origin);
+        let end = origin as usize + bytes.len();
+
+        let mut is_code = vec![false; bytes.len()];
+        let mut labels = BTreeMap::new();
+
+        for flow in flows {
+            for (&addr, instruction) in &flow.instructions {
+                let offset = (addr as usize).checked_sub(origin as usize);
+                if let Some(offset) =
+                    offset.filter(|offset| offset + instruction.len() <= bytes.len())
+                {
+                    is_code[offset] = true;
+                }
+            }
+
+            for (&addr, &kind) in &flow.labels {
+                if !(origin as usize..end).contains(&(addr as usize)) {
+                    continue;
+                }
+
+                let label = labels.entry(addr).or_insert(Label {
+                    name: String::new(),
+                    kind,
+                });
+                label.kind = label.kind.min(kind);
+            }
+        }
+
+        let lines = lines(bytes, origin, &is_code, &labels, self.platform);
+
+        for (addr, label) in labels.iter_mut() {
+            let prefix = match label.kind {
+                LabelKind::Subroutine => "sub",
+                LabelKind::Branch => "label",
+                LabelKind::Data => "data",
+            };
+            label.name = format!("{prefix}_{addr:03x}");
+        }
+
+        Listing {
+            syntax: self.syntax,
+            lines,
+            labels,
+        }
+    }
+}
+
+impl Flow {
+    /// True if any of the bytes that the instructions were decoded from are
+    /// different in `bytes`, where the first byte is at address `origin`. The
+    /// flow needs following again if so.
+    pub fn is_stale(&self, bytes: &[u8], origin: u16) -> bool {
+        self.instructions.iter().any(|(&addr, instruction)| {
+            let start = (addr as usize).wrapping_sub(origin as usize);
+            bytes.get(start..start + instruction.len()) != Some(instruction.as_slice())
+        })
+    }
+}
+
+/// The part of some bytes, where the first byte is at address `origin`, that
+/// fits in the address space.
+fn clamp_to_address_space(bytes: &[u8], origin: u16) -> &[u8] {
+    &bytes[..bytes.len().min(0x10000 - origin as usize)]
+}
+
+/// Split some bytes into lines of code and data.
+fn lines(
+    bytes: &[u8],
+    origin: u16,
+    is_code: &[bool],
+    labels: &BTreeMap<u16, Label>,
+    platform: Platform,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let addr = origin + offset as u16;
+
+        if is_code[offset] {
+            let instruction = Instruction::decode_bytes(&bytes[offset..], platform);
+            let len = instruction.len() as usize;
+
+            lines.push(Line {
+                addr,
+                bytes: bytes[offset..offset + len].to_vec(),
+                contents: LineContents::Instruction(instruction),
+            });
+            offset += len;
+        } else {
+            // Data runs up to the next bit of code, the next label, or the most
+            // bytes allowed on a line.
+            let mut len = 1;
+            while len < DATA_BYTES_PER_LINE
+                && offset + len < bytes.len()
+                && !is_code[offset + len]
+                && !labels.contains_key(&(addr + len as u16))
+            {
+                len += 1;
+            }
+
+            lines.push(Line {
+                addr,
+                bytes: bytes[offset..offset + len].to_vec(),
+                contents: LineContents::Data,
+            });
+            offset += len;
+        }
+    }
+
+    lines
+}
+
+impl Listing {
+    /// The name of the label at some address, if there is one.
+    pub fn label_name(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.name.as_str())
+    }
+
+    /// The index of the line containing some address, if there is one.
+    pub fn line_index(&self, addr: u16) -> Option<usize> {
+        let index = self.lines.partition_point(|line| line.addr <= addr);
+        let line = self.lines.get(index.checked_sub(1)?)?;
+        (addr - line.addr < line.bytes.len() as u16).then_some(index - 1)
+    }
+
+    /// The mnemonic for a single line, with addresses replaced by labels.
+    pub fn mnemonic(&self, line: &Line) -> String {
+        match &line.contents {
+            LineContents::Instruction(instruction) => {
+                self.syntax.format_instruction(instruction, |addr| {
+                    self.label_name(addr)
+                        .map(str::to_string)
+                        .unwrap_or_else(|| self.syntax.format_addr(addr))
+                })
+            }
+            LineContents::Data => self.syntax.format_data(&line.bytes),
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(name) = self.label_name(line.addr) {
+                writeln!(f, "{}", self.syntax.format_label(name))?;
+            }
+
+            let bytes = line
+                .bytes
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            writeln!(
+                f,
+                "{:04X}  {bytes:<width$}  {}",
+                line.addr,
+                self.mnemonic(line),
+                width = DATA_BYTES_PER_LINE * 3 - 1
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_flow_and_labels_targets() {
+        let rom = [
+            0xA2, 0x08, // 200: i := data_208
+            0x22, 0x0A, // 202: :call sub_20a
+            0x12, 0x04, // 204: jump label_204
+            0xFF, 0xFF, // 206: never reached
+            0x3C, 0x00, // 208: sprite data
+            0x00, 0xEE, // 20A: return
+        ];
+
+        let listing = Disassembler::new(Platform::Chip8).disassemble_rom(&rom);
+
+        assert_eq!(listing.label_name(0x204), Some("label_204"));
+        assert_eq!(listing.label_name(0x208), Some("data_208"));
+        assert_eq!(listing.label_name(0x20A), Some("sub_20a"));
+
+        let contents: Vec<_> = listing
+            .lines
+            .iter()
+            .map(|line| (line.addr, line.contents))
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                (
+                    0x200,
+                    LineContents::Instruction(Instruction::LoadIndex { nnn: 0x208 })
+                ),
+                (
+                    0x202,
+                    LineContents::Instruction(Instruction::Call { nnn: 0x20A })
+                ),
+                (
+                    0x204,
+                    LineContents::Instruction(Instruction::Jump { nnn: 0x204 })
+                ),
+                (0x206, LineContents::Data),
+                (0x208, LineContents::Data),
+                (0x20A, LineContents::Instruction(Instruction::Return)),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_flows_from_several_entry_points() {
+        let mut memory = vec![0; 0x210];
+        memory[0x200..0x206].copy_from_slice(&[
+            0x12, 0x00, // 200: jump label_200
+            0x6A, 0x02, // 202: only reached by a computed jump
+            0x00, 0xEE, // 204: return
+        ]);
+
+        let main = Disassembler::new(Platform::Chip8).flow(&memory, 0);
+        let computed = Disassembler::new(Platform::Chip8)
+            .with_entry_point(0x202)
+            .flow(&memory, 0);
+        assert_eq!(main.instructions.keys().collect::<Vec<_>>(), [&0x200]);
+
+        let listing = Disassembler::new(Platform::Chip8).list(&memory, 0, [&main, &computed]);
+        assert_eq!(
+            listing.line_index(0x204),
+            Some(listing.line_index(0x202).unwrap() + 1)
+        );
+        assert!(matches!(
+            listing.lines[listing.line_index(0x204).unwrap()].contents,
+            LineContents::Instruction(Instruction::Return)
+        ));
+
+        // Writing data doesn't affect either flow, but rewriting code does.
+        memory[0x208] = 0xFF;
+        assert!(!main.is_stale(&memory, 0));
+        memory[0x203] = 0x03;
+        assert!(!main.is_stale(&memory, 0));
+        assert!(computed.is_stale(&memory, 0));
+    }
+
+    #[test]
+    fn skips_follow_both_paths() {
+        let rom = [
+            0x30, 0x00, // 200: if v0 != 0x00 then
+            0xF0, 0x00, 0x02, 0x08, // 202: i := long 0x208
+            0x00, 0xFD, // 206: exit
+        ];
+
+        let listing = Disassembler::new(Platform::XoChip).disassemble_rom(&rom);
+
+        assert!(listing
+            .lines
+            .iter()
+            .all(|line| matches!(line.contents, LineContents::Instruction(_))));
+        assert_eq!(listing.line_index(0x204), Some(1));
+    }
+
+    #[test]
+    fn formats_both_syntaxes() {
+        let rom = [0x6A, 0x02, 0x12, 0x00];
+
+        let octo = Disassembler::new(Platform::Chip8).disassemble_rom(&rom);
+        assert_eq!(
+            octo.to_string(),
+            ": label_200\n0200  6A 02        va := 0x02\n0202  12 00        jump label_200\n"
+        );
+
+        let cowgod = Disassembler::new(Platform::Chip8)
+            .with_syntax(Syntax::Cowgod)
+            .disassemble_rom(&rom);
+        assert_eq!(
+            cowgod.to_string(),
+            "label_200:\n0200  6A 02        LD VA, #02\n0202  12 00        JP label_200\n"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Command line interface to the disassembler.
+
+use std::{fs, path::PathBuf};
+
+use clap::Parser;
+use color_eyre::eyre::{eyre, Context};
+use disassembler::{Disassembler, Syntax};
+use platform::Platform;
+use ram::Ram;
+
+/// Disassemble a CHIP8, SUPER-CHIP or XO-CHIP ROM.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// The ROM to disassemble.
+    rom: PathBuf,
+
+    /// The platform the ROM was written for. Guessed from the ROM's file
+    /// extension if not given, or CHIP-8 if that doesn't help.
+    #[arg(short, long)]
+    platform: Option<Platform>,
+
+    /// The syntax used for mnemonics.
+    #[arg(short, long, value_enum, default_value_t)]
+    syntax: Syntax,
+
+    /// Disassemble a range of memory after loading the ROM, like `0x000-0x1FF`,
+    /// instead of just the ROM. Both ends are inclusive.
+    #[arg(short, long, value_parser = parse_range)]
+    range: Option<(u16, u16)>,
+
+    /// An extra address to follow the program's flow from, for code that's
+    /// only reached through jump tables or self-modifying code. Can be given
+    /// more than once.
+    #[arg(short, long = "entry", value_parser = parse_addr)]
+    entry_points: Vec<u16>,
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let args = Args::parse();
+
+    let rom = fs::read(&args.rom).wrap_err_with(|| format!("Failed to read {:?}", args.rom))?;
+
+    let platform = args.platform.unwrap_or_else(|| {
+        args.rom
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Platform::from_rom_extension)
+            .unwrap_or_default()
+    });
+
+    let disassembler = args.entry_points.iter().fold(
+        Disassembler::new(platform).with_syntax(args.syntax),
+        |d, &addr| d.with_entry_point(addr),
+    );
+
+    let listing = match args.range {
+        Some((start, end)) => {
+            let mut ram = Ram::with_size(platform.ram_size());
+            ram.load_program(&rom).map_err(|max| {
+                eyre!(
+                    "ROM is {} bytes, but at most {max} fit in memory",
+                    rom.len()
+                )
+            })?;
+
+            if end as usize >= ram.len() {
+                return Err(eyre!("{end:#X} is past the end of {platform}'s memory"));
+            }
+
+            disassembler.disassemble_ram(&ram, start..=end)
+        }
+        None => disassembler.disassemble_rom(&rom),
+    };
+
+    print!("{listing}");
+
+    Ok(())
+}
+
+/// Parse an address, in hex with or without a leading `0x`.
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|e| format!("Invalid address \"{s}\": {e}"))
+}
+
+/// Parse an inclusive address range like `200-2FF`.
+fn parse_range(s: &str) -> Result<(u16, u16), String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("Invalid range \"{s}\". Expected something like 0x200-0x2FF"))?;
+    let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+
+    if start > end {
+        return Err(format!("Invalid range \"{s}\". The start is after the end"));
+    }
+
+    Ok((start, end))
+}
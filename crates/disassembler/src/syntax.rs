@@ -0,0 +1,192 @@
+//! Formatting of instructions as assembly language mnemonics.
+
+use opcode::Instruction;
+use strum::{Display, EnumIter};
+
+/// The assembly language syntax used for mnemonics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Display, EnumIter, clap::ValueEnum)]
+pub enum Syntax {
+    /// The syntax used by Octo, e.g. `v0 := 0x12`.
+    #[default]
+    Octo,
+
+    /// The classic syntax from Cowgod's CHIP-8 technical reference, e.g.
+    /// `LD V0, #12`.
+    Cowgod,
+}
+
+impl Syntax {
+    /// Format a single instruction.
+    ///
+    /// `target` is used to format addresses referred to by the instruction, so
+    /// that they can be replaced with label names.
+    pub fn format_instruction(
+        &self,
+        instruction: &Instruction,
+        target: impl Fn(u16) -> String,
+    ) -> String {
+        match self {
+            Syntax::Octo => octo(instruction, target),
+            Syntax::Cowgod => cowgod(instruction, target),
+        }
+    }
+
+    /// Format some bytes as data.
+    pub fn format_data(&self, bytes: &[u8]) -> String {
+        match self {
+            Syntax::Octo => bytes
+                .iter()
+                .map(|byte| format!("{byte:#04x}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Syntax::Cowgod => format!(
+                "DB {}",
+                bytes
+                    .iter()
+                    .map(|byte| format!("#{byte:02X}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Format a label definition.
+    pub fn format_label(&self, name: &str) -> String {
+        match self {
+            Syntax::Octo => format!(": {name}"),
+            Syntax::Cowgod => format!("{name}:"),
+        }
+    }
+
+    /// Format a raw address, for when there's no label for it.
+    pub fn format_addr(&self, addr: u16) -> String {
+        match self {
+            Syntax::Octo => format!("{addr:#05x}"),
+            Syntax::Cowgod => format!("#{addr:03X}"),
+        }
+    }
+}
+
+fn octo(instruction: &Instruction, target: impl Fn(u16) -> String) -> String {
+    use Instruction::*;
+
+    let v = |x: &u8| format!("v{x:x}");
+
+    match instruction {
+        ScrollDown { n } => format!("scroll-down {n}"),
+        ScrollUp { n } => format!("scroll-up {n}"),
+        ClearScreen => "clear".to_string(),
+        Return => "return".to_string(),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        LowRes => "lores".to_string(),
+        HighRes => "hires".to_string(),
+        MachineCall { nnn } => format!("native {}", target(*nnn)),
+        Jump { nnn } => format!("jump {}", target(*nnn)),
+        Call { nnn } => format!(":call {}", target(*nnn)),
+        // Octo expresses skips as conditions under which the next instruction
+        // *is* executed, so the comparisons are inverted.
+        SkipIfEqualImm { x, nn } => format!("if {} != {nn:#04x} then", v(x)),
+        SkipIfNotEqualImm { x, nn } => format!("if {} == {nn:#04x} then", v(x)),
+        SkipIfEqual { x, y } => format!("if {} != {} then", v(x), v(y)),
+        SaveRange { x, y } => format!("save {} - {}", v(x), v(y)),
+        LoadRange { x, y } => format!("load {} - {}", v(x), v(y)),
+        LoadImm { x, nn } => format!("{} := {nn:#04x}", v(x)),
+        AddImm { x, nn } => format!("{} += {nn:#04x}", v(x)),
+        Move { x, y } => format!("{} := {}", v(x), v(y)),
+        Or { x, y } => format!("{} |= {}", v(x), v(y)),
+        And { x, y } => format!("{} &= {}", v(x), v(y)),
+        Xor { x, y } => format!("{} ^= {}", v(x), v(y)),
+        Add { x, y } => format!("{} += {}", v(x), v(y)),
+        Sub { x, y } => format!("{} -= {}", v(x), v(y)),
+        ShiftRight { x, y } => format!("{} >>= {}", v(x), v(y)),
+        SubReverse { x, y } => format!("{} =- {}", v(x), v(y)),
+        ShiftLeft { x, y } => format!("{} <<= {}", v(x), v(y)),
+        SkipIfNotEqual { x, y } => format!("if {} == {} then", v(x), v(y)),
+        LoadIndex { nnn } => format!("i := {}", target(*nnn)),
+        JumpOffset { nnn } => format!("jump0 {}", target(*nnn)),
+        Random { x, nn } => format!("{} := random {nn:#04x}", v(x)),
+        Draw { x, y, n } => format!("sprite {} {} {n}", v(x), v(y)),
+        SkipIfKey { x } => format!("if {} -key then", v(x)),
+        SkipIfNotKey { x } => format!("if {} key then", v(x)),
+        LoadIndexLong { nnnn } => format!("i := long {}", target(*nnnn)),
+        SelectPlanes { n } => format!("plane {n}"),
+        LoadAudio => "audio".to_string(),
+        GetDelay { x } => format!("{} := delay", v(x)),
+        WaitKey { x } => format!("{} := key", v(x)),
+        SetDelay { x } => format!("delay := {}", v(x)),
+        SetSound { x } => format!("buzzer := {}", v(x)),
+        AddIndex { x } => format!("i += {}", v(x)),
+        FontChar { x } => format!("i := hex {}", v(x)),
+        BigFontChar { x } => format!("i := bighex {}", v(x)),
+        Bcd { x } => format!("bcd {}", v(x)),
+        SetPitch { x } => format!("pitch := {}", v(x)),
+        Store { x } => format!("save {}", v(x)),
+        Load { x } => format!("load {}", v(x)),
+        SaveFlags { x } => format!("saveflags {}", v(x)),
+        LoadFlags { x } => format!("loadflags {}", v(x)),
+        Unknown { opcode } => format!("{:#04x} {:#04x}", opcode >> 8, opcode & 0xFF),
+    }
+}
+
+fn cowgod(instruction: &Instruction, target: impl Fn(u16) -> String) -> String {
+    use Instruction::*;
+
+    let v = |x: &u8| format!("V{x:X}");
+
+    match instruction {
+        ScrollDown { n } => format!("SCD {n}"),
+        ScrollUp { n } => format!("SCU {n}"),
+        ClearScreen => "CLS".to_string(),
+        Return => "RET".to_string(),
+        ScrollRight => "SCR".to_string(),
+        ScrollLeft => "SCL".to_string(),
+        Exit => "EXIT".to_string(),
+        LowRes => "LOW".to_string(),
+        HighRes => "HIGH".to_string(),
+        MachineCall { nnn } => format!("SYS {}", target(*nnn)),
+        Jump { nnn } => format!("JP {}", target(*nnn)),
+        Call { nnn } => format!("CALL {}", target(*nnn)),
+        SkipIfEqualImm { x, nn } => format!("SE {}, #{nn:02X}", v(x)),
+        SkipIfNotEqualImm { x, nn } => format!("SNE {}, #{nn:02X}", v(x)),
+        SkipIfEqual { x, y } => format!("SE {}, {}", v(x), v(y)),
+        SaveRange { x, y } => format!("SAVE {}, {}", v(x), v(y)),
+        LoadRange { x, y } => format!("LOAD {}, {}", v(x), v(y)),
+        LoadImm { x, nn } => format!("LD {}, #{nn:02X}", v(x)),
+        AddImm { x, nn } => format!("ADD {}, #{nn:02X}", v(x)),
+        Move { x, y } => format!("LD {}, {}", v(x), v(y)),
+        Or { x, y } => format!("OR {}, {}", v(x), v(y)),
+        And { x, y } => format!("AND {}, {}", v(x), v(y)),
+        Xor { x, y } => format!("XOR {}, {}", v(x), v(y)),
+        Add { x, y } => format!("ADD {}, {}", v(x), v(y)),
+        Sub { x, y } => format!("SUB {}, {}", v(x), v(y)),
+        ShiftRight { x, y } => format!("SHR {}, {}", v(x), v(y)),
+        SubReverse { x, y } => format!("SUBN {}, {}", v(x), v(y)),
+        ShiftLeft { x, y } => format!("SHL {}, {}", v(x), v(y)),
+        SkipIfNotEqual { x, y } => format!("SNE {}, {}", v(x), v(y)),
+        LoadIndex { nnn } => format!("LD I, {}", target(*nnn)),
+        JumpOffset { nnn } => format!("JP V0, {}", target(*nnn)),
+        Random { x, nn } => format!("RND {}, #{nn:02X}", v(x)),
+        Draw { x, y, n } => format!("DRW {}, {}, {n}", v(x), v(y)),
+        SkipIfKey { x } => format!("SKP {}", v(x)),
+        SkipIfNotKey { x } => format!("SKNP {}", v(x)),
+        LoadIndexLong { nnnn } => format!("LD I, LONG {}", target(*nnnn)),
+        SelectPlanes { n } => format!("PLANE {n}"),
+        LoadAudio => "AUDIO".to_string(),
+        GetDelay { x } => format!("LD {}, DT", v(x)),
+        WaitKey { x } => format!("LD {}, K", v(x)),
+        SetDelay { x } => format!("LD DT, {}", v(x)),
+        SetSound { x } => format!("LD ST, {}", v(x)),
+        AddIndex { x } => format!("ADD I, {}", v(x)),
+        FontChar { x } => format!("LD F, {}", v(x)),
+        BigFontChar { x } => format!("LD HF, {}", v(x)),
+        Bcd { x } => format!("LD B, {}", v(x)),
+        SetPitch { x } => format!("PITCH {}", v(x)),
+        Store { x } => format!("LD [I], {}", v(x)),
+        Load { x } => format!("LD {}, [I]", v(x)),
+        SaveFlags { x } => format!("LD R, {}", v(x)),
+        LoadFlags { x } => format!("LD {}, R", v(x)),
+        Unknown { opcode } => format!("DW #{opcode:04X}"),
+    }
+}
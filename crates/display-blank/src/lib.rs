@@ -55,7 +55,18 @@ impl Display for BlankDisplay {
     }
 
     #[inline]
-    fn flip_pixel(&mut self, _x: u32, _y: u32) {
+    fn pixel_planes(&self, _x: u32, _y: u32) -> u8 {
+        0
+    }
+
+    #[inline]
+    fn flip_pixel(&mut self, _x: u32, _y: u32) -> bool {
+        // no-op
+        false
+    }
+
+    #[inline]
+    fn clear(&mut self) {
         // no-op
     }
 }
@@ -1,54 +1,41 @@
 //! The basic CHIP8 display.
 
-use display::Display;
-use image::{ImageBuffer, Pixel, RgbaImage};
+use std::ops::Range;
+
+use display::{Display, Palette, PlaneBuffer, PlaneImage};
+use image::RgbaImage;
 
 const WIDTH: u32 = 64;
 const HEIGHT: u32 = 32;
 
 /// The basic CHIP8 display.
 ///
-/// The CHIP8 display is black-and-white, and is 64 pixels wide and 32 pixels
-/// tall. Each pixel can be "on" or "off".
+/// The CHIP8 display is 64 pixels wide and 32 pixels tall. Each pixel can be
+/// "on" or "off", and is drawn in the first or second colour of its
+/// [`Palette`].
 ///
-/// Original interpreters updated the display at 60 Hz, but Rust Chip currently
-/// rerenders as fast as the host GPU allows. Someday, this
-/// will be changed to only rerender whenever an instruction is
-/// executed that updates the CHIP8 display.
+/// Like the original interpreters, the display is shown at 60 Hz. Drawing
+/// only touches the pixels, and the images are rendered from them once a
+/// frame, by [`PlaneImage::update()`], and only where they changed.
 #[derive(Clone, Debug)]
 pub struct Chip8Display {
-    /// While we *could* just use bitwise operations on numbers to represent
-    /// the display (since each pixel can only be on or off), we instead use
-    /// an [`image::RgbaImage`]. This is done for the following reasons:
-    ///
-    /// 1. It's easier to convert an [`image::RgbaImage`] to a GPU texture
-    ///    for rendering.
-    /// 2. This allows the future implementation of a multi-color display, such
-    ///    as that described by the XO-CHIP specification.
-    buf: RgbaImage,
+    /// The pixels, packed a row to a word so that sprites are drawn a row at
+    /// a time. Drawing only ever writes to this.
+    pixels: PlaneBuffer,
+
+    /// The images rendered from `pixels` once a frame, for the renderer.
+    image: PlaneImage,
 }
 
 impl Chip8Display {
-    /// Instantiate a new CHIP8 display.
+    /// Instantiate a new CHIP8 display, with every pixel off.
     pub fn new() -> Self {
         tracing::info!("Initializing CHIP8 display");
 
-        let mut buf: RgbaImage = ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
-            if (x % 2 == 0 && y % 2 != 0) || (x % 2 != 0 && y % 2 == 0) {
-                image::Rgba([0, 0, 0, 255])
-            } else {
-                image::Rgba([255, 255, 255, 255])
-            }
-        });
-
-        // Add some orientating pixels for testing purposes.
-
-        buf[(0, 0)] = image::Rgba([255, 0, 0, 255]); // top-left
-        buf[(63, 0)] = image::Rgba([0, 255, 0, 255]); // top-right
-        buf[(0, 31)] = image::Rgba([0, 0, 255, 255]); // bottom-left
-        buf[(63, 31)] = image::Rgba([255, 0, 255, 255]); // bottom-right
-
-        Self { buf }
+        Self {
+            pixels: PlaneBuffer::new(WIDTH, HEIGHT, 1),
+            image: PlaneImage::new(WIDTH, HEIGHT, Palette::default()),
+        }
     }
 }
 
@@ -67,12 +54,12 @@ impl Drop for Chip8Display {
 impl Display for Chip8Display {
     #[inline]
     fn dimensions(&self) -> (u32, u32) {
-        self.buf.dimensions()
+        self.pixels.dimensions()
     }
 
     #[inline]
     fn as_rgba8_image(&self) -> &RgbaImage {
-        &self.buf
+        self.image.image()
     }
 
     #[inline]
@@ -80,8 +67,69 @@ impl Display for Chip8Display {
         false
     }
 
-    fn flip_pixel(&mut self, x: u32, y: u32) {
-        let p = self.buf.get_pixel_mut(x % WIDTH, y % HEIGHT);
-        p.invert();
+    #[inline]
+    fn pixel_planes(&self, x: u32, y: u32) -> u8 {
+        self.pixels.get(x % WIDTH, y % HEIGHT)
+    }
+
+    #[inline]
+    fn flip_pixel(&mut self, x: u32, y: u32) -> bool {
+        self.xor_row(0, x, y, 0x8000, 1, false)
+    }
+
+    fn xor_row(&mut self, plane: u8, x: u32, y: u32, bits: u16, len: u32, clip: bool) -> bool {
+        if plane != 0 {
+            return false;
+        }
+
+        let (x, y) = (x % WIDTH, y % HEIGHT);
+        let len = len.min(16);
+        let bits = (bits as u64) << 48;
+
+        let mut collision = self.pixels.xor_row(0, x, y, bits, len);
+
+        // Whatever went past the right edge wraps around to the left.
+        let fits = WIDTH - x;
+        if !clip && len > fits {
+            collision |= self.pixels.xor_row(0, 0, y, bits << fits, len - fits);
+        }
+
+        self.image.mark_stale(y..y + 1);
+        collision
+    }
+
+    fn clear(&mut self) {
+        self.pixels.clear_planes(u8::MAX);
+        self.image.mark_stale(0..HEIGHT);
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.image.set_palette(palette);
+    }
+
+    #[inline]
+    fn plane_indices(&self) -> Option<&[u8]> {
+        Some(self.image.indices())
+    }
+
+    #[inline]
+    fn palette(&self) -> Option<&Palette> {
+        Some(self.image.palette())
+    }
+
+    #[inline]
+    fn update_image(&mut self) {
+        self.image.update(&self.pixels);
+    }
+
+    #[inline]
+    fn take_dirty_rows(&mut self) -> Option<Range<u32>> {
+        self.image.take_dirty_rows(&self.pixels)
+    }
+
+    #[inline]
+    fn end_frame(&mut self) -> bool {
+        self.update_image();
+        false
     }
 }
@@ -0,0 +1,344 @@
+//! A display filter that simulates phosphor persistence, to hide the flicker
+//! of CHIP8 games.
+//!
+//! CHIP8 games move sprites by drawing over them with XOR to erase them, and
+//! drawing them again somewhere else, so sprites are often missing from the
+//! frame that's shown. On the CRTs of the time, the phosphor kept glowing for a
+//! moment after a pixel was turned off, which hid most of that.
+
+use std::ops::Range;
+
+use display::{DirtyRows, Display, Palette};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// The decay that [`Persistence::default()`] uses.
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+/// How a [`PhosphorDisplay`] blends frames together.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Persistence {
+    /// Pixels light up straight away, but fade out over several frames when
+    /// they're turned off. `decay` is the fraction of a pixel's brightness
+    /// that's kept from one frame to the next, from 0 (no fading at all) to 1
+    /// (never fading).
+    ///
+    /// Each colour channel fades separately, and the brighter of the fading
+    /// pixel and what's drawn now wins, so this suits light-on-dark colours.
+    Fade { decay: f32 },
+
+    /// Show the average of the last two frames, so that sprites that are only
+    /// drawn every other frame are shown at half brightness instead of
+    /// flickering.
+    Blend,
+}
+
+impl Default for Persistence {
+    fn default() -> Self {
+        Persistence::Fade {
+            decay: DEFAULT_DECAY,
+        }
+    }
+}
+
+/// Wraps any [`Display`], and blends the frames it shows with earlier ones
+/// according to a [`Persistence`].
+///
+/// Drawing goes straight through to the wrapped display. Only the image
+/// returned by [`Display::as_rgba8_image()`] is filtered, and it moves on
+/// a frame every [`Display::end_frame()`]. Like the wrapped display's own
+/// images, it's only brought up to date with what's drawn in between by
+/// [`Display::update_image()`].
+#[derive(Debug)]
+pub struct PhosphorDisplay {
+    inner: Box<dyn Display>,
+    persistence: Persistence,
+
+    /// The red, green and blue of every pixel in earlier frames: the image
+    /// shown at the end of the last frame when fading, or the wrapped
+    /// display's own image then when blending.
+    afterglow: Vec<[f32; 3]>,
+
+    /// The filtered image.
+    buf: RgbaImage,
+
+    /// The rows that were drawn to since `buf` was last worked out.
+    stale: DirtyRows,
+
+    /// The rows of `buf` that changed since the renderer last uploaded it.
+    dirty: DirtyRows,
+}
+
+impl PhosphorDisplay {
+    pub fn new(mut inner: Box<dyn Display>, persistence: Persistence) -> Self {
+        inner.update_image();
+        let buf = inner.as_rgba8_image().clone();
+        let afterglow = buf.pixels().map(channels).collect();
+
+        Self {
+            inner,
+            persistence: clamp(persistence),
+            afterglow,
+            stale: DirtyRows::default(),
+            dirty: DirtyRows::all(buf.height()),
+            buf,
+        }
+    }
+
+    #[inline]
+    pub fn persistence(&self) -> Persistence {
+        self.persistence
+    }
+
+    /// The wrapped display.
+    #[inline]
+    pub fn inner(&self) -> &dyn Display {
+        self.inner.as_ref()
+    }
+
+    /// Work out the colour of one pixel, without moving on a frame. Returns
+    /// the unrounded colour, and true if the shown pixel changed.
+    fn refresh_pixel(&mut self, x: u32, y: u32) -> ([f32; 3], bool) {
+        let index = (y * self.buf.width() + x) as usize;
+        let current = *self.inner.as_rgba8_image().get_pixel(x, y);
+        let afterglow = self.afterglow[index];
+
+        let colour = match self.persistence {
+            Persistence::Fade { decay } => {
+                let current = channels(&current);
+                [0, 1, 2].map(|c| current[c].max(afterglow[c] * decay))
+            }
+            Persistence::Blend => {
+                let current = channels(&current);
+                [0, 1, 2].map(|c| (current[c] + afterglow[c]) / 2.0)
+            }
+        };
+
+        let [r, g, b] = colour.map(|c| c.round().clamp(0.0, 255.0) as u8);
+        let pixel = Rgba([r, g, b, current[3]]);
+        let changed = *self.buf.get_pixel(x, y) != pixel;
+        if changed {
+            self.buf.put_pixel(x, y, pixel);
+            self.dirty.mark_row(y);
+        }
+
+        (colour, changed)
+    }
+
+    /// Mark every row as drawn to.
+    fn mark_all_stale(&mut self) {
+        self.stale.mark(0..self.buf.height());
+    }
+
+    /// Mark the real rows covered by a row that was just drawn to, in the
+    /// wrapped display's current resolution.
+    fn mark_drawn(&mut self, y: u32) {
+        let height = self.inner.dimensions().1;
+        let res_height = self.inner.resolution().1;
+        let scale = height / res_height;
+        let y = y % res_height;
+        self.stale.mark(y * scale..(y + 1) * scale);
+    }
+}
+
+impl Display for PhosphorDisplay {
+    #[inline]
+    fn dimensions(&self) -> (u32, u32) {
+        self.inner.dimensions()
+    }
+
+    #[inline]
+    fn as_rgba8_image(&self) -> &RgbaImage {
+        &self.buf
+    }
+
+    #[inline]
+    fn is_srgb(&self) -> bool {
+        self.inner.is_srgb()
+    }
+
+    #[inline]
+    fn pixel_planes(&self, x: u32, y: u32) -> u8 {
+        self.inner.pixel_planes(x, y)
+    }
+
+    fn flip_pixel(&mut self, x: u32, y: u32) -> bool {
+        let was_on = self.inner.flip_pixel(x, y);
+        self.mark_drawn(y);
+        was_on
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        self.mark_all_stale();
+    }
+
+    #[inline]
+    fn resolution(&self) -> (u32, u32) {
+        self.inner.resolution()
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.inner.set_hires(hires);
+        self.mark_all_stale();
+    }
+
+    #[inline]
+    fn plane_count(&self) -> u8 {
+        self.inner.plane_count()
+    }
+
+    fn flip_plane_pixel(&mut self, plane: u8, x: u32, y: u32) -> bool {
+        let was_on = self.inner.flip_plane_pixel(plane, x, y);
+        self.mark_drawn(y);
+        was_on
+    }
+
+    fn xor_row(&mut self, plane: u8, x: u32, y: u32, bits: u16, len: u32, clip: bool) -> bool {
+        let collision = self.inner.xor_row(plane, x, y, bits, len, clip);
+        self.mark_drawn(y);
+        collision
+    }
+
+    fn clear_planes(&mut self, planes: u8) {
+        self.inner.clear_planes(planes);
+        self.mark_all_stale();
+    }
+
+    fn scroll_planes(&mut self, planes: u8, dx: i32, dy: i32) {
+        self.inner.scroll_planes(planes, dx, dy);
+        self.mark_all_stale();
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.inner.set_palette(palette);
+        self.inner.update_image();
+        self.stale = DirtyRows::default();
+
+        // Start afresh, rather than fading from the old colours.
+        self.buf = self.inner.as_rgba8_image().clone();
+        self.afterglow = self.buf.pixels().map(channels).collect();
+        self.dirty.mark(0..self.buf.height());
+    }
+
+    /// Work out the colour of the pixels in the rows drawn to, without moving
+    /// on a frame.
+    fn update_image(&mut self) {
+        self.inner.update_image();
+
+        if let Some(rows) = self.stale.take() {
+            let width = self.buf.width();
+            for y in rows {
+                for x in 0..width {
+                    self.refresh_pixel(x, y);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn take_dirty_rows(&mut self) -> Option<Range<u32>> {
+        self.update_image();
+        self.dirty.take()
+    }
+
+    fn end_frame(&mut self) -> bool {
+        // Every pixel is worked out again below.
+        let mut changed = self.inner.end_frame();
+        self.stale = DirtyRows::default();
+
+        let (width, height) = self.buf.dimensions();
+        for y in 0..height {
+            for x in 0..width {
+                let (colour, pixel_changed) = self.refresh_pixel(x, y);
+                changed |= pixel_changed;
+
+                let index = (y * width + x) as usize;
+                self.afterglow[index] = match self.persistence {
+                    Persistence::Fade { .. } => colour,
+                    Persistence::Blend => channels(self.inner.as_rgba8_image().get_pixel(x, y)),
+                };
+            }
+        }
+
+        changed
+    }
+}
+
+/// The red, green and blue of a pixel.
+#[inline]
+fn channels(pixel: &Rgba<u8>) -> [f32; 3] {
+    [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]
+}
+
+/// Keep the decay between 0 and 1, so that pixels never get brighter.
+fn clamp(persistence: Persistence) -> Persistence {
+    match persistence {
+        Persistence::Fade { decay } => Persistence::Fade {
+            decay: decay.clamp(0.0, 1.0),
+        },
+        Persistence::Blend => Persistence::Blend,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use display_chip8::Chip8Display;
+
+    use super::*;
+
+    fn display(persistence: Persistence) -> PhosphorDisplay {
+        let mut inner = Chip8Display::new();
+        inner.clear();
+        PhosphorDisplay::new(Box::new(inner), persistence)
+    }
+
+    fn red(display: &PhosphorDisplay, x: u32, y: u32) -> u8 {
+        display.as_rgba8_image().get_pixel(x, y)[0]
+    }
+
+    #[test]
+    fn pixels_fade_out() {
+        let mut display = display(Persistence::Fade { decay: 0.5 });
+
+        // Pixels light up straight away.
+        display.flip_pixel(3, 4);
+        display.update_image();
+        assert_eq!(red(&display, 3, 4), 255);
+        display.end_frame();
+
+        display.flip_pixel(3, 4);
+        display.update_image();
+        assert_eq!(red(&display, 3, 4), 128);
+        assert_eq!(display.pixel_planes(3, 4), 0);
+        display.end_frame();
+        assert_eq!(red(&display, 3, 4), 128);
+        display.end_frame();
+        assert_eq!(red(&display, 3, 4), 64);
+
+        // Eventually the pixel is completely off, and stays that way.
+        let frames = (0..20).take_while(|_| display.end_frame()).count();
+        assert!(frames < 20);
+        assert_eq!(red(&display, 3, 4), 0);
+        assert!(!display.end_frame());
+    }
+
+    #[test]
+    fn flickering_pixels_are_blended() {
+        let mut display = display(Persistence::Blend);
+
+        display.flip_pixel(0, 0);
+        display.end_frame();
+        for _ in 0..4 {
+            display.flip_pixel(0, 0);
+            display.end_frame();
+            assert_eq!(red(&display, 0, 0), 128);
+        }
+
+        // A pixel that stays on is shown at full brightness.
+        display.flip_pixel(1, 1);
+        display.end_frame();
+        display.end_frame();
+        assert_eq!(red(&display, 1, 1), 255);
+    }
+}
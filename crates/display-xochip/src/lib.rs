@@ -0,0 +1,247 @@
+//! The high-resolution, multi-plane display used by SUPER-CHIP and XO-CHIP.
+
+use std::ops::Range;
+
+use display::{Display, Palette, PlaneBuffer, PlaneImage};
+use image::RgbaImage;
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 64;
+
+/// The number of bit planes. XO-CHIP itself only specifies two planes (four
+/// colours), but Octo allows for up to four planes (sixteen colours), which is
+/// every colour in a [`Palette`].
+const PLANE_COUNT: u8 = 4;
+
+/// The SUPER-CHIP and XO-CHIP display.
+///
+/// The display is always 128 pixels wide and 64 pixels tall. In the
+/// low-resolution mode, programs draw to a 64x32 display, and every pixel they
+/// draw covers a 2x2 block of real pixels.
+///
+/// Each pixel can be on or off in each of several bit planes, and its colour is
+/// picked from the [`Palette`] based on the combination of planes it's on in.
+/// Programs that don't know about bit planes only ever draw to the first plane,
+/// which makes this a plain black-and-white display for SUPER-CHIP.
+#[derive(Clone, Debug)]
+pub struct XoChipDisplay {
+    /// The bit planes, packed so that sprites are drawn a row at a time.
+    /// Drawing only ever writes to this.
+    planes: PlaneBuffer,
+
+    hires: bool,
+
+    /// The images rendered from `planes` once a frame, for the renderer.
+    image: PlaneImage,
+}
+
+impl XoChipDisplay {
+    /// Instantiate a new display, in low-resolution mode with every pixel off.
+    pub fn new() -> Self {
+        tracing::info!("Initializing XO-CHIP display");
+
+        Self {
+            planes: PlaneBuffer::new(WIDTH, HEIGHT, PLANE_COUNT),
+            hires: false,
+            image: PlaneImage::new(WIDTH, HEIGHT, Palette::default()),
+        }
+    }
+
+    /// The size of a pixel in the current resolution, in real pixels.
+    #[inline]
+    fn scale(&self) -> u32 {
+        if self.hires {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// XOR pixels onto a row of one plane, in the current resolution, without
+    /// wrapping around. See [`PlaneBuffer::xor_row()`].
+    fn xor_span(&mut self, plane: u8, x: u32, y: u32, bits: u64, len: u32) -> bool {
+        let scale = self.scale();
+        let (bits, len) = if scale == 2 {
+            (double_bits(bits), len * 2)
+        } else {
+            (bits, len)
+        };
+        let (x, y) = (x * scale, y * scale);
+
+        // Every real row is the same, so collisions only need checking once.
+        let collision = self.planes.xor_row(plane, x, y, bits, len);
+        for dy in 1..scale {
+            self.planes.xor_row(plane, x, y + dy, bits, len);
+        }
+
+        self.image.mark_stale(y..y + scale);
+        collision
+    }
+}
+
+impl Default for XoChipDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for XoChipDisplay {
+    fn drop(&mut self) {
+        tracing::info!("Destroying XO-CHIP display");
+    }
+}
+
+impl Display for XoChipDisplay {
+    #[inline]
+    fn dimensions(&self) -> (u32, u32) {
+        self.planes.dimensions()
+    }
+
+    #[inline]
+    fn as_rgba8_image(&self) -> &RgbaImage {
+        self.image.image()
+    }
+
+    #[inline]
+    fn is_srgb(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn pixel_planes(&self, x: u32, y: u32) -> u8 {
+        self.planes.get(x % WIDTH, y % HEIGHT)
+    }
+
+    #[inline]
+    fn flip_pixel(&mut self, x: u32, y: u32) -> bool {
+        self.flip_plane_pixel(0, x, y)
+    }
+
+    fn clear(&mut self) {
+        self.clear_planes(u8::MAX);
+    }
+
+    #[inline]
+    fn resolution(&self) -> (u32, u32) {
+        (WIDTH / self.scale(), HEIGHT / self.scale())
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        // Modern SUPER-CHIP interpreters (and Octo) clear the display when
+        // switching resolutions.
+        self.hires = hires;
+        self.clear();
+    }
+
+    #[inline]
+    fn plane_count(&self) -> u8 {
+        PLANE_COUNT
+    }
+
+    #[inline]
+    fn flip_plane_pixel(&mut self, plane: u8, x: u32, y: u32) -> bool {
+        self.xor_row(plane, x, y, 0x8000, 1, false)
+    }
+
+    fn xor_row(&mut self, plane: u8, x: u32, y: u32, bits: u16, len: u32, clip: bool) -> bool {
+        if plane >= PLANE_COUNT {
+            return false;
+        }
+
+        let (width, height) = self.resolution();
+        let (x, y) = (x % width, y % height);
+        let len = len.min(16);
+        let bits = (bits as u64) << 48;
+
+        let mut collision = self.xor_span(plane, x, y, bits, len);
+
+        // Whatever went past the right edge wraps around to the left.
+        let fits = width - x;
+        if !clip && len > fits {
+            collision |= self.xor_span(plane, 0, y, bits << fits, len - fits);
+        }
+
+        collision
+    }
+
+    fn clear_planes(&mut self, planes: u8) {
+        self.planes.clear_planes(planes);
+        self.image.mark_stale(0..HEIGHT);
+    }
+
+    fn scroll_planes(&mut self, planes: u8, dx: i32, dy: i32) {
+        let scale = self.scale() as i32;
+        self.planes.scroll_planes(planes, dx * scale, dy * scale);
+        self.image.mark_stale(0..HEIGHT);
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.image.set_palette(palette);
+    }
+
+    #[inline]
+    fn plane_indices(&self) -> Option<&[u8]> {
+        Some(self.image.indices())
+    }
+
+    #[inline]
+    fn palette(&self) -> Option<&Palette> {
+        Some(self.image.palette())
+    }
+
+    #[inline]
+    fn update_image(&mut self) {
+        self.image.update(&self.planes);
+    }
+
+    #[inline]
+    fn take_dirty_rows(&mut self) -> Option<Range<u32>> {
+        self.image.take_dirty_rows(&self.planes)
+    }
+
+    #[inline]
+    fn end_frame(&mut self) -> bool {
+        self.update_image();
+        false
+    }
+}
+
+/// Double every pixel in the top half of a row of packed pixels, for drawing
+/// in the low-resolution mode.
+fn double_bits(bits: u64) -> u64 {
+    (0..32)
+        .filter(|i| bits & (1 << (63 - i)) != 0)
+        .fold(0, |doubled, i| doubled | 0b11 << (62 - 2 * i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_resolution_sprites_wrap_around() {
+        let mut display = XoChipDisplay::new();
+
+        // An 8-pixel row 4 pixels from the right edge of the 64x32 display.
+        assert!(!display.xor_row(1, 60, 31, 0xFF00, 8, false));
+        assert_eq!(display.pixel_planes(119, 62), 0);
+        assert_eq!(display.pixel_planes(120, 62), 0b10);
+        assert_eq!(display.pixel_planes(127, 63), 0b10);
+        assert_eq!(display.pixel_planes(7, 63), 0b10);
+        assert_eq!(display.pixel_planes(8, 63), 0);
+
+        // Drawing it again erases it.
+        assert!(display.xor_row(1, 60, 31, 0xFF00, 8, false));
+        display.update_image();
+        assert!(display
+            .plane_indices()
+            .unwrap()
+            .iter()
+            .all(|&mask| mask == 0));
+
+        // Clipped rows don't wrap.
+        display.xor_row(0, 60, 31, 0xFF00, 8, true);
+        assert_eq!(display.pixel_planes(0, 63), 0);
+        assert_eq!(display.pixel_planes(127, 63), 0b01);
+    }
+}
@@ -0,0 +1,65 @@
+//! Tracking which rows of a display's image changed, so that only those need
+//! to be uploaded to the GPU.
+
+use std::ops::Range;
+
+/// The span of rows of a display's image that changed since they were last
+/// taken. See [`crate::Display::take_dirty_rows()`].
+///
+/// A single span is kept rather than a list of rows, since sprites are drawn
+/// in runs of neighbouring rows and a copy to the GPU is cheapest in one go.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirtyRows(Option<Range<u32>>);
+
+impl DirtyRows {
+    /// Every row of an image `height` rows tall.
+    pub fn all(height: u32) -> Self {
+        let mut dirty = Self::default();
+        dirty.mark(0..height);
+        dirty
+    }
+
+    /// Mark some rows as changed.
+    #[inline]
+    pub fn mark(&mut self, rows: Range<u32>) {
+        if rows.is_empty() {
+            return;
+        }
+
+        self.0 = Some(match self.0.take() {
+            Some(dirty) => dirty.start.min(rows.start)..dirty.end.max(rows.end),
+            None => rows,
+        });
+    }
+
+    /// Mark a single row as changed.
+    #[inline]
+    pub fn mark_row(&mut self, y: u32) {
+        self.mark(y..y + 1);
+    }
+
+    /// Return the rows that changed, if any did, and start again with none.
+    #[inline]
+    pub fn take(&mut self) -> Option<Range<u32>> {
+        self.0.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_merged_into_one_span() {
+        let mut dirty = DirtyRows::default();
+        assert_eq!(dirty.take(), None);
+
+        dirty.mark_row(10);
+        dirty.mark(3..5);
+        dirty.mark(7..7);
+        assert_eq!(dirty.take(), Some(3..11));
+        assert_eq!(dirty.take(), None);
+
+        assert_eq!(DirtyRows::all(32).take(), Some(0..32));
+    }
+}
@@ -0,0 +1,236 @@
+//! Handing finished frames from the emulator thread to the renderer, without
+//! either thread ever waiting for the other.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use triple_buffer::{Input, Output, TripleBuffer};
+
+use crate::{DirtyRows, Display, Palette};
+
+/// A copy of a display's image at the end of a frame, as published by a
+/// [`FramePublisher`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// True if `data` holds the plane bitmask of every pixel, to be looked up
+    /// in `palette`, rather than RGBA colours. See
+    /// [`Display::plane_indices()`].
+    pub indexed: bool,
+    /// True if the RGBA colours are in the sRGB colour space.
+    pub is_srgb: bool,
+    /// Every pixel, row by row.
+    pub data: Vec<u8>,
+    /// The display's colours, if it has them.
+    pub palette: Option<Palette>,
+    /// The rows that changed since the last frame that the [`FrameReader`]
+    /// took, if any did.
+    pub dirty_rows: Option<Range<u32>>,
+}
+
+impl Frame {
+    /// The number of bytes in each row of `data`.
+    #[inline]
+    pub fn bytes_per_row(&self) -> usize {
+        let bytes_per_pixel = if self.indexed { 1 } else { 4 };
+        bytes_per_pixel * self.width as usize
+    }
+
+    /// The data for some rows of pixels.
+    pub fn rows(&self, rows: Range<u32>) -> &[u8] {
+        let row_bytes = self.bytes_per_row();
+        let start = (rows.start as usize * row_bytes).min(self.data.len());
+        let end = (rows.end as usize * row_bytes).min(self.data.len());
+        &self.data[start..end]
+    }
+
+    /// Copy a display's whole image into this frame, reusing its allocation.
+    fn copy_from(&mut self, display: &dyn Display) {
+        (self.width, self.height) = display.dimensions();
+        self.is_srgb = display.is_srgb();
+        self.palette = display.palette().copied();
+
+        self.data.clear();
+        match display.plane_indices() {
+            Some(indices) => {
+                self.indexed = true;
+                self.data.extend_from_slice(indices);
+            }
+            None => {
+                self.indexed = false;
+                self.data
+                    .extend_from_slice(display.as_rgba8_image().as_raw());
+            }
+        }
+    }
+}
+
+/// Create a triple buffer for handing frames from the emulator thread to the
+/// renderer.
+///
+/// The publisher always has a buffer of its own to copy the next frame into,
+/// and the reader always has the latest frame to itself, so neither side ever
+/// blocks. Frames that the reader doesn't get to in time are skipped.
+pub fn frame_channel() -> (FramePublisher, FrameReader) {
+    let (input, output) = TripleBuffer::default().split();
+    let wake_pending = Arc::new(AtomicBool::new(false));
+
+    (
+        FramePublisher {
+            input,
+            unread: DirtyRows::default(),
+            wake_pending: Arc::clone(&wake_pending),
+        },
+        FrameReader {
+            output,
+            wake_pending,
+        },
+    )
+}
+
+/// The sending half of [`frame_channel()`], used by the emulator thread.
+#[derive(Debug)]
+pub struct FramePublisher {
+    input: Input<Frame>,
+    /// The rows that changed since the last frame that the reader is known to
+    /// have taken. Frames the reader skips still have to be uploaded, so their
+    /// rows are carried over into the next frame.
+    unread: DirtyRows,
+    /// Set once the reader has been told about a frame, until it takes one.
+    /// See [`FramePublisher::publish()`].
+    wake_pending: Arc<AtomicBool>,
+}
+
+impl FramePublisher {
+    /// Copy the display's image, and publish it as the latest frame. This
+    /// takes the display's dirty rows. See [`Display::take_dirty_rows()`].
+    ///
+    /// Returns true if the reader should be woken up to take the frame. This
+    /// only happens once until the reader takes a frame, so that a reader that
+    /// takes frames once per vsync is woken at most once per vsync.
+    pub fn publish(&mut self, display: &mut dyn Display) -> bool {
+        let rows = display.take_dirty_rows();
+
+        let mut dirty = self.unread.clone();
+        if let Some(rows) = rows.clone() {
+            dirty.mark(rows);
+        }
+
+        let frame = self.input.input_buffer();
+        frame.copy_from(display);
+        frame.dirty_rows = dirty.clone().take();
+
+        if self.input.publish() {
+            // The last frame was never taken, so the reader is further behind.
+            self.unread = dirty;
+        } else {
+            self.unread = DirtyRows::default();
+            if let Some(rows) = rows {
+                self.unread.mark(rows);
+            }
+        }
+
+        !self.wake_pending.swap(true, Ordering::AcqRel)
+    }
+}
+
+/// The receiving half of [`frame_channel()`], used by the renderer.
+#[derive(Debug)]
+pub struct FrameReader {
+    output: Output<Frame>,
+    wake_pending: Arc<AtomicBool>,
+}
+
+impl FrameReader {
+    /// Take the latest frame, if one was published since the last call. This
+    /// never blocks.
+    pub fn take(&mut self) -> Option<&Frame> {
+        // Rearm the wake-up first, so that a frame published right after the
+        // update below still wakes the reader.
+        self.wake_pending.store(false, Ordering::Release);
+
+        if self.output.update() {
+            Some(self.output.output_buffer())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, RgbaImage};
+
+    use super::*;
+
+    /// A display that reports whatever rows it's told to.
+    #[derive(Debug)]
+    struct TestDisplay {
+        buf: RgbaImage,
+        dirty: DirtyRows,
+    }
+
+    impl Display for TestDisplay {
+        fn dimensions(&self) -> (u32, u32) {
+            self.buf.dimensions()
+        }
+
+        fn as_rgba8_image(&self) -> &RgbaImage {
+            &self.buf
+        }
+
+        fn is_srgb(&self) -> bool {
+            false
+        }
+
+        fn pixel_planes(&self, _x: u32, _y: u32) -> u8 {
+            0
+        }
+
+        fn flip_pixel(&mut self, _x: u32, _y: u32) -> bool {
+            false
+        }
+
+        fn clear(&mut self) {}
+
+        fn take_dirty_rows(&mut self) -> Option<Range<u32>> {
+            self.dirty.take()
+        }
+    }
+
+    #[test]
+    fn skipped_frames_carry_their_rows_over() {
+        let (mut publisher, mut reader) = frame_channel();
+        let mut display = TestDisplay {
+            buf: ImageBuffer::new(4, 8),
+            dirty: DirtyRows::all(8),
+        };
+        assert_eq!(reader.take(), None);
+
+        assert!(publisher.publish(&mut display));
+        let frame = reader.take().unwrap();
+        assert_eq!((frame.width, frame.height), (4, 8));
+        assert_eq!(frame.data.len(), 4 * 4 * 8);
+        assert_eq!(frame.dirty_rows, Some(0..8));
+        assert_eq!(reader.take(), None);
+
+        // The reader only wakes up once for two frames, and gets the rows that
+        // changed in both.
+        display.dirty.mark_row(1);
+        assert!(publisher.publish(&mut display));
+        display.dirty.mark_row(5);
+        assert!(!publisher.publish(&mut display));
+        assert_eq!(reader.take().unwrap().dirty_rows, Some(1..6));
+
+        // The publisher only finds out that the reader took a frame when it
+        // publishes the next one, so that one still has the skipped rows.
+        display.dirty.mark_row(3);
+        assert!(publisher.publish(&mut display));
+        assert_eq!(reader.take().unwrap().dirty_rows, Some(1..6));
+        display.dirty.mark_row(7);
+        assert!(publisher.publish(&mut display));
+        assert_eq!(reader.take().unwrap().dirty_rows, Some(3..8));
+    }
+}
@@ -1,8 +1,21 @@
 use std::fmt;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
 
 use image::RgbaImage;
 
+mod dirty;
+mod frame;
+mod palette;
+mod planes;
+mod screenshot;
+
+pub use dirty::DirtyRows;
+pub use frame::{frame_channel, Frame, FramePublisher, FrameReader};
+pub use palette::{Palette, PalettePreset, ParsePalettePresetError, PALETTE_SIZE};
+pub use planes::{PlaneBuffer, PlaneImage};
+pub use screenshot::{capture_file_name, save_screenshot, screenshot, upscale, MAX_SCALE};
+
 /// A [`Display`] that can be synchronized between threads. The display may or
 /// may not even exist.
 pub type DisplayRef = Arc<Mutex<Option<Box<dyn Display>>>>;
@@ -12,6 +25,10 @@ pub type DisplayRef = Arc<Mutex<Option<Box<dyn Display>>>>;
 /// This allows for the implementation of _multiple_ different sorts of CHIP8
 /// displays, from the base, black-and-white 64x32 original display to the
 /// upgraded, multicolour XO-CHIP display.
+///
+/// All coordinates passed to a display are in terms of its current
+/// [`Display::resolution()`], which may be smaller than the size of the image
+/// returned by [`Display::as_rgba8_image()`].
 pub trait Display: Send + Sync + fmt::Debug {
     /// Return the dimensions of the CHIP8 display as a pair of `(width, height)`.
     fn dimensions(&self) -> (u32, u32);
@@ -23,10 +40,182 @@ pub trait Display: Send + Sync + fmt::Debug {
     /// it's in the regular, linear RGB colour space.
     fn is_srgb(&self) -> bool;
 
+    /// Return the bitmask of the bit planes that a pixel is on in, for testing
+    /// and debugging. Unlike every other method, the coordinates are in terms of
+    /// [`Display::dimensions()`], not the current resolution.
+    fn pixel_planes(&self, x: u32, y: u32) -> u8;
+
     /// Flip a pixel at some location.
     ///
+    /// Returns true if the pixel was turned off by the flip, which CHIP8
+    /// programs use for collision detection.
+    ///
     /// Out-of-bounds accesses will be silently ignored, for the sake of emulator
     /// stability. Generally, [`Display`] implementations will use some form of
     /// wrap-around to accomplish this.
-    fn flip_pixel(&mut self, x: u32, y: u32);
+    fn flip_pixel(&mut self, x: u32, y: u32) -> bool;
+
+    /// Turn every pixel off.
+    fn clear(&mut self);
+
+    /// Return the resolution that CHIP8 programs currently draw at, as a pair
+    /// of `(width, height)`.
+    ///
+    /// Displays that can switch between resolutions may scale this up to fill
+    /// [`Display::dimensions()`].
+    #[inline]
+    fn resolution(&self) -> (u32, u32) {
+        self.dimensions()
+    }
+
+    /// Switch between the SUPER-CHIP high-resolution and low-resolution modes.
+    ///
+    /// This is a no-op for displays without a high-resolution mode.
+    #[inline]
+    fn set_hires(&mut self, _hires: bool) {}
+
+    /// The number of bit planes this display has. Each plane is an independent
+    /// layer of on-or-off pixels, with the colour of a pixel determined by the
+    /// combination of planes it's on in.
+    #[inline]
+    fn plane_count(&self) -> u8 {
+        1
+    }
+
+    /// Flip a pixel at some location in one bit plane. See [`Display::flip_pixel()`].
+    ///
+    /// Flips in planes that don't exist are ignored.
+    #[inline]
+    fn flip_plane_pixel(&mut self, plane: u8, x: u32, y: u32) -> bool {
+        if plane == 0 {
+            self.flip_pixel(x, y)
+        } else {
+            false
+        }
+    }
+
+    /// XOR a row of a sprite onto one bit plane, starting at some location and
+    /// going right. `bits` holds up to 16 pixels with the leftmost in its most
+    /// significant bit, and `len` is how many of them there are.
+    ///
+    /// Pixels past the right edge wrap around to the left, or are dropped if
+    /// `clip` is set. Returns true if any pixel was turned off by the XOR.
+    ///
+    /// Displays that store their planes as packed bits should override this to
+    /// draw the whole row at once. By default, pixels are flipped one by one.
+    fn xor_row(&mut self, plane: u8, x: u32, y: u32, bits: u16, len: u32, clip: bool) -> bool {
+        let (width, height) = self.resolution();
+        let mut collision = false;
+
+        for i in 0..len.min(16) {
+            if bits & (0x8000 >> i) == 0 {
+                continue;
+            }
+
+            let px = x + i;
+            if clip && px >= width {
+                break;
+            }
+
+            collision |= self.flip_plane_pixel(plane, px % width, y % height);
+        }
+
+        collision
+    }
+
+    /// Turn every pixel off in the bit planes selected by the `planes` bitmask.
+    #[inline]
+    fn clear_planes(&mut self, planes: u8) {
+        if planes & 1 != 0 {
+            self.clear();
+        }
+    }
+
+    /// Scroll the bit planes selected by the `planes` bitmask by some amount of
+    /// pixels. Positive values scroll right and down. Pixels scrolled off the
+    /// edge of the display are lost.
+    ///
+    /// This is a no-op for displays without scrolling support.
+    #[inline]
+    fn scroll_planes(&mut self, _planes: u8, _dx: i32, _dy: i32) {}
+
+    /// Change the colours that the display draws with. Pixels that are already
+    /// drawn are recoloured straight away.
+    ///
+    /// This is a no-op for displays that don't draw anything.
+    #[inline]
+    fn set_palette(&mut self, _palette: &Palette) {}
+
+    /// Return the plane bitmask of every pixel in [`Display::dimensions()`], one
+    /// byte each, row by row, if the display keeps them. Together with
+    /// [`Display::palette()`], this lets the renderer upload a quarter of the
+    /// data of [`Display::as_rgba8_image()`] and look the colours up on the
+    /// GPU.
+    #[inline]
+    fn plane_indices(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Return the colours that [`Display::plane_indices()`] map to, if the
+    /// display has them.
+    #[inline]
+    fn palette(&self) -> Option<&Palette> {
+        None
+    }
+
+    /// Bring [`Display::as_rgba8_image()`] and [`Display::plane_indices()`] up
+    /// to date with what's been drawn.
+    ///
+    /// Displays that keep their pixels packed into a [`PlaneBuffer`] only
+    /// render them into those images once a frame, in [`Display::end_frame()`]
+    /// and [`Display::take_dirty_rows()`]. Anything else that reads the images
+    /// partway through a frame should call this first. It's a no-op for
+    /// displays that draw straight into their images.
+    #[inline]
+    fn update_image(&mut self) {}
+
+    /// Return the rows of [`Display::as_rgba8_image()`] and
+    /// [`Display::plane_indices()`] that changed since the last call, or `None`
+    /// if nothing did, after bringing them up to date. A [`FramePublisher`]
+    /// uses this to hand only what changed to the renderer, so nothing else
+    /// should call it.
+    ///
+    /// Displays that don't keep track report every row, every time.
+    #[inline]
+    fn take_dirty_rows(&mut self) -> Option<Range<u32>> {
+        Some(0..self.dimensions().1)
+    }
+
+    /// Called at the end of every 60 Hz frame, for displays whose image
+    /// changes over time even when nothing is drawn, and for displays that
+    /// bring their images up to date once a frame. See
+    /// [`Display::update_image()`].
+    ///
+    /// Returns true if the image changed over time.
+    #[inline]
+    fn end_frame(&mut self) -> bool {
+        false
+    }
+}
+
+/// Copy what's drawn on one display onto another, which is cleared first. The
+/// displays should be the same size.
+///
+/// This goes through the bit planes, so the colours come from the new display.
+pub fn copy_pixels(from: &dyn Display, to: &mut dyn Display) {
+    let (width, height) = from.dimensions();
+    let (res_width, res_height) = from.resolution();
+    let (scale_x, scale_y) = (width / res_width, height / res_height);
+
+    to.set_hires(from.resolution() != (width / 2, height / 2));
+    to.clear();
+
+    for y in 0..res_height {
+        for x in 0..res_width {
+            let planes = from.pixel_planes(x * scale_x, y * scale_y);
+            for plane in (0..8).filter(|plane| planes & (1 << plane) != 0) {
+                to.flip_plane_pixel(plane, x, y);
+            }
+        }
+    }
 }
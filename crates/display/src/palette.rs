@@ -0,0 +1,187 @@
+//! The colours that displays draw with.
+
+use std::str::FromStr;
+
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoEnumIterator};
+use thiserror::Error;
+
+/// The number of colours in a palette: one for every combination of the four
+/// bit planes that Octo allows.
+pub const PALETTE_SIZE: usize = 16;
+
+/// The colours for palette entries 4 to 15, for pixels on in the third or
+/// fourth plane, that most presets share. Programs that use more than two
+/// planes are rare, so presets only pick their first four colours.
+#[rustfmt::skip]
+const EXTRA_COLOURS: [[u8; 3]; PALETTE_SIZE - 4] = [
+    [0xFF, 0x00, 0x00], [0x00, 0xFF, 0x00],
+    [0x00, 0x00, 0xFF], [0xFF, 0xFF, 0x00],
+    [0x88, 0x00, 0x00], [0x00, 0x88, 0x00],
+    [0x00, 0x00, 0x88], [0x88, 0x88, 0x00],
+    [0xFF, 0x00, 0xFF], [0x00, 0xFF, 0xFF],
+    [0x88, 0x00, 0x88], [0x00, 0x88, 0x88],
+];
+
+/// The colours a display draws with, indexed by the bitmask of the bit planes
+/// a pixel is on in.
+///
+/// Colour 0 is the background, and colour 1 is used for pixels on the first
+/// plane, which is all that CHIP8 and SUPER-CHIP programs draw to. XO-CHIP
+/// programs use the first four colours, or all sixteen with Octo's extra
+/// planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Palette {
+    pub colours: [[u8; 3]; PALETTE_SIZE],
+}
+
+impl Palette {
+    /// A palette with four colours of its own, and the usual colours for the
+    /// rest.
+    pub const fn with_four(colours: [[u8; 3]; 4]) -> Self {
+        let mut all = [[0; 3]; PALETTE_SIZE];
+
+        let mut i = 0;
+        while i < PALETTE_SIZE {
+            all[i] = if i < 4 {
+                colours[i]
+            } else {
+                EXTRA_COLOURS[i - 4]
+            };
+            i += 1;
+        }
+
+        Self { colours: all }
+    }
+
+    /// The colour of a pixel that's on in the planes selected by the `planes`
+    /// bitmask. Planes past the fourth are ignored.
+    #[inline]
+    pub fn colour(&self, planes: u8) -> Rgba<u8> {
+        let [r, g, b] = self.colours[planes as usize % PALETTE_SIZE];
+        Rgba([r, g, b, 0xFF])
+    }
+
+    /// The preset that this palette is, if it's one of them.
+    pub fn preset(&self) -> Option<PalettePreset> {
+        PalettePreset::iter().find(|preset| preset.palette() == *self)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PalettePreset::default().palette()
+    }
+}
+
+/// The built-in palettes.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Display, EnumIter,
+)]
+pub enum PalettePreset {
+    /// White on black, with greys for XO-CHIP's other two colours.
+    #[default]
+    Classic,
+    /// An amber monochrome monitor.
+    Amber,
+    /// A green phosphor monochrome monitor.
+    #[strum(serialize = "Green phosphor")]
+    GreenPhosphor,
+    /// A greenish handheld LCD, with dark pixels on a light background.
+    #[strum(serialize = "LCD")]
+    Lcd,
+    /// The colours that Octo starts with.
+    Octo,
+    /// Colours that stay easy to tell apart with any kind of colour blindness,
+    /// from Okabe and Ito's palette.
+    #[strum(serialize = "High contrast")]
+    HighContrast,
+}
+
+impl PalettePreset {
+    pub fn palette(self) -> Palette {
+        match self {
+            PalettePreset::Classic => Palette::with_four([
+                [0x00, 0x00, 0x00],
+                [0xFF, 0xFF, 0xFF],
+                [0xAA, 0xAA, 0xAA],
+                [0x55, 0x55, 0x55],
+            ]),
+            PalettePreset::Amber => Palette::with_four([
+                [0x1A, 0x0F, 0x00],
+                [0xFF, 0xB0, 0x00],
+                [0xB3, 0x7B, 0x00],
+                [0x66, 0x46, 0x00],
+            ]),
+            PalettePreset::GreenPhosphor => Palette::with_four([
+                [0x05, 0x14, 0x05],
+                [0x33, 0xFF, 0x33],
+                [0x22, 0xAA, 0x22],
+                [0x11, 0x66, 0x11],
+            ]),
+            PalettePreset::Lcd => Palette::with_four([
+                [0x9B, 0xBC, 0x0F],
+                [0x0F, 0x38, 0x0F],
+                [0x8B, 0xAC, 0x0F],
+                [0x30, 0x62, 0x30],
+            ]),
+            PalettePreset::Octo => Palette::with_four([
+                [0x99, 0x66, 0x00],
+                [0xFF, 0xCC, 0x00],
+                [0xFF, 0x66, 0x00],
+                [0x66, 0x22, 0x00],
+            ]),
+            #[rustfmt::skip]
+            PalettePreset::HighContrast => Palette {
+                colours: [
+                    [0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF],
+                    [0xE6, 0x9F, 0x00], [0x56, 0xB4, 0xE9],
+                    [0x00, 0x9E, 0x73], [0xF0, 0xE4, 0x42],
+                    [0x00, 0x72, 0xB2], [0xD5, 0x5E, 0x00],
+                    [0xCC, 0x79, 0xA7], [0x99, 0x99, 0x99],
+                    [0x7F, 0x4F, 0x00], [0x2B, 0x5A, 0x75],
+                    [0x00, 0x4F, 0x3A], [0x78, 0x72, 0x21],
+                    [0x00, 0x39, 0x59], [0x6A, 0x2F, 0x00],
+                ],
+            },
+        }
+    }
+}
+
+impl FromStr for PalettePreset {
+    type Err = ParsePalettePresetError;
+
+    /// Parse a preset from its name, ignoring case, spaces and dashes, like
+    /// `classic` or `green-phosphor`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalize = |s: &str| s.to_ascii_lowercase().replace(['-', ' ', '_'], "");
+        let name = normalize(s);
+
+        PalettePreset::iter()
+            .find(|preset| normalize(&preset.to_string()) == name)
+            .ok_or_else(|| ParsePalettePresetError(s.to_string()))
+    }
+}
+
+/// The error returned when parsing an unknown palette preset name.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error(
+    "Unknown palette \"{0}\". Expected classic, amber, green-phosphor, lcd, octo or high-contrast"
+)]
+pub struct ParsePalettePresetError(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_parse_from_their_names() {
+        for preset in PalettePreset::iter() {
+            assert_eq!(preset.to_string().parse(), Ok(preset));
+            assert_eq!(preset.palette().preset(), Some(preset));
+        }
+        assert_eq!("green-phosphor".parse(), Ok(PalettePreset::GreenPhosphor));
+        assert!("sepia".parse::<PalettePreset>().is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! A compact framebuffer that stores each bit plane as packed bits, and the
+//! images rendered from it.
+
+use std::ops::Range;
+
+use image::{ImageBuffer, RgbaImage};
+
+use crate::{DirtyRows, Palette};
+
+/// The number of pixels in a word of a [`PlaneBuffer`].
+const WORD_BITS: u32 = u64::BITS;
+
+/// A framebuffer that stores every bit plane as rows of packed bits, so that
+/// sprites can be drawn by XORing a whole row at a time.
+///
+/// Planes are stored one after another, each as rows of whole words, with the
+/// leftmost pixel of each word in its most significant bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaneBuffer {
+    width: u32,
+    height: u32,
+    plane_count: u8,
+    words_per_row: usize,
+    words: Vec<u64>,
+}
+
+impl PlaneBuffer {
+    /// Create a framebuffer with every pixel off.
+    pub fn new(width: u32, height: u32, plane_count: u8) -> Self {
+        let words_per_row = width.div_ceil(WORD_BITS) as usize;
+
+        Self {
+            width,
+            height,
+            plane_count,
+            words_per_row,
+            words: vec![0; words_per_row * height as usize * plane_count as usize],
+        }
+    }
+
+    #[inline]
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    #[inline]
+    pub fn plane_count(&self) -> u8 {
+        self.plane_count
+    }
+
+    /// The index of the first word of a row of a plane.
+    #[inline]
+    fn row_start(&self, plane: u8, y: u32) -> usize {
+        (plane as usize * self.height as usize + y as usize) * self.words_per_row
+    }
+
+    /// Return the bitmask of the planes that a pixel is on in.
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        let word = (x / WORD_BITS) as usize;
+        let bit = WORD_BITS - 1 - x % WORD_BITS;
+
+        (0..self.plane_count).fold(0, |mask, plane| {
+            let on = self.words[self.row_start(plane, y) + word] >> bit & 1;
+            mask | (on as u8) << plane
+        })
+    }
+
+    /// XOR up to 64 pixels onto a row of one plane, starting at `x` and going
+    /// right. `bits` holds the pixels with the leftmost in its most significant
+    /// bit, and `len` is how many of them there are.
+    ///
+    /// Pixels past the right edge are dropped, so callers that want them to
+    /// wrap around have to XOR them separately. Returns true if any pixel was
+    /// turned off.
+    pub fn xor_row(&mut self, plane: u8, x: u32, y: u32, bits: u64, len: u32) -> bool {
+        if plane >= self.plane_count || y >= self.height || x >= self.width || len == 0 {
+            return false;
+        }
+
+        // Drop pixels past the end of the sprite, and past the right edge.
+        let len = len.min(WORD_BITS).min(self.width - x);
+        let bits = bits & (u64::MAX << (WORD_BITS - len));
+
+        let start = self.row_start(plane, y);
+        let word = (x / WORD_BITS) as usize;
+        let shift = x % WORD_BITS;
+
+        // The row can straddle two words.
+        let mut collision = self.xor_word(start + word, bits >> shift);
+        if shift > 0 && word + 1 < self.words_per_row {
+            collision |= self.xor_word(start + word + 1, bits << (WORD_BITS - shift));
+        }
+
+        collision
+    }
+
+    #[inline]
+    fn xor_word(&mut self, index: usize, bits: u64) -> bool {
+        let old = self.words[index];
+        self.words[index] = old ^ bits;
+        old & bits != 0
+    }
+
+    /// Turn every pixel off in the planes selected by the `planes` bitmask.
+    pub fn clear_planes(&mut self, planes: u8) {
+        for plane in (0..self.plane_count).filter(|plane| planes & (1 << plane) != 0) {
+            let start = self.row_start(plane, 0);
+            let end = self.row_start(plane, self.height);
+            self.words[start..end].fill(0);
+        }
+    }
+
+    /// Scroll the planes selected by the `planes` bitmask by some amount of
+    /// pixels. Positive values scroll right and down. Pixels scrolled off the
+    /// edge are lost.
+    pub fn scroll_planes(&mut self, planes: u8, dx: i32, dy: i32) {
+        let words_per_row = self.words_per_row;
+        let height = self.height as usize;
+        let (width, plane_count) = (self.width, self.plane_count);
+
+        for plane in (0..plane_count).filter(|plane| planes & (1 << plane) != 0) {
+            let start = self.row_start(plane, 0);
+            let end = self.row_start(plane, self.height);
+            let words = &mut self.words[start..end];
+
+            // Move whole rows up or down, then clear the rows left behind.
+            let rows = (dy.unsigned_abs() as usize).min(height);
+            let moved = (height - rows) * words_per_row;
+            let gap = rows * words_per_row;
+            if dy > 0 {
+                words.copy_within(..moved, gap);
+                words[..gap].fill(0);
+            } else if dy < 0 {
+                words.copy_within(gap.., 0);
+                words[moved..].fill(0);
+            }
+
+            if dx != 0 {
+                for row in words.chunks_exact_mut(words_per_row) {
+                    shift_row(row, dx, width);
+                }
+            }
+        }
+    }
+
+    /// Write the plane bitmask of every pixel in a row to `out`, which must be
+    /// as long as the row.
+    pub fn row_masks(&self, y: u32, out: &mut [u8]) {
+        out.fill(0);
+
+        for plane in 0..self.plane_count {
+            let start = self.row_start(plane, y);
+            let row = &self.words[start..start + self.words_per_row];
+
+            for (x, mask) in out.iter_mut().enumerate() {
+                let word = row[x / WORD_BITS as usize];
+                let bit = WORD_BITS as usize - 1 - x % WORD_BITS as usize;
+                *mask |= ((word >> bit & 1) as u8) << plane;
+            }
+        }
+    }
+}
+
+/// Shift a row of words some amount of pixels, carrying bits between adjacent
+/// words. Positive values shift right. Pixels shifted past either edge of a
+/// row `width` pixels wide are lost.
+fn shift_row(row: &mut [u64], dx: i32, width: u32) {
+    let words = (dx.unsigned_abs() / WORD_BITS) as usize;
+    let bits = dx.unsigned_abs() % WORD_BITS;
+    let word = |row: &[u64], i: Option<usize>| i.and_then(|i| row.get(i)).copied().unwrap_or(0);
+
+    if dx > 0 {
+        // Work from the right, so that every word is read before it's written.
+        for i in (0..row.len()).rev() {
+            let high = word(row, i.checked_sub(words));
+            let low = word(row, i.checked_sub(words + 1));
+            row[i] = high >> bits | low.checked_shl(WORD_BITS - bits).unwrap_or(0);
+        }
+    } else {
+        for i in 0..row.len() {
+            let low = word(row, Some(i + words));
+            let high = word(row, Some(i + words + 1));
+            row[i] = low << bits | high.checked_shr(WORD_BITS - bits).unwrap_or(0);
+        }
+    }
+
+    // Drop pixels shifted past the right edge, into the last word's padding.
+    let padding = row.len() as u32 * WORD_BITS - width;
+    if let Some(last) = row.last_mut() {
+        *last &= u64::MAX.checked_shl(padding).unwrap_or(0);
+    }
+}
+
+/// The images that a display keeping its pixels in a [`PlaneBuffer`] hands to
+/// the renderer: the plane bitmask of every pixel, and its colour.
+///
+/// Drawing only marks the rows it touches as stale. They're rendered from the
+/// planes in one go by [`PlaneImage::update()`], which displays call once a
+/// frame, so that drawing a sprite is just a few word XORs.
+#[derive(Debug, Clone)]
+pub struct PlaneImage {
+    palette: Palette,
+    /// For every pixel, a bitmask of the planes that it's on in.
+    indices: Vec<u8>,
+    /// The colour of every pixel, since it's easy to convert an
+    /// [`image::RgbaImage`] to a GPU texture.
+    buf: RgbaImage,
+    /// The rows that were drawn to since the images were last updated.
+    stale: DirtyRows,
+    /// The rows of the images that changed since they were last taken. See
+    /// [`crate::Display::take_dirty_rows()`].
+    dirty: DirtyRows,
+}
+
+impl PlaneImage {
+    /// Create images for a framebuffer with every pixel off.
+    pub fn new(width: u32, height: u32, palette: Palette) -> Self {
+        Self {
+            palette,
+            indices: vec![0; (width * height) as usize],
+            buf: ImageBuffer::from_pixel(width, height, palette.colour(0)),
+            stale: DirtyRows::default(),
+            dirty: DirtyRows::all(height),
+        }
+    }
+
+    /// Mark some rows as drawn to, so that they're rendered again by the next
+    /// [`PlaneImage::update()`].
+    #[inline]
+    pub fn mark_stale(&mut self, rows: Range<u32>) {
+        self.stale.mark(rows);
+    }
+
+    /// Render the stale rows from `planes`, which must be the same size as
+    /// the images.
+    pub fn update(&mut self, planes: &PlaneBuffer) {
+        let rows = match self.stale.take() {
+            Some(rows) => rows,
+            None => return,
+        };
+
+        let width = self.buf.width();
+        for y in rows.clone() {
+            let start = (y * width) as usize;
+            let indices = &mut self.indices[start..start + width as usize];
+            planes.row_masks(y, indices);
+
+            for (x, &mask) in indices.iter().enumerate() {
+                self.buf[(x as u32, y)] = self.palette.colour(mask);
+            }
+        }
+
+        self.dirty.mark(rows);
+    }
+
+    /// Change the colours, recolouring every pixel.
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette;
+        for (p, &mask) in self.buf.pixels_mut().zip(&self.indices) {
+            *p = palette.colour(mask);
+        }
+        self.dirty.mark(0..self.buf.height());
+    }
+
+    #[inline]
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    #[inline]
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    #[inline]
+    pub fn image(&self) -> &RgbaImage {
+        &self.buf
+    }
+
+    /// Return the rows that changed since the last call, after updating the
+    /// images from `planes`.
+    pub fn take_dirty_rows(&mut self, planes: &PlaneBuffer) -> Option<Range<u32>> {
+        self.update(planes);
+        self.dirty.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_xored_across_words() {
+        let mut buffer = PlaneBuffer::new(128, 64, 2);
+
+        // A 16-pixel row straddling the two words of a row.
+        assert!(!buffer.xor_row(1, 56, 3, 0xFF00_FF00 << 32, 24));
+        assert_eq!(buffer.get(55, 3), 0);
+        assert_eq!(buffer.get(56, 3), 0b10);
+        assert_eq!(buffer.get(64, 3), 0);
+        assert_eq!(buffer.get(72, 3), 0b10);
+        assert_eq!(buffer.get(79, 3), 0b10);
+        assert_eq!(buffer.get(80, 3), 0);
+
+        // Drawing it again erases it, which is a collision.
+        assert!(buffer.xor_row(1, 56, 3, 0xFF00_FF00 << 32, 24));
+        assert!((0..128).all(|x| buffer.get(x, 3) == 0));
+
+        // Pixels past the right edge are dropped.
+        assert!(!buffer.xor_row(0, 124, 0, u64::MAX, 8));
+        let mut row = [0; 128];
+        buffer.row_masks(0, &mut row);
+        assert_eq!(row.iter().filter(|&&mask| mask == 1).count(), 4);
+    }
+
+    #[test]
+    fn planes_scroll_and_clear_separately() {
+        let mut buffer = PlaneBuffer::new(64, 32, 2);
+        buffer.xor_row(0, 0, 0, 1 << 63, 1);
+        buffer.xor_row(1, 0, 0, 1 << 63, 1);
+
+        buffer.scroll_planes(0b01, 2, 1);
+        assert_eq!(buffer.get(0, 0), 0b10);
+        assert_eq!(buffer.get(2, 1), 0b01);
+
+        buffer.clear_planes(0b10);
+        assert_eq!(buffer.get(0, 0), 0);
+        assert_eq!(buffer.get(2, 1), 0b01);
+    }
+
+    #[test]
+    fn planes_scroll_across_words_and_off_the_edges() {
+        let mut buffer = PlaneBuffer::new(100, 8, 1);
+        buffer.xor_row(0, 60, 2, 0xF << 60, 4);
+        buffer.xor_row(0, 99, 3, 1 << 63, 1);
+
+        // The first row carries from one word into the next, and the second
+        // goes off the right edge, into the padding of its last word.
+        buffer.scroll_planes(0b1, 6, 4);
+        let on = |buffer: &PlaneBuffer| {
+            (0..8)
+                .flat_map(|y| (0..100).map(move |x| (x, y)))
+                .filter(|&(x, y)| buffer.get(x, y) != 0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(on(&buffer), [(66, 6), (67, 6), (68, 6), (69, 6)]);
+
+        // Scrolling back doesn't bring it back.
+        buffer.scroll_planes(0b1, -70, -6);
+        assert_eq!(on(&buffer), []);
+        buffer.xor_row(0, 64, 7, 1 << 63, 1);
+        buffer.scroll_planes(0b1, -64, -7);
+        assert_eq!(on(&buffer), [(0, 0)]);
+        buffer.scroll_planes(0b1, 0, 8);
+        assert_eq!(on(&buffer), []);
+    }
+
+    #[test]
+    fn images_only_render_stale_rows() {
+        let mut buffer = PlaneBuffer::new(64, 32, 1);
+        let mut image = PlaneImage::new(64, 32, Palette::default());
+        assert_eq!(image.take_dirty_rows(&buffer), Some(0..32));
+
+        // Nothing is rendered until the images are updated.
+        buffer.xor_row(0, 8, 5, 1 << 63, 1);
+        image.mark_stale(5..6);
+        assert_eq!(image.indices()[5 * 64 + 8], 0);
+
+        assert_eq!(image.take_dirty_rows(&buffer), Some(5..6));
+        assert_eq!(image.indices()[5 * 64 + 8], 1);
+        assert_eq!(image.image()[(8, 5)], Palette::default().colour(1));
+        assert_eq!(image.take_dirty_rows(&buffer), None);
+    }
+}
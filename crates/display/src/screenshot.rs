@@ -0,0 +1,89 @@
+//! Saving the contents of a display as a PNG, and naming captures.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::imageops::{self, FilterType};
+use image::{ImageResult, RgbaImage};
+
+use crate::Display;
+
+/// The largest factor that [`upscale()`] scales images up by.
+pub const MAX_SCALE: u32 = 32;
+
+/// Copy a display's image, scaled up by a whole number so that pixels stay
+/// crisp. See [`upscale()`].
+pub fn screenshot(display: &dyn Display, scale: u32) -> RgbaImage {
+    upscale(display.as_rgba8_image(), scale)
+}
+
+/// Scale an image up by a whole number, without smoothing. A `scale` of 0 is
+/// treated as 1, and one above [`MAX_SCALE`] as [`MAX_SCALE`].
+pub fn upscale(image: &RgbaImage, scale: u32) -> RgbaImage {
+    let scale = scale.clamp(1, MAX_SCALE);
+
+    if scale == 1 {
+        return image.clone();
+    }
+
+    imageops::resize(
+        image,
+        image.width() * scale,
+        image.height() * scale,
+        FilterType::Nearest,
+    )
+}
+
+/// Save a display's image as a PNG. See [`screenshot()`].
+pub fn save_screenshot(display: &dyn Display, scale: u32, path: &Path) -> ImageResult<()> {
+    screenshot(display, scale).save_with_format(path, image::ImageFormat::Png)
+}
+
+/// A file name for a screenshot or clip of a ROM taken now, like
+/// `pong-20240131-174502.png`. The time is in UTC.
+pub fn capture_file_name(rom_name: &str, extension: &str) -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    format!("{rom_name}-{}.{extension}", timestamp(secs))
+}
+
+/// Format a Unix time as `YYYYMMDD-HHMMSS`.
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Convert days since the epoch to a date in the proleptic Gregorian
+    // calendar. See http://howardhinnant.github.io/date_algorithms.html.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(timestamp(0), "19700101-000000");
+        assert_eq!(timestamp(951_782_400), "20000229-000000");
+        assert_eq!(timestamp(1_706_723_102), "20240131-174502");
+    }
+}
@@ -0,0 +1,437 @@
+//! Breakpoints, memory watchpoints, and breakpoints on events like collisions.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+
+use cpu::{Access, Cpu, MemoryAccess, Step};
+use opcode::Instruction;
+use ram::Ram;
+
+use crate::condition::Condition;
+
+/// Everything that can pause the emulator while it's running.
+///
+/// Checking breakpoints happens before and after every instruction, so the
+/// machine skips the checks entirely when [`Breakpoints::is_empty()`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Breakpoints {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    events: EventBreakpoints,
+    step_target: Option<StepTarget>,
+}
+
+/// Pauses before the instruction at an address is executed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Breakpoint {
+    /// Only pause if this is true.
+    pub condition: Option<Condition>,
+    /// The number of times the breakpoint has been reached, whether or not the
+    /// condition was true.
+    pub hits: u64,
+}
+
+/// Pauses when an instruction accesses a range of memory.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Watchpoint {
+    /// The addresses being watched. Addresses wrap around at the end of memory
+    /// in the same way that the CPU's do, so `0x1234` in a 4 kiB RAM is `0x234`.
+    pub addrs: RangeInclusive<u16>,
+    pub read: bool,
+    pub write: bool,
+    /// Pause when an instruction in the range is about to be executed.
+    pub execute: bool,
+}
+
+/// Events that pause the emulator when they happen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct EventBreakpoints {
+    /// Pause before `00E0` clears the display.
+    pub display_clear: bool,
+    /// Pause after a `DXYN` sprite collides with something on the display.
+    pub collision: bool,
+    /// Pause before `FX0A` starts waiting for a key.
+    pub key_wait: bool,
+    /// Pause after the sound timer becomes non-zero.
+    pub sound_start: bool,
+    /// Pause before executing an unknown opcode, instead of halting.
+    pub unknown_opcode: bool,
+}
+
+/// Pauses at the first of some addresses to be reached, for stepping through
+/// source code a line at a time. Cleared as soon as the emulator pauses for any
+/// reason.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct StepTarget {
+    pub addrs: BTreeSet<u16>,
+    /// Only pause when the call stack is at most this deep, to step over
+    /// subroutine calls or out of the current subroutine.
+    pub max_depth: Option<u8>,
+}
+
+/// The reason that a breakpoint paused the emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BreakReason {
+    /// The PC reached a breakpoint, and its condition was true.
+    Breakpoint,
+    /// An instruction was about to access memory covered by a watchpoint.
+    Watchpoint {
+        addr: u16,
+        access: Access,
+    },
+    DisplayClear,
+    Collision,
+    KeyWait,
+    SoundStart,
+    UnknownOpcode,
+    /// The PC reached the [`StepTarget`].
+    Step,
+}
+
+/// A breakpoint being hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Break {
+    /// The address of the instruction responsible.
+    pub addr: u16,
+    pub reason: BreakReason,
+    /// True if the instruction was executed before pausing, and false if the
+    /// PC still points at it.
+    pub after: bool,
+}
+
+impl Breakpoints {
+    /// Returns true if nothing can pause the emulator.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.events == EventBreakpoints::default()
+            && self.step_target.is_none()
+    }
+
+    /// The PC breakpoints, by address.
+    pub fn breakpoints(&self) -> &BTreeMap<u16, Breakpoint> {
+        &self.breakpoints
+    }
+
+    /// Returns true if there's a PC breakpoint at some address.
+    pub fn contains(&self, addr: u16) -> bool {
+        self.breakpoints.contains_key(&addr)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn events(&self) -> EventBreakpoints {
+        self.events
+    }
+
+    /// Add an unconditional breakpoint, unless there's already a breakpoint at
+    /// `addr`.
+    pub fn add(&mut self, addr: u16) {
+        self.breakpoints.entry(addr).or_default();
+    }
+
+    pub fn remove(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Change the condition of the breakpoint at `addr`, and reset its hit
+    /// count. Does nothing if there isn't one.
+    pub fn set_condition(&mut self, addr: u16, condition: Option<Condition>) {
+        if let Some(breakpoint) = self.breakpoints.get_mut(&addr) {
+            breakpoint.condition = condition;
+            breakpoint.hits = 0;
+        }
+    }
+
+    /// Add a watchpoint, unless an identical one already exists.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) {
+        self.watchpoints.retain(|w| w != watchpoint);
+    }
+
+    pub fn set_events(&mut self, events: EventBreakpoints) {
+        self.events = events;
+    }
+
+    pub fn step_target(&self) -> Option<&StepTarget> {
+        self.step_target.as_ref()
+    }
+
+    pub fn set_step_target(&mut self, target: Option<StepTarget>) {
+        self.step_target = target;
+    }
+
+    /// Check whether the instruction at the PC should be paused on before it's
+    /// executed.
+    pub(crate) fn check_before(&mut self, cpu: &Cpu, ram: &Ram) -> Option<BreakReason> {
+        let pc = cpu.pc;
+
+        if let Some(breakpoint) = self.breakpoints.get_mut(&pc) {
+            breakpoint.hits += 1;
+
+            let hit = match &breakpoint.condition {
+                Some(condition) => condition.evaluate(cpu, ram, breakpoint.hits),
+                None => true,
+            };
+            if hit {
+                return Some(BreakReason::Breakpoint);
+            }
+        }
+
+        if let Some(target) = &self.step_target {
+            let depth_ok = match target.max_depth {
+                Some(max_depth) => cpu.sp <= max_depth,
+                None => true,
+            };
+            if depth_ok && target.addrs.contains(&pc) {
+                return Some(BreakReason::Step);
+            }
+        }
+
+        let instruction = cpu.fetch(ram);
+
+        match instruction {
+            Instruction::Unknown { .. } if self.events.unknown_opcode => {
+                return Some(BreakReason::UnknownOpcode);
+            }
+            Instruction::ClearScreen if self.events.display_clear => {
+                return Some(BreakReason::DisplayClear);
+            }
+            Instruction::WaitKey { .. } if self.events.key_wait && !cpu.is_waiting_for_key() => {
+                return Some(BreakReason::KeyWait);
+            }
+            _ => {}
+        }
+
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+
+        let fetch = MemoryAccess {
+            access: Access::Execute,
+            addr: pc,
+            len: instruction.len(),
+        };
+
+        [Some(fetch), cpu.data_access(&instruction)]
+            .into_iter()
+            .flatten()
+            .find_map(|access| self.watched(access, ram))
+    }
+
+    /// Check whether an instruction that was just executed should be paused
+    /// on. `sound_was_on` is whether the sound timer was non-zero beforehand.
+    pub(crate) fn check_after(
+        &self,
+        cpu: &Cpu,
+        step: &Step,
+        sound_was_on: bool,
+    ) -> Option<BreakReason> {
+        let collided = matches!(step.instruction, Instruction::Draw { .. }) && cpu.v[0xF] != 0;
+
+        if self.events.collision && collided {
+            Some(BreakReason::Collision)
+        } else if self.events.sound_start && !sound_was_on && cpu.sound_timer > 0 {
+            Some(BreakReason::SoundStart)
+        } else {
+            None
+        }
+    }
+
+    /// The first watched address in a memory access, if any.
+    fn watched(&self, access: MemoryAccess, ram: &Ram) -> Option<BreakReason> {
+        let wrap = |addr: u16| addr as usize & (ram.len() - 1);
+
+        self.watchpoints
+            .iter()
+            .filter(|watchpoint| match access.access {
+                Access::Read => watchpoint.read,
+                Access::Write => watchpoint.write,
+                Access::Execute => watchpoint.execute,
+            })
+            .find_map(|watchpoint| {
+                let (start, end) = (*watchpoint.addrs.start(), *watchpoint.addrs.end());
+                let covers_ram = end
+                    .checked_sub(start)
+                    .map(|len| len as usize + 1 >= ram.len());
+                // A range that runs past the end of memory wraps around to the
+                // start, so it's watched in two halves.
+                let watched = |addr: usize| match covers_ram {
+                    Some(true) => true,
+                    Some(false) if wrap(start) <= wrap(end) => {
+                        (wrap(start)..=wrap(end)).contains(&addr)
+                    }
+                    Some(false) => addr >= wrap(start) || addr <= wrap(end),
+                    None => false,
+                };
+
+                (0..access.len)
+                    .map(|offset| access.addr.wrapping_add(offset))
+                    .find(|&addr| watched(wrap(addr)))
+            })
+            .map(|addr| BreakReason::Watchpoint {
+                addr: wrap(addr) as u16,
+                access: access.access,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::Machine;
+
+    /// Create a machine running a CHIP-8 program.
+    fn machine(program: &[u8]) -> Machine {
+        let mut machine =
+            Machine::new(Arc::new(Mutex::new(Ram::new())), Arc::new(Mutex::new(None)));
+        machine.load_rom(program.to_vec()).unwrap();
+        machine
+    }
+
+    #[rustfmt::skip]
+    const PROGRAM: [u8; 10] = [
+        0x60, 0x05, // 200: V0 = 5
+        0xA3, 0x00, // 202: I = 0x300
+        0xF0, 0x33, // 204: BCD of V0 at I
+        0xF0, 0x18, // 206: sound timer = V0
+        0x12, 0x08, // 208: jump 0x208
+    ];
+
+    #[test]
+    fn pauses_before_breakpoints_and_resumes_past_them() {
+        let mut machine = machine(&PROGRAM);
+        machine.breakpoints_mut().add(0x204);
+
+        let report = machine.run_frame();
+        assert_eq!(
+            report.breakpoint,
+            Some(Break {
+                addr: 0x204,
+                reason: BreakReason::Breakpoint,
+                after: false
+            })
+        );
+        assert_eq!(machine.cpu.pc, 0x204);
+
+        // Running again continues from the breakpoint rather than hitting it
+        // straight away.
+        assert_eq!(machine.run_frame().breakpoint, None);
+        assert_eq!(machine.cpu.pc, 0x208);
+    }
+
+    #[test]
+    fn conditions_are_checked_with_hit_counts() {
+        #[rustfmt::skip]
+        let mut machine = machine(&[
+            0x70, 0x01, // 200: V0 += 1
+            0x12, 0x00, // 202: jump 0x200
+        ]);
+        machine.breakpoints_mut().add(0x202);
+        machine
+            .breakpoints_mut()
+            .set_condition(0x202, Some("V0 == 3 && hits == 3".parse().unwrap()));
+
+        let report = machine.run_frame();
+        assert_eq!(report.breakpoint.map(|hit| hit.addr), Some(0x202));
+        assert_eq!(machine.cpu.v[0], 3);
+        assert_eq!(machine.breakpoints().breakpoints()[&0x202].hits, 3);
+    }
+
+    #[test]
+    fn watchpoints_catch_writes() {
+        let mut machine = machine(&PROGRAM);
+        machine.breakpoints_mut().add_watchpoint(Watchpoint {
+            addrs: 0x302..=0x310,
+            read: true,
+            write: true,
+            execute: false,
+        });
+
+        let report = machine.run_frame();
+        assert_eq!(
+            report.breakpoint,
+            Some(Break {
+                addr: 0x204,
+                reason: BreakReason::Watchpoint {
+                    addr: 0x302,
+                    access: Access::Write
+                },
+                after: false
+            })
+        );
+    }
+
+    #[test]
+    fn watchpoints_wrap_past_the_end_of_memory() {
+        let mut machine = machine(&PROGRAM);
+        machine.breakpoints_mut().add_watchpoint(Watchpoint {
+            addrs: 0xFFF..=0x1300,
+            read: false,
+            write: true,
+            execute: false,
+        });
+
+        let report = machine.run_frame();
+        assert_eq!(
+            report.breakpoint.map(|hit| hit.reason),
+            Some(BreakReason::Watchpoint {
+                addr: 0x300,
+                access: Access::Write
+            })
+        );
+    }
+
+    #[test]
+    fn step_targets_skip_deeper_calls() {
+        #[rustfmt::skip]
+        let mut machine = machine(&[
+            0x22, 0x06, // 200: call 0x206
+            0x22, 0x06, // 202: call 0x206
+            0x12, 0x04, // 204: jump 0x204
+            0x00, 0xEE, // 206: return
+        ]);
+        machine.breakpoints_mut().set_step_target(Some(StepTarget {
+            addrs: [0x202, 0x204, 0x206].into(),
+            max_depth: Some(0),
+        }));
+
+        let report = machine.run_frame();
+        assert_eq!(
+            report.breakpoint.map(|hit| hit.reason),
+            Some(BreakReason::Step)
+        );
+        assert_eq!(machine.cpu.pc, 0x202);
+        assert_eq!(machine.breakpoints().step_target(), None);
+    }
+
+    #[test]
+    fn events_pause_after_sound_starts() {
+        let mut machine = machine(&PROGRAM);
+        machine.breakpoints_mut().set_events(EventBreakpoints {
+            sound_start: true,
+            ..Default::default()
+        });
+
+        let report = machine.run_frame();
+        assert_eq!(
+            report.breakpoint,
+            Some(Break {
+                addr: 0x206,
+                reason: BreakReason::SoundStart,
+                after: true
+            })
+        );
+        assert_eq!(machine.cpu.pc, 0x208);
+    }
+}
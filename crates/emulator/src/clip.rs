@@ -0,0 +1,364 @@
+//! Recording the display to an animated GIF or APNG, for sharing short clips.
+//!
+//! Clips use a 60 Hz timebase: the display is looked at once per frame, and an
+//! image is only added when it's different from the last one, shown for as
+//! many frames as it stayed the same.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use display::Display;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The shortest delay, in hundredths of a second, that GIF viewers reliably
+/// respect. Browsers slow shorter delays down to a tenth of a second, so
+/// images shown for less than this are skipped instead.
+const GIF_MIN_DELAY: u64 = 2;
+
+/// The file formats clips can be saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum ClipFormat {
+    /// An animated GIF. Plays everywhere, but can't show an image for less
+    /// than 1/50th of a second, so some fast changes are lost.
+    #[default]
+    Gif,
+    /// An animated PNG, which keeps every image with exact timings. Only
+    /// written once the recording stops, so the whole clip is kept in memory.
+    Apng,
+}
+
+impl ClipFormat {
+    /// The format for a file extension: `gif`, or `png` or `apng`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "gif" => Some(ClipFormat::Gif),
+            "png" | "apng" => Some(ClipFormat::Apng),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension for the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ClipFormat::Gif => "gif",
+            ClipFormat::Apng => "png",
+        }
+    }
+}
+
+impl fmt::Display for ClipFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipFormat::Gif => write!(f, "GIF"),
+            ClipFormat::Apng => write!(f, "APNG"),
+        }
+    }
+}
+
+/// The reasons a clip can't be recorded.
+#[derive(Error, Debug)]
+pub enum ClipError {
+    #[error("Failed to write the clip")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to encode the GIF")]
+    Gif(#[from] gif::EncodingError),
+
+    #[error("Failed to encode the APNG")]
+    Png(#[from] png::EncodingError),
+
+    #[error("Unknown clip format for {0:?}. Expected a .gif or .png file")]
+    UnknownFormat(PathBuf),
+}
+
+/// How a clip recording is going.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClipStatus {
+    pub path: PathBuf,
+    /// The number of 60 Hz frames recorded.
+    pub frames: u64,
+    /// The number of different images captured.
+    pub images: u64,
+    /// True while frames are still being recorded.
+    pub running: bool,
+    /// Set if recording stopped because of an error.
+    pub error: Option<String>,
+}
+
+/// Records the display once per frame to an animated GIF or APNG.
+pub struct ClipRecorder {
+    path: PathBuf,
+    scale: u32,
+    encoder: ClipEncoder,
+    /// The last image captured and the frame it was captured on. It's written
+    /// out once the next change shows how long it was on screen.
+    pending: Option<(RgbaImage, u64)>,
+    /// The size of the first image. Later images are stretched to fit.
+    size: Option<(u32, u32)>,
+    frames: u64,
+    images: u64,
+}
+
+enum ClipEncoder {
+    /// GIFs are written as they go. The encoder is created with the size of
+    /// the first image.
+    Gif {
+        writer: Option<BufWriter<File>>,
+        encoder: Option<gif::Encoder<BufWriter<File>>>,
+    },
+    /// APNGs need the number of images up front, so they're kept until the
+    /// recording stops, with the number of frames each is shown for.
+    Apng {
+        writer: BufWriter<File>,
+        images: Vec<(RgbaImage, u64)>,
+    },
+}
+
+impl ClipRecorder {
+    /// Start recording to a file, replacing it if it exists. The format comes
+    /// from the file's extension, and images are scaled up by `scale`.
+    pub fn create(path: PathBuf, scale: u32) -> Result<Self, ClipError> {
+        let format = ClipFormat::from_path(&path).ok_or(ClipError::UnknownFormat(path.clone()))?;
+        let writer = BufWriter::new(File::create(&path)?);
+
+        let encoder = match format {
+            ClipFormat::Gif => ClipEncoder::Gif {
+                writer: Some(writer),
+                encoder: None,
+            },
+            ClipFormat::Apng => ClipEncoder::Apng {
+                writer,
+                images: Vec::new(),
+            },
+        };
+
+        Ok(Self {
+            path,
+            scale: scale.max(1),
+            encoder,
+            pending: None,
+            size: None,
+            frames: 0,
+            images: 0,
+        })
+    }
+
+    /// Record the display at the end of a frame.
+    pub fn record(&mut self, display: &dyn Display) -> Result<(), ClipError> {
+        let image = display.as_rgba8_image();
+        let frame = self.frames;
+        self.frames += 1;
+
+        let size = *self.size.get_or_insert(image.dimensions());
+        let image = if image.dimensions() == size {
+            image.clone()
+        } else {
+            imageops::resize(image, size.0, size.1, FilterType::Nearest)
+        };
+
+        match self.pending.take() {
+            Some((pending, start)) if pending == image => {
+                self.pending = Some((pending, start));
+            }
+            // An image that'd be shown too briefly for a GIF is replaced by the
+            // next one, which takes over its time on screen.
+            Some((_, start))
+                if matches!(self.encoder, ClipEncoder::Gif { .. })
+                    && centiseconds(frame) - centiseconds(start) < GIF_MIN_DELAY =>
+            {
+                self.pending = Some((image, start));
+            }
+            Some((pending, start)) => {
+                self.write(pending, start, frame)?;
+                self.pending = Some((image, frame));
+            }
+            None => self.pending = Some((image, frame)),
+        }
+
+        Ok(())
+    }
+
+    /// Write out the last image and finish the file.
+    pub fn finish(mut self) -> Result<ClipStatus, ClipError> {
+        if let Some((pending, start)) = self.pending.take() {
+            let end = self.frames.max(start + 1);
+            self.write(pending, start, end)?;
+        }
+
+        let scale = self.scale;
+        match self.encoder {
+            ClipEncoder::Gif { writer, encoder } => {
+                let mut writer = match encoder {
+                    Some(encoder) => encoder.into_inner()?,
+                    // Nothing was recorded, so there's nothing to write.
+                    None => writer.expect("GIFs keep their writer until the first image"),
+                };
+                writer.flush()?;
+            }
+            ClipEncoder::Apng { writer, images } => write_apng(writer, &images, scale)?,
+        }
+
+        Ok(ClipStatus {
+            path: self.path,
+            frames: self.frames,
+            images: self.images,
+            running: false,
+            error: None,
+        })
+    }
+
+    pub fn status(&self) -> ClipStatus {
+        ClipStatus {
+            path: self.path.clone(),
+            frames: self.frames,
+            images: self.images,
+            running: true,
+            error: None,
+        }
+    }
+
+    /// Write an image that was on screen from frame `start` until `end`.
+    fn write(&mut self, image: RgbaImage, start: u64, end: u64) -> Result<(), ClipError> {
+        self.images += 1;
+
+        match &mut self.encoder {
+            ClipEncoder::Gif { writer, encoder } => {
+                let mut image = display::upscale(&image, self.scale);
+                let (width, height) = (image.width() as u16, image.height() as u16);
+
+                if encoder.is_none() {
+                    let writer = writer
+                        .take()
+                        .expect("GIFs keep their writer until the first image");
+                    let mut new_encoder = gif::Encoder::new(writer, width, height, &[])?;
+                    new_encoder.set_repeat(gif::Repeat::Infinite)?;
+                    *encoder = Some(new_encoder);
+                }
+
+                // Displays have at most 16 colours, so they're never quantized.
+                let mut frame = gif::Frame::from_rgba_speed(width, height, &mut image, 10);
+                frame.delay = (centiseconds(end) - centiseconds(start)) as u16;
+                encoder.as_mut().unwrap().write_frame(&frame)?;
+            }
+            ClipEncoder::Apng { images, .. } => images.push((image, end - start)),
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ClipRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClipRecorder")
+            .field("path", &self.path)
+            .field("scale", &self.scale)
+            .field("frames", &self.frames)
+            .field("images", &self.images)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The time that a 60 Hz frame starts at, rounded to the nearest hundredth of
+/// a second. Delays are worked out from these, so that rounding errors don't
+/// add up over a long clip.
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + 30) / 60
+}
+
+/// Write a whole APNG, with images shown for some number of 60 Hz frames each.
+fn write_apng(
+    writer: BufWriter<File>,
+    images: &[(RgbaImage, u64)],
+    scale: u32,
+) -> Result<(), ClipError> {
+    let (width, height) = match images.first() {
+        Some((image, _)) => (image.width() * scale, image.height() * scale),
+        None => return Ok(()),
+    };
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(images.len() as u32, 0)?;
+
+    let mut writer = encoder.write_header()?;
+    for (image, frames) in images {
+        let frames = (*frames).min(u16::MAX as u64) as u16;
+        writer.set_frame_delay(frames, 60)?;
+        writer.write_image_data(&display::upscale(image, scale))?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use display_chip8::Chip8Display;
+
+    use super::*;
+
+    #[test]
+    fn only_changes_are_captured() {
+        let dir = std::env::temp_dir().join(format!("rust-chip-clip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for format in [ClipFormat::Gif, ClipFormat::Apng] {
+            let path = dir.join(format!("test.{}", format.extension()));
+            let mut display = Chip8Display::new();
+            display.clear();
+
+            let mut recorder = ClipRecorder::create(path.clone(), 2).unwrap();
+            for frame in 0..30 {
+                // A change every 10 frames, and one that's undone straight
+                // away, which GIFs skip.
+                if frame % 10 == 0 {
+                    display.flip_pixel(frame, 0);
+                }
+                if frame == 25 || frame == 26 {
+                    display.flip_pixel(25, 0);
+                }
+                display.end_frame();
+                recorder.record(&display).unwrap();
+            }
+
+            let status = recorder.finish().unwrap();
+            assert_eq!(status.frames, 30);
+            let expected = match format {
+                ClipFormat::Gif => 4,
+                ClipFormat::Apng => 5,
+            };
+            assert_eq!(status.images, expected, "{format}");
+
+            // Every image is there, scaled up, and shown for half a second
+            // altogether.
+            let file = File::open(&path).unwrap();
+            match format {
+                ClipFormat::Gif => {
+                    let mut decoder = gif::DecodeOptions::new().read_info(file).unwrap();
+                    assert_eq!((decoder.width(), decoder.height()), (128, 64));
+                    let mut delay = 0;
+                    while let Some(frame) = decoder.read_next_frame().unwrap() {
+                        delay += frame.delay;
+                    }
+                    assert_eq!(delay, 50);
+                }
+                ClipFormat::Apng => {
+                    let decoder = png::Decoder::new(file).read_info().unwrap();
+                    let info = decoder.info();
+                    assert_eq!((info.width, info.height), (128, 64));
+                    assert_eq!(info.animation_control.unwrap().num_frames, 5);
+                }
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+//! Commands sent from the UI thread to the emulator thread.
+
+use std::path::PathBuf;
+
+use cpu::{Cpu, RngOptions};
+use display::Palette;
+use display_phosphor::Persistence;
+use platform::{Platform, Quirks};
+
+use crate::breakpoints::{EventBreakpoints, StepTarget, Watchpoint};
+use crate::condition::Condition;
+use crate::movie::Movie;
+use crate::pace::Pace;
+use crate::trace::TraceOptions;
+
+/// A command for the emulator thread.
+///
+/// Commands are sent with [`crate::Emulator::send()`], which never blocks. The
+/// emulator thread handles commands in between 60 Hz frames, in the order they
+/// were sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Load a ROM, and start running it from the beginning.
+    LoadRom(Vec<u8>),
+
+    /// Switch to emulating a different platform. This attaches a new display,
+    /// resets the quirks and speed to the platform's defaults, and restarts the
+    /// current ROM.
+    SetPlatform(Platform),
+
+    /// Restart the current ROM from the beginning, keeping the contents of
+    /// memory and the display. See [`crate::Machine::soft_reset()`].
+    SoftReset,
+
+    /// Restart the current ROM from the beginning, with freshly-initialized
+    /// memory and a clear display. See [`crate::Machine::hard_reset()`].
+    HardReset,
+
+    /// Stop executing instructions until [`Command::Resume`] is sent.
+    Pause,
+
+    /// Continue executing instructions after [`Command::Pause`].
+    Resume,
+
+    /// Pause, and then execute a single instruction. See
+    /// [`crate::Machine::step()`].
+    Step,
+
+    /// Change the value of one of the CPU's registers. This is meant for
+    /// debugging while paused, but works at any time.
+    SetRegister(Register, u16),
+
+    /// Pause before executing the instruction at an address.
+    AddBreakpoint(u16),
+
+    /// Remove a breakpoint added with [`Command::AddBreakpoint`].
+    RemoveBreakpoint(u16),
+
+    /// Only pause at the breakpoint at `addr` when a condition is true, or
+    /// always pause if `condition` is `None`.
+    SetBreakpointCondition {
+        addr: u16,
+        condition: Option<Condition>,
+    },
+
+    /// Pause before an instruction accesses some memory.
+    AddWatchpoint(Watchpoint),
+
+    /// Remove a watchpoint added with [`Command::AddWatchpoint`].
+    RemoveWatchpoint(Watchpoint),
+
+    /// Choose which events pause the emulator.
+    SetEventBreakpoints(EventBreakpoints),
+
+    /// Resume, and pause again when the PC reaches a step target. Pausing for
+    /// any other reason cancels it.
+    RunTo(StepTarget),
+
+    /// Start writing every instruction executed to a trace file, replacing any
+    /// trace in progress. See [`crate::Tracer`].
+    StartTrace(TraceOptions),
+
+    /// Stop the trace started by [`Command::StartTrace`].
+    StopTrace,
+
+    /// Start counting the instructions executed, clearing the last profile.
+    /// See [`crate::Emulator::profile()`].
+    StartProfiling,
+
+    /// Stop the profiling started by [`Command::StartProfiling`].
+    StopProfiling,
+
+    /// Set the number of instructions executed per 60 Hz frame.
+    SetSpeed(u32),
+
+    /// Run frames faster or slower than real time, without changing how many
+    /// instructions each one executes. The achieved speed is measured in
+    /// [`crate::Snapshot::speed`].
+    SetPace(Pace),
+
+    /// Write some bytes to memory, starting at an address. Addresses wrap
+    /// around at the end of memory.
+    WriteMemory { addr: u16, bytes: Vec<u8> },
+
+    /// Change the quirks being emulated.
+    SetQuirks(Quirks),
+
+    /// Change how random numbers are generated, and restart the generator. See
+    /// [`crate::Machine::set_rng_options()`].
+    SetRng(RngOptions),
+
+    /// Change the colours the display draws with. See
+    /// [`crate::Machine::set_palette()`].
+    SetPalette(Palette),
+
+    /// Change how the display blends frames together to hide flicker, or stop
+    /// it with `None`. See [`crate::Machine::set_persistence()`].
+    SetPersistence(Option<Persistence>),
+
+    /// Restart the ROM, and record a movie of every key press until
+    /// [`Command::StopMovie`], which saves it to a file. See
+    /// [`crate::Machine::start_recording()`].
+    StartRecording(PathBuf),
+
+    /// Restart the ROM, and play a movie back. Key presses are ignored until
+    /// it finishes. See [`crate::Machine::play_movie()`].
+    PlayMovie(Movie),
+
+    /// Stop recording or playing a movie.
+    StopMovie,
+
+    /// Start recording the display to an animated GIF or APNG, scaled up by a
+    /// whole number, replacing any clip in progress. See
+    /// [`crate::ClipRecorder`].
+    StartClip { path: PathBuf, scale: u32 },
+
+    /// Stop the clip started by [`Command::StartClip`], and finish the file.
+    StopClip,
+
+    /// A key on the keypad was pressed.
+    KeyDown(u8),
+
+    /// A key on the keypad was released.
+    KeyUp(u8),
+
+    /// Shut the emulator thread down.
+    Stop,
+}
+
+/// A CPU register that can be changed with [`Command::SetRegister`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    /// One of the general-purpose registers `V0` through `VF`.
+    V(u8),
+    /// The index register.
+    I,
+    /// The program counter.
+    Pc,
+    /// The stack pointer.
+    Sp,
+    /// The delay timer.
+    DelayTimer,
+    /// The sound timer.
+    SoundTimer,
+}
+
+impl Register {
+    /// The register's current value.
+    pub fn read(self, cpu: &Cpu) -> u16 {
+        match self {
+            Register::V(x) => cpu.v[x as usize & 0xF].into(),
+            Register::I => cpu.i,
+            Register::Pc => cpu.pc,
+            Register::Sp => cpu.sp.into(),
+            Register::DelayTimer => cpu.delay_timer.into(),
+            Register::SoundTimer => cpu.sound_timer.into(),
+        }
+    }
+
+    /// Returns true for the 16-bit registers, `I` and the PC. The rest are 8
+    /// bits.
+    pub fn is_wide(self) -> bool {
+        matches!(self, Register::I | Register::Pc)
+    }
+}
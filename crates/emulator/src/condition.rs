@@ -0,0 +1,386 @@
+//! Conditions for breakpoints, like `V3 == 0x10 && I >= 0x300`.
+//!
+//! Conditions are C-like expressions over integers. They can refer to the
+//! registers `V0` to `VF`, `I`, `PC`, `SP`, the timers `DT` and `ST`, the number
+//! of instructions executed as `cycles`, and the number of times the breakpoint
+//! has been reached (including this time) as `hits`. `[addr]` reads a byte of
+//! memory. Names are case-insensitive.
+//!
+//! The usual arithmetic, bitwise, comparison and logical operators are
+//! supported, with the same precedence as in C. Comparisons and logical
+//! operators evaluate to `1` or `0`, and a condition is true if it's non-zero.
+
+use std::fmt;
+use std::str::FromStr;
+
+use cpu::Cpu;
+use ram::Ram;
+use thiserror::Error;
+
+/// A parsed breakpoint condition.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+/// The error returned when a condition can't be parsed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{message} at column {column}")]
+pub struct ParseConditionError {
+    /// The column (counting from 1) that the error was found at.
+    pub column: usize,
+    pub message: String,
+}
+
+/// The values that a condition can refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    Cycles,
+    Hits,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expr {
+    Number(i64),
+    Operand(Operand),
+    /// A byte of memory.
+    Memory(Box<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators, from lowest to highest precedence.
+const BINARY_OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Every operator and bit of punctuation, longest first so that `<=` isn't
+/// read as `<`.
+const SYMBOLS: [&str; 22] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")",
+];
+
+impl Condition {
+    /// Parse a condition.
+    pub fn parse(source: &str) -> Result<Self, ParseConditionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            end: source.len(),
+        };
+
+        let expr = parser.expression(0)?;
+
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(token.error(format!("Unexpected '{}'", token.text)));
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// The text the condition was parsed from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns true if the condition holds. `hits` is the number of times the
+    /// breakpoint has been reached, including this time.
+    pub fn evaluate(&self, cpu: &Cpu, ram: &Ram, hits: u64) -> bool {
+        evaluate(&self.expr, cpu, ram, hits) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ParseConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn evaluate(expr: &Expr, cpu: &Cpu, ram: &Ram, hits: u64) -> i64 {
+    let eval = |expr: &Expr| evaluate(expr, cpu, ram, hits);
+    let bool = |value: bool| value as i64;
+
+    match expr {
+        Expr::Number(value) => *value,
+        Expr::Operand(operand) => match *operand {
+            Operand::V(x) => cpu.v[x as usize] as i64,
+            Operand::I => cpu.i as i64,
+            Operand::Pc => cpu.pc as i64,
+            Operand::Sp => cpu.sp as i64,
+            Operand::DelayTimer => cpu.delay_timer as i64,
+            Operand::SoundTimer => cpu.sound_timer as i64,
+            Operand::Cycles => cpu.cycles as i64,
+            Operand::Hits => hits as i64,
+        },
+        Expr::Memory(addr) => *ram.get(eval(addr) as u16) as i64,
+        Expr::Unary(op, operand) => {
+            let value = eval(operand);
+            match *op {
+                "-" => value.wrapping_neg(),
+                "~" => !value,
+                _ => bool(value == 0),
+            }
+        }
+        // The logical operators short-circuit.
+        Expr::Binary("&&", lhs, rhs) => bool(eval(lhs) != 0 && eval(rhs) != 0),
+        Expr::Binary("||", lhs, rhs) => bool(eval(lhs) != 0 || eval(rhs) != 0),
+        Expr::Binary(op, lhs, rhs) => {
+            let (lhs, rhs) = (eval(lhs), eval(rhs));
+            match *op {
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => bool(lhs == rhs),
+                "!=" => bool(lhs != rhs),
+                "<" => bool(lhs < rhs),
+                "<=" => bool(lhs <= rhs),
+                ">" => bool(lhs > rhs),
+                ">=" => bool(lhs >= rhs),
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                // Dividing by zero gives zero rather than stopping the emulator.
+                "/" => lhs.checked_div(rhs).unwrap_or(0),
+                _ => lhs.checked_rem(rhs).unwrap_or(0),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Token {
+    text: String,
+    /// The byte offset of the token in the source.
+    offset: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> ParseConditionError {
+        ParseConditionError {
+            column: self.offset + 1,
+            message,
+        }
+    }
+}
+
+/// Split a condition into numbers, names, and symbols.
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseConditionError> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+
+    while offset < source.len() {
+        let rest = &source[offset..];
+        let c = rest.chars().next().unwrap();
+
+        let len = if c.is_whitespace() {
+            offset += c.len_utf8();
+            continue;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len())
+        } else if c == '[' || c == ']' {
+            1
+        } else {
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(symbol) => symbol.len(),
+                None => {
+                    return Err(ParseConditionError {
+                        column: offset + 1,
+                        message: format!("Unexpected '{c}'"),
+                    })
+                }
+            }
+        };
+
+        tokens.push(Token {
+            text: rest[..len].to_string(),
+            offset,
+        });
+        offset += len;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// The length of the source, for errors at the end.
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn next(&mut self) -> Result<&Token, ParseConditionError> {
+        let token = self.tokens.get(self.pos).ok_or(ParseConditionError {
+            column: self.end + 1,
+            message: "Unexpected end of condition".to_string(),
+        })?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Parse an expression whose binary operators are all at or above some
+    /// level of [`BINARY_OPERATORS`].
+    fn expression(&mut self, level: usize) -> Result<Expr, ParseConditionError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.expression(level + 1)?;
+
+        while let Some(&op) = BINARY_OPERATORS[level]
+            .iter()
+            .find(|&&op| self.peek() == Some(op))
+        {
+            self.pos += 1;
+            let rhs = self.expression(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseConditionError> {
+        for op in ["-", "~", "!"] {
+            if self.peek() == Some(op) {
+                self.pos += 1;
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseConditionError> {
+        let token = self.next()?;
+
+        let close = match token.text.as_str() {
+            "(" => ")",
+            "[" => "]",
+            _ => return operand(token),
+        };
+        let memory = close == "]";
+
+        let expr = self.expression(0)?;
+
+        let token = self.next()?;
+        if token.text != close {
+            return Err(token.error(format!("Expected '{close}' but found '{}'", token.text)));
+        }
+
+        Ok(if memory {
+            Expr::Memory(Box::new(expr))
+        } else {
+            expr
+        })
+    }
+}
+
+/// Parse a number or the name of a register.
+fn operand(token: &Token) -> Result<Expr, ParseConditionError> {
+    let text = token.text.to_ascii_lowercase();
+
+    let number = if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    };
+    if let Some(number) = number {
+        return Ok(Expr::Number(number));
+    }
+
+    let operand = match text.as_str() {
+        "i" => Operand::I,
+        "pc" => Operand::Pc,
+        "sp" => Operand::Sp,
+        "dt" => Operand::DelayTimer,
+        "st" => Operand::SoundTimer,
+        "cycles" => Operand::Cycles,
+        "hits" => Operand::Hits,
+        _ => match text.strip_prefix('v').map(|x| u8::from_str_radix(x, 16)) {
+            Some(Ok(x)) if x < 16 && text.len() == 2 => Operand::V(x),
+            _ => return Err(token.error(format!("Unknown name '{}'", token.text))),
+        },
+    };
+
+    Ok(Expr::Operand(operand))
+}
+
+#[cfg(test)]
+mod tests {
+    use platform::{Platform, Quirks};
+
+    use super::*;
+
+    fn eval(source: &str, cpu: &Cpu, ram: &Ram) -> bool {
+        Condition::parse(source).unwrap().evaluate(cpu, ram, 6)
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let mut cpu = Cpu::new(Platform::Chip8, Quirks::CHIP8);
+        let mut ram = Ram::new();
+        cpu.v[3] = 0x10;
+        cpu.i = 0x300;
+        ram.set(0x302, 7);
+
+        assert!(eval("V3 == 0x10 && I >= 0x300", &cpu, &ram));
+        assert!(eval("[I+2] != 0", &cpu, &ram));
+        assert!(eval("[i + 2] == 7", &cpu, &ram));
+        assert!(eval("DT == 0", &cpu, &ram));
+        assert!(eval("hits > 5", &cpu, &ram));
+        assert!(!eval("hits > 5 && vf", &cpu, &ram));
+        assert!(eval("1 + 2 * 3 == 7", &cpu, &ram));
+        assert!(eval("(1 + 2) * 3 == 9 || 1 / 0", &cpu, &ram));
+        assert!(eval("!(v3 & 0b1111)", &cpu, &ram));
+        assert!(eval("-1 < 0", &cpu, &ram));
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let error = |source| Condition::parse(source).unwrap_err();
+
+        assert_eq!(error("V3 ==").column, 6);
+        assert_eq!(error("VG == 1").message, "Unknown name 'VG'");
+        assert_eq!(error("[I + 2 != 0").column, 12);
+        assert_eq!(error("V3 = 1").column, 4);
+        assert_eq!(error("1 2").message, "Unexpected '2'");
+    }
+}
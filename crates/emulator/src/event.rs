@@ -0,0 +1,107 @@
+//! Events sent from the emulator thread to the UI thread.
+
+use display::DisplayRef;
+use thiserror::Error;
+
+use crate::breakpoints::Break;
+
+/// Something that happened on the emulator thread, which the UI thread may want
+/// to react to.
+///
+/// Events are received with [`crate::Emulator::try_recv_event()`]. The emulator
+/// thread never blocks while sending events, and wakes up the UI thread after
+/// sending them. If the UI thread falls far enough behind to fill the channel,
+/// the events waiting for it are merged, keeping only the latest of each kind.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The display has a new frame that should be rendered. The renderer
+    /// picks it up from [`crate::Emulator::take_frame_reader()`].
+    ///
+    /// This event is coalesced: it won't be sent again until the renderer has
+    /// taken a frame, so the UI thread is woken at most once per frame it
+    /// renders.
+    FrameReady,
+
+    /// The emulator attached a new display. The UI thread should use this new
+    /// reference as a signal to create new textures for rendering the display to.
+    DisplayChanged(DisplayRef),
+
+    /// The sound timer became non-zero, so a tone should start playing.
+    SoundOn,
+
+    /// The sound timer reached zero, so the tone should stop playing.
+    SoundOff,
+
+    /// The emulator stopped executing the current ROM.
+    Halted(HaltReason),
+
+    /// Execution hit a breakpoint, and the emulator paused.
+    BreakpointHit(Break),
+}
+
+impl Event {
+    /// True if this event makes an earlier one redundant. Only the latest
+    /// event of each kind matters, and the sound's latest state.
+    fn supersedes(&self, earlier: &Event) -> bool {
+        matches!(
+            (self, earlier),
+            (Event::FrameReady, Event::FrameReady)
+                | (Event::DisplayChanged(_), Event::DisplayChanged(_))
+                | (
+                    Event::SoundOn | Event::SoundOff,
+                    Event::SoundOn | Event::SoundOff
+                )
+                | (Event::Halted(_), Event::Halted(_))
+                | (Event::BreakpointHit(_), Event::BreakpointHit(_))
+        )
+    }
+}
+
+/// Drop every event that a later one supersedes, keeping the rest in order.
+/// What's left is at most one event of each kind.
+pub(crate) fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let mut kept = Vec::with_capacity(events.len());
+
+    for (i, event) in events.iter().enumerate() {
+        if !events[i + 1..].iter().any(|later| later.supersedes(event)) {
+            kept.push(event.clone());
+        }
+    }
+
+    kept
+}
+
+/// The reason that the emulator stopped executing the current ROM.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    #[error(transparent)]
+    Cpu(#[from] cpu::HaltReason),
+
+    #[error("The ROM is {size} bytes long, but at most {max} bytes fit in memory")]
+    RomTooLarge { size: usize, max: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_coalesce_to_the_latest_of_each_kind() {
+        let events = coalesce(vec![
+            Event::SoundOn,
+            Event::FrameReady,
+            Event::Halted(HaltReason::RomTooLarge { size: 2, max: 1 }),
+            Event::SoundOff,
+            Event::FrameReady,
+        ]);
+
+        assert!(matches!(
+            events[..],
+            [
+                Event::Halted(HaltReason::RomTooLarge { size: 2, max: 1 }),
+                Event::SoundOff,
+                Event::FrameReady
+            ]
+        ));
+    }
+}
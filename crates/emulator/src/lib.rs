@@ -1,44 +1,93 @@
 //! The CHIP8 emulator itself.
 //!
-//! Typically, the emulator is run in a background thread. It periodically
-//! wakes up the UI thread to re-paint only when it executes an instruction that
-//! requires re-painting.
+//! Typically, the emulator is run in a background thread. The UI thread talks to
+//! it by sending [`Command`]s, and the emulator thread reports back by sending
+//! [`Event`]s. It periodically wakes up the UI thread to re-paint only when it
+//! executes an instruction that requires re-painting.
+
+mod breakpoints;
+mod clip;
+mod command;
+mod condition;
+mod event;
+mod machine;
+mod movie;
+mod pace;
+mod profile;
+mod snapshot;
+mod trace;
 
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicU64, Ordering},
     Arc, Mutex,
 };
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use color_eyre::eyre::Context;
-use crossbeam::channel::{self, Receiver, Sender};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender, TryRecvError, TrySendError};
 
-use display::{Display, DisplayRef};
-use display_chip8::Chip8Display;
+use display::{frame_channel, DisplayRef, FramePublisher, FrameReader};
 use ram::Ram;
 use ui_thread_waker::UiThreadWaker;
 
+use crate::pace::Throttle;
+
+pub use breakpoints::{
+    Break, BreakReason, Breakpoint, Breakpoints, EventBreakpoints, StepTarget, Watchpoint,
+};
+pub use clip::{ClipError, ClipFormat, ClipRecorder, ClipStatus};
+pub use command::{Command, Register};
+pub use condition::{Condition, ParseConditionError};
+pub use event::{Event, HaltReason};
+pub use machine::{FrameReport, Machine};
+pub use movie::{rom_hash, FrameEnd, Movie, MovieError, MovieInput, MovieStatus, MOVIE_VERSION};
+pub use pace::{Pace, SpeedStatus};
+pub use profile::{Profile, SubroutineProfile};
+pub use snapshot::Snapshot;
+pub use trace::{TraceOptions, TraceStatus, Tracer};
+
+/// The length of a single 60 Hz frame.
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// How often [`Emulator::send_and_wait()`] checks whether its command has been
+/// handled.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How many events can wait for the UI thread before they're merged. See
+/// [`event::coalesce()`].
+const EVENT_CAPACITY: usize = 64;
+
 /// The CHIP8 emulator.
 ///
-/// Some components of this emulator can be switched out at runtime
+/// This is a cheaply-clonable handle to the emulator. Clones all talk to the same
+/// emulator thread, which is started with [`Self::start`].
 #[derive(Debug, Clone)]
 pub struct Emulator {
-    should_run: Arc<AtomicBool>,
+    command_sender: Sender<Command>,
+    command_receiver: Receiver<Command>,
 
-    display: DisplayRef,
-    display_ref_sender: Sender<DisplayRef>,
+    event_sender: Sender<Event>,
+    event_receiver: Receiver<Event>,
 
-    /// A channel receiver for receiving new references to new CHIP8 displays.
-    ///
-    /// The Emulator will send a reference to this channel whenever it switches
-    /// to a brand new display. The UI thread should use this new reference as
-    /// a signal to create new textures for rendering the display to.
-    ///
-    /// This is a bounded channel with a queue size of 1.
-    pub display_ref_receiver: Receiver<DisplayRef>,
+    /// The number of commands sent, and the number the emulator thread has
+    /// finished handling. See [`Self::send_and_wait()`].
+    commands_sent: Arc<Mutex<u64>>,
+    commands_handled: Arc<AtomicU64>,
 
-    frame_ready_to_render: Arc<AtomicBool>,
+    /// The two halves of the channel that finished frames of the display are
+    /// handed to the renderer through. The emulator thread takes the publisher
+    /// when it starts, and the UI thread takes the reader with
+    /// [`Self::take_frame_reader()`].
+    frame_publisher: Arc<Mutex<Option<FramePublisher>>>,
+    frame_reader: Arc<Mutex<Option<FrameReader>>>,
 
+    display: DisplayRef,
     ram: Arc<Mutex<Ram>>,
+    profile: Arc<Mutex<Profile>>,
+    snapshot: Arc<Mutex<Snapshot>>,
+
+    thread: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl Emulator {
@@ -46,112 +95,488 @@ impl Emulator {
     ///
     /// To start it, call [`Self::start`].
     pub fn new() -> Self {
-        let (display_ref_sender, display_ref_receiver) = channel::bounded(1);
+        // Neither thread ever blocks while sending. Commands are unbounded,
+        // since the emulator thread always keeps up with them, but the UI
+        // thread may not handle events while its window is hidden, so they're
+        // bounded and merged when they back up.
+        let (command_sender, command_receiver) = channel::unbounded();
+        let (event_sender, event_receiver) = channel::bounded(EVENT_CAPACITY);
+        let (frame_publisher, frame_reader) = frame_channel();
 
         Self {
-            should_run: Arc::new(AtomicBool::new(false)),
+            command_sender,
+            command_receiver,
+            event_sender,
+            event_receiver,
+            commands_sent: Arc::new(Mutex::new(0)),
+            commands_handled: Arc::new(AtomicU64::new(0)),
+            frame_publisher: Arc::new(Mutex::new(Some(frame_publisher))),
+            frame_reader: Arc::new(Mutex::new(Some(frame_reader))),
             display: Arc::new(Mutex::new(None)),
-            display_ref_sender,
-            display_ref_receiver,
-            frame_ready_to_render: Arc::new(AtomicBool::new(true)),
             ram: Arc::new(Mutex::new(Ram::default())),
+            profile: Arc::new(Mutex::new(Profile::new())),
+            snapshot: Arc::new(Mutex::new(Snapshot::default())),
+            thread: Arc::new(Mutex::new(None)),
         }
     }
 
     /// Start the emulator's main run loop in a background thread.
     ///
-    /// The [`UiThreadWaker`] is used to wake the UI thread whenever repainting
-    /// is required.
+    /// The [`UiThreadWaker`] is used to wake the UI thread whenever an event is
+    /// sent to it.
     ///
     /// Returns an error if the emulator is *already* running.
-    pub fn start(self, waker: impl UiThreadWaker + Send + 'static) -> color_eyre::Result<()> {
-        if self.should_run.load(Ordering::SeqCst) {
+    pub fn start(&self, waker: impl UiThreadWaker + Send + 'static) -> color_eyre::Result<()> {
+        let mut thread = self.thread.lock().unwrap();
+
+        if thread.is_some() {
             return Err(color_eyre::eyre::eyre!("The emulator is already running!"));
         }
 
-        std::thread::Builder::new()
-            .name("emulator".to_string())
-            .spawn(move || {
-                self.main_run_loop(waker);
-            })
-            .wrap_err("Failed to start emulator background thread")?;
+        let emulator = self.clone();
+        let frame_publisher = Arc::clone(&self.frame_publisher);
+        let mut frames = frame_publisher
+            .lock()
+            .unwrap()
+            .take()
+            .expect("The frame publisher is handed back whenever the emulator stops");
+
+        *thread = Some(
+            std::thread::Builder::new()
+                .name("emulator".to_string())
+                .spawn(move || {
+                    emulator.main_run_loop(&mut frames, waker);
+
+                    // Hand the publisher back, so that the emulator can be
+                    // started again.
+                    *frame_publisher.lock().unwrap() = Some(frames);
+                })
+                .wrap_err("Failed to start emulator background thread")?,
+        );
 
         Ok(())
     }
 
-    /// The emulator's main run loop. This is run in a background thread by [`Self::start()`].
-    fn main_run_loop(self, waker: impl UiThreadWaker) {
-        self.should_run.store(true, Ordering::SeqCst);
+    /// Stop the emulator, and wait for its background thread to finish.
+    pub fn stop(&mut self) {
+        tracing::info!("Stopping emulator");
 
-        tracing::info!("Starting main run loop");
+        self.send(Command::Stop);
 
-        self.attach_display(Box::new(Chip8Display::new()), &waker)
-            .unwrap();
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            if thread.join().is_err() {
+                tracing::error!("The emulator thread panicked");
+            }
+        }
+    }
 
-        let mut x = 0;
-        let mut y = 0;
+    /// Send a command to the emulator thread. This never blocks.
+    pub fn send(&self, command: Command) {
+        self.send_counted(command);
+    }
 
-        while self.should_run.load(Ordering::Acquire) {
-            {
-                let mut display = self.display.lock().unwrap();
-                let display = display.as_mut().unwrap();
-                display.flip_pixel(x, y);
+    /// Send a command to the emulator thread, and wait for it to be handled.
+    /// Afterwards, [`Self::snapshot()`] reflects the command.
+    ///
+    /// This is for debugger frontends that run in their own thread. Don't call
+    /// it from the UI thread, which the emulator thread sometimes waits on.
+    pub fn send_and_wait(&self, command: Command) {
+        let sent = self.send_counted(command);
+
+        while self.commands_handled.load(Ordering::Acquire) < sent {
+            let running = match self.thread.lock().unwrap().as_ref() {
+                Some(thread) => !thread.is_finished(),
+                None => false,
+            };
+            if !running {
+                return;
             }
 
-            x = (x + 1) % 64;
-            y = (y + 1) % 32;
+            std::thread::sleep(COMMAND_POLL_INTERVAL);
+        }
+    }
 
-            self.set_frame_ready_to_render();
-            waker.wake_ui_thread();
+    /// Send a command, and return how many commands have been sent in total.
+    fn send_counted(&self, command: Command) -> u64 {
+        // Counting and sending happen under the same lock, so that the count
+        // matches the order commands are handled in.
+        let mut sent = self.commands_sent.lock().unwrap();
+        *sent += 1;
 
-            std::thread::sleep(std::time::Duration::from_millis(10));
-        }
+        // We hold on to a receiver ourselves, so the channel can't be disconnected.
+        self.command_sender.send(command).unwrap();
+
+        *sent
     }
 
-    /// Stop the emulator.
-    pub fn stop(&mut self) {
-        tracing::info!("Stopping emulator");
-        self.should_run.store(false, Ordering::SeqCst);
+    /// Receive the next event from the emulator thread, if there is one. This
+    /// never blocks.
+    pub fn try_recv_event(&self) -> Option<Event> {
+        self.event_receiver.try_recv().ok()
     }
 
-    /// Attach a new display to the emulator. This is usually done when switching
-    /// between emulating different types of CHIP8.
-    fn attach_display(
-        &self,
-        display: Box<dyn Display>,
-        waker: &impl UiThreadWaker,
-    ) -> color_eyre::Result<()> {
-        *self.display.lock().unwrap() = Some(display);
-        self.display_ref_sender.send(Arc::clone(&self.display))?;
-        self.set_frame_ready_to_render();
-        waker.wake_ui_thread();
-        Ok(())
+    /// Take the receiving end of the channel that the emulator thread hands
+    /// finished frames of the display through, for the renderer. This returns
+    /// `None` if it's already been taken.
+    ///
+    /// Reading frames never blocks the emulator thread, unlike locking the
+    /// display itself.
+    pub fn take_frame_reader(&self) -> Option<FrameReader> {
+        self.frame_reader.lock().unwrap().take()
     }
 
-    /// Check if the emulator has prepared a new frame for rendering.
+    /// A reference to the emulator's RAM.
     ///
-    /// If this function returns `true`, the frame should be rendered. Once
-    /// the frame is successfully rendered, notify the emulator with
-    /// [`Self::notify_frame_rendered`].
+    /// The emulator thread holds the lock on the RAM while executing each frame's
+    /// instructions, so keep any locks short.
     #[inline]
-    pub fn is_frame_ready_to_render(&self) -> bool {
-        self.frame_ready_to_render.load(Ordering::Acquire)
+    pub fn ram(&self) -> &Arc<Mutex<Ram>> {
+        &self.ram
     }
 
-    /// Notify the emulator that its latest frame has been successfully rendered.
+    /// The profile recorded by [`Command::StartProfiling`]. It keeps its
+    /// contents after profiling stops, until profiling starts again.
     ///
-    /// This will cause future calls to [`Self::is_frame_ready_to_render`] to
-    /// return `false` until the emulator has a new frame available for rendering.
+    /// The emulator thread holds the lock while executing each frame's
+    /// instructions, so keep any locks short.
     #[inline]
-    pub fn notify_frame_rendered(&self) {
-        self.frame_ready_to_render.store(false, Ordering::Release);
+    pub fn profile(&self) -> &Arc<Mutex<Profile>> {
+        &self.profile
     }
 
-    /// Call to atomically notify other threads that the emulator has a new frame
-    /// ready for rendering, once they check with [`Self::is_frame_ready_to_render`].
-    #[inline]
-    fn set_frame_ready_to_render(&self) {
-        self.frame_ready_to_render.store(true, Ordering::Release);
+    /// A copy of the CPU's registers, the keypad state, and so on, as of the end
+    /// of the last frame or command.
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    /// The emulator's main run loop. This is run in a background thread by [`Self::start()`].
+    fn main_run_loop(self, frames: &mut FramePublisher, waker: impl UiThreadWaker) {
+        tracing::info!("Starting main run loop");
+
+        let mut machine = Machine::new(Arc::clone(&self.ram), Arc::clone(&self.display));
+        let mut paused = false;
+
+        self.send_event(Event::DisplayChanged(Arc::clone(&self.display)), &waker);
+        self.publish_frame(&machine, frames, &waker);
+
+        let mut throttle = Throttle::new(Instant::now(), machine.cpu.cycles);
+        // Set if the display changed in a frame that was skipped rather than
+        // shown, while running faster than real time.
+        let mut display_unshown = false;
+
+        loop {
+            let running = !paused && machine.is_runnable();
+
+            // Handle commands until it's time for the next frame.
+            loop {
+                let received = match throttle.deadline(running) {
+                    Some(deadline) => self.command_receiver.recv_deadline(deadline),
+                    // Uncapped frames still handle any commands that are waiting.
+                    None => self.command_receiver.try_recv().map_err(|e| match e {
+                        TryRecvError::Empty => RecvTimeoutError::Timeout,
+                        TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                    }),
+                };
+                let command = match received {
+                    Ok(command) => command,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+
+                match command {
+                    Command::Stop => {
+                        tracing::info!("Stopping main run loop");
+                        return;
+                    }
+                    command => {
+                        self.handle_command(
+                            command,
+                            &mut machine,
+                            &mut paused,
+                            &mut throttle,
+                            frames,
+                            &waker,
+                        );
+                        self.commands_handled.fetch_add(1, Ordering::AcqRel);
+                    }
+                }
+            }
+
+            if !paused {
+                let report = machine.run_frame();
+                paused = report.breakpoint.is_some();
+                display_unshown |= report.display_changed;
+
+                let now = Instant::now();
+                throttle.measure(machine.cpu.cycles, now);
+
+                // Fast-forwarded frames are only shown at the real frame rate,
+                // and the rest are skipped.
+                if paused || throttle.should_refresh(now) {
+                    self.update_snapshot(&machine, paused, &throttle);
+                    if std::mem::take(&mut display_unshown) {
+                        self.publish_frame(&machine, frames, &waker);
+                    }
+                }
+                self.send_frame_report(report, throttle.pace(), &waker);
+            }
+
+            throttle.end_frame(running, Instant::now());
+        }
+    }
+
+    /// Handle a single command on the emulator thread.
+    fn handle_command(
+        &self,
+        command: Command,
+        machine: &mut Machine,
+        paused: &mut bool,
+        throttle: &mut Throttle,
+        frames: &mut FramePublisher,
+        waker: &impl UiThreadWaker,
+    ) {
+        tracing::debug!(?command, "Handling command");
+
+        // Movies can only be played back exactly if nothing but the keypad
+        // changes while they're recorded.
+        if matches!(
+            command,
+            Command::LoadRom(_)
+                | Command::SetPlatform(_)
+                | Command::SoftReset
+                | Command::HardReset
+                | Command::SetRng(_)
+        ) {
+            machine.stop_movie();
+        }
+
+        let result = match command {
+            Command::LoadRom(rom) => machine.load_rom(rom),
+            Command::SetPlatform(platform) => {
+                let result = machine.set_platform(platform);
+                self.send_event(Event::DisplayChanged(Arc::clone(&self.display)), waker);
+                result
+            }
+            Command::SoftReset => {
+                machine.soft_reset();
+                Ok(())
+            }
+            Command::HardReset => machine.hard_reset(),
+            Command::Pause => {
+                *paused = true;
+                machine.breakpoints_mut().set_step_target(None);
+                Ok(())
+            }
+            Command::Resume => {
+                *paused = false;
+                Ok(())
+            }
+            Command::Step => {
+                *paused = true;
+                machine.breakpoints_mut().set_step_target(None);
+                let report = machine.step();
+                self.send_frame_report(report, throttle.pace(), waker);
+                Ok(())
+            }
+            Command::SetRegister(register, value) => {
+                machine.set_register(register, value);
+                Ok(())
+            }
+            Command::AddBreakpoint(addr) => {
+                machine.breakpoints_mut().add(addr);
+                Ok(())
+            }
+            Command::RemoveBreakpoint(addr) => {
+                machine.breakpoints_mut().remove(addr);
+                Ok(())
+            }
+            Command::SetBreakpointCondition { addr, condition } => {
+                machine.breakpoints_mut().set_condition(addr, condition);
+                Ok(())
+            }
+            Command::AddWatchpoint(watchpoint) => {
+                machine.breakpoints_mut().add_watchpoint(watchpoint);
+                Ok(())
+            }
+            Command::RemoveWatchpoint(watchpoint) => {
+                machine.breakpoints_mut().remove_watchpoint(&watchpoint);
+                Ok(())
+            }
+            Command::SetEventBreakpoints(events) => {
+                machine.breakpoints_mut().set_events(events);
+                Ok(())
+            }
+            Command::RunTo(target) => {
+                machine.breakpoints_mut().set_step_target(Some(target));
+                *paused = false;
+                Ok(())
+            }
+            Command::StartTrace(options) => {
+                machine.start_trace(options);
+                Ok(())
+            }
+            Command::StopTrace => {
+                machine.stop_trace();
+                Ok(())
+            }
+            Command::StartProfiling => {
+                machine.start_profiling(Arc::clone(&self.profile));
+                Ok(())
+            }
+            Command::StopProfiling => {
+                machine.stop_profiling();
+                Ok(())
+            }
+            Command::SetPace(pace) => {
+                // The sound is muted while running faster than real time, so
+                // it has to stop or start again if it's playing.
+                if pace.is_fast() != throttle.pace().is_fast() && machine.cpu.sound_timer > 0 {
+                    let event = if pace.is_fast() {
+                        Event::SoundOff
+                    } else {
+                        Event::SoundOn
+                    };
+                    self.send_event(event, waker);
+                }
+                throttle.set_pace(pace, Instant::now(), machine.cpu.cycles);
+                Ok(())
+            }
+            Command::SetSpeed(instructions_per_frame) => {
+                machine.set_instructions_per_frame(instructions_per_frame);
+                Ok(())
+            }
+            Command::WriteMemory { addr, bytes } => {
+                machine.write_memory(addr, &bytes);
+                Ok(())
+            }
+            Command::SetQuirks(quirks) => {
+                machine.set_quirks(quirks);
+                Ok(())
+            }
+            Command::SetRng(options) => {
+                machine.set_rng_options(options);
+                Ok(())
+            }
+            Command::SetPalette(palette) => {
+                machine.set_palette(palette);
+                Ok(())
+            }
+            Command::SetPersistence(persistence) => {
+                machine.set_persistence(persistence);
+                self.send_event(Event::DisplayChanged(Arc::clone(&self.display)), waker);
+                Ok(())
+            }
+            Command::StartRecording(path) => {
+                if let Err(e) = machine.start_recording(path) {
+                    tracing::warn!("Failed to start recording a movie: {e}");
+                }
+                Ok(())
+            }
+            Command::PlayMovie(movie) => {
+                let platform = machine.platform();
+                if let Err(e) = machine.play_movie(movie) {
+                    tracing::warn!("Failed to play a movie: {e}");
+                }
+                if machine.platform() != platform {
+                    self.send_event(Event::DisplayChanged(Arc::clone(&self.display)), waker);
+                }
+                Ok(())
+            }
+            Command::StopMovie => {
+                machine.stop_movie();
+                Ok(())
+            }
+            Command::StartClip { path, scale } => {
+                machine.start_clip(path, scale);
+                Ok(())
+            }
+            Command::StopClip => {
+                machine.stop_clip();
+                Ok(())
+            }
+            Command::KeyDown(key) => {
+                machine.set_key(key, true);
+                Ok(())
+            }
+            Command::KeyUp(key) => {
+                machine.set_key(key, false);
+                Ok(())
+            }
+            Command::Stop => unreachable!("Stop commands are handled by the main run loop"),
+        };
+
+        if let Err(reason) = result {
+            tracing::error!("Emulator halted: {reason}");
+            self.send_event(Event::Halted(reason), waker);
+        }
+
+        self.update_snapshot(machine, *paused, throttle);
+
+        // Loading, resetting and switching platforms all change the display.
+        self.publish_frame(machine, frames, waker);
+    }
+
+    /// Refresh the snapshot returned by [`Self::snapshot()`].
+    fn update_snapshot(&self, machine: &Machine, paused: bool, throttle: &Throttle) {
+        *self.snapshot.lock().unwrap() = Snapshot::new(machine, paused, throttle.status());
+    }
+
+    /// Send the events corresponding to everything that happened in a frame.
+    /// The display is left to the caller, which skips fast-forwarded frames.
+    fn send_frame_report(&self, report: FrameReport, pace: Pace, waker: &impl UiThreadWaker) {
+        // Fast-forwarding mutes the sound, which would only chirp.
+        if !pace.is_fast() {
+            if report.sound_started {
+                self.send_event(Event::SoundOn, waker);
+            }
+            if report.sound_stopped {
+                self.send_event(Event::SoundOff, waker);
+            }
+        }
+        if let Some(reason) = report.halted {
+            self.send_event(Event::Halted(reason), waker);
+        }
+        if let Some(hit) = report.breakpoint {
+            self.send_event(Event::BreakpointHit(hit), waker);
+        }
+    }
+
+    /// Hand the display's current image to the renderer, and send an
+    /// [`Event::FrameReady`] if the renderer has taken the last frame it was
+    /// told about. See [`FramePublisher::publish()`].
+    fn publish_frame(
+        &self,
+        machine: &Machine,
+        frames: &mut FramePublisher,
+        waker: &impl UiThreadWaker,
+    ) {
+        let woken = match machine.display().lock().unwrap().as_mut() {
+            Some(display) => frames.publish(display.as_mut()),
+            None => false,
+        };
+
+        if woken {
+            self.send_event(Event::FrameReady, waker);
+        }
+    }
+
+    /// Send an event to the UI thread, and wake it up to handle it.
+    fn send_event(&self, event: Event, waker: &impl UiThreadWaker) {
+        // We hold on to a receiver ourselves, so the channel can't be
+        // disconnected, only full.
+        if let Err(TrySendError::Full(event)) = self.event_sender.try_send(event) {
+            tracing::debug!("The UI thread is behind, so merging its events");
+
+            let mut events: Vec<Event> = self.event_receiver.try_iter().collect();
+            events.push(event);
+            for event in event::coalesce(events) {
+                // There's room for one of each kind, since this thread is the
+                // only one that sends events.
+                self.event_sender.try_send(event).unwrap();
+            }
+        }
+        waker.wake_ui_thread();
     }
 }
 
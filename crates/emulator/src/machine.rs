@@ -0,0 +1,887 @@
+//! The emulated CHIP8 machine, independent of any threading or timing.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use cpu::{Cpu, Rng, RngOptions, STACK_SIZE};
+use display::{Display, DisplayRef, Palette};
+use display_chip8::Chip8Display;
+use display_phosphor::{Persistence, PhosphorDisplay};
+use display_xochip::XoChipDisplay;
+use keypad::Keypad;
+use opcode::Instruction;
+use platform::{Platform, Quirks};
+use ram::Ram;
+
+use crate::breakpoints::{Break, Breakpoints};
+use crate::clip::{ClipError, ClipRecorder, ClipStatus};
+use crate::command::Register;
+use crate::event::HaltReason;
+use crate::movie::{self, FrameEnd, Movie, MovieError, MovieInput, MovieStatus, Playback};
+use crate::profile::Profile;
+use crate::trace::{TraceOptions, TraceStatus, Tracer};
+
+/// A complete CHIP8 machine: a CPU, its RAM, display, and keypad, plus the ROM
+/// being run.
+///
+/// The machine doesn't keep time by itself. Call [`Machine::run_frame()`] at
+/// 60 Hz to run it at the right speed, or as fast as possible for headless use.
+#[derive(Debug)]
+pub struct Machine {
+    pub cpu: Cpu,
+    pub keypad: Keypad,
+
+    ram: Arc<Mutex<Ram>>,
+    display: DisplayRef,
+
+    platform: Platform,
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    /// How the CPU's random number generator is created on every hard reset.
+    rng: RngOptions,
+    /// The colours the display draws with.
+    palette: Palette,
+    /// How the display blends frames together to hide flicker, if it does.
+    persistence: Option<Persistence>,
+
+    rom: Option<Vec<u8>>,
+    halted: Option<HaltReason>,
+
+    /// The number of times the machine has been reset, including by loading a
+    /// ROM.
+    resets: u64,
+    /// The number of frames run since the last hard reset.
+    frame: u64,
+    /// The CPU's cycle count when the current frame started.
+    frame_start_cycle: u64,
+    /// Set when stepping or a breakpoint changes the number of instructions
+    /// that the current frame runs.
+    frame_disturbed: bool,
+
+    breakpoints: Breakpoints,
+    /// The address of the instruction that the last breakpoint paused before.
+    /// Breakpoints are ignored there once, so that running again doesn't
+    /// immediately hit the same breakpoint.
+    resume_addr: Option<u16>,
+    /// The breakpoint that stopped the last call to [`Machine::run_frame()`] or
+    /// [`Machine::step()`], if any.
+    last_break: Option<Break>,
+
+    tracer: Option<Tracer>,
+    /// The status of the last trace, once it's stopped.
+    last_trace: Option<TraceStatus>,
+
+    /// The profile being recorded, if profiling.
+    profile: Option<Arc<Mutex<Profile>>>,
+
+    movie: Option<MovieState>,
+    /// How the last movie ended, once it has.
+    last_movie: Option<MovieStatus>,
+
+    clip: Option<ClipRecorder>,
+    /// The status of the last clip, once it's stopped.
+    last_clip: Option<ClipStatus>,
+}
+
+/// A movie being recorded or played.
+#[derive(Debug)]
+enum MovieState {
+    Recording { movie: Movie, path: PathBuf },
+    Playing(Playback),
+}
+
+/// A summary of what happened during a call to [`Machine::run_frame()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameReport {
+    /// True if anything on the display changed.
+    pub display_changed: bool,
+    /// True if the sound timer became non-zero.
+    pub sound_started: bool,
+    /// True if the sound timer reached zero.
+    pub sound_stopped: bool,
+    /// Set if the machine halted during this frame.
+    pub halted: Option<HaltReason>,
+    /// Set if execution stopped at a breakpoint.
+    pub breakpoint: Option<Break>,
+}
+
+impl Machine {
+    /// Create a new machine for the default platform, with no ROM loaded.
+    ///
+    /// The machine's RAM and display are stored in `ram` and `display`, replacing
+    /// whatever was there before, so that other threads can look at them.
+    pub fn new(ram: Arc<Mutex<Ram>>, display: DisplayRef) -> Self {
+        let platform = Platform::default();
+        let quirks = platform.default_quirks();
+
+        *ram.lock().unwrap() = Ram::with_size(platform.ram_size());
+        *display.lock().unwrap() = Some(new_display(platform, &Palette::default(), None));
+
+        Self {
+            cpu: Cpu::new(platform, quirks),
+            keypad: Keypad::new(),
+            ram,
+            display,
+            platform,
+            quirks,
+            instructions_per_frame: platform.default_instructions_per_frame(),
+            rng: RngOptions::default(),
+            palette: Palette::default(),
+            persistence: None,
+            rom: None,
+            halted: None,
+            frame: 0,
+            frame_start_cycle: 0,
+            frame_disturbed: false,
+            resets: 0,
+            breakpoints: Breakpoints::default(),
+            resume_addr: None,
+            last_break: None,
+            tracer: None,
+            last_trace: None,
+            profile: None,
+            movie: None,
+            last_movie: None,
+            clip: None,
+            last_clip: None,
+        }
+    }
+
+    /// The platform being emulated.
+    #[inline]
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// The quirks being emulated.
+    #[inline]
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// The number of instructions executed per call to [`Machine::run_frame()`].
+    #[inline]
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /// How the CPU's random number generator is created.
+    #[inline]
+    pub fn rng_options(&self) -> RngOptions {
+        self.rng
+    }
+
+    /// The colours the display draws with.
+    #[inline]
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// How the display blends frames together to hide flicker, if it does.
+    #[inline]
+    pub fn persistence(&self) -> Option<Persistence> {
+        self.persistence
+    }
+
+    /// The number of frames run since the last hard reset.
+    #[inline]
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// A reference to the machine's RAM, shared with other threads.
+    #[inline]
+    pub fn ram(&self) -> &Arc<Mutex<Ram>> {
+        &self.ram
+    }
+
+    /// A reference to the machine's display, shared with other threads.
+    #[inline]
+    pub fn display(&self) -> &DisplayRef {
+        &self.display
+    }
+
+    /// The ROM currently loaded, if any.
+    #[inline]
+    pub fn rom(&self) -> Option<&[u8]> {
+        self.rom.as_deref()
+    }
+
+    /// The reason the machine halted, if it has.
+    #[inline]
+    pub fn halted(&self) -> Option<HaltReason> {
+        self.halted
+    }
+
+    /// The number of times the machine has been reset, including by loading a
+    /// ROM. Anything worked out about the program should be thrown away when
+    /// this changes.
+    #[inline]
+    pub fn resets(&self) -> u64 {
+        self.resets
+    }
+
+    /// The breakpoints that pause [`Machine::run_frame()`].
+    #[inline]
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    #[inline]
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// The breakpoint that stopped the last frame or step, if any.
+    #[inline]
+    pub fn last_break(&self) -> Option<Break> {
+        self.last_break
+    }
+
+    /// Returns true if the machine has a ROM loaded and hasn't halted.
+    #[inline]
+    pub fn is_runnable(&self) -> bool {
+        self.rom.is_some() && self.halted.is_none()
+    }
+
+    /// Load a ROM, and restart the machine to run it.
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), HaltReason> {
+        self.rom = Some(rom);
+        self.hard_reset()
+    }
+
+    /// Switch to emulating a different platform, and restart the machine.
+    ///
+    /// This attaches a new display, so the renderer must be told about it.
+    pub fn set_platform(&mut self, platform: Platform) -> Result<(), HaltReason> {
+        self.platform = platform;
+        self.quirks = platform.default_quirks();
+        self.instructions_per_frame = platform.default_instructions_per_frame();
+
+        *self.display.lock().unwrap() =
+            Some(new_display(platform, &self.palette, self.persistence));
+
+        self.hard_reset()
+    }
+
+    /// Change the quirks being emulated, without restarting the machine.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.cpu.set_quirks(quirks);
+    }
+
+    /// Set the number of instructions executed per call to [`Machine::run_frame()`].
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    /// Change how the CPU's random number generator is created, and restart it
+    /// with the new options straight away. With a fixed seed, every hard reset
+    /// restarts the same sequence of random numbers.
+    pub fn set_rng_options(&mut self, options: RngOptions) {
+        self.rng = options;
+        self.cpu.set_rng(options.create());
+    }
+
+    /// Change the colours the display draws with, recolouring what's already
+    /// drawn. Displays attached later use them too.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+
+        if let Some(display) = self.display.lock().unwrap().as_mut() {
+            display.set_palette(&palette);
+        }
+    }
+
+    /// Change how the display blends frames together to hide flicker, or stop
+    /// it with `None`. The display is replaced with a new one, with the same
+    /// pixels drawn on it.
+    pub fn set_persistence(&mut self, persistence: Option<Persistence>) {
+        self.persistence = persistence;
+
+        let mut display = self.display.lock().unwrap();
+        let mut new = new_display(self.platform, &self.palette, persistence);
+        if let Some(old) = display.as_ref() {
+            display::copy_pixels(old.as_ref(), new.as_mut());
+        }
+        *display = Some(new);
+    }
+
+    /// Change the value of one of the CPU's registers. Values that are too large
+    /// for the register are truncated, and the stack pointer is limited to the
+    /// size of the stack.
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::V(x) => self.cpu.v[x as usize & 0xF] = value as u8,
+            Register::I => self.cpu.i = value,
+            Register::Pc => self.cpu.pc = value,
+            Register::Sp => self.cpu.sp = (value as usize).min(STACK_SIZE) as u8,
+            Register::DelayTimer => self.cpu.delay_timer = value as u8,
+            Register::SoundTimer => self.cpu.sound_timer = value as u8,
+        }
+    }
+
+    /// Start recording the display to an animated GIF or APNG at the end of
+    /// every frame, stopping any clip already in progress. Errors are reported
+    /// through [`Machine::clip_status()`].
+    pub fn start_clip(&mut self, path: PathBuf, scale: u32) {
+        self.stop_clip();
+
+        match ClipRecorder::create(path.clone(), scale) {
+            Ok(clip) => self.clip = Some(clip),
+            Err(e) => self.last_clip = Some(failed_clip(path, 0, 0, e)),
+        }
+    }
+
+    /// Stop the clip started by [`Machine::start_clip()`], if there is one,
+    /// and finish the file.
+    pub fn stop_clip(&mut self) {
+        if let Some(clip) = self.clip.take() {
+            let status = clip.status();
+
+            match clip.finish() {
+                Ok(status) => self.last_clip = Some(status),
+                Err(e) => {
+                    self.last_clip =
+                        Some(failed_clip(status.path, status.frames, status.images, e));
+                }
+            }
+        }
+    }
+
+    /// The status of the current clip, or the last one if none is running.
+    pub fn clip_status(&self) -> Option<ClipStatus> {
+        match &self.clip {
+            Some(clip) => Some(clip.status()),
+            None => self.last_clip.clone(),
+        }
+    }
+
+    /// Start writing a line to a trace file for every instruction executed,
+    /// stopping any trace already in progress. Errors are reported through
+    /// [`Machine::trace_status()`].
+    pub fn start_trace(&mut self, options: TraceOptions) {
+        self.stop_trace();
+
+        match Tracer::create(options.clone()) {
+            Ok(tracer) => self.tracer = Some(tracer),
+            Err(e) => self.last_trace = Some(failed_trace(options.path, 0, e)),
+        }
+    }
+
+    /// Stop the trace started by [`Machine::start_trace()`], if there is one.
+    pub fn stop_trace(&mut self) {
+        if let Some(tracer) = self.tracer.take() {
+            let status = tracer.status();
+
+            match tracer.finish() {
+                Ok(status) => self.last_trace = Some(status),
+                Err(e) => {
+                    self.last_trace = Some(failed_trace(status.path, status.bytes_written, e));
+                }
+            }
+        }
+    }
+
+    /// The status of the current trace, or the last one if none is running.
+    pub fn trace_status(&self) -> Option<TraceStatus> {
+        match &self.tracer {
+            Some(tracer) => Some(tracer.status()),
+            None => self.last_trace.clone(),
+        }
+    }
+
+    /// Start counting the instructions executed in `profile`, which is cleared
+    /// first. Replaces any profile already being recorded.
+    pub fn start_profiling(&mut self, profile: Arc<Mutex<Profile>>) {
+        *profile.lock().unwrap() = Profile::new();
+        self.profile = Some(profile);
+    }
+
+    /// Stop recording the profile. It's left as it is, for looking at.
+    pub fn stop_profiling(&mut self) {
+        self.profile = None;
+    }
+
+    #[inline]
+    pub fn is_profiling(&self) -> bool {
+        self.profile.is_some()
+    }
+
+    /// Press or release a key on the keypad. Recorded if a movie is being
+    /// recorded, and ignored if one is being played.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if let Some(MovieState::Playing(_)) = self.movie {
+            return;
+        }
+
+        if self.keypad.is_pressed(key) == pressed {
+            return;
+        }
+
+        if pressed {
+            self.keypad.press(key);
+        } else {
+            self.keypad.release(key);
+        }
+
+        if let Some(MovieState::Recording { movie, .. }) = &mut self.movie {
+            movie.inputs.push(MovieInput {
+                frame: self.frame,
+                cycle: self.cpu.cycles,
+                key,
+                pressed,
+            });
+        }
+    }
+
+    /// Restart the ROM, and start recording a movie that's saved to `path` by
+    /// [`Machine::stop_movie()`]. Replaces any movie being recorded or played.
+    ///
+    /// If the random number generator doesn't have a fixed seed, a seed is
+    /// picked for this run and saved in the movie.
+    pub fn start_recording(&mut self, path: PathBuf) -> Result<(), MovieError> {
+        self.stop_movie();
+
+        let result = self.try_start_recording(path);
+        if let Err(e) = &result {
+            self.last_movie = Some(MovieStatus::Failed(e.to_string()));
+        }
+        result
+    }
+
+    fn try_start_recording(&mut self, path: PathBuf) -> Result<(), MovieError> {
+        let rom_sha256 = movie::rom_hash(self.rom.as_deref().ok_or(MovieError::NoRom)?);
+        let seed = match self.rng.seed {
+            Some(seed) => seed,
+            None => Rng::from_time(self.rng.kind).state(),
+        };
+
+        self.hard_reset()?;
+        self.cpu.set_rng(Rng::new(self.rng.kind, seed));
+        self.keypad.release_all();
+
+        let movie = Movie {
+            version: movie::MOVIE_VERSION,
+            rom_sha256,
+            platform: self.platform,
+            quirks: self.quirks,
+            instructions_per_frame: self.instructions_per_frame,
+            rng: self.rng.kind,
+            seed,
+            frames: 0,
+            inputs: Vec::new(),
+            frame_ends: Vec::new(),
+        };
+        self.movie = Some(MovieState::Recording { movie, path });
+
+        Ok(())
+    }
+
+    /// Restart the ROM in the state a movie was recorded in, and play it back.
+    /// The ROM must already be loaded, and the platform is switched to the
+    /// movie's, so the display may be replaced. Replaces any movie being
+    /// recorded or played.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        self.stop_movie();
+
+        let result = self.try_play_movie(movie);
+        if let Err(e) = &result {
+            self.last_movie = Some(MovieStatus::Failed(e.to_string()));
+        }
+        result
+    }
+
+    fn try_play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        movie.check_rom(self.rom.as_deref().ok_or(MovieError::NoRom)?)?;
+
+        if movie.platform != self.platform {
+            self.set_platform(movie.platform)?;
+        }
+        self.set_quirks(movie.quirks);
+        self.set_instructions_per_frame(movie.instructions_per_frame);
+
+        self.hard_reset()?;
+        self.cpu.set_rng(Rng::new(movie.rng, movie.seed));
+        self.keypad.release_all();
+
+        self.movie = Some(MovieState::Playing(Playback::new(movie)));
+
+        Ok(())
+    }
+
+    /// Stop recording or playing a movie. Recordings are saved.
+    pub fn stop_movie(&mut self) {
+        match self.movie.take() {
+            Some(MovieState::Recording { mut movie, path }) => {
+                movie.frames = self.frame;
+
+                self.last_movie = Some(match movie.save(&path) {
+                    Ok(()) => MovieStatus::Saved {
+                        path,
+                        frames: movie.frames,
+                    },
+                    Err(e) => {
+                        tracing::error!(?path, "Failed to save movie: {e}");
+                        MovieStatus::Failed(format!("Failed to save {}: {e}", path.display()))
+                    }
+                });
+            }
+            Some(MovieState::Playing(_)) => self.last_movie = None,
+            None => {}
+        }
+    }
+
+    /// The progress of the movie being recorded or played, or how the last one
+    /// ended.
+    pub fn movie_status(&self) -> Option<MovieStatus> {
+        match &self.movie {
+            Some(MovieState::Recording { movie, .. }) => Some(MovieStatus::Recording {
+                frames: self.frame,
+                inputs: movie.inputs.len(),
+            }),
+            Some(MovieState::Playing(playback)) => Some(MovieStatus::Playing {
+                frame: self.frame,
+                frames: playback.frames(),
+            }),
+            None => self.last_movie.clone(),
+        }
+    }
+
+    /// Write some bytes to memory, starting at `addr`. Addresses wrap around at
+    /// the end of memory.
+    pub fn write_memory(&mut self, addr: u16, bytes: &[u8]) {
+        let mut ram = self.ram.lock().unwrap();
+
+        for (offset, &byte) in bytes.iter().enumerate() {
+            ram.set(addr.wrapping_add(offset as u16), byte);
+        }
+    }
+
+    /// Restart the current ROM from the beginning, without touching memory or
+    /// the display.
+    ///
+    /// The CPU's registers, timers and stack are cleared, and execution restarts
+    /// from [`ram::PROGRAM_START_ADDRESS`]. Since memory isn't reloaded, any
+    /// changes the ROM made to itself are kept.
+    pub fn soft_reset(&mut self) {
+        self.cpu.reset();
+        self.halted = None;
+        self.resets += 1;
+        self.reset_profile_stack();
+    }
+
+    /// Restart the current ROM from the beginning, with fresh memory, a fresh
+    /// CPU, and a clear display.
+    ///
+    /// Memory is reinitialized with [`Ram::with_size()`], which reloads the
+    /// system font, and then the ROM is reloaded.
+    pub fn hard_reset(&mut self) -> Result<(), HaltReason> {
+        self.cpu = Cpu::new(self.platform, self.quirks);
+        self.cpu.set_rng(self.rng.create());
+        self.halted = None;
+        self.frame = 0;
+        self.frame_start_cycle = 0;
+        self.frame_disturbed = false;
+        self.resets += 1;
+        self.reset_profile_stack();
+
+        let mut ram = Ram::with_size(self.platform.ram_size());
+
+        if let Some(rom) = &self.rom {
+            if let Err(max) = ram.load_program(rom) {
+                let reason = HaltReason::RomTooLarge {
+                    size: rom.len(),
+                    max,
+                };
+                self.halted = Some(reason);
+                return Err(reason);
+            }
+        }
+
+        *self.ram.lock().unwrap() = ram;
+
+        if let Some(display) = self.display.lock().unwrap().as_mut() {
+            display.set_hires(false);
+            display.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Run a single 60 Hz frame: execute up to [`Machine::instructions_per_frame()`]
+    /// instructions, and then count the timers down.
+    ///
+    /// Execution stops early if it hits one of the [`Machine::breakpoints()`],
+    /// which is recorded in the report. The timers still count down.
+    ///
+    /// Does nothing if the machine isn't runnable.
+    pub fn run_frame(&mut self) -> FrameReport {
+        let mut report = FrameReport::default();
+
+        if !self.is_runnable() {
+            return report;
+        }
+
+        let sound_was_on = self.cpu.sound_timer > 0;
+
+        let check_breakpoints = !self.breakpoints.is_empty();
+        let playback = match &self.movie {
+            Some(MovieState::Playing(playback)) => Some(playback),
+            _ => None,
+        };
+
+        match playback.map(|playback| playback.frame_end(self.frame)) {
+            // Frames that were stepped through or stopped at a breakpoint while
+            // recording run to exactly where they ended.
+            Some(Some(end)) => {
+                while self.cpu.cycles < end
+                    && report.breakpoint.is_none()
+                    && report.halted.is_none()
+                {
+                    self.run_instructions(1, check_breakpoints, &mut report);
+                }
+            }
+            // Playback carries on with the rest of a frame that was cut short
+            // by a breakpoint.
+            Some(None) => {
+                let done = self.cpu.cycles - self.frame_start_cycle;
+                let count = (self.instructions_per_frame as u64).saturating_sub(done);
+                self.run_instructions(count as u32, check_breakpoints, &mut report);
+            }
+            None => {
+                self.run_instructions(self.instructions_per_frame, check_breakpoints, &mut report);
+            }
+        }
+        self.last_break = report.breakpoint;
+
+        // Stepping is over once anything stops execution.
+        if report.breakpoint.is_some() || report.halted.is_some() {
+            self.breakpoints.set_step_target(None);
+        }
+
+        if report.breakpoint.is_some() {
+            // Movies have to stay in step with the timers, so a breakpoint
+            // during playback pauses partway through the frame.
+            if let Some(MovieState::Playing(_)) = self.movie {
+                return report;
+            }
+            self.frame_disturbed = true;
+        }
+
+        self.cpu.tick_timers();
+        if let Some(display) = self.display.lock().unwrap().as_mut() {
+            report.display_changed |= display.end_frame();
+        }
+        self.end_frame();
+        self.record_clip();
+
+        if let Some(profile) = &self.profile {
+            profile.lock().unwrap().end_frame();
+        }
+
+        let sound_is_on = self.cpu.sound_timer > 0;
+        report.sound_started = !sound_was_on && sound_is_on;
+        report.sound_stopped = sound_was_on && !sound_is_on;
+
+        report
+    }
+
+    /// Execute a single instruction, without counting the timers down. This is
+    /// for stepping through a program in a debugger, so breakpoints are ignored.
+    ///
+    /// Does nothing if the machine isn't runnable.
+    pub fn step(&mut self) -> FrameReport {
+        let mut report = FrameReport::default();
+
+        if self.is_runnable() {
+            self.resume_addr = None;
+            self.last_break = None;
+            self.frame_disturbed = true;
+            self.run_instructions(1, false, &mut report);
+        }
+
+        report
+    }
+
+    /// Execute up to `count` instructions, stopping early if the CPU has to
+    /// wait for something, or if `check_breakpoints` is set and a breakpoint is
+    /// hit.
+    fn run_instructions(&mut self, count: u32, check_breakpoints: bool, report: &mut FrameReport) {
+        let mut ram = self.ram.lock().unwrap();
+        let mut display = self.display.lock().unwrap();
+        let display = display.as_mut().unwrap().as_mut();
+        let mut profile = self.profile.as_ref().map(|profile| profile.lock().unwrap());
+
+        for _ in 0..count {
+            let pc = self.cpu.pc;
+
+            if let Some(MovieState::Playing(playback)) = &mut self.movie {
+                playback.apply(self.frame, self.cpu.cycles, &mut self.keypad);
+            }
+
+            if check_breakpoints && self.resume_addr != Some(pc) {
+                if let Some(reason) = self.breakpoints.check_before(&self.cpu, &ram) {
+                    self.resume_addr = Some(pc);
+                    report.breakpoint = Some(Break {
+                        addr: pc,
+                        reason,
+                        after: false,
+                    });
+                    break;
+                }
+            }
+
+            if let Some(tracer) = &mut self.tracer {
+                if let Err(e) = tracer.record(&self.cpu, &ram) {
+                    let status = tracer.status();
+                    self.tracer = None;
+                    self.last_trace = Some(failed_trace(status.path, status.bytes_written, e));
+                }
+            }
+
+            let sound_was_on = self.cpu.sound_timer > 0;
+
+            match self.cpu.step(&mut ram, display, &self.keypad) {
+                Ok(step) => {
+                    report.display_changed |= step.display_changed;
+
+                    if let Some(profile) = &mut profile {
+                        profile.record(step.addr, &step.instruction);
+                    }
+
+                    // `FX0A` is executed again every step until a key is
+                    // pressed, which shouldn't count as reaching it again.
+                    if !step.waiting_for_key {
+                        self.resume_addr = None;
+                    }
+
+                    if check_breakpoints {
+                        if let Some(reason) =
+                            self.breakpoints.check_after(&self.cpu, &step, sound_was_on)
+                        {
+                            report.breakpoint = Some(Break {
+                                addr: step.addr,
+                                reason,
+                                after: true,
+                            });
+                            break;
+                        }
+                    }
+
+                    // Nothing can change until the next frame if we're waiting
+                    // on a key, or for the vertical blank after drawing.
+                    let waiting_for_vblank = self.quirks.display_wait
+                        && matches!(step.instruction, Instruction::Draw { .. });
+
+                    if step.waiting_for_key || waiting_for_vblank {
+                        break;
+                    }
+                }
+                Err(reason) => {
+                    tracing::warn!("CPU halted: {reason}");
+                    self.halted = Some(reason.into());
+                    report.halted = self.halted;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Move on to the next frame, keeping track of where frames end for the
+    /// movie being recorded or played.
+    fn end_frame(&mut self) {
+        match &mut self.movie {
+            Some(MovieState::Recording { movie, .. }) if self.frame_disturbed => {
+                movie.frame_ends.push(FrameEnd {
+                    frame: self.frame,
+                    cycle: self.cpu.cycles,
+                });
+            }
+            Some(MovieState::Playing(playback)) => playback.end_frame(self.frame),
+            _ => {}
+        }
+
+        self.frame += 1;
+        self.frame_start_cycle = self.cpu.cycles;
+        self.frame_disturbed = false;
+
+        if let Some(MovieState::Playing(playback)) = &self.movie {
+            if playback.is_finished(self.frame) {
+                self.last_movie = Some(MovieStatus::Finished { frames: self.frame });
+                self.movie = None;
+            }
+        }
+    }
+
+    /// Add the display to the clip being recorded, if any.
+    fn record_clip(&mut self) {
+        if let Some(clip) = &mut self.clip {
+            let display = self.display.lock().unwrap();
+            let result = match display.as_ref() {
+                Some(display) => clip.record(display.as_ref()),
+                None => Ok(()),
+            };
+            drop(display);
+
+            if let Err(e) = result {
+                let status = clip.status();
+                self.clip = None;
+                self.last_clip = Some(failed_clip(status.path, status.frames, status.images, e));
+            }
+        }
+    }
+
+    /// The CPU's call stack was cleared, so the profile's is out of date.
+    fn reset_profile_stack(&self) {
+        if let Some(profile) = &self.profile {
+            profile.lock().unwrap().reset_stack();
+        }
+    }
+}
+
+/// Log a trace failing, and describe it.
+fn failed_trace(path: PathBuf, bytes_written: u64, error: io::Error) -> TraceStatus {
+    tracing::error!(?path, "Tracing failed: {error}");
+
+    TraceStatus {
+        path,
+        bytes_written,
+        running: false,
+        full: false,
+        error: Some(error.to_string()),
+    }
+}
+
+/// Log a clip failing, and describe it.
+fn failed_clip(path: PathBuf, frames: u64, images: u64, error: ClipError) -> ClipStatus {
+    tracing::error!(?path, "Recording a clip failed: {error}");
+
+    ClipStatus {
+        path,
+        frames,
+        images,
+        running: false,
+        error: Some(error.to_string()),
+    }
+}
+
+/// Create the right sort of display for some platform, drawing with a palette,
+/// and blending frames together if there's a persistence.
+fn new_display(
+    platform: Platform,
+    palette: &Palette,
+    persistence: Option<Persistence>,
+) -> Box<dyn Display> {
+    let mut display: Box<dyn Display> = match platform {
+        Platform::Chip8 => Box::new(Chip8Display::new()),
+        Platform::SuperChip | Platform::XoChip => Box::new(XoChipDisplay::new()),
+    };
+    display.set_palette(palette);
+
+    match persistence {
+        Some(persistence) => Box::new(PhosphorDisplay::new(display, persistence)),
+        None => display,
+    }
+}
@@ -0,0 +1,311 @@
+//! Input movies: recordings of every keypad change, for replaying a run
+//! exactly.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use keypad::Keypad;
+use platform::{Platform, Quirks};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::event::HaltReason;
+
+/// The version of the movie file format written by this version of the
+/// emulator.
+pub const MOVIE_VERSION: u32 = 1;
+
+/// A recording of a run: the state the machine started in, and every change to
+/// the keypad after that.
+///
+/// Everything else about a run is deterministic, so playing a movie back
+/// reproduces the run exactly, down to the instruction. Movies are saved as
+/// JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Movie {
+    /// The file format version. See [`MOVIE_VERSION`].
+    pub version: u32,
+    /// The SHA-256 hash of the ROM, in hex. See [`rom_hash()`].
+    pub rom_sha256: String,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub rng: cpu::RngKind,
+    pub seed: u32,
+    /// The number of frames recorded.
+    pub frames: u64,
+    /// Every keypad change, in the order they happened.
+    pub inputs: Vec<MovieInput>,
+    /// The cycle counts that frames ended at, for the frames that didn't run
+    /// the usual number of instructions because of stepping or breakpoints.
+    pub frame_ends: Vec<FrameEnd>,
+}
+
+/// A key being pressed or released during a movie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MovieInput {
+    /// The number of frames run since the movie started.
+    pub frame: u64,
+    /// The CPU's cycle count, so that changes made while stepping through a
+    /// frame are replayed at the right instruction.
+    pub cycle: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// The cycle count at the end of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameEnd {
+    pub frame: u64,
+    pub cycle: u64,
+}
+
+/// The reasons a movie can't be loaded or played.
+#[derive(Error, Debug)]
+pub enum MovieError {
+    #[error("Failed to read or write the movie")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid movie file")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Unsupported movie version {0}. Expected version {MOVIE_VERSION}")]
+    UnsupportedVersion(u32),
+
+    #[error("The movie was recorded with a different ROM (SHA-256 {expected})")]
+    WrongRom { expected: String },
+
+    #[error("No ROM is loaded")]
+    NoRom,
+
+    #[error(transparent)]
+    Halted(#[from] HaltReason),
+}
+
+/// The progress of the movie being recorded or played, or how the last one
+/// ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieStatus {
+    Recording {
+        frames: u64,
+        inputs: usize,
+    },
+    Playing {
+        frame: u64,
+        frames: u64,
+    },
+    /// Playback reached the end of the movie. The keypad is back under the
+    /// user's control.
+    Finished {
+        frames: u64,
+    },
+    /// The recording was saved to a file.
+    Saved {
+        path: PathBuf,
+        frames: u64,
+    },
+    Failed(String),
+}
+
+impl Movie {
+    /// Read a movie from a file.
+    pub fn load(path: &Path) -> Result<Self, MovieError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a movie, checking that it's in a version this emulator understands.
+    pub fn read(reader: impl Read) -> Result<Self, MovieError> {
+        let movie: Movie = serde_json::from_reader(reader)?;
+
+        if movie.version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(movie.version));
+        }
+
+        Ok(movie)
+    }
+
+    /// Write the movie to a file, replacing it if it already exists.
+    pub fn save(&self, path: &Path) -> Result<(), MovieError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write(&self, writer: impl Write) -> Result<(), MovieError> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Check that `rom` is the ROM this movie was recorded with.
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        if rom_hash(rom) == self.rom_sha256 {
+            Ok(())
+        } else {
+            Err(MovieError::WrongRom {
+                expected: self.rom_sha256.clone(),
+            })
+        }
+    }
+}
+
+/// The SHA-256 hash of a ROM, in lowercase hex, which identifies the ROM a
+/// movie was recorded with.
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha256::digest(rom)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A movie being played back.
+#[derive(Debug)]
+pub(crate) struct Playback {
+    movie: Movie,
+    /// The index of the next input to apply.
+    next_input: usize,
+    /// The index of the next frame end.
+    next_frame_end: usize,
+}
+
+impl Playback {
+    pub(crate) fn new(movie: Movie) -> Self {
+        Self {
+            movie,
+            next_input: 0,
+            next_frame_end: 0,
+        }
+    }
+
+    /// Apply every input that's due by some frame and cycle.
+    pub(crate) fn apply(&mut self, frame: u64, cycle: u64, keypad: &mut Keypad) {
+        while let Some(input) = self.movie.inputs.get(self.next_input) {
+            if (input.frame, input.cycle) > (frame, cycle) {
+                break;
+            }
+
+            if input.pressed {
+                keypad.press(input.key);
+            } else {
+                keypad.release(input.key);
+            }
+            self.next_input += 1;
+        }
+    }
+
+    /// The cycle count that a frame has to end at, if it was an unusual one.
+    pub(crate) fn frame_end(&self, frame: u64) -> Option<u64> {
+        self.movie
+            .frame_ends
+            .get(self.next_frame_end)
+            .filter(|end| end.frame == frame)
+            .map(|end| end.cycle)
+    }
+
+    /// Move on to the next frame, after `frame` ends.
+    pub(crate) fn end_frame(&mut self, frame: u64) {
+        if self.frame_end(frame).is_some() {
+            self.next_frame_end += 1;
+        }
+    }
+
+    /// Returns true once every input has been applied and every frame played.
+    pub(crate) fn is_finished(&self, frame: u64) -> bool {
+        self.next_input == self.movie.inputs.len() && frame >= self.movie.frames
+    }
+
+    pub(crate) fn frames(&self) -> u64 {
+        self.movie.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::Machine;
+
+    #[test]
+    fn playback_reproduces_recordings() {
+        #[rustfmt::skip]
+        let rom = vec![
+            0xC0, 0xFF, // V0 = random
+            0x80, 0x14, // V0 += V1
+            0xE2, 0xA1, // skip if key V2 isn't pressed
+            0x71, 0x01, // V1 += 1
+            0xF3, 0x07, // V3 = DT
+            0x33, 0x00, // skip if V3 == 0
+            0x12, 0x00, // jump back to the start
+            0xF0, 0x15, // DT = V0
+            0x12, 0x00, // jump back to the start
+        ];
+
+        let new_machine = || {
+            let mut machine =
+                Machine::new(Arc::new(Mutex::new(Default::default())), Default::default());
+            machine.load_rom(rom.clone()).unwrap();
+            machine
+        };
+
+        let dir = std::env::temp_dir().join(format!("rust-chip-movie-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.json");
+
+        let mut recorder = new_machine();
+        recorder.start_recording(path.clone()).unwrap();
+        for frame in 0..20 {
+            if frame == 5 {
+                recorder.set_key(0, true);
+            }
+            if frame == 9 {
+                // Stepping changes the keypad partway through a frame.
+                recorder.step();
+                recorder.step();
+                recorder.set_key(0, false);
+            }
+            // So does stopping at a breakpoint.
+            if frame == 12 {
+                recorder.breakpoints_mut().add(0x200);
+            }
+            if frame == 13 {
+                recorder.breakpoints_mut().remove(0x200);
+            }
+            recorder.run_frame();
+        }
+        recorder.stop_movie();
+
+        assert!(matches!(
+            recorder.movie_status(),
+            Some(MovieStatus::Saved { frames: 20, .. })
+        ));
+
+        let movie = Movie::load(&path).unwrap();
+        assert_eq!(movie.inputs.len(), 2);
+        assert_eq!(movie.frame_ends.len(), 2);
+
+        let mut player = new_machine();
+        player.play_movie(movie).unwrap();
+        for _ in 0..20 {
+            player.run_frame();
+        }
+
+        assert_eq!(
+            player.movie_status(),
+            Some(MovieStatus::Finished { frames: 20 })
+        );
+        assert_eq!(player.cpu.v, recorder.cpu.v);
+        assert_eq!(player.cpu.cycles, recorder.cpu.cycles);
+
+        let mut other_rom = new_machine();
+        other_rom.load_rom(vec![0x12, 0x00]).unwrap();
+        assert!(matches!(
+            other_rom.play_movie(Movie::load(&path).unwrap()),
+            Err(MovieError::WrongRom { .. })
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
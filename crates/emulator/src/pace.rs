@@ -0,0 +1,223 @@
+//! Running the emulator faster or slower than the real machine, and measuring
+//! how fast it actually runs.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::FRAME_DURATION;
+
+/// How often the achieved speed is measured.
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How fast the emulator runs compared to the real machine's 60 Hz frames.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Pace {
+    /// 60 frames a second.
+    #[default]
+    RealTime,
+    /// Some multiple of real time: above 1 to fast-forward, or below 1 for slow
+    /// motion.
+    Scaled(f32),
+    /// As many frames as the host can manage, for benchmarking.
+    Uncapped,
+}
+
+impl Pace {
+    /// The real time between frames, or `None` if they run back to back.
+    pub fn frame_duration(self) -> Option<Duration> {
+        match self {
+            Self::RealTime => Some(FRAME_DURATION),
+            Self::Scaled(scale) => Some(FRAME_DURATION.div_f64(scale.max(0.01) as f64)),
+            Self::Uncapped => None,
+        }
+    }
+
+    /// True if frames run faster than real time. Most of them can't be shown,
+    /// and the sound is muted, since it would only chirp.
+    pub fn is_fast(self) -> bool {
+        match self {
+            Self::RealTime => false,
+            Self::Scaled(scale) => scale > 1.0,
+            Self::Uncapped => true,
+        }
+    }
+}
+
+impl fmt::Display for Pace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RealTime => write!(f, "100%"),
+            Self::Scaled(scale) => write!(f, "{}%", (scale * 100.0).round()),
+            Self::Uncapped => write!(f, "Uncapped"),
+        }
+    }
+}
+
+/// How fast the emulator is running. See [`crate::Snapshot::speed`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SpeedStatus {
+    pub pace: Pace,
+    /// The number of instructions executed per second of real time, measured
+    /// about once a second. `None` until the first measurement.
+    pub instructions_per_second: Option<f64>,
+}
+
+/// Keeps the emulator thread's frames to its [`Pace`], and measures how fast
+/// it actually runs.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    pace: Pace,
+    /// When the next frame should run.
+    next_frame: Instant,
+    /// When the UI should next be refreshed, while running faster than real
+    /// time.
+    next_refresh: Instant,
+
+    /// When the current measurement started, and the CPU's cycle count then.
+    measure_start: Instant,
+    measure_cycles: u64,
+    instructions_per_second: Option<f64>,
+}
+
+impl Throttle {
+    pub(crate) fn new(now: Instant, cycles: u64) -> Self {
+        Self {
+            pace: Pace::default(),
+            next_frame: now + FRAME_DURATION,
+            next_refresh: now,
+            measure_start: now,
+            measure_cycles: cycles,
+            instructions_per_second: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn pace(&self) -> Pace {
+        self.pace
+    }
+
+    /// Change the pace, starting from the next frame. The speed is measured
+    /// afresh, so that it isn't an average of the old and new paces.
+    pub(crate) fn set_pace(&mut self, pace: Pace, now: Instant, cycles: u64) {
+        self.pace = pace;
+        self.next_frame = now;
+        self.measure_start = now;
+        self.measure_cycles = cycles;
+        self.instructions_per_second = None;
+    }
+
+    /// When the next frame should run, or `None` to run it straight away.
+    /// Frames are only uncapped while `running`, so that a paused emulator
+    /// doesn't spin.
+    pub(crate) fn deadline(&self, running: bool) -> Option<Instant> {
+        if running && self.pace == Pace::Uncapped {
+            None
+        } else {
+            Some(self.next_frame)
+        }
+    }
+
+    /// Work out when the frame after this one should run.
+    pub(crate) fn end_frame(&mut self, running: bool, now: Instant) {
+        let duration = if running {
+            self.pace.frame_duration()
+        } else {
+            Some(FRAME_DURATION)
+        };
+
+        match duration {
+            Some(duration) => {
+                self.next_frame += duration;
+
+                // If we've fallen more than a frame behind (e.g. because the
+                // host was suspended), don't try to catch up.
+                if now > self.next_frame + duration {
+                    self.next_frame = now;
+                }
+            }
+            None => self.next_frame = now,
+        }
+    }
+
+    /// Returns true if the UI should be shown this frame. That's every frame,
+    /// unless frames are running faster than real time, in which case it's at
+    /// most once every real frame.
+    pub(crate) fn should_refresh(&mut self, now: Instant) -> bool {
+        if !self.pace.is_fast() {
+            return true;
+        }
+
+        if now < self.next_refresh {
+            return false;
+        }
+
+        self.next_refresh = now + FRAME_DURATION;
+        true
+    }
+
+    /// Count the CPU's cycles, and update the measured speed once enough time
+    /// has passed.
+    pub(crate) fn measure(&mut self, cycles: u64, now: Instant) {
+        // Resetting the machine resets its cycle count.
+        if cycles < self.measure_cycles {
+            self.measure_start = now;
+            self.measure_cycles = cycles;
+            return;
+        }
+
+        let elapsed = now.duration_since(self.measure_start);
+        if elapsed >= MEASURE_INTERVAL {
+            let executed = cycles - self.measure_cycles;
+            self.instructions_per_second = Some(executed as f64 / elapsed.as_secs_f64());
+            self.measure_start = now;
+            self.measure_cycles = cycles;
+        }
+    }
+
+    pub(crate) fn status(&self) -> SpeedStatus {
+        SpeedStatus {
+            pace: self.pace,
+            instructions_per_second: self.instructions_per_second,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_scale_the_frame_duration() {
+        assert_eq!(Pace::RealTime.frame_duration(), Some(FRAME_DURATION));
+        assert_eq!(
+            Pace::Scaled(0.25).frame_duration(),
+            Some(FRAME_DURATION * 4)
+        );
+        assert_eq!(Pace::Uncapped.frame_duration(), None);
+
+        assert!(!Pace::Scaled(0.5).is_fast());
+        assert!(Pace::Scaled(4.0).is_fast());
+        assert_eq!(Pace::Scaled(0.25).to_string(), "25%");
+    }
+
+    #[test]
+    fn fast_frames_refresh_the_ui_at_the_real_frame_rate() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(start, 0);
+        assert!(throttle.should_refresh(start));
+        assert!(throttle.should_refresh(start));
+
+        throttle.set_pace(Pace::Uncapped, start, 0);
+        assert_eq!(throttle.deadline(true), None);
+        assert_eq!(throttle.deadline(false), Some(start));
+
+        assert!(throttle.should_refresh(start));
+        assert!(!throttle.should_refresh(start + FRAME_DURATION / 2));
+        assert!(throttle.should_refresh(start + FRAME_DURATION));
+
+        throttle.measure(1000, start + Duration::from_millis(500));
+        assert_eq!(throttle.status().instructions_per_second, None);
+        throttle.measure(3000, start + Duration::from_secs(2));
+        assert_eq!(throttle.status().instructions_per_second, Some(1500.0));
+    }
+}
@@ -0,0 +1,270 @@
+//! Counting where a program spends its time, to find hot spots.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use opcode::Instruction;
+
+/// The number of addresses counted: every address the PC can hold.
+const ADDRESS_COUNT: usize = 1 << 16;
+
+/// Counts of the instructions executed while profiling, by address, by kind,
+/// and by the subroutines they were executed in.
+///
+/// Subroutines are followed with a call stack of our own, since the CPU's only
+/// records return addresses. If profiling starts inside a subroutine, its
+/// instructions are counted as being outside any subroutine until it returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// The number of instructions executed at each address.
+    addrs: Vec<u64>,
+    /// The number executed of each kind of instruction, by opcode pattern.
+    classes: BTreeMap<&'static str, u64>,
+    /// The number of instructions executed with each call stack, outermost
+    /// subroutine first.
+    stacks: BTreeMap<Vec<u16>, u64>,
+    /// The number of times each subroutine was called.
+    calls: BTreeMap<u16, u64>,
+    /// The subroutines currently being executed.
+    stack: Vec<u16>,
+    instructions: u64,
+    frames: u64,
+}
+
+/// The time spent in a single subroutine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubroutineProfile {
+    pub addr: u16,
+    pub calls: u64,
+    /// Instructions executed in the subroutine, including in the subroutines
+    /// it calls. Recursive calls are only counted once.
+    pub inclusive: u64,
+    /// Instructions executed in the subroutine itself.
+    pub exclusive: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self {
+            addrs: vec![0; ADDRESS_COUNT],
+            classes: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            stack: Vec::new(),
+            instructions: 0,
+            frames: 0,
+        }
+    }
+
+    /// The number of instructions executed at each address, indexed by
+    /// address.
+    pub fn addrs(&self) -> &[u64] {
+        &self.addrs
+    }
+
+    /// The number executed of each kind of instruction, by opcode pattern like
+    /// `8XY4`.
+    pub fn classes(&self) -> &BTreeMap<&'static str, u64> {
+        &self.classes
+    }
+
+    /// The number of instructions executed with each call stack, outermost
+    /// subroutine first. Instructions outside any subroutine have an empty
+    /// stack.
+    pub fn stacks(&self) -> &BTreeMap<Vec<u16>, u64> {
+        &self.stacks
+    }
+
+    /// The total number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The number of frames run.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The time spent in each subroutine, by address.
+    pub fn subroutines(&self) -> Vec<SubroutineProfile> {
+        let mut subroutines: BTreeMap<u16, SubroutineProfile> = self
+            .calls
+            .iter()
+            .map(|(&addr, &calls)| {
+                let profile = SubroutineProfile {
+                    addr,
+                    calls,
+                    inclusive: 0,
+                    exclusive: 0,
+                };
+                (addr, profile)
+            })
+            .collect();
+
+        for (stack, &count) in &self.stacks {
+            for (depth, addr) in stack.iter().enumerate() {
+                let subroutine = subroutines.entry(*addr).or_insert(SubroutineProfile {
+                    addr: *addr,
+                    calls: 0,
+                    inclusive: 0,
+                    exclusive: 0,
+                });
+
+                if !stack[..depth].contains(addr) {
+                    subroutine.inclusive += count;
+                }
+                if depth == stack.len() - 1 {
+                    subroutine.exclusive += count;
+                }
+            }
+        }
+
+        subroutines.into_values().collect()
+    }
+
+    /// Count an instruction that was just executed.
+    pub(crate) fn record(&mut self, addr: u16, instruction: &Instruction) {
+        self.addrs[addr as usize] += 1;
+        *self.classes.entry(instruction.pattern()).or_default() += 1;
+        self.instructions += 1;
+
+        match self.stacks.get_mut(&self.stack) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        match instruction {
+            Instruction::Call { nnn } => {
+                self.stack.push(*nnn);
+                *self.calls.entry(*nnn).or_default() += 1;
+            }
+            Instruction::Return => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Forget the subroutines being executed, when the CPU is reset.
+    pub(crate) fn reset_stack(&mut self) {
+        self.stack.clear();
+    }
+
+    /// Write the number of instructions executed at each address as CSV, with
+    /// a column for the label at each address if `label` gives one. Addresses
+    /// that were never executed are left out.
+    pub fn write_csv(
+        &self,
+        mut writer: impl Write,
+        label: impl Fn(u16) -> Option<String>,
+    ) -> io::Result<()> {
+        writeln!(writer, "address,label,instructions,percent")?;
+
+        for (addr, &count) in self.addrs.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+
+            let addr = addr as u16;
+            let label = label(addr).unwrap_or_default();
+            let percent = count as f64 * 100.0 / self.instructions as f64;
+            writeln!(writer, "{addr:#06X},{label},{count},{percent:.3}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the call stacks in the folded format read by flame graph tools:
+    /// one line per stack, with the subroutines separated by `;`, followed by
+    /// the number of instructions executed. Subroutines are named by `name`.
+    pub fn write_folded(
+        &self,
+        mut writer: impl Write,
+        name: impl Fn(u16) -> String,
+    ) -> io::Result<()> {
+        for (stack, count) in &self.stacks {
+            let names: Vec<_> = std::iter::once("main".to_string())
+                .chain(stack.iter().map(|&addr| name(addr)))
+                .collect();
+            writeln!(writer, "{} {count}", names.join(";"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use ram::Ram;
+
+    use super::*;
+    use crate::Machine;
+
+    #[test]
+    fn counts_time_in_subroutines() {
+        let mut machine =
+            Machine::new(Arc::new(Mutex::new(Ram::new())), Arc::new(Mutex::new(None)));
+        #[rustfmt::skip]
+        machine.load_rom(vec![
+            0x22, 0x06, // 200: call 0x206
+            0x12, 0x02, // 202: jump 0x202
+            0x00, 0x00, // 204
+            0x22, 0x0A, // 206: call 0x20A
+            0x00, 0xEE, // 208: return
+            0x70, 0x01, // 20A: V0 += 1
+            0x00, 0xEE, // 20C: return
+        ]).unwrap();
+
+        let profile = Arc::new(Mutex::new(Profile::new()));
+        machine.start_profiling(Arc::clone(&profile));
+        for _ in 0..6 {
+            machine.step();
+        }
+        machine.stop_profiling();
+
+        let profile = profile.lock().unwrap();
+        assert_eq!(profile.instructions(), 6);
+        assert_eq!(profile.addrs()[0x20A], 1);
+        assert_eq!(profile.classes()["00EE"], 2);
+        assert_eq!(
+            profile.subroutines(),
+            [
+                SubroutineProfile {
+                    addr: 0x206,
+                    calls: 1,
+                    inclusive: 4,
+                    exclusive: 2
+                },
+                SubroutineProfile {
+                    addr: 0x20A,
+                    calls: 1,
+                    inclusive: 2,
+                    exclusive: 2
+                },
+            ]
+        );
+
+        let mut folded = Vec::new();
+        profile
+            .write_folded(&mut folded, |addr| format!("{addr:03X}"))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "main 2\nmain;206 2\nmain;206;20A 2\n"
+        );
+    }
+}
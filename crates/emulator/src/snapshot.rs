@@ -0,0 +1,97 @@
+//! A copy of the machine's state, for the UI thread to look at.
+
+use cpu::{Cpu, Rng, RngOptions};
+use keypad::Keypad;
+use platform::Platform;
+
+use crate::{
+    Break, Breakpoints, ClipStatus, HaltReason, Machine, MovieStatus, SpeedStatus, TraceStatus,
+};
+
+/// A copy of the parts of the machine's state that aren't already shared, like
+/// [`ram::Ram`] and the display.
+///
+/// The emulator thread refreshes its snapshot after every frame and every
+/// command, so it's at most one frame out of date. Get it with
+/// [`crate::Emulator::snapshot()`].
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub cpu: Cpu,
+    pub keypad: Keypad,
+    pub platform: Platform,
+    /// How random numbers are generated whenever the machine is reset.
+    pub rng: RngOptions,
+    /// The random number generator's current state. A generator seeded with
+    /// [`Rng::state()`] carries on with the same numbers.
+    pub generator: Rng,
+    /// True if the emulator is paused.
+    pub paused: bool,
+    /// How fast the emulator is running.
+    pub speed: SpeedStatus,
+    /// Set if the machine halted.
+    pub halted: Option<HaltReason>,
+    /// The number of times the machine has been reset, including by loading a
+    /// ROM.
+    pub resets: u64,
+    pub breakpoints: Breakpoints,
+    /// The breakpoint that the emulator last paused at, if it's still paused
+    /// there.
+    pub last_break: Option<Break>,
+    /// The status of the current or last trace, if there's been one.
+    pub trace: Option<TraceStatus>,
+    /// True while a profile is being recorded.
+    pub profiling: bool,
+    /// The progress of the movie being recorded or played, or how the last one
+    /// ended.
+    pub movie: Option<MovieStatus>,
+    /// The status of the current or last clip, if there's been one.
+    pub clip: Option<ClipStatus>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(machine: &Machine, paused: bool, speed: SpeedStatus) -> Self {
+        Self {
+            cpu: machine.cpu.clone(),
+            keypad: machine.keypad,
+            platform: machine.platform(),
+            rng: machine.rng_options(),
+            generator: *machine.cpu.rng(),
+            paused,
+            speed,
+            halted: machine.halted(),
+            resets: machine.resets(),
+            breakpoints: machine.breakpoints().clone(),
+            last_break: machine.last_break().filter(|_| paused),
+            trace: machine.trace_status(),
+            profiling: machine.is_profiling(),
+            movie: machine.movie_status(),
+            clip: machine.clip_status(),
+        }
+    }
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        let platform = Platform::default();
+        let cpu = Cpu::new(platform, platform.default_quirks());
+        let generator = *cpu.rng();
+
+        Self {
+            cpu,
+            keypad: Keypad::new(),
+            platform,
+            rng: RngOptions::default(),
+            generator,
+            paused: false,
+            speed: SpeedStatus::default(),
+            halted: None,
+            resets: 0,
+            breakpoints: Breakpoints::default(),
+            last_break: None,
+            trace: None,
+            profiling: false,
+            movie: None,
+            clip: None,
+        }
+    }
+}
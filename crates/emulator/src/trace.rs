@@ -0,0 +1,227 @@
+//! Logging every executed instruction to a file, for comparing against other
+//! emulators.
+//!
+//! Each instruction is written on its own line, with the state of the CPU
+//! *before* it was executed:
+//!
+//! ```text
+//! # cycle    pc   opcode   registers                          i      sp   dt    st    ; mnemonic
+//! 0000000012 0206 D015     V=0A0B000000000000000000000000000F I=0050 SP=0 DT=00 ST=00 ; sprite v0 v1 5
+//! ```
+//!
+//! The columns are fixed-width (the opcode column is padded to fit the 4-byte
+//! XO-CHIP `F000 NNNN`), so traces can be compared with `diff`, or cut down to
+//! just the columns another emulator writes. Lines starting with `#` are
+//! comments. The format won't change, other than possibly adding columns
+//! before the `;`.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use cpu::Cpu;
+use disassembler::Syntax;
+use ram::Ram;
+
+/// The header written at the start of every trace.
+const HEADER: &str =
+    "# cycle    pc   opcode   registers                          i      sp   dt    st    ; mnemonic\n";
+
+/// What to trace, and where to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraceOptions {
+    pub path: PathBuf,
+    /// Only instructions at these addresses are traced. If empty, everything
+    /// is.
+    pub ranges: Vec<RangeInclusive<u16>>,
+    /// Stop tracing once the file reaches this size in bytes.
+    pub max_bytes: Option<u64>,
+}
+
+/// How a trace is going.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraceStatus {
+    pub path: PathBuf,
+    pub bytes_written: u64,
+    /// True while instructions are still being traced.
+    pub running: bool,
+    /// True if tracing stopped because the file reached its maximum size.
+    pub full: bool,
+    /// Set if tracing stopped because of an error.
+    pub error: Option<String>,
+}
+
+/// Writes a trace line for each instruction executed.
+pub struct Tracer {
+    writer: Box<dyn Write + Send>,
+    options: TraceOptions,
+    bytes_written: u64,
+    full: bool,
+}
+
+impl Tracer {
+    /// Start tracing to the file in `options`, replacing it if it exists.
+    pub fn create(options: TraceOptions) -> io::Result<Self> {
+        let file = File::create(&options.path)?;
+        Self::new(BufWriter::new(file), options)
+    }
+
+    /// Start tracing to any writer. `options.path` is only used for status
+    /// reports.
+    pub fn new(writer: impl Write + Send + 'static, options: TraceOptions) -> io::Result<Self> {
+        let mut tracer = Self {
+            writer: Box::new(writer),
+            options,
+            bytes_written: 0,
+            full: false,
+        };
+
+        tracer.write(HEADER)?;
+
+        Ok(tracer)
+    }
+
+    /// Trace the instruction at the PC, which is about to be executed.
+    pub fn record(&mut self, cpu: &Cpu, ram: &Ram) -> io::Result<()> {
+        if self.full {
+            return Ok(());
+        }
+
+        let pc = cpu.pc;
+        if !self.options.ranges.is_empty()
+            && !self.options.ranges.iter().any(|range| range.contains(&pc))
+        {
+            return Ok(());
+        }
+
+        let line = format_line(cpu, ram);
+
+        if let Some(max_bytes) = self.options.max_bytes {
+            if self.bytes_written + line.len() as u64 > max_bytes {
+                self.full = true;
+                tracing::warn!(path = ?self.options.path, "Trace reached its maximum size");
+                return self.writer.flush();
+            }
+        }
+
+        self.write(&line)
+    }
+
+    /// Flush everything traced so far to the file.
+    pub fn finish(mut self) -> io::Result<TraceStatus> {
+        self.writer.flush()?;
+        let mut status = self.status();
+        status.running = false;
+        Ok(status)
+    }
+
+    pub fn status(&self) -> TraceStatus {
+        TraceStatus {
+            path: self.options.path.clone(),
+            bytes_written: self.bytes_written,
+            running: !self.full,
+            full: self.full,
+            error: None,
+        }
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.writer.write_all(text.as_bytes())?;
+        self.bytes_written += text.len() as u64;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("options", &self.options)
+            .field("bytes_written", &self.bytes_written)
+            .field("full", &self.full)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Format the trace line for the instruction at the PC.
+fn format_line(cpu: &Cpu, ram: &Ram) -> String {
+    let instruction = cpu.fetch(ram);
+
+    let opcode: String = (0..instruction.len())
+        .map(|offset| format!("{:02X}", ram[cpu.pc.wrapping_add(offset)]))
+        .collect();
+    let v: String = cpu.v.iter().map(|v| format!("{v:02X}")).collect();
+    let mnemonic = Syntax::Octo.format_instruction(&instruction, |addr| format!("{addr:#05X}"));
+
+    format!(
+        "{:010} {:04X} {opcode:<8} V={v} I={:04X} SP={:X} DT={:02X} ST={:02X} ; {mnemonic}\n",
+        cpu.cycles, cpu.pc, cpu.i, cpu.sp, cpu.delay_timer, cpu.sound_timer,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use platform::{Platform, Quirks};
+
+    use super::*;
+
+    /// A writer that can still be read after the tracer has taken it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lines_have_a_stable_format() {
+        let mut cpu = Cpu::new(Platform::XoChip, Quirks::XO_CHIP);
+        let mut ram = Ram::new();
+        ram.load_program(&[0xF0, 0x00, 0x12, 0x34]).unwrap();
+        cpu.v[0] = 0x0A;
+        cpu.v[0xF] = 0x01;
+        cpu.cycles = 12;
+
+        assert_eq!(
+            format_line(&cpu, &ram),
+            "0000000012 0200 F0001234 V=0A000000000000000000000000000001 I=0000 SP=0 DT=00 ST=00 ; i := long 0x1234\n"
+        );
+    }
+
+    #[test]
+    fn filters_and_caps_the_trace() {
+        let output = Shared::default();
+        let options = TraceOptions {
+            path: PathBuf::from("test.trace"),
+            ranges: vec![0x202..=0x203],
+            max_bytes: Some((HEADER.len() + 200) as u64),
+        };
+        let mut tracer = Tracer::new(output.clone(), options).unwrap();
+
+        let mut cpu = Cpu::new(Platform::Chip8, Quirks::CHIP8);
+        let ram = Ram::new();
+
+        for pc in [0x200, 0x202, 0x204, 0x202, 0x202] {
+            cpu.pc = pc;
+            tracer.record(&cpu, &ram).unwrap();
+        }
+
+        let status = tracer.finish().unwrap();
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = output.lines().skip(1).collect();
+
+        // Each line is just under 100 bytes, so only two fit.
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line[11..15] == *"0202"));
+        assert!(status.full);
+        assert_eq!(status.bytes_written, output.len() as u64);
+    }
+}
@@ -0,0 +1,243 @@
+//! A server for GDB's remote serial protocol, so that CHIP8 programs can be
+//! debugged with GDB, LLDB, or anything else that speaks the protocol.
+//!
+//! The server listens on localhost, and serves one debugger at a time. While a
+//! debugger is connected, it pauses and resumes the emulator, and manages
+//! breakpoints and watchpoints through the same [`Command`]s as the built-in
+//! debugger, so the two can be used side by side.
+//!
+//! The registers are described to the debugger with a target description (see
+//! [`target`]), since neither GDB nor LLDB knows about the CHIP8. Memory is
+//! addressed the same way as the CPU addresses it, starting from 0.
+//!
+//! [`Command`]: emulator::Command
+
+mod packet;
+mod session;
+pub mod target;
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use emulator::Emulator;
+
+/// How often the server checks for a new connection.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A running GDB server. It's stopped when dropped.
+#[derive(Debug)]
+pub struct GdbServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GdbServer {
+    /// Start listening for debuggers on a localhost port, in a background
+    /// thread. Port 0 picks any free port; see [`Self::local_addr()`].
+    pub fn start(emulator: &Emulator, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let emulator = emulator.clone();
+            let stop = Arc::clone(&stop);
+
+            std::thread::Builder::new()
+                .name("gdb-stub".to_string())
+                .spawn(move || serve(listener, &emulator, &stop))?
+        };
+
+        tracing::info!(%local_addr, "GDB server listening");
+
+        Ok(Self {
+            local_addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Disconnect any debugger, and stop listening.
+    pub fn stop(self) {
+        // Dropping does the work.
+    }
+}
+
+impl Drop for GdbServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("The GDB server thread panicked");
+            }
+        }
+
+        tracing::info!(local_addr = %self.local_addr, "GDB server stopped");
+    }
+}
+
+/// Accept debuggers one at a time, until `stop` is set.
+fn serve(listener: TcpListener, emulator: &Emulator, stop: &AtomicBool) {
+    while !stop.load(Ordering::Acquire) {
+        let (stream, peer) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(error) => {
+                tracing::warn!("Failed to accept a GDB connection: {error}");
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        tracing::info!(%peer, "Debugger connected");
+
+        let result = stream
+            .set_nonblocking(false)
+            .and_then(|()| session::run(stream, emulator, stop));
+
+        match result {
+            Ok(()) => tracing::info!(%peer, "Debugger disconnected"),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                tracing::info!(%peer, "Debugger disconnected");
+            }
+            Err(error) => tracing::warn!(%peer, "GDB connection failed: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+
+    use emulator::Command;
+    use ui_thread_waker::UiThreadWaker;
+
+    use super::*;
+
+    struct NoUiThread;
+
+    impl UiThreadWaker for NoUiThread {
+        fn wake_ui_thread(&self) {}
+    }
+
+    /// A minimal debugger, with acknowledgements turned off.
+    struct Client(BufReader<TcpStream>);
+
+    impl Client {
+        fn connect(server: &GdbServer) -> Self {
+            let mut client = Self(BufReader::new(
+                TcpStream::connect(server.local_addr()).unwrap(),
+            ));
+
+            client.send("QStartNoAckMode");
+            assert_eq!(client.0.read_until(b'+', &mut Vec::new()).unwrap(), 1);
+            assert_eq!(client.reply(), "OK");
+
+            client
+        }
+
+        fn send(&mut self, packet: &str) {
+            let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            write!(self.0.get_mut(), "${packet}#{checksum:02x}").unwrap();
+        }
+
+        fn reply(&mut self) -> String {
+            let mut packet = Vec::new();
+            self.0.read_until(b'$', &mut packet).unwrap();
+            packet.clear();
+            self.0.read_until(b'#', &mut packet).unwrap();
+            self.0.read_exact(&mut [0; 2]).unwrap();
+
+            packet.pop();
+            String::from_utf8(packet).unwrap()
+        }
+
+        fn exchange(&mut self, packet: &str) -> String {
+            self.send(packet);
+            self.reply()
+        }
+    }
+
+    #[test]
+    fn debugs_a_running_program() {
+        let mut emulator = Emulator::new();
+        emulator.start(NoUiThread).unwrap();
+        emulator.send_and_wait(Command::Pause);
+        #[rustfmt::skip]
+        emulator.send_and_wait(Command::LoadRom(vec![
+            0x60, 0x05, // 200: V0 = 5
+            0xA3, 0x00, // 202: I = 0x300
+            0x70, 0x01, // 204: V0 += 1
+            0x12, 0x04, // 206: jump 0x204
+        ]));
+
+        let server = GdbServer::start(&emulator, 0).unwrap();
+        let mut client = Client::connect(&server);
+
+        assert_eq!(client.exchange("?"), "T05");
+        assert!(client
+            .exchange("qXfer:features:read:target.xml:0,1000")
+            .contains("<reg name=\"pc\" bitsize=\"16\""));
+
+        // Run to a breakpoint.
+        assert_eq!(client.exchange("Z0,204,2"), "OK");
+        assert_eq!(client.exchange("c"), "T05swbreak:;");
+        let registers = client.exchange("g");
+        assert_eq!(registers.len(), 46);
+        assert_eq!(&registers[..2], "05");
+        assert_eq!(&registers[32..40], "00030402");
+
+        // Step over it.
+        assert_eq!(client.exchange("s"), "T05");
+        assert_eq!(client.exchange("p0"), "06");
+        assert_eq!(client.exchange("p11"), "0602");
+
+        assert_eq!(client.exchange("M300,2:abcd"), "OK");
+        assert_eq!(client.exchange("m300,2"), "abcd");
+        assert_eq!(client.exchange("P0=2a"), "OK");
+        assert_eq!(emulator.snapshot().cpu.v[0], 0x2A);
+
+        // 16-bit registers are little-endian, like the debugger's host.
+        assert_eq!(client.exchange("P11=0402"), "OK");
+        assert_eq!(emulator.snapshot().cpu.pc, 0x204);
+        assert_eq!(&client.exchange("g")[36..40], "0402");
+        assert_eq!(client.exchange("P11=04"), "E01");
+
+        // Watchpoints have to fit in memory, and stray bytes aren't commands.
+        assert_eq!(client.exchange("Z2,ffff,2"), "E01");
+        assert_eq!(client.exchange("Z2,10000,1"), "E01");
+        assert_eq!(client.exchange("Z2,0,10000"), "OK");
+        assert_eq!(client.exchange("z2,0,10000"), "OK");
+        assert_eq!(client.exchange("\u{e9}"), "");
+
+        // Run until interrupted.
+        assert_eq!(client.exchange("z0,204,2"), "OK");
+        client.send("c");
+        std::thread::sleep(Duration::from_millis(50));
+        client.0.get_mut().write_all(&[0x03]).unwrap();
+        assert_eq!(client.reply(), "T02");
+        assert!(emulator.snapshot().paused);
+
+        assert_eq!(client.exchange("D"), "OK");
+        assert!(!emulator.snapshot().paused);
+
+        drop(server);
+        emulator.stop();
+    }
+}
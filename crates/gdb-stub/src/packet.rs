@@ -0,0 +1,222 @@
+//! Framing for the remote serial protocol: `$data#checksum` packets, and the
+//! `+`/`-` acknowledgements that go with them.
+
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long a read waits before giving up, so that the session can notice it's
+/// being stopped, or check on the emulator while it's running.
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// The byte GDB sends to interrupt the target while it's running.
+pub const INTERRUPT: u8 = 0x03;
+
+/// Something received from the debugger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received {
+    /// A packet, with escaped bytes already un-escaped.
+    Packet(Vec<u8>),
+    /// A request to interrupt the running target.
+    Interrupt,
+}
+
+/// A connection to a debugger.
+pub struct Connection<'a> {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    stop: &'a AtomicBool,
+    /// True once the debugger has turned acknowledgements off with
+    /// `QStartNoAckMode`.
+    no_ack: bool,
+    /// The last packet sent, to send again if the debugger asks for it.
+    last_sent: Vec<u8>,
+}
+
+impl<'a> Connection<'a> {
+    /// Wrap a connected socket. Reads give up when `stop` is set.
+    pub fn new(stream: TcpStream, stop: &'a AtomicBool) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            stop,
+            no_ack: false,
+            last_sent: Vec::new(),
+        })
+    }
+
+    /// Stop sending and expecting acknowledgements.
+    pub fn disable_acks(&mut self) {
+        self.no_ack = true;
+    }
+
+    /// Wait for the next packet or interrupt. Returns `None` when the debugger
+    /// disconnects or the server is stopped.
+    pub fn receive(&mut self) -> io::Result<Option<Received>> {
+        loop {
+            match self.poll()? {
+                Some(received) => return Ok(Some(received)),
+                None if self.stop.load(Ordering::Acquire) => return Ok(None),
+                None => {}
+            }
+        }
+    }
+
+    /// Like [`Self::receive()`], but gives up and returns `None` after a short
+    /// timeout if nothing arrives.
+    pub fn poll(&mut self) -> io::Result<Option<Received>> {
+        loop {
+            let byte = match self.read_byte(false)? {
+                Some(byte) => byte,
+                None => return Ok(None),
+            };
+
+            match byte {
+                b'$' => {
+                    if let Some(packet) = self.read_packet()? {
+                        return Ok(Some(Received::Packet(packet)));
+                    }
+                }
+                INTERRUPT => return Ok(Some(Received::Interrupt)),
+                // The debugger didn't get our last packet intact.
+                b'-' if !self.no_ack => {
+                    let last_sent = self.last_sent.clone();
+                    self.writer.write_all(&last_sent)?;
+                }
+                // Acknowledgements, and anything else between packets.
+                _ => {}
+            }
+        }
+    }
+
+    /// Send a packet.
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let checksum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{checksum:02x}").as_bytes());
+
+        self.writer.write_all(&packet)?;
+        self.last_sent = packet;
+        Ok(())
+    }
+
+    /// Read the rest of a packet after its `$`, and acknowledge it. Returns
+    /// `None` if the checksum was wrong, after asking for it to be sent again.
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut data = Vec::new();
+        let mut sum = 0u8;
+
+        loop {
+            let byte = self.read_byte(true)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+            if byte == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(byte);
+            data.push(byte);
+        }
+
+        let mut checksum = [0; 2];
+        for digit in &mut checksum {
+            *digit = self.read_byte(true)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+        }
+
+        let checksum = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+        if self.no_ack {
+            return Ok(Some(unescape(&data)));
+        }
+
+        if checksum == Some(sum) {
+            self.writer.write_all(b"+")?;
+            Ok(Some(unescape(&data)))
+        } else {
+            tracing::warn!("Received a GDB packet with a bad checksum");
+            self.writer.write_all(b"-")?;
+            Ok(None)
+        }
+    }
+
+    /// Read a single byte. Returns `None` if nothing arrived in time, unless
+    /// `wait` is true, in which case it keeps waiting until the server is
+    /// stopped.
+    fn read_byte(&mut self, wait: bool) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if !wait || self.stop.load(Ordering::Acquire) {
+                        return Ok(None);
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Escape the bytes that can't appear in binary data, like the responses to
+/// `qXfer` requests.
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+
+    for &byte in data {
+        if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+            escaped.extend_from_slice(&[b'}', byte ^ 0x20]);
+        } else {
+            escaped.push(byte);
+        }
+    }
+
+    escaped
+}
+
+/// Undo [`escape()`].
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => {
+                if let Some(&escaped) = bytes.next() {
+                    unescaped.push(escaped ^ 0x20);
+                }
+            }
+            byte => unescaped.push(byte),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping_round_trips() {
+        let data = b"a$b#c}d*e";
+        let escaped = escape(data);
+
+        assert!(!escaped[1..].contains(&b'$'));
+        assert_eq!(unescape(&escaped), data);
+    }
+}
@@ -0,0 +1,486 @@
+//! A single debugger's connection, and the packets it sends.
+
+use std::io;
+use std::net::TcpStream;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cpu::Access;
+use emulator::{BreakReason, Command, Emulator, HaltReason, Register, Snapshot, Watchpoint};
+
+use crate::packet::{self, Connection, Received};
+use crate::target;
+
+/// The largest packet we accept, which is also advertised to the debugger.
+const PACKET_SIZE: usize = 0x1000;
+
+/// The reply to a packet that's malformed, or asks for something impossible.
+const ERROR: &str = "E01";
+
+/// Signal numbers used in stop replies.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// What to do after handling a packet.
+enum Response {
+    Reply(Vec<u8>),
+    /// The reply has already been sent.
+    Sent,
+    /// Send a reply, and then close the connection.
+    ReplyAndClose(Vec<u8>),
+    Close,
+}
+
+impl From<&str> for Response {
+    fn from(reply: &str) -> Self {
+        Self::Reply(reply.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for Response {
+    fn from(reply: String) -> Self {
+        Self::Reply(reply.into_bytes())
+    }
+}
+
+/// Talk to a debugger until it disconnects or `stop` is set. The emulator is
+/// paused as soon as the debugger connects.
+pub fn run(stream: TcpStream, emulator: &Emulator, stop: &AtomicBool) -> io::Result<()> {
+    let mut session = Session {
+        connection: Connection::new(stream, stop)?,
+        emulator,
+        stop,
+    };
+
+    emulator.send_and_wait(Command::Pause);
+
+    loop {
+        let packet = match session.connection.receive()? {
+            Some(Received::Packet(packet)) => String::from_utf8_lossy(&packet).into_owned(),
+            // We're already stopped, but say so again anyway.
+            Some(Received::Interrupt) => {
+                let reply = session.stop_reply(SIGINT);
+                session.connection.send(&reply)?;
+                continue;
+            }
+            None => return Ok(()),
+        };
+
+        tracing::trace!(packet, "Received GDB packet");
+
+        match session.handle(&packet)? {
+            Response::Reply(reply) => session.connection.send(&reply)?,
+            Response::Sent => {}
+            Response::ReplyAndClose(reply) => {
+                session.connection.send(&reply)?;
+                return Ok(());
+            }
+            Response::Close => return Ok(()),
+        }
+    }
+}
+
+struct Session<'a> {
+    connection: Connection<'a>,
+    emulator: &'a Emulator,
+    stop: &'a AtomicBool,
+}
+
+impl Session<'_> {
+    fn handle(&mut self, packet: &str) -> io::Result<Response> {
+        // The packet may not be ASCII, so split after its first character
+        // rather than its first byte.
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let response = match command {
+            "?" => Response::Reply(self.stop_reply(SIGTRAP)),
+            "g" => self.read_registers().into(),
+            "G" => self.write_registers(args).into(),
+            "p" => self.read_register(args).into(),
+            "P" => self.write_register(args).into(),
+            "m" => self.read_memory(args).into(),
+            "M" => self.write_memory(args).into(),
+            "c" => {
+                if !self.jump(args) {
+                    return Ok(ERROR.into());
+                }
+                match self.resume()? {
+                    Some(reply) => Response::Reply(reply),
+                    None => Response::Close,
+                }
+            }
+            "s" => {
+                if !self.jump(args) {
+                    return Ok(ERROR.into());
+                }
+                self.emulator.send_and_wait(Command::Step);
+                Response::Reply(self.stop_reply(SIGTRAP))
+            }
+            "Z" => self.breakpoint(args, true).into(),
+            "z" => self.breakpoint(args, false).into(),
+            "H" | "T" => "OK".into(),
+            "D" => {
+                self.emulator.send_and_wait(Command::Resume);
+                Response::ReplyAndClose(b"OK".to_vec())
+            }
+            "k" => Response::Close,
+            _ => self.query(packet)?,
+        };
+
+        Ok(response)
+    }
+
+    /// Handle `q` and `Q` packets, and anything else. Packets we don't
+    /// support get an empty reply.
+    fn query(&mut self, packet: &str) -> io::Result<Response> {
+        let response = match packet {
+            _ if packet.starts_with("qSupported") => format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+"
+            )
+            .into(),
+            "QStartNoAckMode" => {
+                // The reply is the last packet that gets acknowledged.
+                self.connection.send(b"OK")?;
+                self.connection.disable_acks();
+                Response::Sent
+            }
+            "qAttached" => "1".into(),
+            "qC" => "QC1".into(),
+            "qfThreadInfo" => "m1".into(),
+            "qsThreadInfo" => "l".into(),
+            "qSymbol::" => "OK".into(),
+            _ => match packet.strip_prefix("qXfer:features:read:target.xml:") {
+                Some(range) => target_description(range),
+                None => "".into(),
+            },
+        };
+
+        Ok(response)
+    }
+
+    fn read_registers(&self) -> String {
+        let cpu = self.emulator.snapshot().cpu;
+
+        (0..target::REGISTER_COUNT)
+            .filter_map(target::register)
+            .map(|register| format_register(register, register.read(&cpu)))
+            .collect()
+    }
+
+    /// Handle `G`, which writes every register at once.
+    fn write_registers(&self, args: &str) -> &'static str {
+        let mut values = Vec::with_capacity(target::REGISTER_COUNT);
+        let mut rest = args;
+
+        for register in (0..target::REGISTER_COUNT).filter_map(target::register) {
+            let digits = target::size(register) * 2;
+            match rest
+                .get(..digits)
+                .and_then(|value| parse_register(register, value))
+            {
+                Some(value) => values.push((register, value)),
+                None => return ERROR,
+            }
+            rest = &rest[digits..];
+        }
+
+        for (register, value) in values {
+            self.emulator
+                .send_and_wait(Command::SetRegister(register, value));
+        }
+
+        "OK"
+    }
+
+    /// Handle `p`, which reads a single register.
+    fn read_register(&self, args: &str) -> String {
+        match parse_hex(args).and_then(|number| target::register(number as usize)) {
+            Some(register) => {
+                let cpu = self.emulator.snapshot().cpu;
+                format_register(register, register.read(&cpu))
+            }
+            None => ERROR.to_string(),
+        }
+    }
+
+    /// Handle `P`, which writes a single register.
+    fn write_register(&self, args: &str) -> &'static str {
+        let (number, value) = match args.split_once('=') {
+            Some(args) => args,
+            None => return ERROR,
+        };
+
+        let register = parse_hex(number).and_then(|number| target::register(number as usize));
+        let value = register.and_then(|register| parse_register(register, value));
+
+        match register.zip(value) {
+            Some((register, value)) => {
+                self.emulator
+                    .send_and_wait(Command::SetRegister(register, value));
+                "OK"
+            }
+            None => ERROR,
+        }
+    }
+
+    /// Handle `m`, which reads memory. Reads that run off the end of memory
+    /// are cut short.
+    fn read_memory(&self, args: &str) -> String {
+        let range = match parse_range(args) {
+            Some(range) => range,
+            None => return ERROR.to_string(),
+        };
+
+        let ram = self.emulator.ram().lock().unwrap();
+        let start = *range.start() as usize;
+        if start >= ram.len() {
+            return ERROR.to_string();
+        }
+        let end = (*range.end() as usize)
+            .min(ram.len() - 1)
+            .min(start + PACKET_SIZE / 2 - 1);
+
+        ram.get_range(start as u16..=end as u16)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Handle `M`, which writes memory.
+    fn write_memory(&self, args: &str) -> &'static str {
+        let (range, data) = match args.split_once(':') {
+            Some(args) => args,
+            None => return ERROR,
+        };
+        let range = match parse_range(range) {
+            Some(range) => range,
+            None => return ERROR,
+        };
+
+        let bytes: Option<Vec<u8>> = (0..data.len() / 2)
+            .map(|index| data.get(index * 2..index * 2 + 2).and_then(parse_hex))
+            .map(|byte| byte.map(|byte| byte as u8))
+            .collect();
+
+        match bytes {
+            Some(bytes) if bytes.len() == range.len() && data.len() % 2 == 0 => {
+                self.emulator.send_and_wait(Command::WriteMemory {
+                    addr: *range.start(),
+                    bytes,
+                });
+                "OK"
+            }
+            _ => ERROR,
+        }
+    }
+
+    /// Move the PC to the address given to `c` or `s`, if there is one.
+    /// Returns false if the address is malformed.
+    fn jump(&self, args: &str) -> bool {
+        if args.is_empty() {
+            return true;
+        }
+
+        match parse_hex(args) {
+            Some(addr) => {
+                self.emulator
+                    .send_and_wait(Command::SetRegister(Register::Pc, addr as u16));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Let the emulator run until it stops, or the debugger interrupts it.
+    /// Returns the stop reply, or `None` if the server is being stopped.
+    fn resume(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.emulator.snapshot().halted.is_some() {
+            return Ok(Some(self.stop_reply(SIGTRAP)));
+        }
+
+        self.emulator.send_and_wait(Command::Resume);
+
+        loop {
+            // Waiting for something from the debugger also paces the loop.
+            if let Some(Received::Interrupt) = self.connection.poll()? {
+                self.emulator.send_and_wait(Command::Pause);
+                return Ok(Some(self.stop_reply(SIGINT)));
+            }
+
+            if self.stop.load(Ordering::Acquire) {
+                return Ok(None);
+            }
+
+            let snapshot = self.emulator.snapshot();
+            if snapshot.paused || snapshot.halted.is_some() {
+                return Ok(Some(self.stop_reply(SIGTRAP)));
+            }
+        }
+    }
+
+    /// Handle `Z` and `z`, which add and remove breakpoints and watchpoints.
+    fn breakpoint(&self, args: &str, add: bool) -> &'static str {
+        // Breakpoints can have conditions after a `;`, which we don't support.
+        let mut args = args.split(';').next().unwrap_or_default().split(',');
+        let kind = args.next();
+        let addr = args.next().and_then(parse_hex);
+        let len = match args.next() {
+            Some(len) => parse_hex(len),
+            None => Some(1),
+        };
+
+        // The watched range has to fit in the address space.
+        let addrs = addr.zip(len).and_then(|(addr, len)| {
+            let end = (addr as u64).checked_add((len as u64).max(1) - 1)?;
+            (end <= u16::MAX as u64).then_some(addr as u16..=end as u16)
+        });
+        let addrs = match addrs {
+            Some(addrs) => addrs,
+            None => return ERROR,
+        };
+        let addr = *addrs.start();
+        let watchpoint = |read, write| Watchpoint {
+            addrs: addrs.clone(),
+            read,
+            write,
+            execute: false,
+        };
+
+        let command = match (kind, add) {
+            (Some("0" | "1"), true) => Command::AddBreakpoint(addr),
+            (Some("0" | "1"), false) => Command::RemoveBreakpoint(addr),
+            (Some("2"), true) => Command::AddWatchpoint(watchpoint(false, true)),
+            (Some("2"), false) => Command::RemoveWatchpoint(watchpoint(false, true)),
+            (Some("3"), true) => Command::AddWatchpoint(watchpoint(true, false)),
+            (Some("3"), false) => Command::RemoveWatchpoint(watchpoint(true, false)),
+            (Some("4"), true) => Command::AddWatchpoint(watchpoint(true, true)),
+            (Some("4"), false) => Command::RemoveWatchpoint(watchpoint(true, true)),
+            // An empty reply means the kind isn't supported.
+            _ => return "",
+        };
+
+        self.emulator.send_and_wait(command);
+        "OK"
+    }
+
+    /// The reply saying why the emulator stopped. `signal` is reported if it
+    /// didn't stop because of a breakpoint or a halt.
+    fn stop_reply(&self, signal: u8) -> Vec<u8> {
+        let mut snapshot = self.emulator.snapshot();
+
+        // Our watchpoints stop before the access, but debuggers expect to see
+        // its effects, so finish off the instruction first.
+        let watched = match snapshot.last_break {
+            Some(hit) if !hit.after => match hit.reason {
+                BreakReason::Watchpoint { addr, access } if access != Access::Execute => {
+                    Some((addr, access))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if watched.is_some() {
+            self.emulator.send_and_wait(Command::Step);
+            snapshot = self.emulator.snapshot();
+        }
+
+        stop_reply(&snapshot, watched, signal).into_bytes()
+    }
+}
+
+/// Format a stop reply. `watched` is the watchpoint access that stopped the
+/// emulator, if any.
+fn stop_reply(snapshot: &Snapshot, watched: Option<(u16, Access)>, signal: u8) -> String {
+    if let Some(reason) = snapshot.halted {
+        return match reason {
+            HaltReason::Cpu(cpu::HaltReason::Exit) => "W00".to_string(),
+            HaltReason::Cpu(
+                cpu::HaltReason::UnknownInstruction { .. } | cpu::HaltReason::MachineCall { .. },
+            ) => format!("T{SIGILL:02x}"),
+            _ => format!("T{SIGSEGV:02x}"),
+        };
+    }
+
+    if let Some((addr, access)) = watched {
+        let kind = match access {
+            Access::Read => "rwatch",
+            _ => "watch",
+        };
+        return format!("T{SIGTRAP:02x}{kind}:{addr:x};");
+    }
+
+    match snapshot.last_break.map(|hit| hit.reason) {
+        Some(BreakReason::Breakpoint) => format!("T{SIGTRAP:02x}swbreak:;"),
+        Some(BreakReason::Watchpoint { .. }) => format!("T{SIGTRAP:02x}hwbreak:;"),
+        Some(_) => format!("T{SIGTRAP:02x}"),
+        None => format!("T{signal:02x}"),
+    }
+}
+
+/// Handle `qXfer:features:read:target.xml:offset,length`.
+fn target_description(range: &str) -> Response {
+    let (offset, len) = match range.split_once(',') {
+        Some((offset, len)) => (parse_hex(offset), parse_hex(len)),
+        None => (None, None),
+    };
+    let (offset, len) = match offset.zip(len) {
+        Some((offset, len)) => (offset as usize, len as usize),
+        None => return ERROR.into(),
+    };
+
+    let description = target::description();
+    let description = description.as_bytes();
+    if offset > description.len() {
+        return ERROR.into();
+    }
+
+    let end = description.len().min(offset + len);
+    let more = if end < description.len() { b'm' } else { b'l' };
+
+    let mut reply = vec![more];
+    reply.extend(packet::escape(&description[offset..end]));
+    Response::Reply(reply)
+}
+
+/// Format a register's value in hex, least significant byte first. See
+/// [`target`].
+fn format_register(register: Register, value: u16) -> String {
+    value.to_le_bytes()[..target::size(register)]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Parse a register's value from hex, least significant byte first. The value
+/// must be exactly as wide as the register.
+fn parse_register(register: Register, text: &str) -> Option<u16> {
+    if text.len() != target::size(register) * 2 {
+        return None;
+    }
+
+    let mut bytes = [0; 2];
+    for (i, byte) in bytes.iter_mut().take(target::size(register)).enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(u16::from_le_bytes(bytes))
+}
+
+/// Parse an `addr,length` pair.
+fn parse_range(args: &str) -> Option<RangeInclusive<u16>> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = parse_hex(addr)?;
+    let len = parse_hex(len)?;
+
+    if len == 0 || addr > u16::MAX.into() {
+        return None;
+    }
+
+    Some(addr as u16..=addr.saturating_add(len - 1).min(u16::MAX.into()) as u16)
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
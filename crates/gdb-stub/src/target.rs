@@ -0,0 +1,83 @@
+//! The CHIP8's registers, as the debugger sees them.
+//!
+//! Neither GDB nor LLDB knows about the CHIP8, so the registers are described
+//! by a target description sent to the debugger. That doesn't say which byte
+//! order the target uses, so debuggers assume their host's, which is
+//! little-endian on x86 and ARM. Registers are sent in this order, least
+//! significant byte first:
+//!
+//! | Number | Register | Bytes |
+//! |--------|----------|-------|
+//! | 0–15   | `v0`–`vf` | 1     |
+//! | 16     | `i`      | 2     |
+//! | 17     | `pc`     | 2     |
+//! | 18     | `sp`     | 1     |
+//! | 19     | `dt`     | 1     |
+//! | 20     | `st`     | 1     |
+
+use std::fmt::Write;
+
+use emulator::Register;
+
+/// The number of registers.
+pub const REGISTER_COUNT: usize = 21;
+
+/// The register with a GDB register number, if there is one.
+pub fn register(number: usize) -> Option<Register> {
+    match number {
+        0..=15 => Some(Register::V(number as u8)),
+        16 => Some(Register::I),
+        17 => Some(Register::Pc),
+        18 => Some(Register::Sp),
+        19 => Some(Register::DelayTimer),
+        20 => Some(Register::SoundTimer),
+        _ => None,
+    }
+}
+
+/// The size of a register in bytes.
+pub fn size(register: Register) -> usize {
+    if register.is_wide() {
+        2
+    } else {
+        1
+    }
+}
+
+/// The target description, in GDB's XML format.
+pub fn description() -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\"?>\n",
+        "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n",
+        "<target version=\"1.0\">\n",
+        "  <feature name=\"org.rust-chip.chip8\">\n",
+    ));
+
+    for number in 0..REGISTER_COUNT {
+        let register = register(number).unwrap();
+        let (name, kind) = match register {
+            Register::V(x) => (format!("v{x:x}"), "uint8"),
+            Register::I => ("i".to_string(), "data_ptr"),
+            Register::Pc => ("pc".to_string(), "code_ptr"),
+            Register::Sp => ("sp".to_string(), "uint8"),
+            Register::DelayTimer => ("dt".to_string(), "uint8"),
+            Register::SoundTimer => ("st".to_string(), "uint8"),
+        };
+        // LLDB needs to be told which register is the PC.
+        let generic = if register == Register::Pc {
+            " generic=\"pc\""
+        } else {
+            ""
+        };
+
+        writeln!(
+            xml,
+            "    <reg name=\"{name}\" bitsize=\"{}\" type=\"{kind}\" regnum=\"{number}\"{generic}/>",
+            size(register) * 8,
+        )
+        .unwrap();
+    }
+
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
@@ -0,0 +1,238 @@
+//! Runs a ROM without a window, as fast as possible, and prints the state it
+//! ends up in.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use clap::Parser;
+use color_eyre::eyre::{bail, Context};
+use sha2::{Digest, Sha256};
+
+use cpu::{RngKind, RngOptions};
+use display::PalettePreset;
+use display_phosphor::Persistence;
+use emulator::{Machine, Movie, MovieStatus};
+use platform::Platform;
+
+/// The number of frames run when there's no movie to say how many: ten
+/// seconds' worth.
+const DEFAULT_FRAMES: u64 = 600;
+
+/// Run a CHIP8, SUPER-CHIP or XO-CHIP ROM without a window, and print the
+/// state it ends up in.
+///
+/// With a fixed seed or a movie, the output is the same every run, so it can
+/// be compared against the output of a known-good run in regression tests.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// The ROM or Octo source file to run.
+    rom: PathBuf,
+
+    /// The platform to run the ROM on. Defaults to the one for the ROM's file
+    /// extension, or CHIP-8.
+    #[arg(short, long)]
+    platform: Option<Platform>,
+
+    /// The number of 60 Hz frames to run. Defaults to the length of the movie,
+    /// or ten seconds.
+    #[arg(short, long)]
+    frames: Option<u64>,
+
+    /// Play back a movie recorded in the emulator.
+    #[arg(short, long)]
+    movie: Option<PathBuf>,
+
+    /// Seed the random number generator. Ignored when playing a movie, which
+    /// has its own seed.
+    #[arg(long, default_value_t = 0)]
+    seed: u32,
+
+    /// The random number generator to use: `xorshift` or `vip`.
+    #[arg(long, default_value_t = RngKind::Xorshift)]
+    rng: RngKind,
+
+    /// Save a screenshot of the display at the end as a PNG, named after the
+    /// ROM and the time unless a path is given.
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    screenshot: Option<Option<PathBuf>>,
+
+    /// Record the display to an animated GIF, or an APNG if the file ends in
+    /// `.png`, with an image for every frame that changed it.
+    #[arg(long, value_name = "PATH")]
+    clip: Option<PathBuf>,
+
+    /// The colours to draw screenshots and clips with: `classic`, `amber`,
+    /// `green-phosphor`, `lcd`, `octo` or `high-contrast`.
+    #[arg(long, default_value_t = PalettePreset::Classic)]
+    palette: PalettePreset,
+
+    /// Fade pixels out over several frames when they're turned off, to hide
+    /// flicker in screenshots and clips. The decay is the fraction of a
+    /// pixel's brightness kept each frame, 0.6 by default.
+    #[arg(long, value_name = "DECAY", num_args = 0..=1, default_missing_value = "0.6")]
+    fade: Option<f32>,
+
+    /// Show the average of the last two frames, to hide flicker in screenshots
+    /// and clips.
+    #[arg(long, conflicts_with = "fade")]
+    blend: bool,
+
+    /// Scale screenshots and clips up by a whole number, up to 32.
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=display::MAX_SCALE as i64),
+    )]
+    scale: u32,
+
+    /// Print how many instructions a second the run achieved. This varies
+    /// from run to run, so it's left out of the output otherwise.
+    #[arg(long)]
+    benchmark: bool,
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let args = Args::parse();
+
+    let (rom, rom_platform) = load_rom(&args.rom)?;
+    let platform = args.platform.or(rom_platform).unwrap_or_default();
+
+    let mut machine = Machine::new(Arc::new(Mutex::new(Default::default())), Default::default());
+    machine.set_platform(platform)?;
+    machine.set_rng_options(RngOptions {
+        kind: args.rng,
+        seed: Some(args.seed),
+    });
+    machine.load_rom(rom)?;
+
+    machine.set_palette(args.palette.palette());
+    if let Some(decay) = args.fade {
+        machine.set_persistence(Some(Persistence::Fade { decay }));
+    } else if args.blend {
+        machine.set_persistence(Some(Persistence::Blend));
+    }
+
+    let mut frames = args.frames.unwrap_or(DEFAULT_FRAMES);
+
+    if let Some(path) = &args.movie {
+        let movie = Movie::load(path).wrap_err_with(|| format!("Failed to load {path:?}"))?;
+        frames = args.frames.unwrap_or(movie.frames);
+        machine.play_movie(movie)?;
+    }
+
+    if let Some(path) = &args.clip {
+        machine.start_clip(path.clone(), args.scale);
+    }
+
+    let start = Instant::now();
+    let start_cycles = machine.cpu.cycles;
+
+    for _ in 0..frames {
+        if !machine.is_runnable() {
+            break;
+        }
+        machine.run_frame();
+    }
+
+    let elapsed = start.elapsed();
+    let instructions = machine.cpu.cycles - start_cycles;
+
+    machine.stop_clip();
+
+    print_state(&machine);
+
+    if args.benchmark {
+        println!(
+            "benchmark: {instructions} instructions in {:.3}s ({:.0} instructions/s)",
+            elapsed.as_secs_f64(),
+            instructions as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+
+    if let Some(status) = machine.clip_status() {
+        if let Some(error) = status.error {
+            bail!("Failed to record a clip to {:?}: {error}", status.path);
+        }
+        println!("clip: {} ({} images)", status.path.display(), status.images);
+    }
+
+    if let Some(path) = &args.screenshot {
+        let path = path.clone().unwrap_or_else(|| {
+            let rom_name = args.rom.file_stem().unwrap_or_default().to_string_lossy();
+            display::capture_file_name(&rom_name, "png").into()
+        });
+        save_screenshot(&machine, args.scale, &path)?;
+        println!("screenshot: {}", path.display());
+    }
+
+    if let Some(MovieStatus::Playing { frame, frames }) = machine.movie_status() {
+        bail!("Stopped at frame {frame}, before the movie's {frames} frames were played");
+    }
+
+    Ok(())
+}
+
+/// Read a ROM, assembling it first if it's Octo source. Returns the platform
+/// that the ROM's file extension or source asks for, if any.
+fn load_rom(path: &Path) -> color_eyre::Result<(Vec<u8>, Option<Platform>)> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    if extension == Some("8o") {
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read Octo source from {path:?}"))?;
+        let program = assembler::assemble(&source)
+            .wrap_err_with(|| format!("Failed to assemble {path:?}"))?;
+
+        return Ok((program.rom, Some(program.platform)));
+    }
+
+    let rom = std::fs::read(path).wrap_err_with(|| format!("Failed to read ROM from {path:?}"))?;
+
+    Ok((rom, extension.and_then(Platform::from_rom_extension)))
+}
+
+/// Save a PNG of the machine's display.
+fn save_screenshot(machine: &Machine, scale: u32, path: &Path) -> color_eyre::Result<()> {
+    let mut display = machine.display().lock().unwrap();
+    match display.as_mut() {
+        Some(display) => {
+            display.update_image();
+            display::save_screenshot(display.as_ref(), scale, path)
+                .wrap_err_with(|| format!("Failed to save a screenshot to {path:?}"))
+        }
+        None => bail!("There's no display to take a screenshot of"),
+    }
+}
+
+/// Print the machine's state, one `name: value` pair per line.
+fn print_state(machine: &Machine) {
+    let cpu = &machine.cpu;
+
+    println!("frames: {}", machine.frame());
+    println!("cycles: {}", cpu.cycles);
+    match machine.halted() {
+        Some(reason) => println!("halted: {reason}"),
+        None => println!("halted: no"),
+    }
+
+    let v: Vec<_> = cpu.v.iter().map(|v| format!("{v:02X}")).collect();
+    println!("v: {}", v.join(" "));
+    println!("i: {:04X}", cpu.i);
+    println!("pc: {:04X}", cpu.pc);
+    println!("sp: {}", cpu.sp);
+    println!("dt: {}", cpu.delay_timer);
+    println!("st: {}", cpu.sound_timer);
+
+    let mut display = machine.display().lock().unwrap();
+    if let Some(display) = display.as_mut() {
+        display.update_image();
+        let hash = Sha256::digest(display.as_rgba8_image().as_raw());
+        let hash: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        println!("display: {hash}");
+    }
+}
@@ -0,0 +1,212 @@
+//! Runs test ROMs headlessly and compares the display they end up with against
+//! golden images in `tests/golden`.
+//!
+//! Goldens are text, one character per pixel: `.` for a pixel that's off, `#`
+//! for one on the first plane, and the hex digit of the plane mask otherwise.
+//!
+//! The goldens of vendored ROMs are written by hand from the pass screen that
+//! the ROM's author documents, so that they check this emulator against
+//! something independent of it. Only the goldens of the Octo stand-ins are
+//! written from the current output, by running with `UPDATE_GOLDEN=1`; check
+//! the diff by eye before committing it.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use cpu::{RngKind, RngOptions};
+use emulator::Machine;
+use platform::Platform;
+
+/// A test ROM, and how to run it.
+struct Case {
+    /// The name of the ROM in `tests/roms`, and of its golden.
+    name: &'static str,
+    /// The ROM's file in `tests/roms`: a ROM, or Octo source.
+    rom: &'static str,
+    /// The platform to run on, instead of the one the ROM asks for.
+    platform: Option<Platform>,
+    /// The number of frames to run, unless the ROM exits first.
+    frames: u64,
+    /// Keypad changes, as `(frame, key, pressed)`, made before that frame runs.
+    keys: &'static [(u64, u8, bool)],
+}
+
+impl Case {
+    const fn new(name: &'static str, rom: &'static str) -> Self {
+        Self {
+            name,
+            rom,
+            platform: None,
+            frames: 120,
+            keys: &[],
+        }
+    }
+
+    const fn on(mut self, platform: Platform) -> Self {
+        self.platform = Some(platform);
+        self
+    }
+}
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+/// Run a case and render the display it ends up with.
+fn run(case: &Case) -> String {
+    let path = tests_dir().join("roms").join(case.rom);
+
+    let (rom, rom_platform) = if case.rom.ends_with(".8o") {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let program = assembler::assemble(&source)
+            .unwrap_or_else(|err| panic!("Failed to assemble {}: {err}", case.rom));
+        (program.rom, Some(program.platform))
+    } else {
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        (
+            std::fs::read(&path).unwrap(),
+            extension.and_then(Platform::from_rom_extension),
+        )
+    };
+
+    let mut machine = Machine::new(Arc::new(Mutex::new(Default::default())), Default::default());
+    machine
+        .set_platform(case.platform.or(rom_platform).unwrap_or_default())
+        .unwrap();
+    machine.set_rng_options(RngOptions {
+        kind: RngKind::Xorshift,
+        seed: Some(0),
+    });
+    machine.load_rom(rom).unwrap();
+
+    for frame in 0..case.frames {
+        for &(_, key, pressed) in case.keys.iter().filter(|(at, ..)| *at == frame) {
+            machine.set_key(key, pressed);
+        }
+        if !machine.is_runnable() {
+            break;
+        }
+        machine.run_frame();
+    }
+
+    let display = machine.display().lock().unwrap();
+    let display = display.as_ref().expect("The machine has no display");
+    let (width, height) = display.dimensions();
+
+    let mut image = String::new();
+    for y in 0..height {
+        for x in 0..width {
+            let c = match display.pixel_planes(x, y) {
+                0 => '.',
+                1 => '#',
+                planes => char::from_digit(planes as u32, 16).unwrap(),
+            };
+            image.push(c);
+        }
+        image.push('\n');
+    }
+
+    if let Some(reason) = machine.halted() {
+        writeln!(image, "halted: {reason}").unwrap();
+    }
+
+    image
+}
+
+/// Run a case, and check its display against the golden, or write the golden
+/// if `UPDATE_GOLDEN` is set.
+fn check(case: Case) {
+    let actual = run(&case);
+    let golden = tests_dir()
+        .join("golden")
+        .join(format!("{}.txt", case.name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() && case.rom.ends_with(".8o") {
+        std::fs::write(&golden, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&golden).unwrap_or_else(|_| {
+        panic!(
+            "There's no golden image for {} at {golden:?}. Run with UPDATE_GOLDEN=1 to write it",
+            case.name
+        )
+    });
+    // Goldens checked out on Windows may have CRLF line endings.
+    let expected = expected.replace("\r\n", "\n");
+
+    if actual != expected {
+        let row = actual
+            .lines()
+            .zip(expected.lines())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| actual.lines().count().min(expected.lines().count()));
+
+        let path = std::env::temp_dir().join(format!("rust-chip-{}.txt", case.name));
+        std::fs::write(&path, &actual).unwrap();
+
+        panic!(
+            "{}'s display doesn't match its golden image, starting at row {row}. The actual \
+             display was written to {path:?}",
+            case.name
+        );
+    }
+}
+
+#[test]
+fn ibm_logo() {
+    check(Case::new("ibm-logo", "ibm-logo.ch8"));
+}
+
+#[test]
+fn opcodes() {
+    check(Case::new("opcodes", "opcodes.8o"));
+}
+
+#[test]
+fn flags() {
+    check(Case::new("flags", "flags.8o"));
+}
+
+#[test]
+fn quirks_chip8() {
+    check(Case::new("quirks-chip8", "quirks.8o").on(Platform::Chip8));
+}
+
+#[test]
+fn quirks_super_chip() {
+    check(Case::new("quirks-schip", "quirks.8o").on(Platform::SuperChip));
+}
+
+#[test]
+fn quirks_xo_chip() {
+    check(Case::new("quirks-xochip", "quirks.8o").on(Platform::XoChip));
+}
+
+#[test]
+fn keypad() {
+    check(Case {
+        keys: &[
+            (5, 0x1, true),
+            (8, 0x1, false),
+            (12, 0xA, true),
+            (15, 0xA, false),
+            (20, 0xF, true),
+            (23, 0xF, false),
+            (30, 0x5, true),
+            (40, 0x5, false),
+        ],
+        ..Case::new("keypad", "keypad.8o")
+    });
+}
+
+#[test]
+fn super_chip() {
+    check(Case::new("schip", "schip.8o"));
+}
+
+#[test]
+fn xo_chip() {
+    check(Case::new("xochip", "xochip.8o"));
+}
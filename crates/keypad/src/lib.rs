@@ -0,0 +1,70 @@
+//! The CHIP8 system's 16-key hexadecimal keypad.
+
+/// The number of keys on the keypad.
+pub const KEY_COUNT: usize = 16;
+
+/// The CHIP8's hexadecimal keypad.
+///
+/// The COSMAC VIP's keypad had 16 keys, labelled `0` through `F`, laid out like
+/// so:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+///
+/// Keys are identified by their hexadecimal value. Only the bottom 4 bits of a
+/// key are used, so out-of-range keys wrap around instead of panicking.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Keypad {
+    keys: [bool; KEY_COUNT],
+}
+
+impl Keypad {
+    /// Create a new keypad, with no keys pressed.
+    pub const fn new() -> Self {
+        Self {
+            keys: [false; KEY_COUNT],
+        }
+    }
+
+    /// Mark a key as being pressed.
+    #[inline]
+    pub fn press(&mut self, key: u8) {
+        self.keys[key_to_usize(key)] = true;
+    }
+
+    /// Mark a key as being released.
+    #[inline]
+    pub fn release(&mut self, key: u8) {
+        self.keys[key_to_usize(key)] = false;
+    }
+
+    /// Returns true if a key is currently pressed.
+    #[inline]
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.keys[key_to_usize(key)]
+    }
+
+    /// Release every key.
+    pub fn release_all(&mut self) {
+        self.keys = [false; KEY_COUNT];
+    }
+
+    /// Iterate over every key that is currently pressed, in ascending order.
+    pub fn pressed_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter(|(_, pressed)| **pressed)
+            .map(|(key, _)| key as u8)
+    }
+}
+
+/// Chop off everything but the bottom 4 bits of a key.
+#[inline]
+const fn key_to_usize(key: u8) -> usize {
+    (key & 0xF) as usize
+}
@@ -0,0 +1,381 @@
+//! Decoding of raw CHIP8, SUPER-CHIP and XO-CHIP opcodes into instructions.
+
+use platform::Platform;
+
+/// A single, decoded CHIP8 instruction.
+///
+/// Almost every instruction is 2 bytes long. The only exception is the XO-CHIP
+/// instruction `F000 NNNN`, which is followed by a 16-bit address and is thus 4
+/// bytes long. See [`Instruction::len()`].
+///
+/// In the documentation below, `X` and `Y` are register indices, `N` is a 4-bit
+/// constant, `NN` is an 8-bit constant, and `NNN` is a 12-bit address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `00CN` (SUPER-CHIP): Scroll the display down by `N` pixels.
+    ScrollDown { n: u8 },
+    /// `00DN` (XO-CHIP): Scroll the display up by `N` pixels.
+    ScrollUp { n: u8 },
+    /// `00E0`: Clear the display.
+    ClearScreen,
+    /// `00EE`: Return from a subroutine.
+    Return,
+    /// `00FB` (SUPER-CHIP): Scroll the display right by 4 pixels.
+    ScrollRight,
+    /// `00FC` (SUPER-CHIP): Scroll the display left by 4 pixels.
+    ScrollLeft,
+    /// `00FD` (SUPER-CHIP): Exit the interpreter.
+    Exit,
+    /// `00FE` (SUPER-CHIP): Switch to the 64x32 low-resolution mode.
+    LowRes,
+    /// `00FF` (SUPER-CHIP): Switch to the 128x64 high-resolution mode.
+    HighRes,
+    /// `0NNN`: Call a machine language routine on the host computer. Not
+    /// supported by any modern interpreter.
+    MachineCall { nnn: u16 },
+    /// `1NNN`: Jump to `NNN`.
+    Jump { nnn: u16 },
+    /// `2NNN`: Call the subroutine at `NNN`.
+    Call { nnn: u16 },
+    /// `3XNN`: Skip the next instruction if `VX == NN`.
+    SkipIfEqualImm { x: u8, nn: u8 },
+    /// `4XNN`: Skip the next instruction if `VX != NN`.
+    SkipIfNotEqualImm { x: u8, nn: u8 },
+    /// `5XY0`: Skip the next instruction if `VX == VY`.
+    SkipIfEqual { x: u8, y: u8 },
+    /// `5XY2` (XO-CHIP): Save `VX` through `VY` to memory starting at `I`.
+    SaveRange { x: u8, y: u8 },
+    /// `5XY3` (XO-CHIP): Load `VX` through `VY` from memory starting at `I`.
+    LoadRange { x: u8, y: u8 },
+    /// `6XNN`: `VX = NN`.
+    LoadImm { x: u8, nn: u8 },
+    /// `7XNN`: `VX += NN`, without affecting `VF`.
+    AddImm { x: u8, nn: u8 },
+    /// `8XY0`: `VX = VY`.
+    Move { x: u8, y: u8 },
+    /// `8XY1`: `VX |= VY`.
+    Or { x: u8, y: u8 },
+    /// `8XY2`: `VX &= VY`.
+    And { x: u8, y: u8 },
+    /// `8XY3`: `VX ^= VY`.
+    Xor { x: u8, y: u8 },
+    /// `8XY4`: `VX += VY`, with `VF` set to the carry.
+    Add { x: u8, y: u8 },
+    /// `8XY5`: `VX -= VY`, with `VF` set to the inverted borrow.
+    Sub { x: u8, y: u8 },
+    /// `8XY6`: `VX = VY >> 1`, with `VF` set to the bit shifted out.
+    ShiftRight { x: u8, y: u8 },
+    /// `8XY7`: `VX = VY - VX`, with `VF` set to the inverted borrow.
+    SubReverse { x: u8, y: u8 },
+    /// `8XYE`: `VX = VY << 1`, with `VF` set to the bit shifted out.
+    ShiftLeft { x: u8, y: u8 },
+    /// `9XY0`: Skip the next instruction if `VX != VY`.
+    SkipIfNotEqual { x: u8, y: u8 },
+    /// `ANNN`: `I = NNN`.
+    LoadIndex { nnn: u16 },
+    /// `BNNN`: Jump to `NNN + V0`.
+    JumpOffset { nnn: u16 },
+    /// `CXNN`: `VX = random() & NN`.
+    Random { x: u8, nn: u8 },
+    /// `DXYN`: Draw an `N`-row sprite from memory at `I` to `(VX, VY)`, with `VF`
+    /// set if any pixels were erased.
+    Draw { x: u8, y: u8, n: u8 },
+    /// `EX9E`: Skip the next instruction if the key `VX` is pressed.
+    SkipIfKey { x: u8 },
+    /// `EXA1`: Skip the next instruction if the key `VX` is not pressed.
+    SkipIfNotKey { x: u8 },
+    /// `F000 NNNN` (XO-CHIP): `I = NNNN`.
+    LoadIndexLong { nnnn: u16 },
+    /// `FN01` (XO-CHIP): Select the bit planes that drawing instructions affect.
+    SelectPlanes { n: u8 },
+    /// `F002` (XO-CHIP): Load 16 bytes of audio pattern data from memory at `I`.
+    LoadAudio,
+    /// `FX07`: `VX = delay timer`.
+    GetDelay { x: u8 },
+    /// `FX0A`: Wait for a key press, and store it in `VX`.
+    WaitKey { x: u8 },
+    /// `FX15`: `delay timer = VX`.
+    SetDelay { x: u8 },
+    /// `FX18`: `sound timer = VX`.
+    SetSound { x: u8 },
+    /// `FX1E`: `I += VX`.
+    AddIndex { x: u8 },
+    /// `FX29`: Point `I` at the small font character for the digit in `VX`.
+    FontChar { x: u8 },
+    /// `FX30` (SUPER-CHIP): Point `I` at the large font character for the digit
+    /// in `VX`.
+    BigFontChar { x: u8 },
+    /// `FX33`: Store the binary-coded decimal representation of `VX` at `I`.
+    Bcd { x: u8 },
+    /// `FX3A` (XO-CHIP): Set the audio pitch register to `VX`.
+    SetPitch { x: u8 },
+    /// `FX55`: Store `V0` through `VX` to memory starting at `I`.
+    Store { x: u8 },
+    /// `FX65`: Load `V0` through `VX` from memory starting at `I`.
+    Load { x: u8 },
+    /// `FX75` (SUPER-CHIP): Save `V0` through `VX` to the persistent flag
+    /// registers.
+    SaveFlags { x: u8 },
+    /// `FX85` (SUPER-CHIP): Load `V0` through `VX` from the persistent flag
+    /// registers.
+    LoadFlags { x: u8 },
+    /// An opcode that isn't a valid instruction on the platform it was decoded
+    /// for.
+    Unknown { opcode: u16 },
+}
+
+impl Instruction {
+    /// Decode a single instruction for some platform.
+    ///
+    /// `next_word` is the 16-bit word immediately following `opcode` in memory.
+    /// It's only used by the 4-byte XO-CHIP instruction `F000 NNNN`, and is
+    /// ignored otherwise.
+    ///
+    /// Instructions belonging to a newer platform than `platform` decode to
+    /// [`Instruction::Unknown`], or [`Instruction::MachineCall`] where the
+    /// original CHIP8 would have interpreted them as such.
+    pub const fn decode(opcode: u16, next_word: u16, platform: Platform) -> Self {
+        use Instruction::*;
+
+        let x = ((opcode >> 8) & 0xF) as u8;
+        let y = ((opcode >> 4) & 0xF) as u8;
+        let n = (opcode & 0xF) as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        let hires = platform.has_hires();
+        let xo = platform.has_xo_chip_extensions();
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => ClearScreen,
+                0x00EE => Return,
+                0x00FB if hires => ScrollRight,
+                0x00FC if hires => ScrollLeft,
+                0x00FD if hires => Exit,
+                0x00FE if hires => LowRes,
+                0x00FF if hires => HighRes,
+                _ if opcode & 0xFFF0 == 0x00C0 && hires => ScrollDown { n },
+                _ if opcode & 0xFFF0 == 0x00D0 && xo => ScrollUp { n },
+                _ if hires => Unknown { opcode },
+                _ => MachineCall { nnn },
+            },
+            0x1 => Jump { nnn },
+            0x2 => Call { nnn },
+            0x3 => SkipIfEqualImm { x, nn },
+            0x4 => SkipIfNotEqualImm { x, nn },
+            0x5 => match n {
+                0x0 => SkipIfEqual { x, y },
+                0x2 if xo => SaveRange { x, y },
+                0x3 if xo => LoadRange { x, y },
+                _ => Unknown { opcode },
+            },
+            0x6 => LoadImm { x, nn },
+            0x7 => AddImm { x, nn },
+            0x8 => match n {
+                0x0 => Move { x, y },
+                0x1 => Or { x, y },
+                0x2 => And { x, y },
+                0x3 => Xor { x, y },
+                0x4 => Add { x, y },
+                0x5 => Sub { x, y },
+                0x6 => ShiftRight { x, y },
+                0x7 => SubReverse { x, y },
+                0xE => ShiftLeft { x, y },
+                _ => Unknown { opcode },
+            },
+            0x9 => match n {
+                0x0 => SkipIfNotEqual { x, y },
+                _ => Unknown { opcode },
+            },
+            0xA => LoadIndex { nnn },
+            0xB => JumpOffset { nnn },
+            0xC => Random { x, nn },
+            0xD => Draw { x, y, n },
+            0xE => match nn {
+                0x9E => SkipIfKey { x },
+                0xA1 => SkipIfNotKey { x },
+                _ => Unknown { opcode },
+            },
+            _ => match nn {
+                0x00 if xo && x == 0 => LoadIndexLong { nnnn: next_word },
+                0x01 if xo => SelectPlanes { n: x },
+                0x02 if xo && x == 0 => LoadAudio,
+                0x07 => GetDelay { x },
+                0x0A => WaitKey { x },
+                0x15 => SetDelay { x },
+                0x18 => SetSound { x },
+                0x1E => AddIndex { x },
+                0x29 => FontChar { x },
+                0x30 if hires => BigFontChar { x },
+                0x33 => Bcd { x },
+                0x3A if xo => SetPitch { x },
+                0x55 => Store { x },
+                0x65 => Load { x },
+                0x75 if hires => SaveFlags { x },
+                0x85 if hires => LoadFlags { x },
+                _ => Unknown { opcode },
+            },
+        }
+    }
+
+    /// Decode the instruction starting at the beginning of `bytes`.
+    ///
+    /// Missing bytes (for example, at the very end of a ROM) are treated as
+    /// zeroes.
+    pub fn decode_bytes(bytes: &[u8], platform: Platform) -> Self {
+        let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+
+        let opcode = u16::from_be_bytes([byte(0), byte(1)]);
+        let next_word = u16::from_be_bytes([byte(2), byte(3)]);
+
+        Self::decode(opcode, next_word, platform)
+    }
+
+    /// The length of this instruction in memory, in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub const fn len(&self) -> u16 {
+        match self {
+            Instruction::LoadIndexLong { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    /// The opcode pattern that this instruction was decoded from, like `8XY4`,
+    /// for grouping instructions by kind.
+    pub const fn pattern(&self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            ScrollDown { .. } => "00CN",
+            ScrollUp { .. } => "00DN",
+            ClearScreen => "00E0",
+            Return => "00EE",
+            ScrollRight => "00FB",
+            ScrollLeft => "00FC",
+            Exit => "00FD",
+            LowRes => "00FE",
+            HighRes => "00FF",
+            MachineCall { .. } => "0NNN",
+            Jump { .. } => "1NNN",
+            Call { .. } => "2NNN",
+            SkipIfEqualImm { .. } => "3XNN",
+            SkipIfNotEqualImm { .. } => "4XNN",
+            SkipIfEqual { .. } => "5XY0",
+            SaveRange { .. } => "5XY2",
+            LoadRange { .. } => "5XY3",
+            LoadImm { .. } => "6XNN",
+            AddImm { .. } => "7XNN",
+            Move { .. } => "8XY0",
+            Or { .. } => "8XY1",
+            And { .. } => "8XY2",
+            Xor { .. } => "8XY3",
+            Add { .. } => "8XY4",
+            Sub { .. } => "8XY5",
+            ShiftRight { .. } => "8XY6",
+            SubReverse { .. } => "8XY7",
+            ShiftLeft { .. } => "8XYE",
+            SkipIfNotEqual { .. } => "9XY0",
+            LoadIndex { .. } => "ANNN",
+            JumpOffset { .. } => "BNNN",
+            Random { .. } => "CXNN",
+            Draw { .. } => "DXYN",
+            SkipIfKey { .. } => "EX9E",
+            SkipIfNotKey { .. } => "EXA1",
+            LoadIndexLong { .. } => "F000",
+            SelectPlanes { .. } => "FN01",
+            LoadAudio => "F002",
+            GetDelay { .. } => "FX07",
+            WaitKey { .. } => "FX0A",
+            SetDelay { .. } => "FX15",
+            SetSound { .. } => "FX18",
+            AddIndex { .. } => "FX1E",
+            FontChar { .. } => "FX29",
+            BigFontChar { .. } => "FX30",
+            Bcd { .. } => "FX33",
+            SetPitch { .. } => "FX3A",
+            Store { .. } => "FX55",
+            Load { .. } => "FX65",
+            SaveFlags { .. } => "FX75",
+            LoadFlags { .. } => "FX85",
+            Unknown { .. } => "????",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_chip8_instructions() {
+        use Instruction::*;
+
+        let decode = |opcode| Instruction::decode(opcode, 0, Platform::Chip8);
+
+        assert_eq!(decode(0x00E0), ClearScreen);
+        assert_eq!(decode(0x00EE), Return);
+        assert_eq!(decode(0x0123), MachineCall { nnn: 0x123 });
+        assert_eq!(decode(0x1ABC), Jump { nnn: 0xABC });
+        assert_eq!(decode(0x3A42), SkipIfEqualImm { x: 0xA, nn: 0x42 });
+        assert_eq!(decode(0x5120), SkipIfEqual { x: 1, y: 2 });
+        assert_eq!(decode(0x8AB6), ShiftRight { x: 0xA, y: 0xB });
+        assert_eq!(decode(0x8ABE), ShiftLeft { x: 0xA, y: 0xB });
+        assert_eq!(decode(0x8AB8), Unknown { opcode: 0x8AB8 });
+        assert_eq!(decode(0xD125), Draw { x: 1, y: 2, n: 5 });
+        assert_eq!(decode(0xE3A1), SkipIfNotKey { x: 3 });
+        assert_eq!(decode(0xF40A), WaitKey { x: 4 });
+        assert_eq!(decode(0xF465), Load { x: 4 });
+    }
+
+    #[test]
+    fn extensions_are_only_decoded_on_their_platforms() {
+        use Instruction::*;
+
+        assert_eq!(
+            Instruction::decode(0x00FF, 0, Platform::Chip8),
+            MachineCall { nnn: 0x0FF }
+        );
+        assert_eq!(Instruction::decode(0x00FF, 0, Platform::SuperChip), HighRes);
+        assert_eq!(
+            Instruction::decode(0x00C4, 0, Platform::SuperChip),
+            ScrollDown { n: 4 }
+        );
+        assert_eq!(
+            Instruction::decode(0x00D4, 0, Platform::SuperChip),
+            Unknown { opcode: 0x00D4 }
+        );
+        assert_eq!(
+            Instruction::decode(0x00D4, 0, Platform::XoChip),
+            ScrollUp { n: 4 }
+        );
+        assert_eq!(
+            Instruction::decode(0x5123, 0, Platform::SuperChip),
+            Unknown { opcode: 0x5123 }
+        );
+        assert_eq!(
+            Instruction::decode(0x5123, 0, Platform::XoChip),
+            LoadRange { x: 1, y: 2 }
+        );
+        assert_eq!(
+            Instruction::decode(0xF301, 0, Platform::XoChip),
+            SelectPlanes { n: 3 }
+        );
+    }
+
+    #[test]
+    fn long_index_load_is_four_bytes() {
+        let long = Instruction::decode_bytes(&[0xF0, 0x00, 0x12, 0x34], Platform::XoChip);
+        assert_eq!(long, Instruction::LoadIndexLong { nnnn: 0x1234 });
+        assert_eq!(long.len(), 4);
+
+        let short = Instruction::decode_bytes(&[0xA2, 0x34], Platform::XoChip);
+        assert_eq!(short, Instruction::LoadIndex { nnn: 0x234 });
+        assert_eq!(short.len(), 2);
+
+        assert_eq!(
+            Instruction::decode_bytes(&[0xF0, 0x00, 0x12, 0x34], Platform::Chip8),
+            Instruction::Unknown { opcode: 0xF000 }
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Descriptions of the CHIP8 variants that the emulator can run, and their quirks.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter};
+use thiserror::Error;
+
+/// A CHIP8 variant.
+///
+/// Over the years, many extensions to the original CHIP8 were created. Each one
+/// adds instructions, changes the display, or subtly changes the behaviour of
+/// existing instructions (see [`Quirks`]). `rust-chip` supports the three
+/// variants that modern ROMs are written for.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Display, EnumIter,
+)]
+pub enum Platform {
+    /// The original CHIP8 interpreter for the COSMAC VIP.
+    #[default]
+    #[strum(serialize = "CHIP-8")]
+    Chip8,
+
+    /// SUPER-CHIP 1.1, originally for the HP48 calculators. Adds a 128x64
+    /// high-resolution mode, scrolling, and a large font.
+    #[strum(serialize = "SUPER-CHIP")]
+    SuperChip,
+
+    /// XO-CHIP, as implemented by Octo. Builds on SUPER-CHIP, and adds 64 kiB of
+    /// memory, multiple bit planes for colour, and a few new instructions.
+    #[strum(serialize = "XO-CHIP")]
+    XoChip,
+}
+
+impl Platform {
+    /// Guess the platform a ROM was written for from its file extension, using
+    /// the extensions popularized by Octo: `.ch8`, `.sc8` and `.xo8`.
+    pub fn from_rom_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ch8" => Some(Platform::Chip8),
+            "sc8" => Some(Platform::SuperChip),
+            "xo8" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    /// The file extension for ROMs written for this platform. The inverse of
+    /// [`Platform::from_rom_extension()`].
+    pub const fn rom_extension(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "ch8",
+            Platform::SuperChip => "sc8",
+            Platform::XoChip => "xo8",
+        }
+    }
+
+    /// The amount of addressable memory on this platform, in bytes.
+    pub const fn ram_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+
+    /// Returns true if this platform has the SUPER-CHIP high-resolution mode,
+    /// scrolling instructions, and large font.
+    pub const fn has_hires(&self) -> bool {
+        matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+
+    /// Returns true if this platform supports the XO-CHIP extensions.
+    pub const fn has_xo_chip_extensions(&self) -> bool {
+        matches!(self, Platform::XoChip)
+    }
+
+    /// The quirks that ROMs written for this platform usually expect.
+    pub const fn default_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::CHIP8,
+            Platform::SuperChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+
+    /// The number of instructions executed per 60 Hz frame that ROMs written
+    /// for this platform usually expect.
+    pub const fn default_instructions_per_frame(&self) -> u32 {
+        match self {
+            Platform::Chip8 => 11,
+            Platform::SuperChip => 30,
+            Platform::XoChip => 1000,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = ParsePlatformError;
+
+    /// Parse a platform from its name, ignoring case and any dashes. For example,
+    /// `chip8`, `SUPER-CHIP`, `schip` and `xo-chip` are all accepted.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "chip8" => Ok(Platform::Chip8),
+            "superchip" | "schip" => Ok(Platform::SuperChip),
+            "xochip" => Ok(Platform::XoChip),
+            _ => Err(ParsePlatformError(s.to_string())),
+        }
+    }
+}
+
+/// The error returned when parsing an unknown platform name.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Unknown platform \"{0}\". Expected one of chip8, schip or xochip")]
+pub struct ParsePlatformError(String);
+
+/// Behaviours that differ between CHIP8 interpreters.
+///
+/// Different interpreters disagree on how a handful of instructions behave, and
+/// ROMs are written against whatever interpreter their author happened to use.
+/// The names and meanings of these quirks follow those used by Timendus' quirks
+/// test ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Quirks {
+    /// `8XY1`, `8XY2` and `8XY3` reset `VF` to zero.
+    pub vf_reset: bool,
+
+    /// `FX55` and `FX65` increment `I` by `X + 1`.
+    pub memory_increments_i: bool,
+
+    /// `DXYN` waits for the next 60 Hz vertical blank before drawing, limiting
+    /// the program to one sprite per frame.
+    pub display_wait: bool,
+
+    /// Sprites are clipped at the edges of the screen, instead of wrapping
+    /// around to the other side.
+    pub clipping: bool,
+
+    /// `8XY6` and `8XYE` shift `VX` in place, instead of shifting `VY` into `VX`.
+    pub shifting: bool,
+
+    /// `BNNN` jumps to `XNN + VX`, instead of `NNN + V0`.
+    pub jumping: bool,
+}
+
+impl Quirks {
+    /// The behaviour of the original COSMAC VIP interpreter.
+    pub const CHIP8: Self = Self {
+        vf_reset: true,
+        memory_increments_i: true,
+        display_wait: true,
+        clipping: true,
+        shifting: false,
+        jumping: false,
+    };
+
+    /// The behaviour of the (modern) SUPER-CHIP 1.1 interpreter.
+    pub const SUPER_CHIP: Self = Self {
+        vf_reset: false,
+        memory_increments_i: false,
+        display_wait: false,
+        clipping: true,
+        shifting: true,
+        jumping: true,
+    };
+
+    /// The behaviour of Octo's XO-CHIP interpreter.
+    pub const XO_CHIP: Self = Self {
+        vf_reset: false,
+        memory_increments_i: true,
+        display_wait: false,
+        clipping: false,
+        shifting: false,
+        jumping: false,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::default().default_quirks()
+    }
+}
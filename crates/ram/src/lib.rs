@@ -4,14 +4,23 @@ use std::ops::{Bound, Index, IndexMut, Range, RangeBounds};
 
 use sys_font::Font;
 
-/// The RAM is 4 kiB (4096 bytes) in size.
+/// The RAM is 4 kiB (4096 bytes) in size by default.
 pub const RAM_SIZE: u16 = 4096;
 
+/// The largest RAM supported, at 64 kiB (65536 bytes). This is the amount of
+/// memory available to XO-CHIP programs.
+pub const MAX_RAM_SIZE: usize = 0x10000;
+
+/// The address that CHIP-8 programs are loaded to, and start executing from.
+pub const PROGRAM_START_ADDRESS: u16 = 0x200;
+
 /// The main system memory for a CHIP-8.
 ///
-/// This memory is 4 kiB (4 kibibytes, or 4096 bytes) large. Since the CHIP8's
-/// index register and program counter can only address 12 bits, which works
-/// out to 4096 addresses, this is the perfect size.
+/// This memory is 4 kiB (4 kibibytes, or 4096 bytes) large by default. Since the
+/// CHIP8's index register and program counter can only address 12 bits, which
+/// works out to 4096 addresses, this is the perfect size. XO-CHIP programs can
+/// address a full 16 bits, so they get 64 kiB of memory instead (see
+/// [`Ram::with_size()`]).
 ///
 /// All system memory is RAM, and all memory is writable. Program memory
 /// is in the same overal memory pool as code. This allows for self-modifying
@@ -24,67 +33,156 @@ pub const RAM_SIZE: u16 = 4096;
 /// `rust-chip` will *also* reserve the first 512 bytes (addresses `0x000`-`0x1FF`)
 /// of memory for itself - this will be used for things like the system font.
 /// `rust-chip` will not _prevent_ accesses to those parts of memory, but it
-/// will load the program starting at address `0x1FF` and hope that the program
-/// doesn't screw with system memory.
+/// will load the program starting at address [`PROGRAM_START_ADDRESS`] and hope
+/// that the program doesn't screw with system memory.
 ///
 /// It's the wild west out there.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ram {
-    mem: [u8; RAM_SIZE as usize],
+    mem: Box<[u8]>,
 }
 
 impl Ram {
+    /// Create a new 4 kiB RAM, with the system font loaded.
     pub fn new() -> Self {
-        let mut mem = [0; RAM_SIZE as usize];
+        Self::with_size(RAM_SIZE as usize)
+    }
+
+    /// Create a new RAM of some size, with the system font loaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` isn't a power of two between [`RAM_SIZE`] and
+    /// [`MAX_RAM_SIZE`], inclusive.
+    pub fn with_size(size: usize) -> Self {
+        assert!(
+            size.is_power_of_two() && (RAM_SIZE as usize..=MAX_RAM_SIZE).contains(&size),
+            "Invalid RAM size {size:#X}"
+        );
 
-        /// Load the system font
+        let mut mem = vec![0; size].into_boxed_slice();
+
+        /// Load the system fonts
         const FONT_TABLE: [u8; 80] = Font::get_table_as_bytes();
-        for (i, byte) in FONT_TABLE.iter().enumerate() {
-            mem[addr_to_usize(Font::PREFERRED_TABLE_STARTING_ADDRESS) + i] = *byte;
-        }
+        const BIG_FONT_TABLE: [u8; 160] = Font::get_big_table_as_bytes();
+
+        let font_start = Font::PREFERRED_TABLE_STARTING_ADDRESS as usize;
+        mem[font_start..font_start + FONT_TABLE.len()].copy_from_slice(&FONT_TABLE);
+
+        let big_font_start = Font::PREFERRED_BIG_TABLE_STARTING_ADDRESS as usize;
+        mem[big_font_start..big_font_start + BIG_FONT_TABLE.len()].copy_from_slice(&BIG_FONT_TABLE);
 
         Self { mem }
     }
 
+    /// The size of this RAM, in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.mem.len()
+    }
+
     /// Get an immutable reference to a single byte of memory at some address offset.
     ///
-    /// Only the bottom 12 bits of `addr` are used for addressing.
+    /// Addresses wrap around at the end of memory, so only the bottom 12 bits of
+    /// `addr` are used for addressing in a 4 kiB RAM.
     pub fn get(&self, addr: u16) -> &u8 {
-        &self.mem[addr_to_usize(addr)]
+        &self.mem[self.addr_to_usize(addr)]
     }
 
     /// Get a mutable reference to single byte of memory at some address offset.
     ///
-    /// Only the bottom 12 bits of `addr` are used for addressing.
+    /// Addresses wrap around at the end of memory, so only the bottom 12 bits of
+    /// `addr` are used for addressing in a 4 kiB RAM.
     pub fn get_mut(&mut self, addr: u16) -> &mut u8 {
-        &mut self.mem[addr_to_usize(addr)]
+        let addr = self.addr_to_usize(addr);
+        &mut self.mem[addr]
     }
 
     /// Get an immutable reference to a range of memory at some address offset.
     ///
-    /// Only the bottom 12 bits of each address in `addrs` are used for addressing.
+    /// Addresses wrap around at the end of memory, so only the bottom 12 bits of
+    /// each address in `addrs` are used for addressing in a 4 kiB RAM.
     pub fn get_range<R>(&self, addr_range: R) -> &[u8]
     where
         R: RangeBounds<u16>,
     {
-        &self.mem[addr_range_to_usize_range(addr_range)]
+        &self.mem[self.addr_range_to_usize_range(addr_range)]
     }
 
     /// Get a mutable reference to a range of memory at some address offset.
     ///
-    /// Only the bottom 12 bits of each address in `addrs` are used for addressing.
+    /// Addresses wrap around at the end of memory, so only the bottom 12 bits of
+    /// each address in `addrs` are used for addressing in a 4 kiB RAM.
     pub fn get_range_mut<R>(&mut self, addr_range: R) -> &mut [u8]
     where
         R: RangeBounds<u16>,
     {
-        &mut self.mem[addr_range_to_usize_range(addr_range)]
+        let range = self.addr_range_to_usize_range(addr_range);
+        &mut self.mem[range]
     }
 
     /// Set a single byte of memory at some address offset.
     ///
-    /// Only the bottom 12 bits of `addr` are used for addressing.
+    /// Addresses wrap around at the end of memory, so only the bottom 12 bits of
+    /// `addr` are used for addressing in a 4 kiB RAM.
     pub fn set(&mut self, addr: u16, val: u8) {
-        self.mem[addr_to_usize(addr)] = val;
+        let addr = self.addr_to_usize(addr);
+        self.mem[addr] = val;
+    }
+
+    /// Copy a program into memory, starting at [`PROGRAM_START_ADDRESS`].
+    ///
+    /// Returns an error containing the maximum program size if the program
+    /// doesn't fit into memory, in which case memory is left untouched.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), usize> {
+        let start = PROGRAM_START_ADDRESS as usize;
+        let max_len = self.len() - start;
+
+        if program.len() > max_len {
+            return Err(max_len);
+        }
+
+        self.mem[start..start + program.len()].copy_from_slice(program);
+
+        Ok(())
+    }
+
+    /// Wrap an address around to the size of this RAM.
+    #[inline]
+    fn clean_addr(&self, addr: u16) -> usize {
+        addr as usize & (self.len() - 1)
+    }
+
+    /// Little utility function to convert a memory address to a `usize` while
+    /// wrapping it around to the size of this RAM.
+    #[inline]
+    fn addr_to_usize(&self, addr: u16) -> usize {
+        self.clean_addr(addr)
+    }
+
+    /// Normalizes an address range from all of the many `Range*` variants to just
+    /// a concrete `Range<usize>`, with every address wrapped around to the size
+    /// of this RAM.
+    fn addr_range_to_usize_range<R>(&self, addr_range: R) -> Range<usize>
+    where
+        R: RangeBounds<u16>,
+    {
+        let size = self.len();
+
+        let start = match addr_range.start_bound() {
+            Bound::Included(addr) => self.clean_addr(*addr),
+            Bound::Excluded(addr) => (self.clean_addr(*addr) + 1).min(size - 1),
+            Bound::Unbounded => 0,
+        };
+
+        let end = match addr_range.end_bound() {
+            Bound::Included(addr) => (self.clean_addr(*addr) + 1).min(size),
+            Bound::Excluded(addr) => self.clean_addr(*addr),
+            Bound::Unbounded => size,
+        };
+
+        start..end
     }
 }
 
@@ -108,56 +206,6 @@ impl IndexMut<u16> for Ram {
     }
 }
 
-/// Chop off the top 4 bits of a memory address, as all addresses are supposed
-/// to be 12 bits in size.
-#[inline]
-const fn clean_addr(addr: u16) -> u16 {
-    addr & 0b0000_1111_1111_1111
-}
-
-/// Little utility function to convert a memory address to a `usize` while
-/// erasing the top 4 bits of the input `u16`.
-#[inline]
-const fn addr_to_usize(addr: u16) -> usize {
-    clean_addr(addr) as usize
-}
-
-/// Normalizes an address range from all of the many `Range*` variants to just
-/// a concrete `Range<u16>`, with the top 4 bits truncated.
-fn normalize_addr_range<R>(addr_range: R) -> Range<u16>
-where
-    R: RangeBounds<u16>,
-{
-    let start_bound = addr_range.start_bound();
-    let end_bound = addr_range.end_bound();
-
-    let start = match start_bound {
-        Bound::Included(addr) => clean_addr(*addr),
-        Bound::Excluded(addr) => (clean_addr(*addr) + 1).min(RAM_SIZE - 1),
-        Bound::Unbounded => 0,
-    };
-
-    let end = match end_bound {
-        Bound::Included(addr) => (clean_addr(*addr) + 1).min(RAM_SIZE),
-        Bound::Excluded(addr) => clean_addr(*addr),
-        Bound::Unbounded => RAM_SIZE,
-    };
-
-    start..end
-}
-
-/// Converts an address range to a usize range, after normalizing it and
-/// scrubbing the top 4 bits of all addresses.
-#[inline]
-fn addr_range_to_usize_range<R>(addr_range: R) -> Range<usize>
-where
-    R: RangeBounds<u16>,
-{
-    let normalized = normalize_addr_range(addr_range);
-
-    (normalized.start as usize)..(normalized.end as usize)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -200,4 +248,30 @@ mod tests {
             &CharF.as_bytes()[..]
         );
     }
+
+    #[test]
+    fn addresses_wrap_around_to_ram_size() {
+        let mut ram = Ram::new();
+        ram.set(0x1234, 0xAB);
+        assert_eq!(ram[0x0234], 0xAB);
+
+        let mut ram = Ram::with_size(MAX_RAM_SIZE);
+        ram.set(0x1234, 0xAB);
+        assert_eq!(ram[0x1234], 0xAB);
+        assert_eq!(ram[0x0234], 0x00);
+        assert_eq!(ram.get_range(0xFFFE..).len(), 2);
+    }
+
+    #[test]
+    fn programs_load_at_program_start() {
+        let mut ram = Ram::new();
+        ram.load_program(&[0x12, 0x34]).unwrap();
+        assert_eq!(ram.get_range(PROGRAM_START_ADDRESS..=0x201), &[0x12, 0x34]);
+
+        let too_big = vec![0; RAM_SIZE as usize];
+        assert_eq!(
+            ram.load_program(&too_big),
+            Err((RAM_SIZE - PROGRAM_START_ADDRESS) as usize)
+        );
+    }
 }
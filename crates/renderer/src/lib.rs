@@ -1,33 +1,48 @@
-use std::sync::{Arc, Mutex};
-
 use wgpu::util::DeviceExt;
 
-use display::DisplayRef;
+mod screen;
+
+pub use screen::{Rotation, ScaleMode, ScreenOptions};
+
+use display::{FrameReader, Palette, PALETTE_SIZE};
 use display_blank::BlankDisplay;
-use wgpu_display_texture::{WgpuDisplayTexture, WgpuDisplayTextureUpdateError};
+use wgpu_display_texture::WgpuDisplayTexture;
 
 /// A [`wgpu`] renderer for rendering the emulated screen and the GUI.
 #[derive(Debug)]
 pub struct Renderer {
+    /// The size of the paintable area, in physical pixels.
     pub size: (u32, u32),
 
+    screen_options: ScreenOptions,
+
     render_pipeline: wgpu::RenderPipeline,
+    /// The pipeline for displays uploaded as plane indices, which are looked
+    /// up in the palette uniform.
+    indexed_render_pipeline: wgpu::RenderPipeline,
 
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_indices: u32,
 
     display_bind_group_layout: wgpu::BindGroupLayout,
+    indexed_display_bind_group_layout: wgpu::BindGroupLayout,
 
     blank_display_texture_bind_group: wgpu::BindGroup,
 
-    display: DisplayRef,
+    /// Where frames of the emulator's display come from, if attached.
+    frames: Option<FrameReader>,
     display_texture: Option<WgpuDisplayTexture>,
     display_texture_bind_group: Option<wgpu::BindGroup>,
 
     screen_size_uniform: ScreenSizeUniform,
     screen_size_buffer: wgpu::Buffer,
     screen_size_bind_group: wgpu::BindGroup,
+
+    /// The palette last sent to the GPU, if any has been.
+    palette: Option<Palette>,
+    palette_buffer: wgpu::Buffer,
+    palette_bind_group: wgpu::BindGroup,
 }
 
 impl Renderer {
@@ -88,40 +103,66 @@ impl Renderer {
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: target_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let render_pipeline = create_render_pipeline(
+            device,
+            &shader,
+            &render_pipeline_layout,
+            "fs_main",
+            target_format,
+            "Render pipeline",
+        );
+
+        let indexed_display_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    // Integer textures can't be filtered, so the shader loads
+                    // texels without a sampler.
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Uint,
+                    },
+                    count: None,
+                }],
+                label: Some("Indexed display bind group layout"),
+            });
+
+        let palette_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Palette bind group layout"),
+            });
+
+        let indexed_render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Indexed render pipeline layout"),
+                bind_group_layouts: &[
+                    &indexed_display_bind_group_layout,
+                    &screen_size_bind_group_layout,
+                    &palette_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let indexed_render_pipeline = create_render_pipeline(
+            device,
+            &shader,
+            &indexed_render_pipeline_layout,
+            "fs_indexed",
+            target_format,
+            "Indexed render pipeline",
+        );
 
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex buffer"),
@@ -160,8 +201,9 @@ impl Renderer {
                 label: Some("Blank display bind group"),
             });
 
+        let screen_options = ScreenOptions::default();
         let mut screen_size_uniform = ScreenSizeUniform::new();
-        screen_size_uniform.update_size(size);
+        screen_size_uniform.update(size, (1, 1), &screen_options);
 
         let screen_size_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Screen size uniform buffer"),
@@ -178,144 +220,178 @@ impl Renderer {
             label: Some("Screen size bind group"),
         });
 
+        let palette_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Palette uniform buffer"),
+            contents: bytemuck::cast_slice(&[PaletteUniform::new(&Palette::default())]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let palette_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &palette_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: palette_buffer.as_entire_binding(),
+            }],
+            label: Some("Palette bind group"),
+        });
+
         Self {
             size,
 
+            screen_options,
+
             render_pipeline,
+            indexed_render_pipeline,
 
             vertex_buffer,
             index_buffer,
             num_indices,
 
             display_bind_group_layout,
+            indexed_display_bind_group_layout,
 
             blank_display_texture_bind_group,
 
-            display: Arc::new(Mutex::new(None)),
+            frames: None,
             display_texture: None,
             display_texture_bind_group: None,
 
             screen_size_uniform,
             screen_size_buffer,
             screen_size_bind_group,
+
+            palette: None,
+            palette_buffer,
+            palette_bind_group,
         }
     }
 
-    /// Resize the renderer. This has the side-effect of re-configuring the
-    /// render surface, and re-instantiating the render pipeline.
+    /// Resize the renderer's paintable area. The size is in physical pixels,
+    /// not egui's logical points, so that pixel perfect scaling lines up with
+    /// the screen's real pixels on HiDPI displays.
     pub fn resize(&mut self, new_size: (u32, u32), queue: &wgpu::Queue) {
         if new_size != self.size && new_size.0 > 0 && new_size.1 > 0 {
             self.size = new_size;
-
-            // Update the DisplayTexScaleUniform with the new paintable area size.
-            self.screen_size_uniform.update_size(new_size);
-
-            queue.write_buffer(
-                &self.screen_size_buffer,
-                0,
-                bytemuck::cast_slice(&[self.screen_size_uniform]),
-            );
+            self.update_screen_size_uniform(queue);
         }
     }
 
-    /// Attach a new CHIP8-compatible display to the renderer.
-    ///
-    /// This will allocate the GPU textures and bind groups necessary for the
-    /// display. The renderer will then start rendering the display the next
-    /// time [`Renderer::render()`] is called.
-    ///
-    /// Whatever previous display was in use will be released, and its textures
-    /// and bind groups deallocated.
-    pub fn attach_display(
-        &mut self,
-        new_display: DisplayRef,
-        display_label: Option<&str>,
-        display_bind_group_label: Option<&str>,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-    ) {
-        let display_texture = {
-            let new_display = new_display.lock().unwrap();
-
-            if new_display.is_none() {
-                self.detach_display();
-                return;
-            }
-
-            let new_display = new_display.as_ref().unwrap();
-
-            WgpuDisplayTexture::from_chip8_display(
-                device,
-                queue,
-                new_display.as_ref(),
-                display_label,
-            )
-        };
+    #[inline]
+    pub fn screen_options(&self) -> ScreenOptions {
+        self.screen_options
+    }
 
-        let display_texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.display_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&display_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&display_texture.sampler),
-                },
-            ],
-            label: display_bind_group_label,
-        });
+    /// Change how the display is fitted onto the screen.
+    pub fn set_screen_options(&mut self, options: ScreenOptions, queue: &wgpu::Queue) {
+        if options != self.screen_options {
+            self.screen_options = options;
+            self.update_screen_size_uniform(queue);
+        }
+    }
 
-        self.display = new_display;
-        self.display_texture = Some(display_texture);
-        self.display_texture_bind_group = Some(display_texture_bind_group);
+    /// Work out where the display goes from the paintable area, the display
+    /// texture and the screen options, and send it to the GPU.
+    fn update_screen_size_uniform(&mut self, queue: &wgpu::Queue) {
+        let texture_size = self
+            .display_texture
+            .as_ref()
+            .map_or((1, 1), |texture| (texture.size.width, texture.size.height));
+
+        self.screen_size_uniform
+            .update(self.size, texture_size, &self.screen_options);
+
+        queue.write_buffer(
+            &self.screen_size_buffer,
+            0,
+            bytemuck::cast_slice(&[self.screen_size_uniform]),
+        );
     }
 
-    /// Detach the current CHIP8-compatible display.
+    /// Attach the frames of the emulator's display to the renderer. See
+    /// [`display::frame_channel()`].
     ///
-    /// A black 1x1 pixel will be rendered in its place on the next call to
-    /// [`Self::render()`].
-    pub fn detach_display(&mut self) {
-        self.display = Arc::new(Mutex::new(None));
+    /// The renderer starts rendering them once the first frame arrives in
+    /// [`Self::update_display_texture()`]. Until then, and whenever nothing is
+    /// attached, a black 1x1 pixel is rendered instead.
+    pub fn attach_frames(&mut self, frames: FrameReader) {
+        self.frames = Some(frames);
         self.display_texture.take();
         self.display_texture_bind_group.take();
     }
 
-    /// Upload the display's contents to the GPU for rendering in subsequent calls
-    /// to [`Self::render`].
-    ///
-    /// This is a no-op if no display is currently attached.
+    /// Upload the latest frame of the display to the GPU for rendering in
+    /// subsequent calls to [`Self::render`], if there's a new one. This never
+    /// waits for the emulator thread.
     ///
-    /// Returns [`WgpuDisplayTextureUpdateError::DimensionsChanged`] if the dimensions
-    /// of the currently-attached display do not match the dimensions of the
-    /// GPU-side texture that data is being copied to. In this case, the GPU-side
-    /// texture must be recreated, probably using [`Self::attach_display`].
-    pub fn update_display_texture(
-        &self,
-        queue: &wgpu::Queue,
-    ) -> Result<(), WgpuDisplayTextureUpdateError> {
-        if self.display_texture.is_none() {
-            return Ok(());
-        }
-        let display_texture = self.display_texture.as_ref().unwrap();
+    /// Only the rows that changed since the last upload are copied, unless the
+    /// display changed size or format, in which case its texture and bind
+    /// group are created again.
+    pub fn update_display_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let frame = match self.frames.as_mut().and_then(FrameReader::take) {
+            Some(frame) => frame,
+            None => return,
+        };
+        let palette = frame.palette;
+
+        let resized = match &self.display_texture {
+            Some(texture) if texture.matches(frame) => {
+                if let Some(rows) = frame.dirty_rows.clone() {
+                    texture.update_rows(rows.clone(), frame.rows(rows), queue);
+                }
+                false
+            }
+            _ => {
+                let texture =
+                    WgpuDisplayTexture::from_frame(device, queue, frame, Some("CHIP8 Display"));
+                self.display_texture_bind_group = Some(create_display_bind_group(
+                    device,
+                    &texture,
+                    &self.display_bind_group_layout,
+                    &self.indexed_display_bind_group_layout,
+                ));
+                self.display_texture = Some(texture);
+                true
+            }
+        };
 
-        let display = self.display.lock().unwrap();
-        if display.is_none() {
-            return Ok(());
+        // The new texture may be a different size.
+        if resized {
+            self.update_screen_size_uniform(queue);
         }
 
-        let display = display.as_ref().unwrap();
+        // The palette can change without any pixels changing.
+        if let Some(palette) = palette {
+            self.update_palette_uniform(palette, queue);
+        }
+    }
 
-        match display_texture.update(display.as_ref(), queue) {
-            Ok(_) => Ok(()),
-            Err(e @ WgpuDisplayTextureUpdateError::DimensionsChanged { .. }) => Err(e),
+    /// Send the palette to the GPU, if it changed since it was last sent.
+    fn update_palette_uniform(&mut self, palette: Palette, queue: &wgpu::Queue) {
+        if self.palette == Some(palette) {
+            return;
         }
+
+        queue.write_buffer(
+            &self.palette_buffer,
+            0,
+            bytemuck::cast_slice(&[PaletteUniform::new(&palette)]),
+        );
+        self.palette = Some(palette);
     }
 
     /// Render a frame.
     pub fn render<'rp>(&'rp self, render_pass: &mut wgpu::RenderPass<'rp>) {
-        render_pass.set_pipeline(&self.render_pipeline);
+        let indexed = self
+            .display_texture
+            .as_ref()
+            .is_some_and(|texture| texture.indexed);
+
+        if indexed {
+            render_pass.set_pipeline(&self.indexed_render_pipeline);
+            render_pass.set_bind_group(2, &self.palette_bind_group, &[]);
+        } else {
+            render_pass.set_pipeline(&self.render_pipeline);
+        }
 
         if let Some(display_texture_bind_group) = &self.display_texture_bind_group {
             render_pass.set_bind_group(0, display_texture_bind_group, &[]);
@@ -331,6 +407,86 @@ impl Renderer {
     }
 }
 
+/// Create the bind group for a display texture, which has no sampler if the
+/// texture is indexed.
+fn create_display_bind_group(
+    device: &wgpu::Device,
+    texture: &WgpuDisplayTexture,
+    layout: &wgpu::BindGroupLayout,
+    indexed_layout: &wgpu::BindGroupLayout,
+) -> wgpu::BindGroup {
+    let texture_entry = wgpu::BindGroupEntry {
+        binding: 0,
+        resource: wgpu::BindingResource::TextureView(&texture.view),
+    };
+
+    if texture.indexed {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: indexed_layout,
+            entries: &[texture_entry],
+            label: Some("CHIP8 Display Bind Group"),
+        })
+    } else {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                texture_entry,
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("CHIP8 Display Bind Group"),
+        })
+    }
+}
+
+/// Create a render pipeline that draws the display quad with one of the
+/// fragment shaders in `shader.wgsl`.
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+    fragment_entry_point: &str,
+    target_format: wgpu::TextureFormat,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
@@ -369,26 +525,69 @@ const INDICES: &[u16] = &[
 ];
 
 /// A uniform for sending the current paintable area size to the GPU, as well
-/// as the size of the [`WgpuDisplayTexture`] being painted.
+/// as where the [`WgpuDisplayTexture`] is painted in it. See
+/// [`ScreenOptions::layout()`].
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct ScreenSizeUniform {
+    /// The size of the paintable area, in physical pixels.
     screen_size: [f32; 2],
+    /// The top-left corner of the rotated display, in physical pixels.
+    display_origin: [f32; 2],
+    /// The size of the rotated display, in physical pixels.
+    display_size: [f32; 2],
+    /// The number of quarter turns clockwise that the display is rotated by.
+    quarter_turns: u32,
 
     /// This padding is necessary to ensure that this uniform buffer remains
     /// aligned to 16-byte boundaries, which is required for WebGL.
-    _padding: [f32; 2],
+    _padding: u32,
+
+    /// The colour of the area around the display, as RGBA.
+    border: [f32; 4],
 }
 
 impl ScreenSizeUniform {
     fn new() -> Self {
         Self {
             screen_size: [1.0, 1.0],
-            _padding: [0.0, 0.0],
+            display_origin: [0.0, 0.0],
+            display_size: [1.0, 1.0],
+            quarter_turns: 0,
+            _padding: 0,
+            border: [0.0, 0.0, 0.0, 1.0],
         }
     }
 
-    fn update_size(&mut self, (paint_area_width, paint_area_height): (u32, u32)) {
-        self.screen_size = [paint_area_width as f32, paint_area_height as f32];
+    fn update(&mut self, paint_area: (u32, u32), texture: (u32, u32), options: &ScreenOptions) {
+        let (origin, size) = options.layout(paint_area, texture);
+        let [r, g, b] = options.border.map(|c| c as f32 / 255.0);
+
+        self.screen_size = [paint_area.0 as f32, paint_area.1 as f32];
+        self.display_origin = origin;
+        self.display_size = size;
+        self.quarter_turns = options.rotation.quarter_turns();
+        self.border = [r, g, b, 1.0];
+    }
+}
+
+/// A uniform for sending a display's [`Palette`] to the GPU, for displays
+/// uploaded as plane indices.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PaletteUniform {
+    /// Every colour in the palette, as RGBA. Arrays in uniforms have a stride
+    /// of 16 bytes, so each colour takes up a whole `vec4`.
+    colours: [[f32; 4]; PALETTE_SIZE],
+}
+
+impl PaletteUniform {
+    fn new(palette: &Palette) -> Self {
+        let mut colours = [[0.0; 4]; PALETTE_SIZE];
+        for (colour, &[r, g, b]) in colours.iter_mut().zip(&palette.colours) {
+            *colour = [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0];
+        }
+
+        Self { colours }
     }
 }
@@ -0,0 +1,178 @@
+//! How the display is fitted onto the screen: scaling, rotation and the border
+//! around it.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter};
+
+/// How the display is scaled to fit the paintable area.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Display, EnumIter,
+)]
+pub enum ScaleMode {
+    /// Scale by the largest whole number that fits, so that every pixel is the
+    /// same size. Falls back to [`ScaleMode::Contain`] if the display doesn't
+    /// fit at all.
+    #[strum(serialize = "Pixel perfect")]
+    Integer,
+    /// Scale as large as possible while keeping the display's shape.
+    #[default]
+    Contain,
+    /// Fill the whole area, whatever its shape.
+    Stretch,
+    /// Scale as large as possible while keeping the shape given by
+    /// [`ScreenOptions::aspect_ratio`].
+    #[strum(serialize = "Custom aspect ratio")]
+    AspectRatio,
+}
+
+/// How far the display is turned clockwise, like Octo's screen rotation
+/// option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, EnumIter)]
+pub enum Rotation {
+    #[default]
+    None,
+    /// 90 degrees clockwise.
+    Quarter,
+    /// Upside down.
+    Half,
+    /// 270 degrees clockwise, or 90 anticlockwise.
+    ThreeQuarters,
+}
+
+impl Rotation {
+    /// The number of quarter turns clockwise.
+    #[inline]
+    pub fn quarter_turns(self) -> u32 {
+        self as u32
+    }
+
+    /// The number of degrees clockwise.
+    #[inline]
+    pub fn degrees(self) -> u32 {
+        self.quarter_turns() * 90
+    }
+
+    /// True if the display is on its side, so that its width and height are
+    /// swapped on screen.
+    #[inline]
+    pub fn is_sideways(self) -> bool {
+        self.quarter_turns() % 2 == 1
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}°", self.degrees())
+    }
+}
+
+/// Everything about how the display is fitted onto the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenOptions {
+    pub scale_mode: ScaleMode,
+    /// The width of the display divided by its height, before it's rotated,
+    /// for [`ScaleMode::AspectRatio`].
+    pub aspect_ratio: f32,
+    pub rotation: Rotation,
+    /// The colour of the area around the display, in the same colour space as
+    /// the display's own colours.
+    pub border: [u8; 3],
+}
+
+impl Default for ScreenOptions {
+    fn default() -> Self {
+        Self {
+            scale_mode: ScaleMode::default(),
+            aspect_ratio: 4.0 / 3.0,
+            rotation: Rotation::default(),
+            border: [0, 0, 0],
+        }
+    }
+}
+
+impl ScreenOptions {
+    /// Work out where the display goes in a paintable area of `screen` pixels,
+    /// for a display texture of `texture` pixels.
+    ///
+    /// Returns the top-left corner and the size of the rotated display, in
+    /// physical pixels. The corner is rounded to a whole pixel, so that pixel
+    /// perfect scaling stays pixel perfect.
+    pub fn layout(&self, screen: (u32, u32), texture: (u32, u32)) -> ([f32; 2], [f32; 2]) {
+        let screen = [screen.0.max(1) as f32, screen.1.max(1) as f32];
+        let texture = [texture.0.max(1) as f32, texture.1.max(1) as f32];
+
+        // The size of the display before it's scaled, on its side if it's
+        // rotated that way.
+        let unrotated = match self.scale_mode {
+            ScaleMode::AspectRatio => [self.aspect_ratio.max(0.01) * texture[1], texture[1]],
+            _ => texture,
+        };
+        let shape = if self.rotation.is_sideways() {
+            [unrotated[1], unrotated[0]]
+        } else {
+            unrotated
+        };
+
+        let contain = (screen[0] / shape[0]).min(screen[1] / shape[1]);
+        let size = match self.scale_mode {
+            ScaleMode::Stretch => screen,
+            ScaleMode::Integer if contain >= 1.0 => shape.map(|side| side * contain.floor()),
+            _ => shape.map(|side| side * contain),
+        };
+
+        let origin = [
+            ((screen[0] - size[0]) / 2.0).round(),
+            ((screen[1] - size[1]) / 2.0).round(),
+        ];
+
+        (origin, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(options: ScreenOptions, screen: (u32, u32)) -> ([f32; 2], [f32; 2]) {
+        options.layout(screen, (64, 32))
+    }
+
+    #[test]
+    fn displays_are_fitted_to_the_screen() {
+        let options = ScreenOptions::default();
+        assert_eq!(layout(options, (200, 200)), ([0.0, 50.0], [200.0, 100.0]));
+
+        let options = ScreenOptions {
+            scale_mode: ScaleMode::Integer,
+            ..options
+        };
+        assert_eq!(layout(options, (200, 200)), ([4.0, 52.0], [192.0, 96.0]));
+        assert_eq!(layout(options, (50, 50)), ([0.0, 13.0], [50.0, 25.0]));
+
+        let options = ScreenOptions {
+            scale_mode: ScaleMode::Stretch,
+            ..options
+        };
+        assert_eq!(layout(options, (200, 150)), ([0.0, 0.0], [200.0, 150.0]));
+
+        let options = ScreenOptions {
+            scale_mode: ScaleMode::AspectRatio,
+            aspect_ratio: 1.0,
+            ..options
+        };
+        assert_eq!(layout(options, (200, 100)), ([50.0, 0.0], [100.0, 100.0]));
+    }
+
+    #[test]
+    fn sideways_displays_swap_their_width_and_height() {
+        let options = ScreenOptions {
+            scale_mode: ScaleMode::Integer,
+            rotation: Rotation::Quarter,
+            ..Default::default()
+        };
+        assert_eq!(layout(options, (100, 200)), ([2.0, 4.0], [96.0, 192.0]));
+    }
+}
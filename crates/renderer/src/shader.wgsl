@@ -26,44 +26,80 @@ var t_display: texture_2d<f32>;
 var s_display: sampler;
 
 struct ScreenSizeUniform {
+    // All in physical pixels. See `ScreenOptions::layout()`.
     size: vec2<f32>,
-    _padding: vec2<f32>,
+    display_origin: vec2<f32>,
+    display_size: vec2<f32>,
+    quarter_turns: u32,
+    _padding: u32,
+    border: vec4<f32>,
 };
 
 @group(1) @binding(0)
 var<uniform> screen_size: ScreenSizeUniform;
 
-fn aspect_2d_scale_2d(size: vec2<f32>, scale: vec2<f32>) -> vec2<f32> {
-    return scale / size;
+// Turn coordinates in the rotated display back into texture coordinates, by
+// undoing the display's clockwise rotation.
+fn unrotate(st: vec2<f32>, quarter_turns: u32) -> vec2<f32> {
+    switch quarter_turns {
+        case 1u: {
+            return vec2(st.y, 1.0 - st.x);
+        }
+        case 2u: {
+            return 1.0 - st;
+        }
+        case 3u: {
+            return vec2(1.0 - st.y, st.x);
+        }
+        default: {
+            return st;
+        }
+    }
 }
 
-fn aspect_2d(size: vec2<f32>, scale: f32) -> vec2<f32> {
-    return aspect_2d_scale_2d(size, vec2(scale));
-}
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Find the pixel being painted, and where it is in the rotated display,
+    // which the CPU has already scaled and placed.
+    let pixel = in.tex_coords * screen_size.size;
+    let st = (pixel - screen_size.display_origin) / screen_size.display_size;
+
+    // Sample the texture
+    let texel = textureSample(t_display, s_display, unrotate(st, screen_size.quarter_turns));
+
+    // Use the border colour for anything outside the texture coordinate range
+    // of [0.0, 1.0]
+    let limit = abs(floor(st));
 
-fn aspect_contain_2d(size: vec2<f32>) -> vec2<f32> {
-    return aspect_2d(size, max(size.x, size.y));
+    return mix(screen_size.border, texel, step(max(limit.x, limit.y), 0.0));
 }
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // Scale texture coordinates from [0.0, 1.0] to [-1.0, 1.0]
-    var st = 2.0 * (in.tex_coords - 0.5);
+// Displays that upload plane indices rather than colours are drawn with this
+// instead, and the colours are looked up in their palette here.
+@group(0) @binding(0)
+var t_indices: texture_2d<u32>;
 
-    // Scale to fit the aspect ratio of the screen, similar to CSS's background-size: contain
-    let texture_size: vec2<i32> = textureDimensions(t_display);
-    let size_ratio = vec2<f32>(texture_size) / screen_size.size;
-    st = st * aspect_contain_2d(size_ratio);
+struct PaletteUniform {
+    colours: array<vec4<f32>, 16>,
+};
 
-    // Re-scale texture coordinates from [-1.0, 1.0] to [0.0, 1.0]
-    st = (0.5 * st) + 0.5;
+@group(2) @binding(0)
+var<uniform> palette: PaletteUniform;
 
-    // Sample the texture
-    let pixel = textureSample(t_display, s_display, st);
+@fragment
+fn fs_indexed(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = in.tex_coords * screen_size.size;
+    let st = (pixel - screen_size.display_origin) / screen_size.display_size;
+
+    // Integer textures can't be sampled, so find the texel by hand. This is
+    // the same as the nearest-neighbour sampling that `fs_main` uses.
+    let size = textureDimensions(t_indices);
+    let uv = clamp(unrotate(st, screen_size.quarter_turns), vec2(0.0), vec2(1.0));
+    let texel_coords = min(vec2<i32>(uv * vec2<f32>(size)), size - 1);
+    let index = textureLoad(t_indices, texel_coords, 0).r;
+    let texel = palette.colours[index & 15u];
 
-    // Make anything outside the texture coordinate range of [0.0, 1.0] black
     let limit = abs(floor(st));
-    let black = vec4(0.0, 0.0, 0.0, 1.0);
 
-    return mix(black, pixel, step(max(limit.x, limit.y), 0.0));
+    return mix(screen_size.border, texel, step(max(limit.x, limit.y), 0.0));
 }
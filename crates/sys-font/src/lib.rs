@@ -5,6 +5,9 @@ use strum::EnumCount;
 /// The system font, with sprite data representing the hexadecimal numbers from
 /// `0x0` thorugh `0xF`. All characters are 4 pixels wide by 5 pixels tall.
 ///
+/// SUPER-CHIP also introduced a large font, with characters that are 8 pixels
+/// wide by 10 pixels tall. See [`Font::as_big_bytes()`].
+///
 /// The enumeration itself can be used to access individual characters, while
 /// the [`Font::get_table_as_bytes()`] method can be used to convert all
 /// characters to a block of memory used to represent them by the CHIP-8, in the
@@ -30,6 +33,10 @@ impl Font {
     /// convention.
     pub const PREFERRED_TABLE_STARTING_ADDRESS: u16 = 0x050;
 
+    /// The preferred location in the system memory to load the large font table
+    /// to, right after the regular font table.
+    pub const PREFERRED_BIG_TABLE_STARTING_ADDRESS: u16 = 0x0A0;
+
     /// Convert a character to the 5-byte sequence representing it in memory.
     #[allow(dead_code)]
     pub const fn as_bytes(&self) -> [u8; 5] {
@@ -53,6 +60,31 @@ impl Font {
         }
     }
 
+    /// Convert a character to the 10-byte sequence representing it in the large
+    /// font.
+    #[allow(dead_code)]
+    #[rustfmt::skip]
+    pub const fn as_big_bytes(&self) -> [u8; 10] {
+        match self {
+            Font::Char0 => [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+            Font::Char1 => [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+            Font::Char2 => [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+            Font::Char3 => [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+            Font::Char4 => [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+            Font::Char5 => [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+            Font::Char6 => [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+            Font::Char7 => [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+            Font::Char8 => [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+            Font::Char9 => [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+            Font::CharA => [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+            Font::CharB => [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+            Font::CharC => [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+            Font::CharD => [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+            Font::CharE => [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+            Font::CharF => [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+        }
+    }
+
     /// Get all the characters in this font as a single contiguous hunk of memory,
     /// ready for loading into RAM.
     ///
@@ -80,12 +112,59 @@ impl Font {
         ]
     }
 
+    /// Get all the characters in the large font as a single contiguous hunk of
+    /// memory, ready for loading into RAM.
+    ///
+    /// If possible, consider loading this at the memory location given by
+    /// [`Font::PREFERRED_BIG_TABLE_STARTING_ADDRESS`].
+    #[rustfmt::skip]
+    pub const fn get_big_table_as_bytes() -> [u8; 160] {
+        [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ]
+    }
+
     /// Get the offset, in bytes, of a character into the font table returned
     /// by [`Font::get_table_as_bytes()`].
     #[allow(dead_code)]
     pub const fn table_offset(&self) -> usize {
         ((*self as u8) * 5) as usize
     }
+
+    /// Get the offset, in bytes, of a character into the large font table
+    /// returned by [`Font::get_big_table_as_bytes()`].
+    #[allow(dead_code)]
+    pub const fn big_table_offset(&self) -> usize {
+        (*self as usize) * 10
+    }
+
+    /// Get the character for the hexadecimal digit in the bottom 4 bits of
+    /// `digit`.
+    pub const fn from_digit(digit: u8) -> Self {
+        use Font::*;
+
+        const CHARS: [Font; 16] = [
+            Char0, Char1, Char2, Char3, Char4, Char5, Char6, Char7, Char8, Char9, CharA, CharB,
+            CharC, CharD, CharE, CharF,
+        ];
+
+        CHARS[(digit & 0xF) as usize]
+    }
 }
 
 #[cfg(test)]
@@ -163,4 +242,17 @@ mod tests {
             &CharF.as_bytes()[..]
         );
     }
+
+    #[test]
+    fn big_table_is_in_correct_order_and_bytes_are_correct() {
+        const TABLE: [u8; 160] = Font::get_big_table_as_bytes();
+
+        for digit in 0..16 {
+            let c = Font::from_digit(digit);
+            assert_eq!(
+                &TABLE[c.big_table_offset()..c.big_table_offset() + 10],
+                &c.as_big_bytes()[..]
+            );
+        }
+    }
 }
@@ -1,7 +1,9 @@
 //! A utility for managing GPU-side textures for rendering CHIP8-compatible displayering
 //! CHIP8-compatible displays.
 
-use display::Display;
+use std::ops::Range;
+
+use display::{Display, Frame};
 use thiserror::Error;
 
 /// The data contained in a CHIP8-compatible display as a wgpu-compatible Texture.
@@ -15,6 +17,12 @@ pub struct WgpuDisplayTexture {
     pub sampler: wgpu::Sampler,
     /// The size of the texture.
     pub size: wgpu::Extent3d,
+    /// The format of the texture.
+    pub format: wgpu::TextureFormat,
+    /// True if the texture holds the plane bitmask of every pixel as an
+    /// `R8Uint` texture, to be looked up in a palette by the shader, rather
+    /// than colours. See [`Display::plane_indices()`].
+    pub indexed: bool,
 }
 
 impl WgpuDisplayTexture {
@@ -26,8 +34,45 @@ impl WgpuDisplayTexture {
         display: &dyn Display,
         label: Option<&str>,
     ) -> Self {
-        let rgba_buf = display.as_rgba8_image();
-        let (width, height) = display.dimensions();
+        let indexed = display.plane_indices().is_some();
+
+        Self::new(
+            device,
+            queue,
+            display.dimensions(),
+            texture_format(indexed, display.is_srgb()),
+            texture_data(display, indexed),
+            label,
+        )
+    }
+
+    /// Create a new wgpu texture, view, and sampler, ready for GPU rendering,
+    /// from a [`Frame`] published by the emulator.
+    pub fn from_frame(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame: &Frame,
+        label: Option<&str>,
+    ) -> Self {
+        Self::new(
+            device,
+            queue,
+            (frame.width, frame.height),
+            texture_format(frame.indexed, frame.is_srgb),
+            &frame.data,
+            label,
+        )
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        (width, height): (u32, u32),
+        format: wgpu::TextureFormat,
+        data: &[u8],
+        label: Option<&str>,
+    ) -> Self {
+        let indexed = format == wgpu::TextureFormat::R8Uint;
 
         let size = wgpu::Extent3d {
             width,
@@ -41,11 +86,7 @@ impl WgpuDisplayTexture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: if display.is_srgb() {
-                wgpu::TextureFormat::Rgba8UnormSrgb
-            } else {
-                wgpu::TextureFormat::Rgba8Unorm
-            },
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
@@ -56,10 +97,10 @@ impl WgpuDisplayTexture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            rgba_buf,
+            data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                bytes_per_row: std::num::NonZeroU32::new(bytes_per_pixel(indexed) * width),
                 rows_per_image: std::num::NonZeroU32::new(height),
             },
             size,
@@ -81,9 +122,18 @@ impl WgpuDisplayTexture {
             view,
             sampler,
             size,
+            format,
+            indexed,
         }
     }
 
+    /// True if a frame can be copied to this texture, because it's the same
+    /// size and has the same format.
+    pub fn matches(&self, frame: &Frame) -> bool {
+        (self.size.width, self.size.height) == (frame.width, frame.height)
+            && self.format == texture_format(frame.indexed, frame.is_srgb)
+    }
+
     /// Queue a write of new display data to the texture on the GPU.
     ///
     /// If the [`Display`] passed in has different dimensions than the [`Display`]
@@ -95,15 +145,7 @@ impl WgpuDisplayTexture {
         new_display: &D,
         queue: &wgpu::Queue,
     ) -> Result<(), WgpuDisplayTextureUpdateError> {
-        let new_rgba_buf = new_display.as_rgba8_image();
-        let (new_width, new_height) = new_display.dimensions();
-
-        if (new_width != self.size.width) || (new_height != self.size.height) {
-            return Err(WgpuDisplayTextureUpdateError::DimensionsChanged {
-                old: (self.size.width, self.size.height),
-                new: (new_width, new_height),
-            });
-        }
+        self.check_dimensions(new_display.dimensions())?;
 
         queue.write_texture(
             wgpu::ImageCopyTexture {
@@ -112,10 +154,10 @@ impl WgpuDisplayTexture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            new_rgba_buf,
+            texture_data(new_display, self.indexed),
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * self.size.width),
+                bytes_per_row: std::num::NonZeroU32::new(self.bytes_per_row()),
                 rows_per_image: std::num::NonZeroU32::new(self.size.height),
             },
             self.size,
@@ -123,6 +165,94 @@ impl WgpuDisplayTexture {
 
         Ok(())
     }
+
+    /// Queue a write of some rows of new display data to the texture on the
+    /// GPU. `data` is the RGBA data or plane indices of just those rows,
+    /// depending on whether the texture is [`Self::indexed`].
+    ///
+    /// The caller must check that the display is the same size as the texture
+    /// first, using [`Self::check_dimensions()`] or [`Self::matches()`].
+    pub fn update_rows(&self, rows: Range<u32>, data: &[u8], queue: &wgpu::Queue) {
+        let rows = rows.start.min(self.size.height)..rows.end.min(self.size.height);
+        if rows.is_empty() {
+            return;
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: rows.start,
+                    z: 0,
+                },
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(self.bytes_per_row()),
+                rows_per_image: std::num::NonZeroU32::new(rows.len() as u32),
+            },
+            wgpu::Extent3d {
+                width: self.size.width,
+                height: rows.len() as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// The number of bytes in a row of the texture's data.
+    #[inline]
+    pub fn bytes_per_row(&self) -> u32 {
+        bytes_per_pixel(self.indexed) * self.size.width
+    }
+
+    /// Return [`WgpuDisplayTextureUpdateError::DimensionsChanged`] if a
+    /// display of some dimensions can't be copied to this texture.
+    pub fn check_dimensions(
+        &self,
+        (new_width, new_height): (u32, u32),
+    ) -> Result<(), WgpuDisplayTextureUpdateError> {
+        if (new_width != self.size.width) || (new_height != self.size.height) {
+            return Err(WgpuDisplayTextureUpdateError::DimensionsChanged {
+                old: (self.size.width, self.size.height),
+                new: (new_width, new_height),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The data to upload to a texture for a display: its plane indices if the
+/// texture is indexed, or its RGBA image otherwise.
+fn texture_data<D: Display + ?Sized>(display: &D, indexed: bool) -> &[u8] {
+    if indexed {
+        display.plane_indices().unwrap_or_default()
+    } else {
+        display.as_rgba8_image().as_raw()
+    }
+}
+
+fn texture_format(indexed: bool, is_srgb: bool) -> wgpu::TextureFormat {
+    if indexed {
+        wgpu::TextureFormat::R8Uint
+    } else if is_srgb {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    } else {
+        wgpu::TextureFormat::Rgba8Unorm
+    }
+}
+
+#[inline]
+fn bytes_per_pixel(indexed: bool) -> u32 {
+    if indexed {
+        1
+    } else {
+        4
+    }
 }
 
 #[derive(Error, Debug)]
@@ -1,26 +1,145 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crossbeam::channel::TryRecvError;
+use color_eyre::eyre::Context;
 use egui::{Key, KeyboardShortcut, Modifiers};
+use strum::IntoEnumIterator;
 
-use emulator::Emulator;
-use renderer::Renderer;
+use assembler::SymbolMap;
+use cpu::{RngKind, RngOptions};
+use dap_server::DapServer;
+use display::{DisplayRef, Palette, PalettePreset};
+use display_phosphor::{Persistence, DEFAULT_DECAY};
+use emulator::{ClipFormat, Command, Emulator, Event, HaltReason, Movie, MovieStatus, Pace};
+use gdb_stub::GdbServer;
+use platform::Platform;
+use renderer::{Renderer, Rotation, ScaleMode, ScreenOptions};
+
+use crate::debugger::Debugger;
 
 const SHORTCUT_SHOW_HIDE_UI: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::H);
 const SHORTCUT_FULLSCREEN: KeyboardShortcut = KeyboardShortcut::new(Modifiers::ALT, Key::Enter);
 const SHORTCUT_QUIT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::Q);
+const SHORTCUT_SOFT_RESET: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::R);
+const SHORTCUT_HARD_RESET: KeyboardShortcut = KeyboardShortcut::new(
+    Modifiers {
+        ctrl: true,
+        shift: true,
+        ..Modifiers::NONE
+    },
+    Key::R,
+);
+const SHORTCUT_PAUSE: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::P);
+const SHORTCUT_STEP: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::F10);
+const SHORTCUT_DEBUGGER: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::D);
+const SHORTCUT_SCREENSHOT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::F12);
+const SHORTCUT_FAST_FORWARD: KeyboardShortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::T);
+
+/// The key that fast-forwards for as long as it's held down. Tab would clash
+/// with moving between text fields.
+const FAST_FORWARD_KEY: Key = Key::Space;
+/// How many times faster than real time fast-forwarding runs by default.
+const DEFAULT_FAST_FORWARD_SPEED: f32 = 4.0;
+/// The slow motion speeds on offer, as fractions of real time.
+const SLOW_MOTION_SPEEDS: [f32; 2] = [0.25, 0.5];
 
-/// For keeping track of if a new display frame needs to be rendered.
-struct DisplayNeedsFrameRenderedTracker(pub Arc<AtomicBool>);
+/// The port the GDB server listens on by default. This is the port registered
+/// for GDB's remote protocol.
+const DEFAULT_GDB_PORT: u16 = 2159;
+/// The port the DAP server listens on by default. This is the port that most
+/// debug adapters' examples use.
+const DEFAULT_DAP_PORT: u16 = 4711;
+
+/// The keyboard keys mapped to each key on the CHIP8's keypad. The layout of the
+/// keypad is mapped onto the left-hand side of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D  ->  Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+#[rustfmt::skip]
+const KEYPAD_MAPPING: [(Key, u8); 16] = [
+    (Key::Num1, 0x1), (Key::Num2, 0x2), (Key::Num3, 0x3), (Key::Num4, 0xC),
+    (Key::Q,    0x4), (Key::W,    0x5), (Key::E,    0x6), (Key::R,    0xD),
+    (Key::A,    0x7), (Key::S,    0x8), (Key::D,    0x9), (Key::F,    0xE),
+    (Key::Z,    0xA), (Key::X,    0x0), (Key::C,    0xB), (Key::V,    0xF),
+];
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct App {
     fullscreen: bool,
     ui_shown: bool,
+    debugger: Debugger,
+    /// The localhost port that the GDB server listens on.
+    gdb_port: u16,
+    /// The localhost port that the DAP server listens on.
+    dap_port: u16,
+    /// How random numbers are generated.
+    rng: RngOptions,
+    /// The colours the display draws with.
+    palette: Palette,
+    /// How the display blends frames together to hide flicker, if it does.
+    persistence: Option<Persistence>,
+    /// How the display is fitted onto the screen.
+    screen: ScreenOptions,
+    /// The file that movies are recorded to and played from.
+    movie_path: String,
+    /// The whole number that screenshots and clips are scaled up by.
+    capture_scale: u32,
+    /// The format that clips are recorded in.
+    clip_format: ClipFormat,
+    /// How many times faster than real time fast-forwarding runs.
+    fast_forward_speed: f32,
+
+    #[serde(skip)]
+    emulator: Emulator,
+
+    /// How fast the emulator runs when it isn't fast-forwarding: in real time,
+    /// in slow motion, or uncapped.
+    #[serde(skip)]
+    pace: Pace,
+    /// Set while fast-forwarding is switched on, rather than held down.
+    #[serde(skip)]
+    fast_forward: bool,
+    /// The pace last sent to the emulator.
+    #[serde(skip)]
+    sent_pace: Pace,
+
+    /// The GDB server, while it's running.
+    #[serde(skip)]
+    gdb_server: Option<GdbServer>,
+    /// The DAP server, while it's running.
+    #[serde(skip)]
+    dap_server: Option<DapServer>,
+    /// Set if the GDB or DAP server failed to start.
+    #[serde(skip)]
+    debug_server_error: Option<String>,
+    /// Set if a movie couldn't be loaded.
+    #[serde(skip)]
+    movie_error: Option<String>,
+
+    /// The symbols for the current ROM, if it was assembled from Octo source.
+    #[serde(skip)]
+    symbols: Option<SymbolMap>,
+    /// The name of the current ROM's file, without its extension, which
+    /// screenshots are named after.
+    #[serde(skip)]
+    rom_name: Option<String>,
+    /// Where the last screenshot was saved, or why it couldn't be.
+    #[serde(skip)]
+    last_screenshot: Option<Result<PathBuf, String>>,
+
+    /// The display attached by the emulator, for taking screenshots.
+    #[serde(skip)]
+    display: Option<DisplayRef>,
+
+    /// Set if the emulator stopped running the current ROM.
+    #[serde(skip)]
+    halt_reason: Option<HaltReason>,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -29,13 +148,48 @@ impl Default for App {
         Self {
             fullscreen: false,
             ui_shown: true,
+            debugger: Debugger::default(),
+            gdb_port: DEFAULT_GDB_PORT,
+            dap_port: DEFAULT_DAP_PORT,
+            rng: RngOptions::default(),
+            palette: Palette::default(),
+            persistence: None,
+            screen: ScreenOptions::default(),
+            movie_path: "rust-chip-movie.json".to_string(),
+            capture_scale: 1,
+            clip_format: ClipFormat::default(),
+            fast_forward_speed: DEFAULT_FAST_FORWARD_SPEED,
+            emulator: Emulator::default(),
+            pace: Pace::default(),
+            fast_forward: false,
+            sent_pace: Pace::default(),
+            gdb_server: None,
+            dap_server: None,
+            debug_server_error: None,
+            movie_error: None,
+            symbols: None,
+            rom_name: None,
+            last_screenshot: None,
+            display: None,
+            halt_reason: None,
         }
     }
 }
 
 impl App {
     /// Called once before the first frame to handle initializing the app.
-    pub fn new(cc: &eframe::CreationContext<'_>, emulator: &Emulator) -> Self {
+    ///
+    /// `symbols` are the symbols for a ROM that's already been loaded, if any,
+    /// and `rom_path` is where it was loaded from. `rng_kind` and `seed`
+    /// override the saved random number generator options for this run only.
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        emulator: &Emulator,
+        symbols: Option<SymbolMap>,
+        rom_path: Option<&Path>,
+        rng_kind: Option<RngKind>,
+        seed: Option<u32>,
+    ) -> Self {
         // Get the WGPU render state from the eframe creation context.
         let wgpu_render_state = cc
             .wgpu_render_state
@@ -51,41 +205,82 @@ impl App {
             // Create a new renderer. It is stored inside of eframe-wgpu's custom
             // renderer infrastructure via the `paint_callback_resouces` type map,
             // as it must have the same lifetime as the egui render pass.
-            wgpu_renderer.paint_callback_resources.insert(Renderer::new(
-                wgpu_device,
-                wgpu_queue,
-                wgpu_target_format,
-            ));
-
-            // The paint callbacks also require a reference to the emulator, which must
-            // also have the same lifetime as the egui render pass.
-            wgpu_renderer
-                .paint_callback_resources
-                .insert(emulator.clone());
-
-            // The paint callbacks need to keep track of if the emulator has prepared
-            // a new frame for rendering. Basically:
-            //
-            // - In the `prepare()` callback, if the emulator indicates that a
-            //   frame is ready, this is set to `true`
-            // - In the `paint()` callback, if this is set to `true`, then after
-            //   the GPU successfully renders a frame then this will be set to
-            //   `false` and the emulator will be notified that the new frame has
-            //   successfully been rendered.
-            wgpu_renderer
-                .paint_callback_resources
-                .insert(DisplayNeedsFrameRenderedTracker(Arc::new(AtomicBool::new(
-                    false,
-                ))));
+            let mut renderer = Renderer::new(wgpu_device, wgpu_queue, wgpu_target_format);
+            if let Some(frames) = emulator.take_frame_reader() {
+                renderer.attach_frames(frames);
+            }
+            wgpu_renderer.paint_callback_resources.insert(renderer);
         }
 
         // Load previous app state (if any).
-        if let Some(storage) = cc.storage {
+        let mut app: Self = if let Some(storage) = cc.storage {
             eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
         } else {
             Default::default()
+        };
+
+        app.emulator = emulator.clone();
+        app.symbols = symbols;
+        app.rom_name = rom_path.map(rom_name);
+
+        app.emulator.send(Command::SetRng(RngOptions {
+            kind: rng_kind.unwrap_or(app.rng.kind),
+            seed: seed.or(app.rng.seed),
+        }));
+        app.emulator.send(Command::SetPalette(app.palette));
+        if app.persistence.is_some() {
+            app.emulator.send(Command::SetPersistence(app.persistence));
         }
+
+        app
+    }
+}
+
+/// Read a ROM from a file, and send it to the emulator.
+///
+/// If the file extension indicates which platform the ROM was written for (see
+/// [`Platform::from_rom_extension`]), the emulator is switched to that platform
+/// first. Octo source files (`.8o`) are assembled first, and their symbols are
+/// returned.
+pub fn load_rom_file(emulator: &Emulator, path: &Path) -> color_eyre::Result<Option<SymbolMap>> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    // Octo source is assembled on the fly, and run on the simplest platform
+    // that supports everything it uses.
+    if extension == Some("8o") {
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read Octo source from {}", path.display()))?;
+        let program = assembler::assemble(&source)
+            .wrap_err_with(|| format!("Failed to assemble {}", path.display()))?;
+
+        tracing::info!("Loading assembled ROM from {}", path.display());
+
+        emulator.send(Command::SetPlatform(program.platform));
+        emulator.send(Command::LoadRom(program.rom));
+
+        return Ok(Some(program.symbols));
+    }
+
+    let rom = std::fs::read(path)
+        .wrap_err_with(|| format!("Failed to read ROM from {}", path.display()))?;
+
+    tracing::info!("Loading ROM from {}", path.display());
+
+    if let Some(platform) = extension.and_then(Platform::from_rom_extension) {
+        emulator.send(Command::SetPlatform(platform));
     }
+
+    emulator.send(Command::LoadRom(rom));
+
+    Ok(None)
+}
+
+/// The name of a ROM's file, without its extension.
+fn rom_name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
 }
 
 impl eframe::App for App {
@@ -96,12 +291,18 @@ impl eframe::App for App {
 
     /// Called each time the UI needs to be redrawn.
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.handle_emulator_events();
+
         // Show a top menu bar, if the UI isn't hidden
         if self.ui_shown {
             egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
                 egui::menu::bar(ui, |ui| {
                     // File menu
                     ui.menu_button("File", |ui| {
+                        self.show_screenshot_menu(ctx, ui);
+                        ui.menu_button("Record clip", |ui| self.show_clip_menu(ui));
+                        ui.separator();
+
                         if ui
                             .button(shortcut_text_label(ctx, "Quit", &SHORTCUT_QUIT))
                             .clicked()
@@ -110,6 +311,61 @@ impl eframe::App for App {
                         }
                     });
 
+                    // Emulation menu
+                    ui.menu_button("Emulation", |ui| {
+                        if ui
+                            .button(shortcut_text_label(ctx, "Soft reset", &SHORTCUT_SOFT_RESET))
+                            .on_hover_text(
+                                "Restart the ROM, keeping the contents of memory and the display",
+                            )
+                            .clicked()
+                        {
+                            ui.close_menu();
+                            self.soft_reset();
+                        }
+
+                        if ui
+                            .button(shortcut_text_label(ctx, "Hard reset", &SHORTCUT_HARD_RESET))
+                            .on_hover_text(
+                                "Restart the ROM with freshly-initialized memory and a clear display",
+                            )
+                            .clicked()
+                        {
+                            ui.close_menu();
+                            self.hard_reset();
+                        }
+
+                        ui.separator();
+
+                        let pause_label = if self.emulator.snapshot().paused {
+                            "Resume"
+                        } else {
+                            "Pause"
+                        };
+                        if ui
+                            .button(shortcut_text_label(ctx, pause_label, &SHORTCUT_PAUSE))
+                            .clicked()
+                        {
+                            ui.close_menu();
+                            self.toggle_pause();
+                        }
+
+                        if ui
+                            .button(shortcut_text_label(ctx, "Step", &SHORTCUT_STEP))
+                            .on_hover_text("Pause, and execute a single instruction")
+                            .clicked()
+                        {
+                            self.emulator.send(Command::Step);
+                        }
+
+                        ui.menu_button("Speed", |ui| self.show_speed_menu(ctx, ui));
+                        ui.menu_button("Random numbers", |ui| self.show_rng_menu(ui));
+                        ui.menu_button("Movie", |ui| self.show_movie_menu(ui));
+
+                        ui.separator();
+                        self.show_debug_servers_menu(ui);
+                    });
+
                     // View menu
                     ui.menu_button("View", |ui| {
                         ui.checkbox(
@@ -117,6 +373,21 @@ impl eframe::App for App {
                             shortcut_text_label(ctx, "Show UI", &SHORTCUT_SHOW_HIDE_UI),
                         );
 
+                        ui.checkbox(
+                            &mut self.debugger.panel_shown,
+                            shortcut_text_label(ctx, "Debugger", &SHORTCUT_DEBUGGER),
+                        );
+                        ui.checkbox(&mut self.debugger.disassembly_shown, "Disassembly");
+                        ui.checkbox(&mut self.debugger.memory_shown, "Memory");
+                        ui.checkbox(&mut self.debugger.breakpoints_shown, "Breakpoints");
+                        ui.checkbox(&mut self.debugger.trace_shown, "Trace");
+                        ui.checkbox(&mut self.debugger.profiler_shown, "Profiler");
+
+                        ui.separator();
+                        ui.menu_button("Screen", |ui| self.show_screen_menu(ui));
+                        ui.menu_button("Palette", |ui| self.show_palette_menu(ui));
+                        ui.menu_button("Persistence", |ui| self.show_persistence_menu(ui));
+
                         if ui
                             .checkbox(
                                 &mut self.fullscreen,
@@ -127,11 +398,26 @@ impl eframe::App for App {
                             self.fullscreen = !self.fullscreen;
                             self.toggle_fullscreen(frame);
                         }
-                    })
+                    });
+
+                    if self.sent_pace != Pace::RealTime {
+                        ui.separator();
+                        ui.label(speed_text(&self.emulator));
+                    }
+
+                    if let Some(halt_reason) = &self.halt_reason {
+                        ui.separator();
+                        ui.colored_label(ui.visuals().error_fg_color, halt_reason.to_string());
+                    }
                 });
             });
         }
 
+        if self.ui_shown {
+            self.debugger
+                .show(ctx, &self.emulator, self.symbols.as_ref());
+        }
+
         // Render the emulator in the central panel
         egui::CentralPanel::default()
             .frame(egui::Frame::canvas(&egui::Style::default()).stroke(egui::Stroke::none()))
@@ -140,10 +426,56 @@ impl eframe::App for App {
             });
 
         self.handle_keyboard_input(ctx, frame);
+        self.handle_dropped_files(ctx);
+        self.update_pace(ctx);
     }
 }
 
 impl App {
+    /// Handle every event that the emulator has sent since the last update.
+    fn handle_emulator_events(&mut self) {
+        // Programs launched from an editor replace the current ROM's symbols.
+        if let Some(launched) = self.dap_server.as_ref().and_then(DapServer::take_launched) {
+            tracing::info!("Launched {} from the DAP server", launched.path.display());
+            self.symbols = launched.symbols;
+            self.rom_name = Some(rom_name(&launched.path));
+            self.halt_reason = None;
+        }
+
+        while let Some(event) = self.emulator.try_recv_event() {
+            match event {
+                // The renderer takes new frames by itself while painting, which
+                // this event woke the UI thread up to do.
+                Event::FrameReady => {}
+                Event::DisplayChanged(display) => self.display = Some(display),
+                // There's no audio output yet, so the tone is only logged.
+                Event::SoundOn => tracing::debug!("Sound on"),
+                Event::SoundOff => tracing::debug!("Sound off"),
+                Event::Halted(reason) => self.halt_reason = Some(reason),
+                Event::BreakpointHit(hit) => {
+                    tracing::info!(?hit, "Breakpoint hit");
+                    self.debugger.on_break(hit);
+                }
+            }
+        }
+    }
+
+    /// Load ROMs that were dragged and dropped onto the window.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped_files = ctx.input().raw.dropped_files.clone();
+
+        for path in dropped_files.iter().filter_map(|file| file.path.as_ref()) {
+            match load_rom_file(&self.emulator, path) {
+                Ok(symbols) => {
+                    self.symbols = symbols;
+                    self.rom_name = Some(rom_name(path));
+                    self.halt_reason = None;
+                }
+                Err(e) => tracing::error!("{e:?}"),
+            }
+        }
+    }
+
     /// Handle keyboard input. Returns true if input was handled by this function,
     /// and false if it was ignored.
     fn handle_keyboard_input(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) -> bool {
@@ -165,9 +497,567 @@ impl App {
             self.toggle_fullscreen(frame);
         }
 
+        // Check for the hard reset first, as its shortcut is a superset of the
+        // soft reset's.
+        if ctx.input_mut().consume_shortcut(&SHORTCUT_HARD_RESET) {
+            input_handled = true;
+            self.hard_reset();
+        }
+
+        if ctx.input_mut().consume_shortcut(&SHORTCUT_SOFT_RESET) {
+            input_handled = true;
+            self.soft_reset();
+        }
+
+        if ctx.input_mut().consume_shortcut(&SHORTCUT_PAUSE) {
+            input_handled = true;
+            self.toggle_pause();
+        }
+
+        if ctx.input_mut().consume_shortcut(&SHORTCUT_STEP) {
+            input_handled = true;
+            self.emulator.send(Command::Step);
+        }
+
+        if ctx.input_mut().consume_shortcut(&SHORTCUT_DEBUGGER) {
+            input_handled = true;
+            self.debugger.panel_shown = !self.debugger.panel_shown;
+        }
+
+        if ctx.input_mut().consume_shortcut(&SHORTCUT_SCREENSHOT) {
+            input_handled = true;
+            self.save_screenshot();
+        }
+
+        if ctx.input_mut().consume_shortcut(&SHORTCUT_FAST_FORWARD) {
+            input_handled = true;
+            self.fast_forward = !self.fast_forward;
+        }
+
+        // Forward keypad presses to the emulator. Presses with modifiers held are
+        // probably shortcuts, and presses while a text field has focus are
+        // typing, so they're ignored. Releases are always forwarded, so that
+        // keys never get stuck down.
+        let typing = ctx.wants_keyboard_input();
+        for event in &ctx.input().events {
+            if let egui::Event::Key {
+                key,
+                pressed,
+                modifiers,
+                ..
+            } = event
+            {
+                let chip8_key = match KEYPAD_MAPPING.iter().find(|(k, _)| k == key) {
+                    Some((_, chip8_key)) => chip8_key,
+                    None => continue,
+                };
+
+                if !*pressed {
+                    input_handled = true;
+                    self.emulator.send(Command::KeyUp(*chip8_key));
+                } else if modifiers.is_none() && !typing {
+                    input_handled = true;
+                    self.emulator.send(Command::KeyDown(*chip8_key));
+                }
+            }
+        }
+
         input_handled
     }
 
+    fn soft_reset(&mut self) {
+        self.halt_reason = None;
+        self.emulator.send(Command::SoftReset);
+    }
+
+    fn hard_reset(&mut self) {
+        self.halt_reason = None;
+        self.emulator.send(Command::HardReset);
+    }
+
+    /// Show the controls for saving screenshots.
+    fn show_screenshot_menu(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        if ui
+            .button(shortcut_text_label(
+                ctx,
+                "Save screenshot",
+                &SHORTCUT_SCREENSHOT,
+            ))
+            .on_hover_text("Save the display as a PNG in the current directory")
+            .clicked()
+        {
+            self.save_screenshot();
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Scale:");
+            ui.add(
+                egui::DragValue::new(&mut self.capture_scale)
+                    .clamp_range(1..=display::MAX_SCALE)
+                    .suffix("x"),
+            );
+        });
+
+        match &self.last_screenshot {
+            Some(Ok(path)) => {
+                ui.label(format!("Saved {}", path.display()));
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            None => {}
+        }
+    }
+
+    /// Show the controls for recording clips of the display.
+    fn show_clip_menu(&mut self, ui: &mut egui::Ui) {
+        let status = self.emulator.snapshot().clip;
+        let running = status.as_ref().is_some_and(|status| status.running);
+
+        ui.add_enabled_ui(!running, |ui| {
+            for format in [ClipFormat::Gif, ClipFormat::Apng] {
+                ui.radio_value(&mut self.clip_format, format, format.to_string());
+            }
+        });
+
+        if running {
+            if ui.button("Stop").clicked() {
+                self.emulator.send(Command::StopClip);
+            }
+        } else if ui
+            .button("Record")
+            .on_hover_text(
+                "Record the display in the current directory until stopped, using the scale \
+                 for screenshots",
+            )
+            .clicked()
+        {
+            let rom_name = self.rom_name.as_deref().unwrap_or("rust-chip");
+            let path = display::capture_file_name(rom_name, self.clip_format.extension());
+            self.emulator.send(Command::StartClip {
+                path: path.into(),
+                scale: self.capture_scale,
+            });
+        }
+
+        if let Some(clip) = &status {
+            let state = if clip.running {
+                "Recording to"
+            } else {
+                "Recorded to"
+            };
+            ui.label(format!(
+                "{state} {} ({} frames, {} images)",
+                clip.path.display(),
+                clip.frames,
+                clip.images
+            ));
+
+            if let Some(error) = &clip.error {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("Recording failed: {error}"),
+                );
+            }
+        }
+    }
+
+    /// Save the display as a PNG named after the ROM and the time, in the
+    /// current directory.
+    fn save_screenshot(&mut self) {
+        let rom_name = self.rom_name.as_deref().unwrap_or("rust-chip");
+        let path = PathBuf::from(display::capture_file_name(rom_name, "png"));
+
+        let mut display = self.display.as_ref().map(|display| display.lock().unwrap());
+        let result = match display.as_mut().and_then(|display| display.as_mut()) {
+            Some(display) => {
+                display.update_image();
+                display::save_screenshot(display.as_ref(), self.capture_scale, &path)
+                    .map_err(|e| format!("Failed to save a screenshot: {e}"))
+            }
+            None => Err("There's no display to take a screenshot of".to_string()),
+        };
+
+        match &result {
+            Ok(()) => tracing::info!("Saved a screenshot to {}", path.display()),
+            Err(error) => tracing::error!("{error}"),
+        }
+        self.last_screenshot = Some(result.map(|()| path));
+    }
+
+    /// Show the controls for choosing how `CXNN` generates random numbers.
+    fn show_rng_menu(&mut self, ui: &mut egui::Ui) {
+        // Start from the options in use, which may have come from the command
+        // line rather than the saved ones.
+        let mut options = self.emulator.snapshot().rng;
+        let mut changed = false;
+
+        for kind in RngKind::iter() {
+            changed |= ui
+                .radio_value(&mut options.kind, kind, kind.to_string())
+                .changed();
+        }
+
+        ui.separator();
+
+        let mut fixed = options.seed.is_some();
+        if ui
+            .checkbox(&mut fixed, "Fixed seed")
+            .on_hover_text("Generate the same random numbers after every hard reset")
+            .changed()
+        {
+            options.seed = fixed.then_some(0);
+            changed = true;
+        }
+
+        if let Some(seed) = &mut options.seed {
+            ui.horizontal(|ui| {
+                ui.label("Seed:");
+                changed |= ui.add(egui::DragValue::new(seed)).changed();
+            });
+        }
+
+        if changed {
+            self.rng = options;
+            self.emulator.send(Command::SetRng(options));
+        }
+    }
+
+    /// Send the emulator the pace it should run at, if it's changed. It
+    /// fast-forwards while fast-forwarding is switched on or its key is held,
+    /// unless it's already uncapped.
+    fn update_pace(&mut self, ctx: &egui::Context) {
+        let held = !ctx.wants_keyboard_input() && ctx.input().key_down(FAST_FORWARD_KEY);
+        let pace = if (self.fast_forward || held) && self.pace != Pace::Uncapped {
+            Pace::Scaled(self.fast_forward_speed)
+        } else {
+            self.pace
+        };
+
+        if pace != self.sent_pace {
+            self.sent_pace = pace;
+            self.emulator.send(Command::SetPace(pace));
+        }
+
+        // Keep the measured speed in the menu bar up to date.
+        if pace != Pace::RealTime {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+    }
+
+    /// Show the controls for running faster or slower than real time.
+    fn show_speed_menu(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        for scale in SLOW_MOTION_SPEEDS {
+            let pace = Pace::Scaled(scale);
+            ui.radio_value(&mut self.pace, pace, format!("{pace} (slow motion)"));
+        }
+        ui.radio_value(&mut self.pace, Pace::RealTime, Pace::RealTime.to_string());
+        ui.radio_value(&mut self.pace, Pace::Uncapped, Pace::Uncapped.to_string())
+            .on_hover_text(
+                "Run as fast as possible, and show how many instructions a second that is",
+            );
+
+        ui.separator();
+
+        ui.checkbox(
+            &mut self.fast_forward,
+            shortcut_text_label(ctx, "Fast-forward", &SHORTCUT_FAST_FORWARD),
+        )
+        .on_hover_text(format!(
+            "Or hold {:?}. Frames in between are skipped",
+            FAST_FORWARD_KEY
+        ));
+        ui.add(
+            egui::Slider::new(&mut self.fast_forward_speed, 2.0..=16.0)
+                .text("Fast-forward speed")
+                .suffix("×"),
+        );
+    }
+
+    /// Show the controls for how the display is fitted onto the screen.
+    fn show_screen_menu(&mut self, ui: &mut egui::Ui) {
+        for mode in ScaleMode::iter() {
+            ui.radio_value(&mut self.screen.scale_mode, mode, mode.to_string());
+        }
+
+        if self.screen.scale_mode == ScaleMode::AspectRatio {
+            ui.horizontal(|ui| {
+                ui.label("Width / height:");
+                ui.add(
+                    egui::DragValue::new(&mut self.screen.aspect_ratio)
+                        .speed(0.01)
+                        .clamp_range(0.25..=8.0),
+                );
+            });
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Rotation:");
+            for rotation in Rotation::iter() {
+                ui.radio_value(&mut self.screen.rotation, rotation, rotation.to_string());
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.color_edit_button_srgb(&mut self.screen.border);
+            ui.label("Border");
+        });
+    }
+
+    /// Show the controls for picking a preset palette, or the display's colours
+    /// one by one.
+    fn show_palette_menu(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+
+        let mut preset = self.palette.preset();
+        for choice in PalettePreset::iter() {
+            if ui
+                .radio_value(&mut preset, Some(choice), choice.to_string())
+                .changed()
+            {
+                self.palette = choice.palette();
+                changed = true;
+            }
+        }
+        ui.add_enabled(false, egui::RadioButton::new(preset.is_none(), "Custom"));
+
+        ui.separator();
+
+        // Most programs only use the first two colours, and XO-CHIP programs
+        // the first four, so the rest are tucked away.
+        let (main, extra) = self.palette.colours.split_at_mut(4);
+        for (planes, colour) in main.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                changed |= ui.color_edit_button_srgb(colour).changed();
+                ui.label(colour_name(planes));
+            });
+        }
+        ui.collapsing("More XO-CHIP colours", |ui| {
+            for (planes, colour) in extra.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    changed |= ui.color_edit_button_srgb(colour).changed();
+                    ui.label(colour_name(planes + 4));
+                });
+            }
+        });
+
+        if changed {
+            self.emulator.send(Command::SetPalette(self.palette));
+        }
+    }
+
+    /// Show the controls for choosing how the display blends frames together
+    /// to hide flicker.
+    fn show_persistence_menu(&mut self, ui: &mut egui::Ui) {
+        let mut persistence = self.persistence;
+        let mut changed = false;
+
+        changed |= ui.radio_value(&mut persistence, None, "Off").changed();
+
+        let fading = matches!(persistence, Some(Persistence::Fade { .. }));
+        if ui
+            .radio(fading, "Fade")
+            .on_hover_text("Fade pixels out over several frames when they're turned off")
+            .clicked()
+            && !fading
+        {
+            persistence = Some(Persistence::Fade {
+                decay: DEFAULT_DECAY,
+            });
+            changed = true;
+        }
+
+        changed |= ui
+            .radio_value(&mut persistence, Some(Persistence::Blend), "Blend")
+            .on_hover_text("Show the average of the last two frames")
+            .changed();
+
+        if let Some(Persistence::Fade { decay }) = &mut persistence {
+            ui.separator();
+            changed |= ui
+                .add(egui::Slider::new(decay, 0.1..=0.95).text("Decay"))
+                .on_hover_text(
+                    "How much of a pixel's brightness is kept from one frame to the next",
+                )
+                .changed();
+        }
+
+        if changed {
+            self.persistence = persistence;
+            self.emulator.send(Command::SetPersistence(persistence));
+        }
+    }
+
+    /// Show the controls for recording and playing back input movies.
+    fn show_movie_menu(&mut self, ui: &mut egui::Ui) {
+        let status = self.emulator.snapshot().movie;
+        let active = matches!(
+            status,
+            Some(MovieStatus::Recording { .. } | MovieStatus::Playing { .. })
+        );
+
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.add_enabled(
+                !active,
+                egui::TextEdit::singleline(&mut self.movie_path).desired_width(200.0),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!active, egui::Button::new("Record"))
+                .on_hover_text("Restart the ROM, and record every key press")
+                .clicked()
+            {
+                self.halt_reason = None;
+                self.movie_error = None;
+                self.emulator
+                    .send(Command::StartRecording(self.movie_path.clone().into()));
+            }
+
+            if ui
+                .add_enabled(!active, egui::Button::new("Play"))
+                .on_hover_text("Restart the ROM, and play the movie back")
+                .clicked()
+            {
+                self.halt_reason = None;
+                match Movie::load(Path::new(&self.movie_path)) {
+                    Ok(movie) => {
+                        self.movie_error = None;
+                        self.emulator.send(Command::PlayMovie(movie));
+                    }
+                    Err(e) => self.movie_error = Some(format!("{e}: {}", self.movie_path)),
+                }
+            }
+
+            if ui
+                .add_enabled(active, egui::Button::new("Stop"))
+                .on_hover_text("Stop, saving the recording")
+                .clicked()
+            {
+                self.emulator.send(Command::StopMovie);
+            }
+        });
+
+        if let Some(error) = &self.movie_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+            return;
+        }
+
+        match status {
+            Some(MovieStatus::Recording { frames, inputs }) => {
+                ui.label(format!("Recording: {frames} frames, {inputs} inputs"));
+            }
+            Some(MovieStatus::Playing { frame, frames }) => {
+                ui.label(format!("Playing: frame {frame} of {frames}"));
+            }
+            Some(MovieStatus::Finished { frames }) => {
+                ui.label(format!("Played {frames} frames"));
+            }
+            Some(MovieStatus::Saved { path, frames }) => {
+                ui.label(format!("Saved {frames} frames to {}", path.display()));
+            }
+            Some(MovieStatus::Failed(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            None => {}
+        }
+    }
+
+    /// Show the controls for starting and stopping the GDB and DAP servers.
+    fn show_debug_servers_menu(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("debug_servers").show(ui, |ui| {
+            ui.label("GDB server port:");
+            ui.add_enabled(
+                self.gdb_server.is_none(),
+                egui::DragValue::new(&mut self.gdb_port).clamp_range(1..=u16::MAX),
+            );
+            ui.end_row();
+
+            ui.label("DAP server port:");
+            ui.add_enabled(
+                self.dap_server.is_none(),
+                egui::DragValue::new(&mut self.dap_port).clamp_range(1..=u16::MAX),
+            );
+            ui.end_row();
+        });
+
+        match &self.gdb_server {
+            Some(server) => {
+                ui.label(format!("GDB server listening on {}", server.local_addr()));
+                if ui.button("Stop GDB server").clicked() {
+                    ui.close_menu();
+                    self.gdb_server = None;
+                }
+            }
+            None => {
+                if ui
+                    .button("Start GDB server")
+                    .on_hover_text("Let GDB or LLDB debug the ROM over a localhost connection")
+                    .clicked()
+                {
+                    ui.close_menu();
+                    let result = GdbServer::start(&self.emulator, self.gdb_port);
+                    self.gdb_server = self.server_started("GDB", result);
+                }
+            }
+        }
+
+        match &self.dap_server {
+            Some(server) => {
+                ui.label(format!("DAP server listening on {}", server.local_addr()));
+                if ui.button("Stop DAP server").clicked() {
+                    ui.close_menu();
+                    self.dap_server = None;
+                }
+            }
+            None => {
+                if ui
+                    .button("Start DAP server")
+                    .on_hover_text(
+                        "Let VS Code or another editor launch and debug ROMs over a localhost connection",
+                    )
+                    .clicked()
+                {
+                    ui.close_menu();
+                    let result = DapServer::start(&self.emulator, self.dap_port);
+                    self.dap_server = self.server_started("DAP", result);
+                }
+            }
+        }
+
+        if let Some(error) = &self.debug_server_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+
+    /// Record the result of starting a debug server.
+    fn server_started<T>(&mut self, name: &str, result: std::io::Result<T>) -> Option<T> {
+        match result {
+            Ok(server) => {
+                self.debug_server_error = None;
+                Some(server)
+            }
+            Err(error) => {
+                tracing::error!("Failed to start the {name} server: {error}");
+                self.debug_server_error =
+                    Some(format!("Failed to start the {name} server: {error}"));
+                None
+            }
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        if self.emulator.snapshot().paused {
+            self.emulator.send(Command::Resume);
+        } else {
+            self.emulator.send(Command::Pause);
+        }
+    }
+
     fn toggle_ui(&mut self) {
         self.ui_shown = !self.ui_shown;
     }
@@ -181,105 +1071,37 @@ impl App {
         let (rect, _) =
             ui.allocate_exact_size(ui.available_size(), egui::Sense::focusable_noninteractive());
 
+        // The renderer works in physical pixels, which egui's points are
+        // scaled up to on HiDPI screens.
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let size = (
+            (rect.width() * pixels_per_point).round() as u32,
+            (rect.height() * pixels_per_point).round() as u32,
+        );
+        let screen = self.screen;
+
         // Set up the egui paint callback.
         let cb = eframe::egui_wgpu::CallbackFn::new()
             .prepare(
                 move |device, queue, _egui_cmd_encoder, paint_callback_resources| {
-                    // Start by checking some things from the emulator so we
-                    // minimize the amount of actual rendering work that we have
-                    // to do.
-                    let (new_display, frame_ready_to_render) = {
-                        let emulator = paint_callback_resources.get::<Emulator>().unwrap();
-
-                        // Check if the emulator attached a new display.
-                        let new_display = match emulator.display_ref_receiver.try_recv() {
-                            Ok(new_display) => Some(new_display),
-                            Err(TryRecvError::Empty) => None,
-                            Err(TryRecvError::Disconnected) => {
-                                tracing::error!(
-                                    "Failed to check for new display from emulator due to display \
-                                    reference channel disconnection. Is the emulator dead?"
-                                );
-                                None
-                            }
-                        };
+                    let renderer = paint_callback_resources.get_mut::<Renderer>().unwrap();
 
-                        // Check if the emulator has prepared a new frame for rendering.
-                        let frame_ready_to_render = emulator.is_frame_ready_to_render();
-
-                        (new_display, frame_ready_to_render)
-                    };
-
-                    // Start interacting with the renderer.
-                    {
-                        let renderer = paint_callback_resources.get_mut::<Renderer>().unwrap();
-
-                        // If the emulator has attached a new display, then attach
-                        // that same new display to the renderer.
-                        // This has the effect of re-creating textures and bind groups.
-                        if let Some(new_display) = new_display {
-                            renderer.attach_display(
-                                new_display,
-                                Some("CHIP8 Display"),
-                                Some("CHIP8 Display Bind Group"),
-                                device,
-                                queue,
-                            );
-                        }
+                    // Make sure that the renderer will render at the correct size.
+                    renderer.resize(size, queue);
+                    renderer.set_screen_options(screen, queue);
 
-                        // Make sure that the renderer will render at the correct size.
-                        renderer.resize((rect.width() as u32, rect.height() as u32), queue);
-
-                        // If the emulator has prepared a new frame for rendering, then upload the
-                        // frame to the gpu.
-                        //
-                        // Technically, this function throws an error if the display and the GPU-side
-                        // texture don't have matching dimensions. However, this should _normally_
-                        // never happen. For now, we'll just panic if something like that happens.
-                        if frame_ready_to_render {
-                            renderer.update_display_texture(queue).expect(
-                                "Updating GPU-side texture with a new display frame failed.",
-                            );
-                        }
-                    }
-
-                    // If we prepared a new display frame for rendering, then
-                    // get ready to notify the emulator from the `.paint()`
-                    // callback after rendering takes place.
-                    if frame_ready_to_render {
-                        let display_needs_frame_rendered_tracker = paint_callback_resources
-                            .get::<DisplayNeedsFrameRenderedTracker>()
-                            .unwrap();
-                        display_needs_frame_rendered_tracker
-                            .0
-                            .store(true, Ordering::Release);
-                    }
+                    // If the emulator has published a new frame since the last paint,
+                    // then upload it to the GPU. This never waits on the emulator
+                    // thread, and re-creates the texture if the display changed.
+                    renderer.update_display_texture(device, queue);
 
                     Vec::new()
                 },
             )
             .paint(|_info, render_pass, paint_callback_resources| {
-                // Render
-                {
-                    let renderer = paint_callback_resources.get::<Renderer>().unwrap();
-
-                    renderer.render(render_pass);
-                }
+                let renderer = paint_callback_resources.get::<Renderer>().unwrap();
 
-                // If we just rendered a new display frame, then notify the emulator that this was done.
-                let display_needed_frame_rendered = {
-                    let display_needs_frame_rendered_tracker = paint_callback_resources
-                        .get::<DisplayNeedsFrameRenderedTracker>()
-                        .unwrap();
-                    display_needs_frame_rendered_tracker
-                        .0
-                        .fetch_and(false, Ordering::AcqRel)
-                };
-
-                if display_needed_frame_rendered {
-                    let emulator = paint_callback_resources.get::<Emulator>().unwrap();
-                    emulator.notify_frame_rendered();
-                }
+                renderer.render(render_pass);
             });
 
         let paint_callback = egui::PaintCallback {
@@ -291,6 +1113,32 @@ impl App {
     }
 }
 
+/// Describe how fast the emulator is running, and how many instructions a
+/// second that comes to.
+fn speed_text(emulator: &Emulator) -> String {
+    let speed = emulator.snapshot().speed;
+
+    match speed.instructions_per_second {
+        Some(ips) => format!("{}: {ips:.0} instructions/s", speed.pace),
+        None => speed.pace.to_string(),
+    }
+}
+
 fn shortcut_text_label(ctx: &egui::Context, label: &str, shortcut: &KeyboardShortcut) -> String {
     format!("{label} ({})", ctx.format_shortcut(shortcut))
 }
+
+/// Describe the palette colour for the pixels on some combination of bit
+/// planes, numbering the planes from 1 like Octo does.
+fn colour_name(planes: usize) -> String {
+    let numbers: Vec<String> = (0..4)
+        .filter(|plane| planes & (1 << plane) != 0)
+        .map(|plane| (plane + 1).to_string())
+        .collect();
+
+    match numbers.as_slice() {
+        [] => "Background".to_string(),
+        [plane] => format!("Plane {plane}"),
+        [rest @ .., last] => format!("Planes {} and {last}", rest.join(", ")),
+    }
+}
@@ -0,0 +1,279 @@
+//! The breakpoints window, for managing breakpoints, watchpoints and event
+//! breakpoints.
+
+use std::collections::BTreeMap;
+
+use assembler::SymbolMap;
+use cpu::Access;
+use emulator::{Break, BreakReason, Command, Condition, Emulator, Snapshot, Watchpoint};
+
+use super::parse_addr;
+
+/// The state of the breakpoints window.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct BreakpointsWindow {
+    addr_text: String,
+    condition_text: String,
+    watch_start: String,
+    watch_end: String,
+    watch_read: bool,
+    watch_write: bool,
+    watch_execute: bool,
+
+    /// The conditions being edited, by breakpoint address.
+    #[serde(skip)]
+    conditions: BTreeMap<u16, String>,
+    #[serde(skip)]
+    error: Option<String>,
+}
+
+impl Default for BreakpointsWindow {
+    fn default() -> Self {
+        Self {
+            addr_text: String::new(),
+            condition_text: String::new(),
+            watch_start: String::new(),
+            watch_end: String::new(),
+            watch_read: false,
+            watch_write: true,
+            watch_execute: false,
+            conditions: BTreeMap::new(),
+            error: None,
+        }
+    }
+}
+
+impl BreakpointsWindow {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+        last_break: Option<&Break>,
+    ) {
+        if let Some(hit) = last_break.filter(|_| snapshot.paused) {
+            ui.label(format!("Paused: {}", describe(hit, symbols)));
+            ui.separator();
+        }
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        self.show_breakpoints(ui, snapshot, emulator, symbols);
+        ui.separator();
+        self.show_watchpoints(ui, snapshot, emulator, symbols);
+        ui.separator();
+        show_events(ui, snapshot, emulator);
+    }
+
+    fn show_breakpoints(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+    ) {
+        ui.heading("Breakpoints");
+
+        let breakpoints = snapshot.breakpoints.breakpoints();
+
+        // Forget about conditions being edited for breakpoints that are gone.
+        self.conditions
+            .retain(|addr, _| breakpoints.contains_key(addr));
+
+        for (&addr, breakpoint) in breakpoints {
+            ui.horizontal(|ui| {
+                ui.monospace(format_addr(addr, symbols));
+                ui.label(format!("hits: {}", breakpoint.hits));
+
+                let text = self.conditions.entry(addr).or_insert_with(|| {
+                    breakpoint
+                        .condition
+                        .as_ref()
+                        .map_or_else(String::new, |condition| condition.to_string())
+                });
+                let response = ui.add(
+                    egui::TextEdit::singleline(text)
+                        .desired_width(180.0)
+                        .hint_text("condition"),
+                );
+
+                if response.lost_focus() {
+                    match parse_condition(text) {
+                        Ok(condition) => {
+                            if condition != breakpoint.condition {
+                                emulator.send(Command::SetBreakpointCondition { addr, condition });
+                            }
+                            self.error = None;
+                        }
+                        Err(error) => self.error = Some(error),
+                    }
+                }
+
+                if ui.small_button("Remove").clicked() {
+                    emulator.send(Command::RemoveBreakpoint(addr));
+                }
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.addr_text)
+                    .desired_width(100.0)
+                    .hint_text("address or label"),
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.condition_text)
+                    .desired_width(180.0)
+                    .hint_text("condition, like V0 == 3"),
+            );
+
+            if ui.button("Add").clicked() {
+                let addr = parse_addr(&self.addr_text, symbols)
+                    .ok_or_else(|| format!("Unknown address {}", self.addr_text));
+
+                match addr.and_then(|addr| Ok((addr, parse_condition(&self.condition_text)?))) {
+                    Ok((addr, condition)) => {
+                        emulator.send(Command::AddBreakpoint(addr));
+                        emulator.send(Command::SetBreakpointCondition { addr, condition });
+                        self.conditions.remove(&addr);
+                        self.addr_text.clear();
+                        self.condition_text.clear();
+                        self.error = None;
+                    }
+                    Err(error) => self.error = Some(error),
+                }
+            }
+        });
+    }
+
+    fn show_watchpoints(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+    ) {
+        ui.heading("Watchpoints");
+
+        for watchpoint in snapshot.breakpoints.watchpoints() {
+            ui.horizontal(|ui| {
+                let flag = |set: bool, c: char| if set { c } else { '-' };
+                ui.monospace(format!(
+                    "{:04X}-{:04X} {}{}{}",
+                    watchpoint.addrs.start(),
+                    watchpoint.addrs.end(),
+                    flag(watchpoint.read, 'R'),
+                    flag(watchpoint.write, 'W'),
+                    flag(watchpoint.execute, 'X'),
+                ));
+                if ui.small_button("Remove").clicked() {
+                    emulator.send(Command::RemoveWatchpoint(watchpoint.clone()));
+                }
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.watch_start)
+                    .desired_width(60.0)
+                    .hint_text("from"),
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.watch_end)
+                    .desired_width(60.0)
+                    .hint_text("to"),
+            );
+            ui.checkbox(&mut self.watch_read, "R");
+            ui.checkbox(&mut self.watch_write, "W");
+            ui.checkbox(&mut self.watch_execute, "X");
+
+            if ui.button("Add").clicked() {
+                // Watching a single address only needs the start.
+                let start = parse_addr(&self.watch_start, symbols);
+                let end = if self.watch_end.trim().is_empty() {
+                    start
+                } else {
+                    parse_addr(&self.watch_end, symbols)
+                };
+
+                match start.zip(end).filter(|(start, end)| start <= end) {
+                    Some((start, end)) => {
+                        emulator.send(Command::AddWatchpoint(Watchpoint {
+                            addrs: start..=end,
+                            read: self.watch_read,
+                            write: self.watch_write,
+                            execute: self.watch_execute,
+                        }));
+                        self.error = None;
+                    }
+                    None => self.error = Some("Invalid address range".to_string()),
+                }
+            }
+        });
+    }
+}
+
+/// Show a checkbox for each kind of event breakpoint.
+fn show_events(ui: &mut egui::Ui, snapshot: &Snapshot, emulator: &Emulator) {
+    ui.heading("Pause on");
+
+    let mut events = snapshot.breakpoints.events();
+
+    ui.checkbox(&mut events.display_clear, "Display clear (00E0)");
+    ui.checkbox(&mut events.collision, "Sprite collision (DXYN)");
+    ui.checkbox(&mut events.key_wait, "Waiting for a key (FX0A)");
+    ui.checkbox(&mut events.sound_start, "Sound starting");
+    ui.checkbox(&mut events.unknown_opcode, "Unknown opcode");
+
+    if events != snapshot.breakpoints.events() {
+        emulator.send(Command::SetEventBreakpoints(events));
+    }
+}
+
+/// Parse a condition typed in by the user, where an empty condition means the
+/// breakpoint is unconditional.
+fn parse_condition(text: &str) -> Result<Option<Condition>, String> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+
+    Condition::parse(text)
+        .map(Some)
+        .map_err(|error| format!("Invalid condition: {error}"))
+}
+
+/// An address, along with the label it's in if there is one.
+fn format_addr(addr: u16, symbols: Option<&SymbolMap>) -> String {
+    match symbols.and_then(|symbols| symbols.nearest_label(addr)) {
+        Some((name, 0)) => format!("{addr:04X} {name}"),
+        Some((name, offset)) => format!("{addr:04X} {name}+{offset}"),
+        None => format!("{addr:04X}"),
+    }
+}
+
+/// A description of why the emulator paused.
+fn describe(hit: &Break, symbols: Option<&SymbolMap>) -> String {
+    let reason = match hit.reason {
+        BreakReason::Breakpoint => "breakpoint".to_string(),
+        BreakReason::Watchpoint { addr, access } => {
+            let access = match access {
+                Access::Read => "read from",
+                Access::Write => "write to",
+                Access::Execute => "execution of",
+            };
+            format!("watchpoint on {access} {addr:04X}")
+        }
+        BreakReason::DisplayClear => "display clear".to_string(),
+        BreakReason::Collision => "sprite collision".to_string(),
+        BreakReason::KeyWait => "waiting for a key".to_string(),
+        BreakReason::SoundStart => "sound starting".to_string(),
+        BreakReason::UnknownOpcode => "unknown opcode".to_string(),
+        BreakReason::Step => "step".to_string(),
+    };
+
+    format!("{reason} at {}", format_addr(hit.addr, symbols))
+}
@@ -0,0 +1,218 @@
+//! The debugger side panel, showing the CPU's registers, call stack, timers and
+//! keypad.
+
+use assembler::SymbolMap;
+use cpu::STACK_SIZE;
+use emulator::{Command, Emulator, Register, Snapshot};
+
+use super::DockSide;
+
+/// The keypad's layout, as on the COSMAC VIP.
+#[rustfmt::skip]
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Show the contents of the panel. Values can be edited while the emulator is
+/// paused, and are sent to it as commands.
+pub fn show(
+    ui: &mut egui::Ui,
+    snapshot: &Snapshot,
+    emulator: &Emulator,
+    symbols: Option<&SymbolMap>,
+    dock_side: &mut DockSide,
+) {
+    let cpu = &snapshot.cpu;
+    let editable = snapshot.paused;
+
+    ui.horizontal(|ui| {
+        if snapshot.paused {
+            if ui.button("Resume").clicked() {
+                emulator.send(Command::Resume);
+            }
+        } else if ui.button("Pause").clicked() {
+            emulator.send(Command::Pause);
+        }
+
+        if ui
+            .button("Step")
+            .on_hover_text("Pause, and execute a single instruction")
+            .clicked()
+        {
+            emulator.send(Command::Step);
+        }
+
+        ui.separator();
+        ui.label("Dock:");
+        ui.selectable_value(dock_side, DockSide::Left, "Left");
+        ui.selectable_value(dock_side, DockSide::Right, "Right");
+    });
+
+    ui.separator();
+    ui.heading("Registers");
+
+    egui::Grid::new("debugger_v_registers")
+        .num_columns(8)
+        .striped(true)
+        .show(ui, |ui| {
+            for row in 0..4 {
+                for column in 0..4 {
+                    let x = row * 4 + column;
+                    ui.monospace(format!("V{x:X}"));
+                    register_value(
+                        ui,
+                        emulator,
+                        Register::V(x as u8),
+                        cpu.v[x] as u16,
+                        editable,
+                    );
+                }
+                ui.end_row();
+            }
+        });
+
+    ui.add_space(4.0);
+
+    egui::Grid::new("debugger_other_registers")
+        .num_columns(3)
+        .show(ui, |ui| {
+            register_row(ui, emulator, "I", Register::I, cpu.i, editable);
+            if let Some(label) = describe_addr(symbols, cpu.i) {
+                ui.weak(label);
+            }
+            ui.end_row();
+
+            register_row(ui, emulator, "PC", Register::Pc, cpu.pc, editable);
+            if let Some(label) = describe_addr(symbols, cpu.pc) {
+                ui.weak(label);
+            }
+            ui.end_row();
+
+            register_row(ui, emulator, "SP", Register::Sp, cpu.sp as u16, editable);
+            ui.end_row();
+
+            register_row(
+                ui,
+                emulator,
+                "DT",
+                Register::DelayTimer,
+                cpu.delay_timer as u16,
+                editable,
+            );
+            ui.end_row();
+
+            register_row(
+                ui,
+                emulator,
+                "ST",
+                Register::SoundTimer,
+                cpu.sound_timer as u16,
+                editable,
+            );
+            ui.end_row();
+
+            ui.monospace("Cycles");
+            ui.monospace(cpu.cycles.to_string());
+            ui.end_row();
+        });
+
+    ui.separator();
+    ui.heading("Call stack");
+
+    // The innermost frame is where the PC is, and each outer frame is the call
+    // instruction just before a return address.
+    ui.monospace(format_frame(symbols, cpu.pc));
+    for &return_addr in cpu.call_stack().iter().rev() {
+        ui.monospace(format_frame(symbols, return_addr.wrapping_sub(2)));
+    }
+
+    ui.separator();
+    ui.heading("Keypad");
+
+    egui::Grid::new("debugger_keypad").show(ui, |ui| {
+        for row in KEYPAD_LAYOUT {
+            for key in row {
+                let pressed = snapshot.keypad.is_pressed(key);
+                if ui
+                    .selectable_label(pressed, format!(" {key:X} "))
+                    .on_hover_text("Click to press or release")
+                    .clicked()
+                {
+                    emulator.send(if pressed {
+                        Command::KeyUp(key)
+                    } else {
+                        Command::KeyDown(key)
+                    });
+                }
+            }
+            ui.end_row();
+        }
+    });
+}
+
+/// Show a register's name and value, as two cells of a grid.
+fn register_row(
+    ui: &mut egui::Ui,
+    emulator: &Emulator,
+    name: &str,
+    register: Register,
+    value: u16,
+    editable: bool,
+) {
+    ui.monospace(name);
+    register_value(ui, emulator, register, value, editable);
+}
+
+/// Show a register's value in hex. If it's editable, it can be changed by
+/// dragging or typing, and the new value is sent to the emulator.
+fn register_value(
+    ui: &mut egui::Ui,
+    emulator: &Emulator,
+    register: Register,
+    value: u16,
+    editable: bool,
+) {
+    let (digits, max): (usize, u16) = match register {
+        Register::V(_) | Register::DelayTimer | Register::SoundTimer => (2, 0xFF),
+        Register::I | Register::Pc => (4, 0xFFFF),
+        Register::Sp => (2, STACK_SIZE as u16),
+    };
+
+    if !editable {
+        ui.monospace(format!("{value:0digits$X}"));
+        return;
+    }
+
+    let mut new_value = value;
+    let response = ui.add(
+        egui::DragValue::new(&mut new_value)
+            .clamp_range(0..=max)
+            .custom_formatter(move |n, _| format!("{:0digits$X}", n as u16))
+            .custom_parser(|s| {
+                let s = s.trim().trim_start_matches("0x");
+                u16::from_str_radix(s, 16).ok().map(f64::from)
+            }),
+    );
+
+    if response.changed() && new_value != value {
+        emulator.send(Command::SetRegister(register, new_value));
+    }
+}
+
+/// Describe an address relative to the nearest label, like `draw-player+6`.
+fn describe_addr(symbols: Option<&SymbolMap>, addr: u16) -> Option<String> {
+    match symbols?.nearest_label(addr)? {
+        (name, 0) => Some(name.to_string()),
+        (name, offset) => Some(format!("{name}+{offset}")),
+    }
+}
+
+fn format_frame(symbols: Option<&SymbolMap>, addr: u16) -> String {
+    match describe_addr(symbols, addr) {
+        Some(label) => format!("{addr:04X}  {label}"),
+        None => format!("{addr:04X}"),
+    }
+}
@@ -0,0 +1,315 @@
+//! The disassembly window: a listing of the program in memory that follows the
+//! program counter, with breakpoints.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use egui::{Color32, RichText};
+
+use assembler::SymbolMap;
+use disassembler::{Disassembler, Flow, Label, LabelKind, LineContents, Listing};
+use emulator::{Command, Emulator, Snapshot};
+use platform::Platform;
+use ram::PROGRAM_START_ADDRESS;
+
+use super::parse_addr;
+
+/// The background of the instruction that the PC points at.
+const PC_BACKGROUND: Color32 = Color32::from_rgb(0x1E, 0x4B, 0x7A);
+/// The colour of breakpoint markers.
+const BREAKPOINT_COLOR: Color32 = Color32::from_rgb(0xE5, 0x3E, 0x3E);
+/// The colour of labels.
+const LABEL_COLOR: Color32 = Color32::from_rgb(0xDC, 0xDC, 0xAA);
+
+/// The number of lines to show above the PC when scrolling to it.
+const LINES_ABOVE_PC: usize = 4;
+
+/// The state of the disassembly window.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct DisassemblyWindow {
+    /// True if the listing scrolls to keep the PC in view.
+    follow_pc: bool,
+    goto_text: String,
+
+    #[serde(skip)]
+    cache: Option<Cache>,
+    /// An address to scroll to on the next frame.
+    #[serde(skip)]
+    scroll_to: Option<u16>,
+    /// The lines that were visible on the last frame.
+    #[serde(skip)]
+    visible_lines: Range<usize>,
+    #[serde(skip)]
+    error: Option<String>,
+}
+
+/// A listing, along with the flows it was made from. The bytes each flow
+/// decoded as instructions are checked every frame, so that self-modifying
+/// code is disassembled again as soon as it changes. Writes to data don't
+/// affect the flows, so they're only copied into the visible lines.
+struct Cache {
+    platform: Platform,
+    /// The machine's reset count when the flows were followed. The flows are
+    /// thrown away after a reset, since a new ROM may have been loaded.
+    resets: u64,
+    /// The flow from each entry point: the start of the program, and any
+    /// addresses that the PC has been seen at that no flow reached. These are
+    /// usually the targets of computed jumps.
+    flows: BTreeMap<u16, Flow>,
+    listing: Listing,
+}
+
+impl Default for DisassemblyWindow {
+    fn default() -> Self {
+        Self {
+            follow_pc: true,
+            goto_text: String::new(),
+            cache: None,
+            scroll_to: None,
+            visible_lines: 0..0,
+            error: None,
+        }
+    }
+}
+
+impl DisassemblyWindow {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+    ) {
+        let pc = snapshot.cpu.pc;
+        self.refresh(snapshot, emulator, symbols);
+        let listing = match &self.cache {
+            Some(cache) => &cache.listing,
+            None => return,
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Go to:");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.goto_text)
+                    .desired_width(100.0)
+                    .hint_text("address or label"),
+            );
+            let submitted = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+
+            if ui.button("Go").clicked() || submitted {
+                let addr = parse_addr(&self.goto_text, symbols).or_else(|| {
+                    listing
+                        .labels
+                        .iter()
+                        .find(|(_, label)| label.name == self.goto_text.trim())
+                        .map(|(&addr, _)| addr)
+                });
+
+                match addr {
+                    Some(addr) => {
+                        // Stop following the PC, or we'd jump straight back.
+                        self.follow_pc = false;
+                        self.scroll_to = Some(addr);
+                        self.error = None;
+                    }
+                    None => self.error = Some(format!("Unknown address {}", self.goto_text)),
+                }
+            }
+
+            if ui.button("PC").on_hover_text("Go to the PC").clicked() {
+                self.scroll_to = Some(pc);
+            }
+            ui.checkbox(&mut self.follow_pc, "Follow PC");
+        });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        ui.weak("Click a line to toggle a breakpoint.");
+        ui.separator();
+
+        let pc_line = listing.line_index(pc);
+
+        // Only scroll when the PC goes out of view, so that the listing doesn't
+        // jump around constantly while running.
+        if self.follow_pc && self.scroll_to.is_none() {
+            if let Some(index) = pc_line {
+                if !self.visible_lines.contains(&index) {
+                    self.scroll_to = Some(pc);
+                }
+            }
+        }
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let mut scroll_area = egui::ScrollArea::vertical().auto_shrink([false, false]);
+        if let Some(index) = self
+            .scroll_to
+            .take()
+            .and_then(|addr| listing.line_index(addr))
+        {
+            let row = index.saturating_sub(LINES_ABOVE_PC);
+            let spacing = ui.spacing().item_spacing.y;
+            scroll_area = scroll_area.vertical_scroll_offset(row as f32 * (row_height + spacing));
+        }
+
+        scroll_area.show_rows(ui, row_height, listing.lines.len(), |ui, rows| {
+            self.visible_lines = rows.clone();
+
+            for index in rows {
+                let line = &listing.lines[index];
+                let breakpoint = snapshot.breakpoints.contains(line.addr);
+
+                // Paint the highlight behind the line once we know how big it is.
+                let background = ui.painter().add(egui::Shape::Noop);
+
+                let response = ui
+                    .horizontal(|ui| {
+                        let marker = if breakpoint { "●" } else { " " };
+                        ui.monospace(RichText::new(marker).color(BREAKPOINT_COLOR));
+                        ui.monospace(RichText::new(format!("{:04X}", line.addr)).weak());
+
+                        let bytes = line
+                            .bytes
+                            .iter()
+                            .map(|byte| format!("{byte:02X}"))
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        ui.monospace(RichText::new(format!("{bytes:<11}")).weak());
+
+                        if let Some(name) = listing.label_name(line.addr) {
+                            ui.monospace(RichText::new(format!("{name}:")).color(LABEL_COLOR));
+                        }
+
+                        let mnemonic = RichText::new(listing.mnemonic(line)).monospace();
+                        match line.contents {
+                            LineContents::Instruction(_) => ui.label(mnemonic),
+                            LineContents::Data => ui.label(mnemonic.weak()),
+                        };
+                    })
+                    .response
+                    .interact(egui::Sense::click());
+
+                if Some(index) == pc_line {
+                    let rect = response.rect.expand2(egui::vec2(2.0, 0.0));
+                    ui.painter().set(
+                        background,
+                        egui::Shape::rect_filled(rect, 2.0, PC_BACKGROUND),
+                    );
+                } else if response.hovered() {
+                    let rect = response.rect.expand2(egui::vec2(2.0, 0.0));
+                    let color = ui.visuals().widgets.hovered.bg_fill;
+                    ui.painter()
+                        .set(background, egui::Shape::rect_filled(rect, 2.0, color));
+                }
+
+                let response = match symbols.and_then(|symbols| symbols.line_of(line.addr)) {
+                    Some(source_line) => response.on_hover_text(format!("Line {source_line}")),
+                    None => response,
+                };
+
+                if response.clicked() {
+                    emulator.send(if breakpoint {
+                        Command::RemoveBreakpoint(line.addr)
+                    } else {
+                        Command::AddBreakpoint(line.addr)
+                    });
+                }
+            }
+        });
+    }
+
+    /// Follow the flows again from any entry points whose code has changed, or
+    /// from the PC if it's gone somewhere that wasn't disassembled as code, and
+    /// list them again if so.
+    fn refresh(&mut self, snapshot: &Snapshot, emulator: &Emulator, symbols: Option<&SymbolMap>) {
+        let pc = snapshot.cpu.pc;
+        let ram = emulator.ram().lock().unwrap();
+        let memory = ram.get_range(..);
+        let disassembler = Disassembler::new(snapshot.platform);
+
+        if self.cache.as_ref().is_some_and(|cache| {
+            cache.platform != snapshot.platform || cache.resets != snapshot.resets
+        }) {
+            self.cache = None;
+        }
+
+        let mut changed = self.cache.is_none();
+        let mut flows = self
+            .cache
+            .as_mut()
+            .map(|cache| std::mem::take(&mut cache.flows))
+            .unwrap_or_default();
+
+        for (&entry_point, flow) in flows.iter_mut() {
+            if flow.is_stale(memory, 0) {
+                *flow = disassembler
+                    .clone()
+                    .with_entry_point(entry_point)
+                    .flow(memory, 0);
+                changed = true;
+            }
+        }
+
+        for entry_point in [PROGRAM_START_ADDRESS, pc] {
+            let reached = flows
+                .values()
+                .any(|flow| flow.instructions.contains_key(&entry_point));
+            if !reached && !flows.contains_key(&entry_point) {
+                let flow = disassembler
+                    .clone()
+                    .with_entry_point(entry_point)
+                    .flow(memory, 0);
+                flows.insert(entry_point, flow);
+                changed = true;
+            }
+        }
+
+        if let Some(cache) = self.cache.as_mut().filter(|_| !changed) {
+            cache.flows = flows;
+
+            // Data isn't worth listing again for, but the visible lines should
+            // still show what's in memory now.
+            let lines = &mut cache.listing.lines;
+            let visible =
+                self.visible_lines.start.min(lines.len())..self.visible_lines.end.min(lines.len());
+            for line in &mut lines[visible] {
+                if line.contents == LineContents::Data {
+                    let start = line.addr as usize;
+                    line.bytes
+                        .copy_from_slice(&memory[start..start + line.bytes.len()]);
+                }
+            }
+            return;
+        }
+
+        let mut listing = disassembler.list(memory, 0, flows.values());
+
+        // Use the names from the source code where we have them. Going in
+        // reverse means that the first name alphabetically wins when an
+        // address has several.
+        if let Some(symbols) = symbols {
+            for (name, &addr) in symbols.labels.iter().rev() {
+                let kind = listing
+                    .labels
+                    .get(&addr)
+                    .map_or(LabelKind::Branch, |label| label.kind);
+                listing.labels.insert(
+                    addr,
+                    Label {
+                        name: name.clone(),
+                        kind,
+                    },
+                );
+            }
+        }
+
+        self.cache = Some(Cache {
+            platform: snapshot.platform,
+            resets: snapshot.resets,
+            flows,
+            listing,
+        });
+    }
+}
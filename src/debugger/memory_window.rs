@@ -0,0 +1,380 @@
+//! The memory window: a hex viewer and editor for the emulator's RAM.
+
+use std::path::Path;
+
+use egui::{Color32, RichText};
+
+use assembler::SymbolMap;
+use emulator::{Command, Emulator, Snapshot};
+use opcode::Instruction;
+use ram::PROGRAM_START_ADDRESS;
+use sys_font::Font;
+
+use super::parse_addr;
+
+/// The number of bytes shown on each row.
+const BYTES_PER_ROW: usize = 16;
+
+/// The colour of the built-in fonts.
+const FONT_COLOR: Color32 = Color32::from_rgb(0x4E, 0xC9, 0xB0);
+/// The background of the bytes that `I` points at.
+const I_BACKGROUND: Color32 = Color32::from_rgb(0x6B, 0x5B, 0x1E);
+/// The background of the instruction that the PC points at.
+const PC_BACKGROUND: Color32 = Color32::from_rgb(0x1E, 0x4B, 0x7A);
+/// The background of the most-executed addresses in the heatmap. Less-executed
+/// addresses fade towards transparent.
+const HEAT_BACKGROUND: Color32 = Color32::from_rgb(0xB0, 0x30, 0x20);
+
+/// The state of the memory window.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct MemoryWindow {
+    goto_text: String,
+    dump_path: String,
+    dump_start: String,
+    dump_end: String,
+    /// Colour the bytes by how many instructions the profiler has counted at
+    /// them.
+    heatmap: bool,
+
+    /// An address to scroll to on the next frame.
+    #[serde(skip)]
+    scroll_to: Option<u16>,
+    /// The address being edited.
+    #[serde(skip)]
+    selected: Option<u16>,
+    #[serde(skip)]
+    edit_text: String,
+    /// The result of the last import or export.
+    #[serde(skip)]
+    status: Option<Result<String, String>>,
+}
+
+impl MemoryWindow {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+    ) {
+        let ram_size = emulator.ram().lock().unwrap().len();
+
+        ui.horizontal(|ui| {
+            ui.label("Go to:");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.goto_text)
+                    .desired_width(100.0)
+                    .hint_text("address or label"),
+            );
+            let submitted = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+
+            if ui.button("Go").clicked() || submitted {
+                match parse_addr(&self.goto_text, symbols) {
+                    Some(addr) => {
+                        self.scroll_to = Some(addr);
+                        self.select(addr, emulator);
+                    }
+                    None => self.status = Some(Err(format!("Unknown address {}", self.goto_text))),
+                }
+            }
+
+            if ui.button("I").on_hover_text("Go to I").clicked() {
+                self.scroll_to = Some(snapshot.cpu.i);
+            }
+            if ui.button("PC").on_hover_text("Go to the PC").clicked() {
+                self.scroll_to = Some(snapshot.cpu.pc);
+            }
+        });
+
+        self.show_editor(ui, snapshot, emulator);
+        self.show_import_export(ui, snapshot, emulator, symbols);
+
+        if let Some(status) = &self.status {
+            match status {
+                Ok(message) => ui.label(message),
+                Err(message) => ui.colored_label(ui.visuals().error_fg_color, message),
+            };
+        }
+
+        ui.horizontal(|ui| {
+            ui.colored_label(FONT_COLOR, "font");
+            ui.colored_label(ui.visuals().weak_text_color(), "interpreter");
+            ui.label("program");
+            ui.label(RichText::new("I").background_color(I_BACKGROUND));
+            ui.label(RichText::new("PC").background_color(PC_BACKGROUND));
+            ui.separator();
+            ui.checkbox(&mut self.heatmap, "Heatmap")
+                .on_hover_text("Colour the bytes by how often the profiler has seen them executed");
+        });
+
+        ui.separator();
+
+        self.show_rows(ui, ram_size, snapshot, emulator);
+    }
+
+    /// Select a byte for editing.
+    fn select(&mut self, addr: u16, emulator: &Emulator) {
+        let byte = *emulator.ram().lock().unwrap().get(addr);
+        self.selected = Some(addr);
+        self.edit_text = format!("{byte:02X}");
+    }
+
+    /// Show the controls for editing the selected byte, and those after it.
+    fn show_editor(&mut self, ui: &mut egui::Ui, snapshot: &Snapshot, emulator: &Emulator) {
+        let addr = match self.selected {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        ui.horizontal(|ui| {
+            ui.monospace(format!("{addr:04X}:"));
+
+            let response = ui.add_enabled(
+                snapshot.paused,
+                egui::TextEdit::singleline(&mut self.edit_text)
+                    .desired_width(160.0)
+                    .hint_text("hex bytes"),
+            );
+            let submitted = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+
+            if ui
+                .add_enabled(snapshot.paused, egui::Button::new("Write"))
+                .clicked()
+                || submitted
+            {
+                match parse_hex_bytes(&self.edit_text) {
+                    Some(bytes) => emulator.send(Command::WriteMemory { addr, bytes }),
+                    None => {
+                        self.status = Some(Err(format!("Invalid bytes {}", self.edit_text)));
+                    }
+                }
+            }
+
+            if !snapshot.paused {
+                ui.weak("Pause to edit");
+            }
+        });
+    }
+
+    fn show_import_export(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+    ) {
+        egui::CollapsingHeader::new("Import / export").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut self.dump_path);
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("From:");
+                ui.add(egui::TextEdit::singleline(&mut self.dump_start).desired_width(60.0));
+                ui.label("to:");
+                ui.add(egui::TextEdit::singleline(&mut self.dump_end).desired_width(60.0));
+            });
+
+            ui.horizontal(|ui| {
+                let range = parse_addr(&self.dump_start, symbols)
+                    .zip(parse_addr(&self.dump_end, symbols))
+                    .filter(|(start, end)| start <= end);
+
+                if ui
+                    .add_enabled(snapshot.paused, egui::Button::new("Import"))
+                    .on_hover_text("Load the file into this range. Pause to import.")
+                    .clicked()
+                {
+                    self.status = Some(match range {
+                        Some((start, end)) => {
+                            import(Path::new(&self.dump_path), start, end, emulator)
+                        }
+                        None => Err("Invalid address range".to_string()),
+                    });
+                }
+
+                if ui
+                    .button("Export")
+                    .on_hover_text("Save this range to the file")
+                    .clicked()
+                {
+                    self.status = Some(match range {
+                        Some((start, end)) => {
+                            export(Path::new(&self.dump_path), start, end, emulator)
+                        }
+                        None => Err("Invalid address range".to_string()),
+                    });
+                }
+            });
+        });
+    }
+
+    /// Show the bytes themselves. Only the visible rows are read from memory,
+    /// so this is just as fast for 64 kiB as for 4 kiB.
+    fn show_rows(
+        &mut self,
+        ui: &mut egui::Ui,
+        ram_size: usize,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+    ) {
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let row_count = ram_size / BYTES_PER_ROW;
+
+        let mut scroll_area = egui::ScrollArea::vertical().auto_shrink([false, false]);
+        if let Some(addr) = self.scroll_to.take() {
+            let row = addr as usize % ram_size / BYTES_PER_ROW;
+            let spacing = ui.spacing().item_spacing.y;
+            scroll_area = scroll_area.vertical_scroll_offset(row as f32 * (row_height + spacing));
+        }
+
+        let cpu = &snapshot.cpu;
+        let pc_len = {
+            let ram = emulator.ram().lock().unwrap();
+            let opcode = [*ram.get(cpu.pc), *ram.get(cpu.pc.wrapping_add(1))];
+            Instruction::decode_bytes(&opcode, snapshot.platform).len()
+        };
+        let pc_range = cpu.pc as usize..cpu.pc as usize + pc_len as usize;
+        let i_addr = cpu.i as usize;
+
+        // The heat of each address is scaled logarithmically, so that a few
+        // very hot addresses don't make everything else invisible.
+        let heat = self.heatmap.then(|| {
+            let profile = emulator.profile().lock().unwrap();
+            let max = profile.addrs().iter().copied().max().unwrap_or(0);
+            (profile.addrs()[..ram_size].to_vec(), (max as f32).ln_1p())
+        });
+
+        scroll_area.show_rows(ui, row_height, row_count, |ui, rows| {
+            let start = rows.start * BYTES_PER_ROW;
+            let bytes = {
+                let ram = emulator.ram().lock().unwrap();
+                let end = (rows.end * BYTES_PER_ROW).min(ram.len());
+                if start >= end {
+                    return;
+                }
+                ram.get_range(start as u16..=(end - 1) as u16).to_vec()
+            };
+
+            for (row, chunk) in bytes.chunks(BYTES_PER_ROW).enumerate() {
+                let row_addr = start + row * BYTES_PER_ROW;
+
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 4.0;
+                    ui.monospace(RichText::new(format!("{row_addr:04X}")).weak());
+                    ui.add_space(4.0);
+
+                    for (column, &byte) in chunk.iter().enumerate() {
+                        let addr = row_addr + column;
+
+                        let mut text = RichText::new(format!("{byte:02X}"))
+                            .monospace()
+                            .color(region_color(addr, ui.visuals()));
+                        if pc_range.contains(&addr) {
+                            text = text.background_color(PC_BACKGROUND);
+                        } else if addr == i_addr {
+                            text = text.background_color(I_BACKGROUND);
+                        } else if let Some((counts, max)) = &heat {
+                            if counts[addr] > 0 {
+                                let t = (counts[addr] as f32).ln_1p() / max;
+                                text = text.background_color(HEAT_BACKGROUND.linear_multiply(t));
+                            }
+                        }
+                        if self.selected == Some(addr as u16) {
+                            text = text.underline();
+                        }
+
+                        let hover = match &heat {
+                            Some((counts, _)) => {
+                                format!("{addr:04X}: executed {} times", counts[addr])
+                            }
+                            None => format!("{addr:04X}"),
+                        };
+
+                        if ui
+                            .add(egui::Label::new(text).sense(egui::Sense::click()))
+                            .on_hover_text(hover)
+                            .clicked()
+                        {
+                            self.select(addr as u16, emulator);
+                        }
+                    }
+
+                    ui.add_space(8.0);
+
+                    let ascii: String = chunk
+                        .iter()
+                        .map(|&byte| {
+                            if byte.is_ascii_graphic() {
+                                byte as char
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect();
+                    ui.monospace(RichText::new(ascii).weak());
+                });
+            }
+        });
+    }
+}
+
+/// The colour of a byte, depending on which region of memory it's in.
+fn region_color(addr: usize, visuals: &egui::Visuals) -> Color32 {
+    let font = Font::PREFERRED_TABLE_STARTING_ADDRESS as usize;
+    let big_font = Font::PREFERRED_BIG_TABLE_STARTING_ADDRESS as usize;
+
+    let font_len = Font::get_table_as_bytes().len();
+    let big_font_len = Font::get_big_table_as_bytes().len();
+
+    if (font..font + font_len).contains(&addr)
+        || (big_font..big_font + big_font_len).contains(&addr)
+    {
+        FONT_COLOR
+    } else if addr < PROGRAM_START_ADDRESS as usize {
+        visuals.weak_text_color()
+    } else {
+        visuals.text_color()
+    }
+}
+
+/// Parse whitespace-separated hex bytes, like `12 3 AB`.
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    text.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte.trim_start_matches("0x"), 16).ok())
+        .collect()
+}
+
+/// Load a binary dump into memory from `start` to `end` inclusive. The dump is
+/// cut short if it's longer than the range.
+fn import(path: &Path, start: u16, end: u16, emulator: &Emulator) -> Result<String, String> {
+    if end as usize >= emulator.ram().lock().unwrap().len() {
+        return Err(format!("{end:04X} is past the end of memory"));
+    }
+
+    let mut bytes =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    bytes.truncate((end - start) as usize + 1);
+
+    let message = format!("Imported {} bytes to {start:04X}", bytes.len());
+    emulator.send(Command::WriteMemory { addr: start, bytes });
+
+    Ok(message)
+}
+
+/// Save memory from `start` to `end` inclusive to a binary dump.
+fn export(path: &Path, start: u16, end: u16, emulator: &Emulator) -> Result<String, String> {
+    let bytes = {
+        let ram = emulator.ram().lock().unwrap();
+        if end as usize >= ram.len() {
+            return Err(format!("{end:04X} is past the end of memory"));
+        }
+        ram.get_range(start..=end).to_vec()
+    };
+
+    std::fs::write(path, &bytes).map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+
+    Ok(format!("Exported {} bytes from {start:04X}", bytes.len()))
+}
@@ -0,0 +1,174 @@
+//! Debugging tools, shown alongside the display.
+
+mod breakpoints_window;
+mod cpu_panel;
+mod disassembly_window;
+mod memory_window;
+mod profiler_window;
+mod trace_window;
+
+use std::time::Duration;
+
+use assembler::SymbolMap;
+use emulator::{Break, Emulator};
+
+use breakpoints_window::BreakpointsWindow;
+use disassembly_window::DisassemblyWindow;
+use memory_window::MemoryWindow;
+use profiler_window::ProfilerWindow;
+use trace_window::TraceWindow;
+
+/// How often the debugger refreshes while the emulator is running.
+const REFRESH_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Which side of the window the debugger panel is docked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum DockSide {
+    Left,
+    Right,
+}
+
+/// The state of the debugger UI.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Debugger {
+    /// True if the debugger side panel is shown.
+    pub panel_shown: bool,
+    pub dock_side: DockSide,
+
+    /// True if the disassembly window is shown.
+    pub disassembly_shown: bool,
+    disassembly: DisassemblyWindow,
+
+    /// True if the memory window is shown.
+    pub memory_shown: bool,
+    memory: MemoryWindow,
+
+    /// True if the breakpoints window is shown.
+    pub breakpoints_shown: bool,
+    breakpoints: BreakpointsWindow,
+
+    /// True if the trace window is shown.
+    pub trace_shown: bool,
+    trace: TraceWindow,
+
+    /// True if the profiler window is shown.
+    pub profiler_shown: bool,
+    profiler: ProfilerWindow,
+
+    /// The breakpoint that the emulator last paused at.
+    #[serde(skip)]
+    last_break: Option<Break>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self {
+            panel_shown: false,
+            dock_side: DockSide::Right,
+            disassembly_shown: false,
+            disassembly: DisassemblyWindow::default(),
+            memory_shown: false,
+            memory: MemoryWindow::default(),
+            breakpoints_shown: false,
+            breakpoints: BreakpointsWindow::default(),
+            trace_shown: false,
+            trace: TraceWindow::default(),
+            profiler_shown: false,
+            profiler: ProfilerWindow::default(),
+            last_break: None,
+        }
+    }
+}
+
+impl Debugger {
+    /// Show where the emulator paused after hitting a breakpoint.
+    pub fn on_break(&mut self, hit: Break) {
+        self.last_break = Some(hit);
+        self.disassembly_shown = true;
+    }
+
+    /// Show the debugger's panels and windows.
+    ///
+    /// Side panels have to be added before the central panel, so call this
+    /// before showing the display.
+    pub fn show(&mut self, ctx: &egui::Context, emulator: &Emulator, symbols: Option<&SymbolMap>) {
+        if !self.panel_shown
+            && !self.disassembly_shown
+            && !self.memory_shown
+            && !self.breakpoints_shown
+            && !self.trace_shown
+            && !self.profiler_shown
+        {
+            return;
+        }
+
+        let snapshot = emulator.snapshot();
+
+        if self.panel_shown {
+            let panel = match self.dock_side {
+                DockSide::Left => egui::SidePanel::left("debugger_panel"),
+                DockSide::Right => egui::SidePanel::right("debugger_panel"),
+            };
+
+            panel.resizable(true).show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    cpu_panel::show(ui, &snapshot, emulator, symbols, &mut self.dock_side);
+                });
+            });
+        }
+
+        egui::Window::new("Disassembly")
+            .open(&mut self.disassembly_shown)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                self.disassembly.show(ui, &snapshot, emulator, symbols);
+            });
+
+        egui::Window::new("Memory")
+            .open(&mut self.memory_shown)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                self.memory.show(ui, &snapshot, emulator, symbols);
+            });
+
+        egui::Window::new("Breakpoints")
+            .open(&mut self.breakpoints_shown)
+            .show(ctx, |ui| {
+                self.breakpoints
+                    .show(ui, &snapshot, emulator, symbols, self.last_break.as_ref());
+            });
+
+        egui::Window::new("Trace")
+            .open(&mut self.trace_shown)
+            .show(ctx, |ui| {
+                self.trace.show(ui, &snapshot, emulator, symbols);
+            });
+
+        egui::Window::new("Profiler")
+            .open(&mut self.profiler_shown)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                self.profiler.show(ui, &snapshot, emulator, symbols);
+            });
+
+        // The emulator only wakes the UI up when the display changes, but the
+        // registers and memory change all the time.
+        if !snapshot.paused {
+            ctx.request_repaint_after(REFRESH_INTERVAL);
+        }
+    }
+}
+
+/// Parse an address typed in by the user: either the name of a label, or hex
+/// with or without a `0x` prefix.
+pub fn parse_addr(text: &str, symbols: Option<&SymbolMap>) -> Option<u16> {
+    let text = text.trim();
+
+    if let Some(&addr) = symbols.and_then(|symbols| symbols.labels.get(text)) {
+        return Some(addr);
+    }
+
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).ok()
+}
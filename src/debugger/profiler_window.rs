@@ -0,0 +1,285 @@
+//! The profiler window, for finding where a program spends its time.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use assembler::SymbolMap;
+use emulator::{Command, Emulator, Profile, Snapshot, SubroutineProfile};
+
+/// The number of addresses shown in the hot spots table.
+const HOT_SPOTS_SHOWN: usize = 50;
+
+/// The state of the profiler window.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ProfilerWindow {
+    export_path: String,
+
+    /// The result of the last export.
+    #[serde(skip)]
+    status: Option<Result<String, String>>,
+}
+
+impl Default for ProfilerWindow {
+    fn default() -> Self {
+        Self {
+            export_path: "rust-chip-profile.csv".to_string(),
+            status: None,
+        }
+    }
+}
+
+/// The parts of a profile that are shown, copied out so that the profile
+/// isn't locked while drawing.
+struct Summary {
+    instructions: u64,
+    frames: u64,
+    hot_spots: Vec<(u16, u64)>,
+    classes: Vec<(&'static str, u64)>,
+    subroutines: Vec<SubroutineProfile>,
+}
+
+impl Summary {
+    fn new(profile: &Profile) -> Self {
+        let mut hot_spots: Vec<_> = profile
+            .addrs()
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(addr, &count)| (addr as u16, count))
+            .collect();
+        hot_spots.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        hot_spots.truncate(HOT_SPOTS_SHOWN);
+
+        let mut classes: Vec<_> = profile
+            .classes()
+            .iter()
+            .map(|(&class, &count)| (class, count))
+            .collect();
+        classes.sort_by_key(|&(class, count)| (std::cmp::Reverse(count), class));
+
+        let mut subroutines = profile.subroutines();
+        subroutines.sort_by_key(|subroutine| std::cmp::Reverse(subroutine.inclusive));
+
+        Self {
+            instructions: profile.instructions(),
+            frames: profile.frames(),
+            hot_spots,
+            classes,
+            subroutines,
+        }
+    }
+
+    /// A count as a percentage of all instructions.
+    fn percent(&self, count: u64) -> String {
+        if self.instructions == 0 {
+            return String::new();
+        }
+        format!("{:.1}%", count as f64 * 100.0 / self.instructions as f64)
+    }
+}
+
+impl ProfilerWindow {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+    ) {
+        ui.horizontal(|ui| {
+            if snapshot.profiling {
+                if ui.button("Stop").clicked() {
+                    emulator.send(Command::StopProfiling);
+                }
+                ui.label("Profiling...");
+            } else if ui
+                .button("Start")
+                .on_hover_text("Start counting instructions, clearing the last profile")
+                .clicked()
+            {
+                emulator.send(Command::StartProfiling);
+            }
+        });
+
+        let summary = Summary::new(&emulator.profile().lock().unwrap());
+
+        let per_frame = if summary.frames == 0 {
+            0.0
+        } else {
+            summary.instructions as f64 / summary.frames as f64
+        };
+        ui.label(format!(
+            "{} instructions over {} frames ({per_frame:.1} per frame)",
+            summary.instructions, summary.frames
+        ));
+
+        self.show_export(ui, emulator, symbols);
+
+        if let Some(status) = &self.status {
+            match status {
+                Ok(message) => ui.label(message),
+                Err(message) => ui.colored_label(ui.visuals().error_fg_color, message),
+            };
+        }
+
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::CollapsingHeader::new("Hot spots")
+                .default_open(true)
+                .show(ui, |ui| show_hot_spots(ui, &summary, symbols));
+            egui::CollapsingHeader::new("Subroutines")
+                .default_open(true)
+                .show(ui, |ui| show_subroutines(ui, &summary, symbols));
+            egui::CollapsingHeader::new("Instructions").show(ui, |ui| show_classes(ui, &summary));
+        });
+    }
+
+    fn show_export(&mut self, ui: &mut egui::Ui, emulator: &Emulator, symbols: Option<&SymbolMap>) {
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut self.export_path);
+        });
+
+        ui.horizontal(|ui| {
+            let path = Path::new(&self.export_path);
+            let profile = emulator.profile();
+
+            if ui
+                .button("Export CSV")
+                .on_hover_text("Save the instructions executed at each address")
+                .clicked()
+            {
+                self.status = Some(export(path, |file| {
+                    profile.lock().unwrap().write_csv(file, |addr| {
+                        symbols.and_then(|symbols| symbols.label_at(addr).map(str::to_string))
+                    })
+                }));
+            }
+
+            if ui
+                .button("Export folded stacks")
+                .on_hover_text("Save the call stacks for a flame graph tool, like inferno")
+                .clicked()
+            {
+                self.status = Some(export(path, |file| {
+                    profile
+                        .lock()
+                        .unwrap()
+                        .write_folded(file, |addr| subroutine_name(addr, symbols))
+                }));
+            }
+        });
+    }
+}
+
+fn show_hot_spots(ui: &mut egui::Ui, summary: &Summary, symbols: Option<&SymbolMap>) {
+    egui::Grid::new("profiler_hot_spots")
+        .striped(true)
+        .num_columns(3)
+        .show(ui, |ui| {
+            ui.strong("Address");
+            ui.strong("Instructions");
+            ui.strong("");
+            ui.end_row();
+
+            for &(addr, count) in &summary.hot_spots {
+                ui.monospace(format_addr(addr, symbols));
+                ui.monospace(count.to_string());
+                ui.monospace(summary.percent(count));
+                ui.end_row();
+            }
+        });
+}
+
+fn show_subroutines(ui: &mut egui::Ui, summary: &Summary, symbols: Option<&SymbolMap>) {
+    if summary.subroutines.is_empty() {
+        ui.weak("No subroutines called");
+        return;
+    }
+
+    egui::Grid::new("profiler_subroutines")
+        .striped(true)
+        .num_columns(5)
+        .show(ui, |ui| {
+            ui.strong("Subroutine");
+            ui.strong("Calls");
+            ui.strong("Inclusive")
+                .on_hover_text("Instructions executed in the subroutine and the ones it calls");
+            ui.strong("Exclusive")
+                .on_hover_text("Instructions executed in the subroutine itself");
+            ui.strong("Per call");
+            ui.end_row();
+
+            for subroutine in &summary.subroutines {
+                ui.monospace(subroutine_name(subroutine.addr, symbols));
+                ui.monospace(subroutine.calls.to_string());
+                ui.monospace(format!(
+                    "{} ({})",
+                    subroutine.inclusive,
+                    summary.percent(subroutine.inclusive)
+                ));
+                ui.monospace(subroutine.exclusive.to_string());
+                ui.monospace(match subroutine.calls {
+                    0 => String::new(),
+                    calls => format!("{:.1}", subroutine.inclusive as f64 / calls as f64),
+                });
+                ui.end_row();
+            }
+        });
+}
+
+fn show_classes(ui: &mut egui::Ui, summary: &Summary) {
+    egui::Grid::new("profiler_classes")
+        .striped(true)
+        .num_columns(3)
+        .show(ui, |ui| {
+            ui.strong("Opcode");
+            ui.strong("Instructions");
+            ui.strong("");
+            ui.end_row();
+
+            for &(class, count) in &summary.classes {
+                ui.monospace(class);
+                ui.monospace(count.to_string());
+                ui.monospace(summary.percent(count));
+                ui.end_row();
+            }
+        });
+}
+
+/// An address, along with the label it's in if there is one.
+fn format_addr(addr: u16, symbols: Option<&SymbolMap>) -> String {
+    match symbols.and_then(|symbols| symbols.nearest_label(addr)) {
+        Some((name, 0)) => format!("{addr:04X} {name}"),
+        Some((name, offset)) => format!("{addr:04X} {name}+{offset}"),
+        None => format!("{addr:04X}"),
+    }
+}
+
+/// The name of a subroutine: its label, or its address if it doesn't have one.
+fn subroutine_name(addr: u16, symbols: Option<&SymbolMap>) -> String {
+    match symbols.and_then(|symbols| symbols.label_at(addr)) {
+        Some(name) => name.to_string(),
+        None => format!("sub_{addr:03X}"),
+    }
+}
+
+/// Write a file with `write`, and describe how it went.
+fn export(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+) -> Result<String, String> {
+    let result = File::create(path).and_then(|file| {
+        let mut file = BufWriter::new(file);
+        write(&mut file)?;
+        std::io::Write::flush(&mut file)
+    });
+
+    match result {
+        Ok(()) => Ok(format!("Exported to {}", path.display())),
+        Err(e) => Err(format!("Failed to write {}: {e}", path.display())),
+    }
+}
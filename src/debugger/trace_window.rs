@@ -0,0 +1,156 @@
+//! The trace window, for logging every instruction executed to a file.
+
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use assembler::SymbolMap;
+use emulator::{Command, Emulator, Snapshot, TraceOptions};
+
+use super::parse_addr;
+
+/// The default maximum size of a trace, in MiB.
+const DEFAULT_MAX_MIB: u32 = 100;
+
+/// The state of the trace window.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct TraceWindow {
+    path: String,
+    /// Address ranges like `0x200-0x2FF`, separated by commas.
+    ranges: String,
+    /// The maximum size of the trace in MiB, or zero for no limit.
+    max_mib: u32,
+    /// Restart the ROM when starting a trace, so that it covers everything from
+    /// the first instruction.
+    restart: bool,
+
+    #[serde(skip)]
+    error: Option<String>,
+}
+
+impl Default for TraceWindow {
+    fn default() -> Self {
+        Self {
+            path: "rust-chip.trace".to_string(),
+            ranges: String::new(),
+            max_mib: DEFAULT_MAX_MIB,
+            restart: true,
+            error: None,
+        }
+    }
+}
+
+impl TraceWindow {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        snapshot: &Snapshot,
+        emulator: &Emulator,
+        symbols: Option<&SymbolMap>,
+    ) {
+        let running = snapshot.trace.as_ref().is_some_and(|trace| trace.running);
+
+        ui.add_enabled_ui(!running, |ui| {
+            egui::Grid::new("trace_options")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.path);
+                    ui.end_row();
+
+                    ui.label("Addresses:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.ranges)
+                            .hint_text("all, or 0x200-0x2FF, ..."),
+                    );
+                    ui.end_row();
+
+                    ui.label("Maximum size:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.max_mib)
+                            .suffix(" MiB")
+                            .custom_formatter(|mib, _| {
+                                if mib == 0.0 {
+                                    "unlimited".to_string()
+                                } else {
+                                    format!("{mib} MiB")
+                                }
+                            }),
+                    );
+                    ui.end_row();
+                });
+
+            ui.checkbox(&mut self.restart, "Restart the ROM when starting");
+        });
+
+        ui.horizontal(|ui| {
+            if running {
+                if ui.button("Stop").clicked() {
+                    emulator.send(Command::StopTrace);
+                }
+            } else if ui.button("Start").clicked() {
+                match parse_ranges(&self.ranges, symbols) {
+                    Some(ranges) => {
+                        emulator.send(Command::StartTrace(TraceOptions {
+                            path: PathBuf::from(&self.path),
+                            ranges,
+                            max_bytes: (self.max_mib > 0)
+                                .then_some(self.max_mib as u64 * 1024 * 1024),
+                        }));
+                        if self.restart {
+                            emulator.send(Command::HardReset);
+                        }
+                        self.error = None;
+                    }
+                    None => self.error = Some("Invalid address ranges".to_string()),
+                }
+            }
+        });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        if let Some(trace) = &snapshot.trace {
+            let state = if trace.running {
+                "Tracing to"
+            } else if trace.full {
+                "Reached the maximum size of"
+            } else {
+                "Traced to"
+            };
+            ui.label(format!(
+                "{state} {} ({} KiB)",
+                trace.path.display(),
+                trace.bytes_written / 1024
+            ));
+
+            if let Some(error) = &trace.error {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("Tracing failed: {error}"),
+                );
+            }
+        }
+    }
+}
+
+/// Parse a comma-separated list of address ranges like `0x200-0x2FF`, or
+/// single addresses. Empty text means no ranges.
+fn parse_ranges(text: &str, symbols: Option<&SymbolMap>) -> Option<Vec<RangeInclusive<u16>>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            // Octo labels often have dashes in them, so check for one first.
+            if let Some(addr) = parse_addr(range, symbols) {
+                return Some(addr..=addr);
+            }
+
+            let (start, end) = range.split_once('-')?;
+            let start = parse_addr(start, symbols)?;
+            let end = parse_addr(end, symbols)?;
+            (start <= end).then_some(start..=end)
+        })
+        .collect()
+}
@@ -2,12 +2,36 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod app;
+mod debugger;
 mod egui_ui_thread_waker;
 
+use std::path::PathBuf;
+
+use clap::Parser;
+
 use app::App;
+use cpu::RngKind;
 use egui_ui_thread_waker::EguiUiThreadWaker;
 use emulator::Emulator;
 
+/// A CHIP8, SUPER-CHIP and XO-CHIP emulator.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// A ROM or Octo source file to run.
+    rom: Option<PathBuf>,
+
+    /// Seed the random number generator, so that every run generates the same
+    /// random numbers. Overrides the seed chosen in the Emulation menu.
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// The random number generator to use: `xorshift`, or `vip` to approximate
+    /// the COSMAC VIP's.
+    #[arg(long)]
+    rng: Option<RngKind>,
+}
+
 /// For when compiling to a native target.
 ///
 /// Currently, this app does not support wasm32.
@@ -19,6 +43,15 @@ fn main() -> color_eyre::Result<()> {
     let emulator_app_ref = emulator.clone();
     let emulator_bg_thread_ref = emulator.clone();
 
+    let args = Args::parse();
+
+    // Commands are queued up until the emulator thread starts, so it's fine to
+    // load the ROM now.
+    let symbols = match &args.rom {
+        Some(rom_path) => app::load_rom_file(&emulator, rom_path)?,
+        None => None,
+    };
+
     let options = eframe::NativeOptions {
         hardware_acceleration: eframe::HardwareAcceleration::Required,
         renderer: eframe::Renderer::Wgpu,
@@ -32,12 +65,23 @@ fn main() -> color_eyre::Result<()> {
         Box::new(move |cc| {
             let emu_egui_context = cc.egui_ctx.clone();
 
+            // The app sends the random number generator options, which have
+            // to be handled before the ROM starts running.
+            let app = App::new(
+                cc,
+                &emulator_app_ref,
+                symbols,
+                args.rom.as_deref(),
+                args.rng,
+                args.seed,
+            );
+
             // Start the emulator in its background thread
             emulator_bg_thread_ref
                 .start(EguiUiThreadWaker::from(emu_egui_context))
                 .unwrap();
 
-            Box::new(App::new(cc, &emulator_app_ref))
+            Box::new(app)
         }),
     );
 